@@ -0,0 +1,411 @@
+use std::borrow::Cow;
+
+use crate::{
+	ALLOW_INSTRUCTION, COLOR_SPACE_INSTRUCTION, DECLARE_INSTRUCTION, DEFINE_ADD_INSTRUCTION,
+	DEFINE_APPEND_INSTRUCTION, DEFINE_DEFAULT_INSTRUCTION, DEFINE_INSTRUCTION,
+	DEPRECATED_INSTRUCTION, ELIFDEF_INSTRUCTION, ELIFNDEF_INSTRUCTION, ELIF_INSTRUCTION,
+	ELSE_INSTRUCTION, ENDIF_INSTRUCTION, ENDPRESERVE_INSTRUCTION, ENDSECTION_INSTRUCTION,
+	EXPECT_INSTRUCTION, FUNCTION_MACRO_REGEX, IFDEF_INSTRUCTION, IFNDEF_INSTRUCTION,
+	IF_INSTRUCTION, INCLUDE_FIRST_INSTRUCTION, INCLUDE_INSTRUCTION, INCLUDE_SPIRV_INSTRUCTION,
+	MACRO_REGEX, MODULE_INSTRUCTION, ONCE_INSTRUCTION, PRESERVE_INSTRUCTION, REQUIRES_INSTRUCTION,
+	SECTION_INSTRUCTION,
+};
+
+/// Line of a module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Line<'a> {
+	pub(crate) text: &'a str,
+	/// Line number, starting at 1.
+	pub(crate) number: usize,
+}
+
+/// Node of the directive tree of a module, see [`parse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Node<'a> {
+	/// Line of code.
+	Text(Line<'a>),
+	/// `include` statement.
+	Include(Line<'a>),
+	/// `include_first` statement.
+	IncludeFirst(Line<'a>),
+	/// `include_spirv` statement.
+	IncludeSpirv(Line<'a>),
+	/// `define` statement, with the name and the value of the macro if it has one.
+	/// Values continued on the next lines with `\\` span several lines.
+	Define {
+		line: Line<'a>,
+		name: &'a str,
+		value: Option<Cow<'a, str>>,
+	},
+	/// `define` statement of a function-like macro, with its comma-separated parameters and its body.
+	DefineFunction {
+		line: Line<'a>,
+		name: &'a str,
+		parameters: &'a str,
+		body: Cow<'a, str>,
+	},
+	/// Statement applied where it appears.
+	Statement(Statement, Line<'a>),
+	/// `section` statement, with the name of the section if it has one.
+	Section(Line<'a>, Option<&'a str>),
+	/// `endsection` statement.
+	EndSection(Line<'a>),
+	/// Region between `preserve` and `endpreserve` statements, kept untouched.
+	Preserve {
+		line: Line<'a>,
+		region: String,
+		is_closed: bool,
+	},
+	/// Conditional block.
+	Conditional(Conditional<'a>),
+	/// `else`, `endif` or `endpreserve` statement without a matching opening statement.
+	Unmatched(Line<'a>),
+}
+
+/// Statement of a [`Node::Statement`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Statement {
+	Allow,
+	ColorSpace,
+	Declare,
+	DefineAdd,
+	DefineAppend,
+	DefineDefault,
+	Deprecated,
+	Expect,
+	Module,
+	Once,
+	Requires,
+}
+
+/// Conditional block, from an `ifdef`, `ifndef` or `if` statement to its `endif`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Conditional<'a> {
+	/// Line of the opening statement.
+	pub(crate) line: Line<'a>,
+	/// Branch of the opening statement, then of every `elif`, `elifdef`, `elifndef` or `else` statement.
+	/// Only the first branch whose condition is true is kept.
+	pub(crate) branches: Vec<Branch<'a>>,
+	/// Whether the block has an `endif` statement.
+	pub(crate) is_closed: bool,
+}
+
+/// Branch of a [`Conditional`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Branch<'a> {
+	/// Line of the statement opening the branch.
+	pub(crate) line: Line<'a>,
+	/// Condition of the branch, or [`None`] for an `else` statement.
+	pub(crate) condition: Option<Condition<'a>>,
+	pub(crate) nodes: Vec<Node<'a>>,
+}
+
+/// Condition of a [`Branch`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Condition<'a> {
+	/// `ifdef` or `elifdef` statement, or `ifndef` or `elifndef` if `is_negated`, with its symbol if it has one.
+	Defined {
+		symbol: Option<&'a str>,
+		is_negated: bool,
+	},
+	/// `if` or `elif` statement with its expression.
+	If(&'a str),
+}
+
+/// Nodes of a module, see [`parse`].
+type Nodes<'a> = Vec<Node<'a>>;
+
+self_cell::self_cell!(
+	/// Source of a module with the nodes borrowing it.
+	struct ParsedSource {
+		owner: String,
+		#[covariant]
+		dependent: Nodes,
+	}
+	impl {Debug}
+);
+
+/// Directive tree of a module owning the source it borrows, so it can be kept and evaluated for many builds,
+/// see [`ShaderBuilder::parse`](crate::ShaderBuilder::parse).
+#[derive(Debug)]
+pub(crate) struct Tree(ParsedSource);
+
+impl Tree {
+	/// Parses a module source, see [`parse`].
+	pub(crate) fn new(source: String) -> Self {
+		Self(ParsedSource::new(source, |source| parse(source)))
+	}
+
+	/// Returns the nodes of the tree, borrowing its source.
+	pub(crate) fn nodes(&self) -> &[Node<'_>] {
+		self.0.borrow_dependent()
+	}
+}
+
+/// Parses the statements of a module into a tree, whose nodes are evaluated in order when the module is loaded.
+/// Conditional blocks are nested, and `preserve` regions are kept as they are, even if they contain statements.
+/// Unmatched statements are kept as nodes, so their errors are reported where they appear.
+pub(crate) fn parse(source: &str) -> Vec<Node<'_>> {
+	let mut lines = source.lines().enumerate().map(|(index, text)| Line {
+		text,
+		number: index + 1,
+	});
+	let mut nodes = Vec::new();
+	let mut open_conditionals: Vec<Conditional> = Vec::new();
+	while let Some(line) = lines.next() {
+		let mut tokens = line.text.split_whitespace();
+		let node = match tokens.next() {
+			Some(PRESERVE_INSTRUCTION) => {
+				let mut region = String::new();
+				let mut is_closed = false;
+				for region_line in lines.by_ref() {
+					if region_line.text.trim() == ENDPRESERVE_INSTRUCTION {
+						is_closed = true;
+						break;
+					}
+					region.push_str(region_line.text);
+					region.push('\n');
+				}
+				Node::Preserve {
+					line,
+					region,
+					is_closed,
+				}
+			}
+			Some(SECTION_INSTRUCTION) => Node::Section(line, tokens.next()),
+			Some(ENDSECTION_INSTRUCTION) => Node::EndSection(line),
+			Some(instruction @ (IFDEF_INSTRUCTION | IFNDEF_INSTRUCTION | IF_INSTRUCTION)) => {
+				open_conditionals.push(Conditional {
+					line,
+					branches: vec![Branch {
+						line,
+						condition: Some(condition(instruction, line, tokens.next())),
+						nodes: Vec::new(),
+					}],
+					is_closed: false,
+				});
+				continue;
+			}
+			Some(
+				instruction @ (ELIFDEF_INSTRUCTION | ELIFNDEF_INSTRUCTION | ELIF_INSTRUCTION
+				| ELSE_INSTRUCTION),
+			) => match open_conditionals.last_mut() {
+				Some(conditional) => {
+					conditional.branches.push(Branch {
+						line,
+						condition: (instruction != ELSE_INSTRUCTION)
+							.then(|| condition(instruction, line, tokens.next())),
+						nodes: Vec::new(),
+					});
+					continue;
+				}
+				None => Node::Unmatched(line),
+			},
+			Some(ENDIF_INSTRUCTION) => match open_conditionals.pop() {
+				Some(conditional) => Node::Conditional(Conditional {
+					is_closed: true,
+					..conditional
+				}),
+				None => Node::Unmatched(line),
+			},
+			Some(ENDPRESERVE_INSTRUCTION) => Node::Unmatched(line),
+			_ => match line.text.trim_end().strip_suffix('\\') {
+				Some(text) => continued_definition(Line { text, ..line }, &mut lines),
+				None => statement(line),
+			},
+		};
+		push_node(&mut nodes, &mut open_conditionals, node);
+	}
+	while let Some(conditional) = open_conditionals.pop() {
+		push_node(
+			&mut nodes,
+			&mut open_conditionals,
+			Node::Conditional(conditional),
+		);
+	}
+	nodes
+}
+
+/// Returns the condition of a conditional statement, given the token following the instruction.
+fn condition<'a>(instruction: &str, line: Line<'a>, symbol: Option<&'a str>) -> Condition<'a> {
+	match instruction {
+		IF_INSTRUCTION | ELIF_INSTRUCTION => {
+			Condition::If(line.text.trim()[instruction.len()..].trim())
+		}
+		_ => Condition::Defined {
+			symbol,
+			is_negated: instruction == IFNDEF_INSTRUCTION || instruction == ELIFNDEF_INSTRUCTION,
+		},
+	}
+}
+
+/// Adds a node to the current branch of the innermost open conditional block, or to the module.
+fn push_node<'a>(
+	nodes: &mut Vec<Node<'a>>,
+	open_conditionals: &mut [Conditional<'a>],
+	node: Node<'a>,
+) {
+	match open_conditionals.last_mut() {
+		Some(conditional) => conditional.branches.last_mut().unwrap().nodes.push(node),
+		None => nodes.push(node),
+	}
+}
+
+/// Returns the node of a line ending with `\\`, without it.
+/// If the line is a `define` statement, the value of the macro is continued on the next lines,
+/// up to the first line that does not end with `\\`, and the lines of the value are kept.
+fn continued_definition<'a>(
+	line: Line<'a>,
+	lines: &mut impl Iterator<Item = Line<'a>>,
+) -> Node<'a> {
+	match statement(line) {
+		Node::Define { line, name, value } => Node::Define {
+			line,
+			name,
+			value: Some(Cow::Owned(continued_value(value.as_deref(), lines))),
+		},
+		Node::DefineFunction {
+			line,
+			name,
+			parameters,
+			body,
+		} => Node::DefineFunction {
+			line,
+			name,
+			parameters,
+			body: Cow::Owned(continued_value(Some(&body), lines)),
+		},
+		node => node,
+	}
+}
+
+/// Returns the value of a macro continued on the next lines, see [`continued_definition`].
+fn continued_value<'a>(value: Option<&str>, lines: &mut impl Iterator<Item = Line<'a>>) -> String {
+	let mut value = value.map_or_else(String::new, |value| value.trim_end().to_string());
+	for line in lines {
+		let (text, is_continued) = match line.text.trim_end().strip_suffix('\\') {
+			Some(text) => (text.trim_end(), true),
+			None => (line.text, false),
+		};
+		if !value.is_empty() {
+			value.push('\n');
+		}
+		value.push_str(text);
+		if !is_continued {
+			break;
+		}
+	}
+	value
+}
+
+/// Returns the node of a line that does not affect the structure of the module.
+fn statement(line: Line) -> Node {
+	let trimmed = line.text.trim();
+	let statement = [
+		(ALLOW_INSTRUCTION, Statement::Allow),
+		(COLOR_SPACE_INSTRUCTION, Statement::ColorSpace),
+		(DECLARE_INSTRUCTION, Statement::Declare),
+		(DEFINE_ADD_INSTRUCTION, Statement::DefineAdd),
+		(DEFINE_APPEND_INSTRUCTION, Statement::DefineAppend),
+		(DEFINE_DEFAULT_INSTRUCTION, Statement::DefineDefault),
+		(DEPRECATED_INSTRUCTION, Statement::Deprecated),
+		(EXPECT_INSTRUCTION, Statement::Expect),
+		(MODULE_INSTRUCTION, Statement::Module),
+		(ONCE_INSTRUCTION, Statement::Once),
+		(REQUIRES_INSTRUCTION, Statement::Requires),
+	]
+	.into_iter()
+	.find(|(instruction, _)| trimmed.starts_with(instruction));
+	if let Some((_, statement)) = statement {
+		Node::Statement(statement, line)
+	} else if line.text.starts_with(INCLUDE_FIRST_INSTRUCTION) {
+		Node::IncludeFirst(line)
+	} else if line.text.starts_with(INCLUDE_SPIRV_INSTRUCTION) {
+		Node::IncludeSpirv(line)
+	} else if line.text.starts_with(INCLUDE_INSTRUCTION) {
+		Node::Include(line)
+	} else if let Some(captures) = FUNCTION_MACRO_REGEX.captures(line.text) {
+		Node::DefineFunction {
+			line,
+			name: captures.get(1).unwrap().as_str(),
+			parameters: captures.get(2).unwrap().as_str(),
+			body: Cow::Borrowed(captures.get(3).map_or("", |body| body.as_str())),
+		}
+	} else if let Some(captures) = MACRO_REGEX.captures(line.text) {
+		Node::Define {
+			line,
+			name: captures.get(1).unwrap().as_str(),
+			value: Some(Cow::Borrowed(captures.get(2).unwrap().as_str())),
+		}
+	} else if let Some(name) = line.text.strip_prefix(DEFINE_INSTRUCTION) {
+		Node::Define {
+			line,
+			name: name.trim(),
+			value: None,
+		}
+	} else {
+		Node::Text(line)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::ast::{parse, Branch, Condition, Conditional, Line, Node};
+
+	#[test]
+	fn parse_conditionals() {
+		let line = |text, number| Line { text, number };
+		assert_eq!(
+			parse(
+				"//!ifdef FOG\nfog();\n//!elif SAMPLES > 4\n//!elifndef HAZE\n//!else\n//!endif\n//!endif\n//!ifndef"
+			),
+			[
+				Node::Conditional(Conditional {
+					line: line("//!ifdef FOG", 1),
+					branches: vec![
+						Branch {
+							line: line("//!ifdef FOG", 1),
+							condition: Some(Condition::Defined {
+								symbol: Some("FOG"),
+								is_negated: false,
+							}),
+							nodes: vec![Node::Text(line("fog();", 2))],
+						},
+						Branch {
+							line: line("//!elif SAMPLES > 4", 3),
+							condition: Some(Condition::If("SAMPLES > 4")),
+							nodes: Vec::new(),
+						},
+						Branch {
+							line: line("//!elifndef HAZE", 4),
+							condition: Some(Condition::Defined {
+								symbol: Some("HAZE"),
+								is_negated: true,
+							}),
+							nodes: Vec::new(),
+						},
+						Branch {
+							line: line("//!else", 5),
+							condition: None,
+							nodes: Vec::new(),
+						},
+					],
+					is_closed: true,
+				}),
+				Node::Unmatched(line("//!endif", 7)),
+				Node::Conditional(Conditional {
+					line: line("//!ifndef", 8),
+					branches: vec![Branch {
+						line: line("//!ifndef", 8),
+						condition: Some(Condition::Defined {
+							symbol: None,
+							is_negated: true,
+						}),
+						nodes: Vec::new(),
+					}],
+					is_closed: false,
+				}),
+			]
+		);
+	}
+}
@@ -0,0 +1,222 @@
+use std::{collections::BTreeMap, num};
+
+use crate::WGSLStruct;
+
+/// Declares the bindings of a shader from Rust code, producing both the WGSL declarations,
+/// emitted with [`crate::ShaderBuilder::put_bindings`], and the matching [`wgpu::BindGroupLayoutEntry`] vectors,
+/// so that they can not get out of sync:
+/// ```
+/// # use wgsl_preprocessor::{Bindings, WGSLStruct};
+/// #[repr(C)]
+/// struct Camera {
+/// 	view_projection: [[f32; 4]; 4],
+/// }
+///
+/// impl WGSLStruct for Camera {
+/// 	fn field_types() -> Vec<(&'static str, String)> {
+/// 		vec![("view_projection", "mat4x4<f32>".to_string())]
+/// 	}
+///
+/// 	fn field_definitions(&self) -> Vec<String> {
+/// 		vec![format!("mat4x4<f32>({:?})", self.view_projection).replace(['[', ']'], "")]
+/// 	}
+/// }
+///
+/// let mut bindings = Bindings::new();
+/// bindings
+/// 	.group(0)
+/// 	.uniform::<Camera>("camera")
+/// 	.texture_2d("albedo")
+/// 	.sampler("samp");
+/// assert_eq!(bindings.layout_entries(0).len(), 3);
+/// ```
+/// Bindings are numbered in order of declaration within their group.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+	groups: BTreeMap<u32, Vec<Binding>>,
+	group: u32,
+	visibility: wgpu::ShaderStages,
+	/// Name and WGSL definition of every struct used by a binding, in order of first use.
+	struct_definitions: Vec<(&'static str, String)>,
+}
+
+#[derive(Clone, Debug)]
+struct Binding {
+	name: String,
+	/// Declaration following the attributes, for example `var<uniform> camera: Camera`.
+	declaration: String,
+	entry: wgpu::BindGroupLayoutEntry,
+}
+
+impl Default for Bindings {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Bindings {
+	/// Creates new [`Bindings`], adding bindings to group 0 and visible to all shader stages.
+	pub fn new() -> Self {
+		Self {
+			groups: BTreeMap::new(),
+			group: 0,
+			visibility: wgpu::ShaderStages::all(),
+			struct_definitions: Vec::new(),
+		}
+	}
+
+	/// Adds the following bindings to group `group`.
+	pub fn group(&mut self, group: u32) -> &mut Self {
+		self.group = group;
+		self
+	}
+
+	/// Makes the following bindings visible to the shader stages `visibility`.
+	pub fn visibility(&mut self, visibility: wgpu::ShaderStages) -> &mut Self {
+		self.visibility = visibility;
+		self
+	}
+
+	/// Adds a uniform buffer binding of a `T` struct, whose WGSL definition is emitted with the declarations.
+	///
+	/// # Panics
+	/// Panics if the WGSL layout of the fields does not match the size of `T`,
+	/// or if a field type is not host-shareable.
+	pub fn uniform<T: WGSLStruct>(&mut self, name: &str) -> &mut Self {
+		let (type_name, size) = self.struct_type::<T>();
+		self.push(
+			name,
+			format!("var<uniform> {name}: {type_name}"),
+			wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: num::NonZeroU64::new(size.into()),
+			},
+		)
+	}
+
+	/// Adds a storage buffer binding of a `T` struct, whose WGSL definition is emitted with the declarations.
+	/// The buffer is declared with the `read` access mode if `read_only` is `true`, or `read_write` otherwise.
+	///
+	/// The access mode is validated against the modules of the build: writing to a read-only buffer is an error,
+	/// and a `read_write` buffer that is never written causes the warning `W005`.
+	///
+	/// # Panics
+	/// Panics if the WGSL layout of the fields does not match the size of `T`,
+	/// or if a field type is not host-shareable.
+	pub fn storage<T: WGSLStruct>(&mut self, name: &str, read_only: bool) -> &mut Self {
+		let (type_name, size) = self.struct_type::<T>();
+		let access = if read_only { "read" } else { "read_write" };
+		self.push(
+			name,
+			format!("var<storage, {access}> {name}: {type_name}"),
+			wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only },
+				has_dynamic_offset: false,
+				min_binding_size: num::NonZeroU64::new(size.into()),
+			},
+		)
+	}
+
+	/// Adds a binding of a filterable 2D `texture_2d<f32>`.
+	pub fn texture_2d(&mut self, name: &str) -> &mut Self {
+		self.push(
+			name,
+			format!("var {name}: texture_2d<f32>"),
+			wgpu::BindingType::Texture {
+				sample_type: wgpu::TextureSampleType::Float { filterable: true },
+				view_dimension: wgpu::TextureViewDimension::D2,
+				multisampled: false,
+			},
+		)
+	}
+
+	/// Adds a binding of a filtering `sampler`.
+	pub fn sampler(&mut self, name: &str) -> &mut Self {
+		self.push(
+			name,
+			format!("var {name}: sampler"),
+			wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+		)
+	}
+
+	/// Returns the layout entries of a group, for building a [`wgpu::BindGroupLayoutDescriptor`].
+	pub fn layout_entries(&self, group: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+		self.groups
+			.get(&group)
+			.map(|bindings| bindings.iter().map(|binding| binding.entry).collect())
+			.unwrap_or_default()
+	}
+
+	/// Returns the groups that have bindings, in ascending order.
+	pub fn groups(&self) -> Vec<u32> {
+		self.groups.keys().copied().collect()
+	}
+
+	/// Returns the name of every storage buffer binding and whether it is read-only.
+	pub(crate) fn storage_accesses(&self) -> Vec<(String, bool)> {
+		self.groups
+			.values()
+			.flatten()
+			.filter_map(|binding| match binding.entry.ty {
+				wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Storage { read_only },
+					..
+				} => Some((binding.name.clone(), read_only)),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// Returns the WGSL definitions of the structs and the declarations of the bindings.
+	pub(crate) fn declarations(&self) -> String {
+		let mut declarations: Vec<String> = self
+			.struct_definitions
+			.iter()
+			.map(|(_, struct_definition)| struct_definition.clone())
+			.collect();
+		for (group, bindings) in self.groups.iter() {
+			for binding in bindings {
+				declarations.push(format!(
+					"@group({group}) @binding({}) {};",
+					binding.entry.binding, binding.declaration
+				));
+			}
+		}
+		declarations.join("\n")
+	}
+
+	/// Records the definition of `T` if it was not used yet, returning its name and size.
+	fn struct_type<T: WGSLStruct>(&mut self) -> (&'static str, u32) {
+		let type_name = crate::short_type_name::<T>();
+		let size = crate::struct_layout::<T>(type_name).size;
+		if !self
+			.struct_definitions
+			.iter()
+			.any(|(name, _)| *name == type_name)
+		{
+			let mut struct_definition = format!("struct {type_name} {{\n");
+			for (field_name, field_type) in T::field_types().iter() {
+				struct_definition.push_str(&format!("\t{field_name}: {field_type},\n"));
+			}
+			struct_definition.push('}');
+			self.struct_definitions.push((type_name, struct_definition));
+		}
+		(type_name, size)
+	}
+
+	fn push(&mut self, name: &str, declaration: String, ty: wgpu::BindingType) -> &mut Self {
+		let bindings = self.groups.entry(self.group).or_default();
+		bindings.push(Binding {
+			name: name.to_string(),
+			declaration,
+			entry: wgpu::BindGroupLayoutEntry {
+				binding: bindings.len() as u32,
+				visibility: self.visibility,
+				ty,
+				count: None,
+			},
+		});
+		self
+	}
+}
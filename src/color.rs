@@ -0,0 +1,85 @@
+use std::{fmt, str};
+
+/// Color space of colors, see [`crate::ShaderBuilder::color_space`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorSpace {
+	/// Linear color space, where colors can be blended and lit.
+	#[default]
+	Linear,
+	/// sRGB color space, as in most color pickers and image files.
+	Srgb,
+}
+
+impl fmt::Display for ColorSpace {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Linear => "linear",
+				Self::Srgb => "srgb",
+			}
+		)
+	}
+}
+
+impl str::FromStr for ColorSpace {
+	type Err = String;
+
+	fn from_str(color_space: &str) -> Result<Self, Self::Err> {
+		match color_space {
+			"linear" => Ok(Self::Linear),
+			"srgb" => Ok(Self::Srgb),
+			_ => Err(format!(
+				"unknown color space `{color_space}`, expected `linear` or `srgb`"
+			)),
+		}
+	}
+}
+
+/// Color with an alpha channel, defined with [`crate::ShaderBuilder::put_color`].
+/// Alpha is never converted between color spaces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+	/// 8-bit sRGB color, like `#ff8000ff`.
+	Srgb8([u8; 4]),
+	/// Linear color.
+	Linear([f32; 4]),
+}
+
+impl Color {
+	/// Returns the components of the color in a color space.
+	pub fn components(&self, color_space: ColorSpace) -> [f32; 4] {
+		let (components, source_space) = match *self {
+			Self::Srgb8(components) => (
+				components.map(|component| component as f32 / 255.0),
+				ColorSpace::Srgb,
+			),
+			Self::Linear(components) => (components, ColorSpace::Linear),
+		};
+		let convert: fn(f32) -> f32 = match (source_space, color_space) {
+			(ColorSpace::Srgb, ColorSpace::Linear) => srgb_to_linear,
+			(ColorSpace::Linear, ColorSpace::Srgb) => linear_to_srgb,
+			_ => |component| component,
+		};
+		let [red, green, blue, alpha] = components;
+		[convert(red), convert(green), convert(blue), alpha]
+	}
+}
+
+fn srgb_to_linear(component: f32) -> f32 {
+	if component <= 0.04045 {
+		component / 12.92
+	} else {
+		((component + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+fn linear_to_srgb(component: f32) -> f32 {
+	if component <= 0.0031308 {
+		component * 12.92
+	} else {
+		1.055 * component.powf(1.0 / 2.4) - 0.055
+	}
+}
@@ -0,0 +1,209 @@
+/*!
+Serving builds to other processes, so tools that are not written in Rust, like DCC plugins or Python pipelines,
+can reuse the same preprocessor, enabled by the **daemon** feature.
+
+Requests and responses are JSON objects, one per line.
+A [`BuildRequest`] names the root module and its definitions, as WGSL strings:
+```json
+{"path": "shaders/main.wgsl", "defines": {"SHADOWS": "true", "WIDTH": "256u"}}
+```
+and is answered with a [`BuildResponse`], either the built source or the error of the build:
+```json
+{"status": "ok", "source": "..."}
+{"status": "error", "message": "...", "file": "shaders/lighting.wgsl", "line": 12}
+```
+*/
+use std::{collections::HashMap, io, thread};
+
+use crate::{BuilderConfig, Diagnostic, ShaderBuilder};
+
+/// Request of a build, read from a line of JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BuildRequest {
+	/// Path to the root WGSL module.
+	pub path: String,
+	/// Definitions of the build, as WGSL strings, like the ones put with [`ShaderBuilder::put_constant`].
+	#[serde(default)]
+	pub defines: HashMap<String, String>,
+}
+
+/// Response to a [`BuildRequest`], written as a line of JSON.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BuildResponse {
+	/// The source was built.
+	Ok {
+		/// Built source.
+		source: String,
+	},
+	/// The request could not be read, or the source could not be built.
+	Error {
+		/// Message of the error.
+		message: String,
+		/// Module of the statement that caused the error, if the error was caused by a statement.
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		file: Option<String>,
+		/// Line of the statement that caused the error, see [`Diagnostic`].
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		line: Option<usize>,
+	},
+}
+
+/// Builds the source of a request.
+pub fn build(request: &BuildRequest) -> BuildResponse {
+	let shader_builder = ShaderBuilder::from_config(BuilderConfig {
+		source_path: request.path.clone(),
+		definitions: request.defines.clone(),
+		..BuilderConfig::default()
+	});
+	match shader_builder
+		.and_then(|mut shader_builder| Ok(shader_builder.build_source()?.to_string()))
+	{
+		Ok(source) => BuildResponse::Ok { source },
+		Err(error) => {
+			let diagnostic = Diagnostic::of(&error);
+			BuildResponse::Error {
+				message: diagnostic.map_or_else(
+					|| error.to_string(),
+					|diagnostic| diagnostic.message.clone(),
+				),
+				file: diagnostic.map(|diagnostic| diagnostic.location.path.display().to_string()),
+				line: diagnostic.map(|diagnostic| diagnostic.location.line),
+			}
+		}
+	}
+}
+
+/// Answers the requests read from `reader` until it is closed, writing a response for every line.
+/// Any stream can be served, for example a named pipe on Windows or the standard input and output of a process.
+pub fn handle(reader: impl io::BufRead, mut writer: impl io::Write) -> io::Result<()> {
+	for line in reader.lines() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let response = match serde_json::from_str(&line) {
+			Ok(request) => build(&request),
+			Err(error) => BuildResponse::Error {
+				message: format!("invalid request: {error}"),
+				file: None,
+				line: None,
+			},
+		};
+		serde_json::to_writer(&mut writer, &response)?;
+		writer.write_all(b"\n")?;
+		writer.flush()?;
+	}
+	Ok(())
+}
+
+/// Accepts connections on a Unix socket until it fails, answering the requests of every connection on its own thread.
+/// Errors of a connection only close that connection.
+#[cfg(unix)]
+pub fn serve(listener: &std::os::unix::net::UnixListener) -> io::Result<()> {
+	for stream in listener.incoming() {
+		let stream = stream?;
+		thread::spawn(move || {
+			let reader = io::BufReader::new(stream.try_clone()?);
+			handle(reader, stream)
+		});
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		collections::HashMap,
+		env, fs,
+		io::{self, BufRead, Write},
+		thread,
+	};
+
+	use crate::daemon::{handle, BuildRequest, BuildResponse};
+
+	#[test]
+	fn handle_requests() {
+		let request = serde_json::to_string(&BuildRequest {
+			path: "test_shaders/conditional.wgsl".to_string(),
+			defines: HashMap::from([("QUALITY".to_string(), "4u".to_string())]),
+		})
+		.unwrap();
+		let mut output = Vec::new();
+		handle(
+			io::Cursor::new(format!(
+				"{request}\n\n{{\"path\": \"test_shaders/missing.wgsl\"}}\nnot json\n"
+			)),
+			&mut output,
+		)
+		.unwrap();
+		let responses: Vec<BuildResponse> = output
+			.lines()
+			.map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+			.collect();
+		assert_eq!(responses.len(), 3);
+		assert!(matches!(
+			&responses[0],
+			BuildResponse::Ok { source } if source.contains("const quality: u32 = 4u;")
+		));
+		assert!(matches!(
+			&responses[1],
+			BuildResponse::Error {
+				file: None,
+				line: None,
+				..
+			}
+		));
+		assert!(matches!(
+			&responses[2],
+			BuildResponse::Error { message, .. } if message.starts_with("invalid request")
+		));
+	}
+
+	#[test]
+	fn located_error() {
+		let module_path = env::temp_dir().join("wgsl_preprocessor_daemon.wgsl");
+		fs::write(&module_path, "const A: u32 = 1u;\n//!endif\n").unwrap();
+		let mut output = Vec::new();
+		handle(
+			io::Cursor::new(format!(
+				"{{\"path\": {:?}}}\n",
+				module_path.to_str().unwrap()
+			)),
+			&mut output,
+		)
+		.unwrap();
+		let response: BuildResponse = serde_json::from_slice(&output).unwrap();
+		assert_eq!(
+			response,
+			BuildResponse::Error {
+				message: "`//!endif` without a matching `//!ifdef`".to_string(),
+				file: Some(module_path.display().to_string()),
+				line: Some(2),
+			}
+		);
+		fs::remove_file(&module_path).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn serve() {
+		use std::os::unix::net::{UnixListener, UnixStream};
+
+		let socket_path = env::temp_dir().join("wgsl_preprocessor_daemon.sock");
+		let _ = fs::remove_file(&socket_path);
+		let listener = UnixListener::bind(&socket_path).unwrap();
+		thread::spawn(move || crate::daemon::serve(&listener));
+		let mut stream = UnixStream::connect(&socket_path).unwrap();
+		stream
+			.write_all(b"{\"path\": \"test_shaders/conditional.wgsl\", \"defines\": {\"QUALITY\": \"4u\"}}\n")
+			.unwrap();
+		let mut line = String::new();
+		io::BufReader::new(&stream).read_line(&mut line).unwrap();
+		assert!(matches!(
+			serde_json::from_str(&line).unwrap(),
+			BuildResponse::Ok { .. }
+		));
+		fs::remove_file(&socket_path).unwrap();
+	}
+}
@@ -0,0 +1,259 @@
+use std::{error, fmt, io, path};
+
+/// Location of a line in a WGSL module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+	/// Path to the module.
+	pub path: path::PathBuf,
+	/// Line number, starting at 1.
+	pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}", self.path.display(), self.line)
+	}
+}
+
+/// Origin of a definition, see [`crate::BuildStats::definition_provenance`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Provenance {
+	/// Put from Rust code, with the location of the call to the [`crate::ShaderBuilder`] method.
+	Rust(SourceLocation),
+	/// Defined by a `define` statement in a module.
+	Module(SourceLocation),
+}
+
+impl fmt::Display for Provenance {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Rust(location) => write!(f, "Rust code at {location}"),
+			Self::Module(location) => write!(f, "{location}"),
+		}
+	}
+}
+
+/// Details of a preprocessing error in a statement, carried by the [`std::io::Error`] of the build,
+/// see [`PreprocessError::diagnostic`].
+/// Errors found by naga in the built source are not located in modules, so they carry no [`Diagnostic`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+	/// Description of the error.
+	pub message: String,
+	/// Line of the statement that caused the error.
+	pub location: SourceLocation,
+	/// Name of the statement on the line, like `endif`, or [`None`] if the line is not a statement.
+	pub directive: Option<String>,
+	/// Text of the line, empty if the module can not be read.
+	pub text: String,
+	/// Lines of the `include` statements through which the module was included, from the root module.
+	pub include_chain: Vec<SourceLocation>,
+	/// Texts of the lines of [`Diagnostic::include_chain`], in the same order.
+	pub include_texts: Vec<String>,
+}
+
+impl Diagnostic {
+	/// Returns the [`Diagnostic`] of a build error, if the error was caused by a statement.
+	pub fn of(error: &ex::io::Error) -> Option<&Self> {
+		error.get_ref()?.downcast_ref()
+	}
+
+	/// Renders the error with the offending source line and the include chain, for example:
+	/// ```text
+	/// error: `//!endif` without a matching `//!ifdef`
+	///   ┌─ test_shaders/included.wgsl:4:1
+	///   │
+	/// 4 │ //!endif
+	///   │ ^^^^^^^^ `//!endif` without a matching `//!ifdef`
+	///   │
+	///   ┌─ test_shaders/main.wgsl:1:1
+	///   │
+	/// 1 │ //!include test_shaders/included.wgsl
+	///   │ ------------------------------------- included here
+	/// ```
+	/// The lines are the ones stored when the error occurred, so modules are not read again.
+	#[cfg(feature = "diagnostics")]
+	pub fn render(&self) -> String {
+		use codespan_reporting::{diagnostic, files, term};
+
+		let mut files = files::SimpleFiles::new();
+		let mut label = |location: &SourceLocation, text: &str, style, message: &str| {
+			// Only the line is known, so it is preceded by empty lines to keep its number.
+			let start = location.line - 1;
+			let source = format!("{}{text}", "\n".repeat(start));
+			let file_id = files.add(location.path.display().to_string(), source);
+			diagnostic::Label::new(style, file_id, start..start + text.len()).with_message(message)
+		};
+		let labels = std::iter::once(label(
+			&self.location,
+			&self.text,
+			diagnostic::LabelStyle::Primary,
+			&self.message,
+		))
+		.chain(
+			self.include_chain
+				.iter()
+				.zip(self.include_texts.iter())
+				.rev()
+				.map(|(include, text)| {
+					label(
+						include,
+						text,
+						diagnostic::LabelStyle::Secondary,
+						"included here",
+					)
+				}),
+		)
+		.collect();
+		let mut writer = term::termcolor::NoColor::new(Vec::new());
+		term::emit(
+			&mut writer,
+			&term::Config::default(),
+			&files,
+			&diagnostic::Diagnostic::error()
+				.with_message(&self.message)
+				.with_labels(labels),
+		)
+		.expect("cannot write diagnostic");
+		String::from_utf8(writer.into_inner()).unwrap()
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.location, self.message)?;
+		for include in self.include_chain.iter().rev() {
+			write!(f, ", included from {include}")?;
+		}
+		Ok(())
+	}
+}
+
+impl error::Error for Diagnostic {}
+
+/// Error of a build, see [`crate::ShaderBuilder::build_source`].
+#[derive(Debug)]
+pub enum PreprocessError {
+	/// A statement could not be preprocessed, like an `//!endif` without a matching `//!ifdef`.
+	Statement {
+		/// Module of the statement.
+		path: path::PathBuf,
+		/// Line of the statement, starting at 1.
+		line: usize,
+		/// Name of the statement, like `endif`, or [`None`] if the line is not a statement,
+		/// for example for a constant used as an array size.
+		directive: Option<String>,
+		/// Text of the line, empty if the module can not be read.
+		text: String,
+		/// Error carrying the whole [`Diagnostic`], with the include chain, boxed to keep the error small.
+		error: Box<ex::io::Error>,
+	},
+	/// The build failed for another reason, for example a module could not be read or the build was cancelled.
+	Other(ex::io::Error),
+}
+
+impl PreprocessError {
+	/// Returns the underlying error, which every other API of the crate returns.
+	pub fn io_error(&self) -> &ex::io::Error {
+		match self {
+			Self::Statement { error, .. } => error,
+			Self::Other(error) => error,
+		}
+	}
+
+	/// Returns the kind of the underlying error.
+	pub fn kind(&self) -> io::ErrorKind {
+		self.io_error().kind()
+	}
+
+	/// Returns the [`Diagnostic`] of the error, if it was caused by a statement.
+	pub fn diagnostic(&self) -> Option<&Diagnostic> {
+		Diagnostic::of(self.io_error())
+	}
+}
+
+impl From<ex::io::Error> for PreprocessError {
+	fn from(error: ex::io::Error) -> Self {
+		match Diagnostic::of(&error) {
+			Some(diagnostic) => Self::Statement {
+				path: diagnostic.location.path.clone(),
+				line: diagnostic.location.line,
+				directive: diagnostic.directive.clone(),
+				text: diagnostic.text.clone(),
+				error: Box::new(error),
+			},
+			None => Self::Other(error),
+		}
+	}
+}
+
+impl From<PreprocessError> for ex::io::Error {
+	fn from(error: PreprocessError) -> Self {
+		match error {
+			PreprocessError::Statement { error, .. } => *error,
+			PreprocessError::Other(error) => error,
+		}
+	}
+}
+
+impl fmt::Display for PreprocessError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.io_error().fmt(f)
+	}
+}
+
+impl error::Error for PreprocessError {}
+
+#[cfg(test)]
+mod tests {
+	use std::path;
+
+	use crate::{Diagnostic, ShaderBuilder, SourceLocation};
+
+	#[test]
+	fn included_diagnostic() {
+		let error = ShaderBuilder::new("test_shaders/include_unbalanced_conditional.wgsl")
+			.unwrap()
+			.build_source()
+			.err()
+			.unwrap();
+		assert_eq!(
+			error.diagnostic().unwrap(),
+			&Diagnostic {
+				message: "`//!ifdef` without a matching `//!endif`".to_string(),
+				location: SourceLocation {
+					path: path::PathBuf::from("test_shaders/unbalanced_conditional.wgsl"),
+					line: 1,
+				},
+				directive: Some("ifdef".to_string()),
+				text: "//!ifdef FOG".to_string(),
+				include_chain: vec![SourceLocation {
+					path: path::PathBuf::from("test_shaders/include_unbalanced_conditional.wgsl"),
+					line: 2,
+				}],
+				include_texts: vec![
+					"//!include test_shaders/unbalanced_conditional.wgsl".to_string()
+				],
+			}
+		);
+	}
+
+	#[cfg(feature = "diagnostics")]
+	#[test]
+	fn rendered_diagnostic() {
+		let error = ShaderBuilder::new("test_shaders/include_unbalanced_conditional.wgsl")
+			.unwrap()
+			.build_source()
+			.err()
+			.unwrap();
+		let rendered = error.diagnostic().unwrap().render();
+		assert!(rendered.contains("test_shaders/unbalanced_conditional.wgsl:1:1"));
+		assert!(rendered.contains("1 │ //!ifdef FOG"));
+		assert!(rendered.contains("test_shaders/include_unbalanced_conditional.wgsl:2:1"));
+		assert!(rendered.contains("2 │ //!include test_shaders/unbalanced_conditional.wgsl"));
+		assert!(rendered.contains("included here"));
+		let mut diagnostic = error.diagnostic().unwrap().clone();
+		diagnostic.location.path = path::PathBuf::from("test_shaders/missing.wgsl");
+		assert!(diagnostic.render().contains("1 │ //!ifdef FOG"));
+	}
+}
@@ -0,0 +1,489 @@
+use std::iter;
+
+use crate::WGSLType;
+
+/// Maximum depth of constants referring to other constants, so cycles are reported instead of overflowing the stack.
+const MAX_DEPTH: usize = 32;
+
+lazy_static::lazy_static! {
+	static ref TOKEN_REGEX: regex::Regex = regex::Regex::new(
+		r#"^\s*(?:(0[xX][0-9a-fA-F]+|(?:[0-9]+\.[0-9]*|\.[0-9]+|[0-9]+)(?:[eE][+-]?[0-9]+)?)([fiu]?)|([A-Za-z_][A-Za-z0-9_]*)|(&&|\|\||[=!<>]=|[-+*/%()!<>])|"([^"]*)")"#
+	)
+	.unwrap();
+	static ref IDENTIFIER_REGEX: regex::Regex = regex::Regex::new(r"\b[A-Za-z_][A-Za-z0-9_]*\b").unwrap();
+}
+
+/// Value of a constant expression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+	/// Integer with its suffix, `i` or `u`, or [`None`] if it is abstract.
+	Integer(i64, Option<char>),
+	/// Float with whether it has the `f` suffix.
+	Float(f64, bool),
+	Bool(bool),
+}
+
+impl Value {
+	/// Returns the value as a WGSL literal.
+	fn literal(self) -> String {
+		match self {
+			Self::Integer(value, Some(suffix)) => format!("{value}{suffix}"),
+			Self::Integer(value, None) => value.to_string(),
+			Self::Float(value, is_suffixed) => format!(
+				"{}{}",
+				(value as f32).string_definition(),
+				if is_suffixed { "f" } else { "" }
+			),
+			Self::Bool(value) => value.to_string(),
+		}
+	}
+
+	/// Returns a float, failing if it is not finite as an `f32`, since WGSL has no literals for NaN and infinities.
+	fn float(value: f64, is_suffixed: bool) -> Result<Self, String> {
+		if (value as f32).is_finite() {
+			Ok(Self::Float(value, is_suffixed))
+		} else {
+			Err(format!("`{value:e}` overflows `f32`"))
+		}
+	}
+
+	/// Returns an integer of the type `suffix`, failing if it is out of its range.
+	fn integer(value: i64, suffix: Option<char>) -> Result<Self, String> {
+		let range = match suffix {
+			Some('u') => 0..=u32::MAX as i64,
+			Some(_) => i32::MIN as i64..=i32::MAX as i64,
+			None => i64::MIN..=i64::MAX,
+		};
+		if range.contains(&value) {
+			Ok(Self::Integer(value, suffix))
+		} else {
+			Err(format!(
+				"`{value}` overflows `{}`",
+				if suffix == Some('u') { "u32" } else { "i32" }
+			))
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+	Number(String, Option<char>),
+	Identifier(String),
+	Operator(String),
+	String(String),
+}
+
+fn tokens(expression: &str) -> Result<Vec<Token>, String> {
+	let mut tokens = Vec::new();
+	let mut rest = expression.trim_end();
+	while !rest.is_empty() {
+		let captures = TOKEN_REGEX
+			.captures(rest)
+			.ok_or_else(|| format!("unexpected `{}`", rest.trim_start()))?;
+		tokens.push(if let Some(number) = captures.get(1) {
+			Token::Number(number.as_str().to_string(), captures[2].chars().next())
+		} else if let Some(identifier) = captures.get(3) {
+			Token::Identifier(identifier.as_str().to_string())
+		} else if let Some(operator) = captures.get(4) {
+			Token::Operator(operator.as_str().to_string())
+		} else {
+			Token::String(captures[5].to_string())
+		});
+		rest = &rest[captures[0].len()..];
+	}
+	Ok(tokens)
+}
+
+/// Functions of conditions testing the build, see [`condition`].
+pub(crate) struct Tests<'a> {
+	/// Returns whether a symbol is defined, for `defined(NAME)`.
+	pub(crate) is_defined: &'a dyn Fn(&str) -> bool,
+	/// Returns whether a module was included, for `included("path")`.
+	pub(crate) is_included: &'a dyn Fn(&str) -> bool,
+}
+
+/// Recursive descent parser evaluating an expression.
+struct Parser<'a> {
+	tokens: iter::Peekable<std::vec::IntoIter<Token>>,
+	lookup: &'a dyn Fn(&str) -> Option<String>,
+	/// Functions of conditions, or [`None`] if the expression is not a condition.
+	tests: Option<&'a Tests<'a>>,
+	depth: usize,
+	/// Whether the operand being parsed is short-circuited, so it is parsed without failing on its values.
+	is_skipped: bool,
+	/// Symbols used by the expression, not counting the values of constants.
+	symbols: Vec<String>,
+}
+
+impl Parser<'_> {
+	/// Consumes the next token if it is one of `operators`, and returns it.
+	fn operator(&mut self, operators: &[&str]) -> Option<String> {
+		match self.tokens.peek() {
+			Some(Token::Operator(operator)) if operators.contains(&operator.as_str()) => {
+				let operator = operator.clone();
+				self.tokens.next();
+				Some(operator)
+			}
+			_ => None,
+		}
+	}
+
+	/// Returns the value of an operation, or a placeholder if the operand is short-circuited.
+	fn checked(&self, value: Result<Value, String>) -> Result<Value, String> {
+		match self.is_skipped {
+			true => Ok(value.unwrap_or(Value::Bool(false))),
+			false => value,
+		}
+	}
+
+	/// Returns whether a value is true, integers being true unless they are 0.
+	fn truth(&self, value: Value) -> Result<bool, String> {
+		match value {
+			Value::Bool(value) => Ok(value),
+			Value::Integer(value, _) => Ok(value != 0),
+			Value::Float(..) if self.is_skipped => Ok(false),
+			Value::Float(..) => Err(format!("`{}` is not a boolean", value.literal())),
+		}
+	}
+
+	/// Parses the right operand of `&&` or `||`, skipped if `left` decides the result.
+	fn logical(
+		&mut self,
+		left: Value,
+		is_decisive: bool,
+		operand: fn(&mut Self) -> Result<Value, String>,
+	) -> Result<Value, String> {
+		let left = self.truth(left)?;
+		let is_skipped = self.is_skipped;
+		self.is_skipped |= left == is_decisive;
+		let right = operand(self);
+		self.is_skipped = is_skipped;
+		let right = self.truth(right?)?;
+		Ok(Value::Bool(if left == is_decisive { left } else { right }))
+	}
+
+	fn condition(&mut self) -> Result<Value, String> {
+		let mut value = self.conjunction()?;
+		while self.operator(&["||"]).is_some() {
+			value = self.logical(value, true, Self::conjunction)?;
+		}
+		Ok(value)
+	}
+
+	fn conjunction(&mut self) -> Result<Value, String> {
+		let mut value = self.comparison()?;
+		while self.operator(&["&&"]).is_some() {
+			value = self.logical(value, false, Self::comparison)?;
+		}
+		Ok(value)
+	}
+
+	fn comparison(&mut self) -> Result<Value, String> {
+		let value = self.expression()?;
+		match self.operator(&["==", "!=", "<", "<=", ">", ">="]) {
+			Some(operator) => {
+				let right = self.expression()?;
+				self.checked(compare(&operator, value, right))
+			}
+			None => Ok(value),
+		}
+	}
+
+	fn expression(&mut self) -> Result<Value, String> {
+		let mut value = self.term()?;
+		while let Some(operator) = self.operator(&["+", "-"]) {
+			let right = self.term()?;
+			value = self.checked(binary(&operator, value, right))?;
+		}
+		Ok(value)
+	}
+
+	fn term(&mut self) -> Result<Value, String> {
+		let mut value = self.factor()?;
+		while let Some(operator) = self.operator(&["*", "/", "%"]) {
+			let right = self.factor()?;
+			value = self.checked(binary(&operator, value, right))?;
+		}
+		Ok(value)
+	}
+
+	fn factor(&mut self) -> Result<Value, String> {
+		match self.tokens.next() {
+			Some(Token::Operator(operator)) if operator == "-" => {
+				let value = match self.factor()? {
+					Value::Integer(value, Some('u')) => {
+						Err(format!("`-{value}u` negates an unsigned integer"))
+					}
+					Value::Integer(value, suffix) => value
+						.checked_neg()
+						.ok_or_else(|| format!("`-({value})` overflows"))
+						.and_then(|value| Value::integer(value, suffix)),
+					Value::Float(value, is_suffixed) => Ok(Value::Float(-value, is_suffixed)),
+					Value::Bool(_) => Err("`-` negates a boolean".to_string()),
+				};
+				self.checked(value)
+			}
+			Some(Token::Operator(operator)) if operator == "!" => {
+				let value = self.factor()?;
+				Ok(Value::Bool(!self.truth(value)?))
+			}
+			Some(Token::Operator(operator)) if operator == "(" => {
+				let value = self.condition()?;
+				self.close_parenthesis()?;
+				Ok(value)
+			}
+			Some(Token::Number(number, suffix)) => number_value(&number, suffix),
+			Some(Token::Identifier(name)) if name == "true" || name == "false" => {
+				Ok(Value::Bool(name == "true"))
+			}
+			Some(Token::Identifier(name))
+				if self.tests.is_some() && (name == "defined" || name == "included") =>
+			{
+				let tests = self.tests.unwrap();
+				let has_parenthesis = self.operator(&["("]).is_some();
+				let value = match (name.as_str(), self.tokens.next()) {
+					("defined", Some(Token::Identifier(symbol))) => {
+						let is_defined = (tests.is_defined)(&symbol);
+						self.symbols.push(symbol);
+						is_defined
+					}
+					("included", Some(Token::String(path))) => (tests.is_included)(&path),
+					_ => return Err(format!("invalid argument of `{name}`")),
+				};
+				if has_parenthesis {
+					self.close_parenthesis()?;
+				}
+				Ok(Value::Bool(value))
+			}
+			Some(Token::Identifier(name)) if matches!(self.tokens.peek(), Some(Token::Operator(operator)) if operator == "(") =>
+			{
+				self.tokens.next();
+				let value = self.expression()?;
+				self.close_parenthesis()?;
+				self.checked(convert(&name, value))
+			}
+			Some(Token::Identifier(name)) => {
+				if self.depth == 0 {
+					self.symbols.push(name.clone());
+				}
+				let value =
+					(self.lookup)(&name).ok_or_else(|| format!("`{name}` is not a constant"));
+				if self.depth >= MAX_DEPTH {
+					return Err(format!("`{name}` is defined recursively"));
+				}
+				let value = value.and_then(|value| evaluate(&value, self.lookup, self.depth + 1));
+				self.checked(value)
+			}
+			Some(Token::Operator(operator)) => Err(format!("unexpected `{operator}`")),
+			Some(Token::String(string)) => Err(format!("unexpected `\"{string}\"`")),
+			None => Err("unexpected end of expression".to_string()),
+		}
+	}
+
+	fn close_parenthesis(&mut self) -> Result<(), String> {
+		match self.operator(&[")"]) {
+			Some(_) => Ok(()),
+			None => Err("missing `)`".to_string()),
+		}
+	}
+}
+
+fn number_value(number: &str, suffix: Option<char>) -> Result<Value, String> {
+	let is_hexadecimal = number.starts_with("0x") || number.starts_with("0X");
+	if suffix == Some('f') || !is_hexadecimal && number.contains(['.', 'e', 'E']) {
+		return number
+			.parse()
+			.map_err(|_| format!("invalid number `{number}`"))
+			.and_then(|value| Value::float(value, suffix == Some('f')));
+	}
+	let value = match number
+		.strip_prefix("0x")
+		.or_else(|| number.strip_prefix("0X"))
+	{
+		Some(hexadecimal) => i64::from_str_radix(hexadecimal, 16),
+		None => number.parse(),
+	}
+	.map_err(|_| format!("`{number}` is too large"))?;
+	Value::integer(value, suffix)
+}
+
+/// Applies a conversion like `u32(...)`.
+fn convert(name: &str, value: Value) -> Result<Value, String> {
+	match (name, value) {
+		("f32", Value::Integer(value, _)) => Value::float(value as f64, true),
+		("f32", Value::Float(value, _)) => Value::float(value, true),
+		("i32" | "u32", Value::Integer(value, _)) => Value::integer(value, name.chars().next()),
+		("i32" | "u32", Value::Float(value, _)) => {
+			Value::integer(value.trunc() as i64, name.chars().next())
+		}
+		_ => Err(format!("unsupported function `{name}`")),
+	}
+}
+
+fn binary(operator: &str, left: Value, right: Value) -> Result<Value, String> {
+	match (left, right) {
+		(Value::Bool(_), _) | (_, Value::Bool(_)) => Err(format!(
+			"`{operator}` is not defined for `{}` and `{}`",
+			left.literal(),
+			right.literal()
+		)),
+		(Value::Integer(left, left_suffix), Value::Integer(right, right_suffix)) => {
+			let suffix = match (left_suffix, right_suffix) {
+				(Some(left_suffix), Some(right_suffix)) if left_suffix != right_suffix => {
+					return Err(format!(
+						"`{operator}` mixes `{}` and `{}`",
+						Value::Integer(left, Some(left_suffix)).literal(),
+						Value::Integer(right, Some(right_suffix)).literal()
+					))
+				}
+				(left_suffix, right_suffix) => left_suffix.or(right_suffix),
+			};
+			let value = match operator {
+				"+" => left.checked_add(right),
+				"-" => left.checked_sub(right),
+				"*" => left.checked_mul(right),
+				"/" | "%" if right == 0 => return Err("division by zero".to_string()),
+				"/" => left.checked_div(right),
+				_ => left.checked_rem(right),
+			}
+			.ok_or_else(|| format!("`{left} {operator} {right}` overflows"))?;
+			Value::integer(value, suffix)
+		}
+		(Value::Integer(_, Some(_)), Value::Float(..))
+		| (Value::Float(..), Value::Integer(_, Some(_))) => Err(format!(
+			"`{operator}` mixes `{}` and `{}`",
+			left.literal(),
+			right.literal()
+		)),
+		_ => {
+			let (left, left_suffix) = float(left);
+			let (right, right_suffix) = float(right);
+			let value = match operator {
+				"+" => left + right,
+				"-" => left - right,
+				"*" => left * right,
+				"/" | "%" if right == 0.0 => return Err("division by zero".to_string()),
+				"/" => left / right,
+				_ => left % right,
+			};
+			if !(value as f32).is_finite() {
+				return Err(format!("`{left} {operator} {right}` overflows `f32`"));
+			}
+			Ok(Value::Float(value, left_suffix || right_suffix))
+		}
+	}
+}
+
+/// Compares two values with an operator like `<=`.
+fn compare(operator: &str, left: Value, right: Value) -> Result<Value, String> {
+	let ordering = match (left, right) {
+		(Value::Integer(left, _), Value::Integer(right, _)) => left.partial_cmp(&right),
+		(Value::Bool(left), Value::Bool(right)) if matches!(operator, "==" | "!=") => {
+			left.partial_cmp(&right)
+		}
+		(Value::Bool(_), _) | (_, Value::Bool(_)) => {
+			return Err(format!(
+				"`{operator}` is not defined for `{}` and `{}`",
+				left.literal(),
+				right.literal()
+			))
+		}
+		_ => float(left).0.partial_cmp(&float(right).0),
+	};
+	let Some(ordering) = ordering else {
+		return Ok(Value::Bool(operator == "!="));
+	};
+	Ok(Value::Bool(match operator {
+		"==" => ordering.is_eq(),
+		"!=" => ordering.is_ne(),
+		"<" => ordering.is_lt(),
+		"<=" => ordering.is_le(),
+		">" => ordering.is_gt(),
+		_ => ordering.is_ge(),
+	}))
+}
+
+/// Returns a float or an abstract integer as a float, with whether it has the `f` suffix.
+fn float(value: Value) -> (f64, bool) {
+	match value {
+		Value::Integer(value, _) => (value as f64, false),
+		Value::Float(value, is_suffixed) => (value, is_suffixed),
+		Value::Bool(value) => (value as u8 as f64, false),
+	}
+}
+
+fn evaluate(
+	expression: &str,
+	lookup: &dyn Fn(&str) -> Option<String>,
+	depth: usize,
+) -> Result<Value, String> {
+	let mut parser = Parser {
+		tokens: tokens(expression)?.into_iter().peekable(),
+		lookup,
+		tests: None,
+		depth,
+		is_skipped: false,
+		symbols: Vec::new(),
+	};
+	let value = parser.expression()?;
+	match parser.tokens.next() {
+		Some(_) => Err(format!("unexpected tokens in `{expression}`")),
+		None => Ok(value),
+	}
+}
+
+/// Folds an arithmetic expression of literals and constants to a literal, for example `WIDTH * 2u` to `2048u`.
+/// `lookup` returns the value of a constant, which can itself be an expression.
+/// Fails on division by zero, on overflows of the type of the expression, and on unsupported syntax.
+pub(crate) fn fold(
+	expression: &str,
+	lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+	evaluate(expression, lookup, 0).map(Value::literal)
+}
+
+/// Evaluates the condition of an `if` statement, like `defined(FOG) && SAMPLES > 4`, to a boolean.
+/// Conditions combine comparisons and arithmetic with `&&`, `||` and `!`, integers being true unless they are 0,
+/// and the right operand of `&&` and `||` is only evaluated if it decides the result, like in C.
+/// Returns the value with the symbols used by the condition.
+pub(crate) fn condition(
+	condition: &str,
+	lookup: &dyn Fn(&str) -> Option<String>,
+	tests: &Tests,
+) -> Result<(bool, Vec<String>), String> {
+	let mut parser = Parser {
+		tokens: tokens(condition)?.into_iter().peekable(),
+		lookup,
+		tests: Some(tests),
+		depth: 0,
+		is_skipped: false,
+		symbols: Vec::new(),
+	};
+	let value = parser.condition()?;
+	if parser.tokens.next().is_some() {
+		return Err(format!("unexpected tokens in `{condition}`"));
+	}
+	Ok((parser.truth(value)?, parser.symbols))
+}
+
+/// Replaces the constants of an expression by their values in parentheses, without folding it,
+/// so the expression does not depend on the order in which definitions are substituted.
+/// Names that `lookup` does not know are left untouched.
+pub(crate) fn expand(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+	expand_with_depth(expression, lookup, 0)
+}
+
+fn expand_with_depth(
+	expression: &str,
+	lookup: &dyn Fn(&str) -> Option<String>,
+	depth: usize,
+) -> String {
+	IDENTIFIER_REGEX
+		.replace_all(expression, |captures: &regex::Captures| {
+			match lookup(&captures[0]).filter(|_| depth < MAX_DEPTH) {
+				Some(value) => format!("({})", expand_with_depth(&value, lookup, depth + 1)),
+				None => captures[0].to_string(),
+			}
+		})
+		.into_owned()
+}
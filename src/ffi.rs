@@ -0,0 +1,178 @@
+/*!
+C API preprocessing a shader with a list of definitions, for engines that are not written in Rust,
+enabled by the **ffi** feature.
+The functions are exported from a dynamic library built with
+`cargo rustc --lib --release --features ffi --crate-type cdylib`.
+
+The functions are declared in C as:
+```c
+char *wgsl_preprocessor_build(
+	const char *path,
+	const char *const *define_names,
+	const char *const *define_values,
+	size_t define_count,
+	char **error
+);
+void wgsl_preprocessor_free_string(char *string);
+```
+*/
+use std::{any, collections::HashMap, ffi, os::raw, panic, ptr};
+
+use crate::{BuilderConfig, ShaderBuilder};
+
+/// Builds the shader whose root module is at `path`, with `define_count` definitions named by `define_names`
+/// and valued by `define_values`, as WGSL strings like `256u` or `true`.
+/// Returns the built source, or a null pointer if the build failed,
+/// in which case the message of the error is written to `error` if it is not null.
+/// Panics of the build are caught and reported as errors.
+/// Returned strings must be freed with [`wgsl_preprocessor_free_string`].
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string, `define_names` and `define_values` must point to
+/// `define_count` valid NUL-terminated strings each, or may be null if `define_count` is 0,
+/// and `error` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn wgsl_preprocessor_build(
+	path: *const raw::c_char,
+	define_names: *const *const raw::c_char,
+	define_values: *const *const raw::c_char,
+	define_count: usize,
+	error: *mut *mut raw::c_char,
+) -> *mut raw::c_char {
+	if !error.is_null() {
+		*error = ptr::null_mut();
+	}
+	let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+		string(path).and_then(|path| {
+			let mut definitions = HashMap::new();
+			for index in 0..define_count {
+				if define_names.is_null() || define_values.is_null() {
+					return Err("definitions are null".to_string());
+				}
+				definitions.insert(
+					string(*define_names.add(index))?,
+					string(*define_values.add(index))?,
+				);
+			}
+			build(path, definitions)
+		})
+	}))
+	.unwrap_or_else(|payload| Err(panic_message(payload)));
+	match result {
+		Ok(source) => source,
+		Err(message) => {
+			if !error.is_null() {
+				*error = c_string(message);
+			}
+			ptr::null_mut()
+		}
+	}
+}
+
+/// Frees a string returned by [`wgsl_preprocessor_build`]. Null pointers are ignored.
+///
+/// # Safety
+/// `string` must be null or a string returned by this library that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn wgsl_preprocessor_free_string(string: *mut raw::c_char) {
+	if !string.is_null() {
+		drop(ffi::CString::from_raw(string));
+	}
+}
+
+fn build(path: String, definitions: HashMap<String, String>) -> Result<*mut raw::c_char, String> {
+	let mut shader_builder = ShaderBuilder::from_config(BuilderConfig {
+		source_path: path,
+		definitions,
+		..BuilderConfig::default()
+	})
+	.map_err(|error| error.to_string())?;
+	let source = shader_builder
+		.build_source()
+		.map_err(|error| error.to_string())?;
+	ffi::CString::new(source)
+		.map(ffi::CString::into_raw)
+		.map_err(|_| "built source contains a NUL character".to_string())
+}
+
+/// Copies a NUL-terminated string, failing if it is null or not UTF-8.
+unsafe fn string(string: *const raw::c_char) -> Result<String, String> {
+	if string.is_null() {
+		return Err("string is null".to_string());
+	}
+	ffi::CStr::from_ptr(string)
+		.to_str()
+		.map(str::to_string)
+		.map_err(|error| error.to_string())
+}
+
+/// Returns the message of a panic, which must not unwind into the C caller.
+fn panic_message(payload: Box<dyn any::Any + Send>) -> String {
+	let message = payload
+		.downcast_ref::<&str>()
+		.map(|message| message.to_string())
+		.or_else(|| payload.downcast_ref::<String>().cloned())
+		.unwrap_or_else(|| "unknown panic".to_string());
+	format!("the build panicked: {message}")
+}
+
+/// Returns a message as a string allocated by this library, replacing NUL characters, which C strings can not contain.
+fn c_string(message: String) -> *mut raw::c_char {
+	ffi::CString::new(message.replace('\0', " "))
+		.unwrap()
+		.into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{ffi, panic, ptr};
+
+	use crate::ffi::{wgsl_preprocessor_build, wgsl_preprocessor_free_string};
+
+	#[test]
+	fn build() {
+		let path = ffi::CString::new("test_shaders/conditional.wgsl").unwrap();
+		let name = ffi::CString::new("QUALITY").unwrap();
+		let value = ffi::CString::new("4u").unwrap();
+		let mut error = ptr::null_mut();
+		unsafe {
+			let source = wgsl_preprocessor_build(
+				path.as_ptr(),
+				&name.as_ptr(),
+				&value.as_ptr(),
+				1,
+				&mut error,
+			);
+			assert!(error.is_null());
+			assert!(ffi::CStr::from_ptr(source)
+				.to_str()
+				.unwrap()
+				.contains("const quality: u32 = 4u;"));
+			wgsl_preprocessor_free_string(source);
+
+			let missing_path = ffi::CString::new("test_shaders/missing.wgsl").unwrap();
+			let source = wgsl_preprocessor_build(
+				missing_path.as_ptr(),
+				ptr::null(),
+				ptr::null(),
+				0,
+				&mut error,
+			);
+			assert!(source.is_null());
+			assert!(ffi::CStr::from_ptr(error)
+				.to_str()
+				.unwrap()
+				.contains("test_shaders/missing.wgsl"));
+			wgsl_preprocessor_free_string(error);
+		}
+	}
+
+	#[test]
+	fn caught_panic() {
+		let payload = panic::catch_unwind(|| panic!("invalid state")).unwrap_err();
+		assert_eq!(
+			super::panic_message(payload),
+			"the build panicked: invalid state"
+		);
+	}
+}
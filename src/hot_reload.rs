@@ -0,0 +1,562 @@
+use std::{collections::HashMap, error, fmt, future, io, path, pin, sync, task, thread, time};
+
+use crate::{Diagnostic, ShaderBuilder};
+
+/// Modification times of modules, by path.
+type ModificationTimes = Vec<(path::PathBuf, Option<time::SystemTime>)>;
+
+/// Delays rebuilds until the modified modules stop changing, see [`ShaderWatcher::debounce`].
+#[derive(Debug, Default)]
+struct Debounce {
+	duration: time::Duration,
+	/// Modification times of the modules when they were last seen changing, and when.
+	pending: Option<(ModificationTimes, time::Instant)>,
+}
+
+impl Debounce {
+	/// Returns whether modules with the given modification times have not changed for the duration of the debounce.
+	fn is_settled(&mut self, modification_times: ModificationTimes) -> bool {
+		match &self.pending {
+			Some((pending_times, changed)) if *pending_times == modification_times => {
+				changed.elapsed() >= self.duration
+			}
+			_ => {
+				self.pending = Some((modification_times, time::Instant::now()));
+				self.duration.is_zero()
+			}
+		}
+	}
+}
+
+/// Rebuilds a [`ShaderBuilder`] when one of the modules read by its last build is modified.
+/// Modules are polled, so no background thread is needed.
+///
+/// A failed rebuild keeps the source of the last successful build,
+/// so applications can keep using it while showing the error, see [`ShaderWatcher::last_error`].
+pub struct ShaderWatcher {
+	shader_builder: ShaderBuilder,
+	modification_times: ModificationTimes,
+	debounce: Debounce,
+	last_error: Option<ex::io::Error>,
+}
+
+impl ShaderWatcher {
+	/// Creates a new [`ShaderWatcher`] and builds the shader for the first time.
+	pub fn new(mut shader_builder: ShaderBuilder) -> Result<Self, ex::io::Error> {
+		shader_builder.build_source()?;
+		let mut shader_watcher = Self {
+			shader_builder,
+			modification_times: Vec::new(),
+			debounce: Debounce::default(),
+			last_error: None,
+		};
+		shader_watcher.record_modification_times();
+		Ok(shader_watcher)
+	}
+
+	/// Delays rebuilds until the modified modules have not changed for `debounce`,
+	/// so editors saving through temporary files or in several writes trigger a single rebuild.
+	/// Rebuilds are not delayed by default.
+	pub fn debounce(&mut self, debounce: time::Duration) -> &mut Self {
+		self.debounce.duration = debounce;
+		self
+	}
+
+	/// Rebuilds the shader if a module read by the last successful build was modified since it was last polled,
+	/// and has not changed since for the duration set with [`ShaderWatcher::debounce`].
+	/// Returns [`None`] if no module was modified, or the result of the new build otherwise.
+	/// A failed build is not retried until a module is modified again,
+	/// and [`ShaderWatcher::shader_builder`] keeps the source of the last successful build.
+	pub fn poll(&mut self) -> Option<Result<&str, ex::io::Error>> {
+		let modification_times: ModificationTimes = self
+			.modification_times
+			.iter()
+			.map(|(module_path, _)| (module_path.clone(), Self::modification_time(module_path)))
+			.collect();
+		if modification_times == self.modification_times {
+			self.debounce.pending = None;
+			return None;
+		}
+		if !self.debounce.is_settled(modification_times) {
+			return None;
+		}
+		self.debounce.pending = None;
+		Some(self.rebuild())
+	}
+
+	/// Returns the source of the last successful build, which is kept while rebuilds fail.
+	pub fn last_good_source(&self) -> &str {
+		&self.shader_builder.source_string
+	}
+
+	/// Returns the error of the last rebuild if it failed,
+	/// or [`None`] if the shader was rebuilt successfully since, or was never rebuilt.
+	pub fn last_error(&self) -> Option<&ex::io::Error> {
+		self.last_error.as_ref()
+	}
+
+	/// Returns the watched [`ShaderBuilder`].
+	pub fn shader_builder(&self) -> &ShaderBuilder {
+		&self.shader_builder
+	}
+
+	/// Returns the watched [`ShaderBuilder`] mutably, for example to change constants before the next build.
+	pub fn shader_builder_mut(&mut self) -> &mut ShaderBuilder {
+		&mut self.shader_builder
+	}
+
+	/// Returns whether a module read by the last successful build has a different modification time.
+	fn is_modified(
+		&self,
+		modification_times: &HashMap<path::PathBuf, Option<time::SystemTime>>,
+	) -> bool {
+		self.modification_times
+			.iter()
+			.any(|(module_path, modification_time)| {
+				modification_times.get(module_path) != Some(modification_time)
+			})
+	}
+
+	fn rebuild(&mut self) -> Result<&str, ex::io::Error> {
+		match self.shader_builder.build_source() {
+			Ok(_) => {
+				self.record_modification_times();
+				self.last_error = None;
+				Ok(&self.shader_builder.source_string)
+			}
+			Err(error) => {
+				let error = ex::io::Error::from(error);
+				for (module_path, modification_time) in self.modification_times.iter_mut() {
+					*modification_time = Self::modification_time(module_path);
+				}
+				self.last_error = Some(copy_error(&error));
+				Err(error)
+			}
+		}
+	}
+
+	fn record_modification_times(&mut self) {
+		self.modification_times = self
+			.shader_builder
+			.build_stats()
+			.files_read
+			.iter()
+			.map(|module_path| (module_path.clone(), Self::modification_time(module_path)))
+			.collect();
+	}
+
+	fn modification_time(module_path: &path::Path) -> Option<time::SystemTime> {
+		module_path
+			.metadata()
+			.and_then(|metadata| metadata.modified())
+			.ok()
+	}
+}
+
+/// Returns a copy of a build error, keeping its [`Diagnostic`] if it has one,
+/// since [`ex::io::Error`] can not be cloned.
+fn copy_error(error: &ex::io::Error) -> ex::io::Error {
+	let inner = match Diagnostic::of(error) {
+		Some(diagnostic) => io::Error::new(error.kind(), diagnostic.clone()),
+		None => io::Error::new(error.kind(), (**error).to_string()),
+	};
+	match error {
+		ex::io::Error::Filesystem(module_path, action, _) => {
+			ex::io::Error::Filesystem(module_path.clone(), action, inner)
+		}
+		ex::io::Error::Filesystem2(source_path, destination_path, action, _) => {
+			ex::io::Error::Filesystem2(source_path.clone(), destination_path.clone(), action, inner)
+		}
+	}
+}
+
+/// Watches several shaders, and rebuilds together the shaders affected by the modules modified since the last poll,
+/// so saving a module included by many shaders reports a single result.
+/// Every module is checked once per poll, even if it is read by several shaders,
+/// and the affected shaders are rebuilt in parallel.
+#[derive(Default)]
+pub struct ShaderWatcherSet {
+	shader_watchers: Vec<ShaderWatcher>,
+	debounce: Debounce,
+}
+
+/// Result of the rebuild of the shaders of a [`ShaderWatcherSet`] affected by modified modules.
+#[derive(Debug)]
+pub struct BatchReport {
+	/// Indices of the shaders that were rebuilt successfully, in increasing order.
+	pub rebuilt: Vec<usize>,
+	/// Indices of the shaders whose rebuild failed, in increasing order, with their errors.
+	pub errors: Vec<(usize, ex::io::Error)>,
+}
+
+impl BatchReport {
+	/// Returns whether every affected shader was rebuilt successfully.
+	pub fn is_success(&self) -> bool {
+		self.errors.is_empty()
+	}
+}
+
+impl ShaderWatcherSet {
+	/// Creates a new empty [`ShaderWatcherSet`].
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Delays rebuilds until no watched module has changed for `debounce`, see [`ShaderWatcher::debounce`].
+	/// The debounces of the watched shaders are not used.
+	pub fn debounce(&mut self, debounce: time::Duration) -> &mut Self {
+		self.debounce.duration = debounce;
+		self
+	}
+
+	/// Adds a shader to the set, and returns its index.
+	pub fn push(&mut self, shader_watcher: ShaderWatcher) -> usize {
+		self.shader_watchers.push(shader_watcher);
+		self.shader_watchers.len() - 1
+	}
+
+	/// Returns the watcher of the shader at `index`.
+	pub fn shader_watcher(&self, index: usize) -> &ShaderWatcher {
+		&self.shader_watchers[index]
+	}
+
+	/// Returns the watcher of the shader at `index` mutably.
+	pub fn shader_watcher_mut(&mut self, index: usize) -> &mut ShaderWatcher {
+		&mut self.shader_watchers[index]
+	}
+
+	/// Returns the number of shaders in the set.
+	pub fn len(&self) -> usize {
+		self.shader_watchers.len()
+	}
+
+	/// Returns whether the set has no shaders.
+	pub fn is_empty(&self) -> bool {
+		self.shader_watchers.is_empty()
+	}
+
+	/// Rebuilds the shaders reading a module that was modified since the last poll,
+	/// once no module has changed for the duration set with [`ShaderWatcherSet::debounce`].
+	/// Returns [`None`] if no module was modified, or the results of the rebuilds otherwise.
+	pub fn poll(&mut self) -> Option<BatchReport> {
+		let mut modification_times = HashMap::new();
+		for shader_watcher in self.shader_watchers.iter() {
+			for (module_path, _) in shader_watcher.modification_times.iter() {
+				modification_times
+					.entry(module_path.clone())
+					.or_insert_with(|| ShaderWatcher::modification_time(module_path));
+			}
+		}
+		let mut affected: Vec<(usize, &mut ShaderWatcher)> = self
+			.shader_watchers
+			.iter_mut()
+			.enumerate()
+			.filter(|(_, shader_watcher)| shader_watcher.is_modified(&modification_times))
+			.collect();
+		if affected.is_empty() {
+			self.debounce.pending = None;
+			return None;
+		}
+		let mut settled_times: ModificationTimes = modification_times.into_iter().collect();
+		settled_times.sort();
+		if !self.debounce.is_settled(settled_times) {
+			return None;
+		}
+		self.debounce.pending = None;
+		let threads = thread::available_parallelism().map_or(1, usize::from);
+		let chunk_size = affected.len().div_ceil(threads);
+		let results: Vec<(usize, Result<(), ex::io::Error>)> = thread::scope(|scope| {
+			let workers: Vec<_> = affected
+				.chunks_mut(chunk_size)
+				.map(|chunk| {
+					scope.spawn(move || {
+						chunk
+							.iter_mut()
+							.map(|(index, shader_watcher)| {
+								(*index, shader_watcher.rebuild().map(|_| ()))
+							})
+							.collect::<Vec<_>>()
+					})
+				})
+				.collect();
+			workers
+				.into_iter()
+				.flat_map(|worker| worker.join().unwrap())
+				.collect()
+		});
+		let mut report = BatchReport {
+			rebuilt: Vec::new(),
+			errors: Vec::new(),
+		};
+		for (index, result) in results {
+			match result {
+				Ok(()) => report.rebuilt.push(index),
+				Err(error) => report.errors.push((index, error)),
+			}
+		}
+		Some(report)
+	}
+}
+
+/// Error of a rebuild in [`HotSwap::update`].
+#[derive(Debug)]
+pub enum HotSwapError {
+	/// The shader could not be preprocessed.
+	Build(ex::io::Error),
+	/// The built shader module was rejected by the device.
+	Validation(wgpu::Error),
+}
+
+impl fmt::Display for HotSwapError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Build(error) => write!(f, "{error}"),
+			Self::Validation(error) => write!(f, "{error}"),
+		}
+	}
+}
+
+impl error::Error for HotSwapError {}
+
+/// Creates objects from a shader module, see [`HotSwap::new`].
+type CreateFn<T> = Box<dyn FnMut(&wgpu::Device, wgpu::ShaderModule) -> T>;
+
+/// Owns objects created from a watched shader, like a [`wgpu::ShaderModule`] and the pipelines using it,
+/// and replaces them when the shader is rebuilt successfully.
+/// If a rebuild fails, the objects of the last successful build are kept alongside the error,
+/// so applications can keep rendering with them while showing the error, see [`HotSwap::last_error`].
+pub struct HotSwap<T> {
+	shader_watcher: ShaderWatcher,
+	create: CreateFn<T>,
+	current: sync::Arc<T>,
+	last_error: Option<HotSwapError>,
+}
+
+impl<T> HotSwap<T> {
+	/// Creates a new [`HotSwap`], building the shader and creating the objects for the first time.
+	///
+	/// # Arguments
+	/// - `device` - Device that creates the shader module.
+	/// - `shader_builder` - Builder of the watched shader.
+	/// - `create` - Creates the objects, for example pipelines, from the shader module of every successful build.
+	pub fn new(
+		device: &wgpu::Device,
+		shader_builder: ShaderBuilder,
+		create: impl FnMut(&wgpu::Device, wgpu::ShaderModule) -> T + 'static,
+	) -> Result<Self, HotSwapError> {
+		let mut shader_watcher = ShaderWatcher::new(shader_builder).map_err(HotSwapError::Build)?;
+		let mut create: CreateFn<T> = Box::new(create);
+		let current = Self::create(device, &mut shader_watcher, &mut create)?;
+		Ok(Self {
+			shader_watcher,
+			create,
+			current: sync::Arc::new(current),
+			last_error: None,
+		})
+	}
+
+	/// Rebuilds the shader and recreates the objects if a module was modified.
+	/// Returns [`None`] if no module was modified, `Some(Ok(()))` if the objects were replaced,
+	/// or the error of the rebuild, in which case the current objects are kept.
+	/// The error is kept until the next successful rebuild, see [`HotSwap::last_error`].
+	///
+	/// Validation errors of the shader module are captured with an error scope.
+	/// On the web, where error scopes resolve asynchronously, they are reported by the device instead.
+	pub fn update(&mut self, device: &wgpu::Device) -> Option<Result<(), &HotSwapError>> {
+		let result = match self.shader_watcher.poll()? {
+			Ok(_) => Self::create(device, &mut self.shader_watcher, &mut self.create),
+			Err(error) => Err(HotSwapError::Build(error)),
+		};
+		match result {
+			Ok(current) => {
+				self.current = sync::Arc::new(current);
+				self.last_error = None;
+				Some(Ok(()))
+			}
+			Err(error) => Some(Err(self.last_error.insert(error))),
+		}
+	}
+
+	/// Returns the error of the last rebuild if it failed,
+	/// or [`None`] if the objects were replaced since, or the shader was never rebuilt.
+	pub fn last_error(&self) -> Option<&HotSwapError> {
+		self.last_error.as_ref()
+	}
+
+	/// Returns the objects of the last successful build.
+	/// The returned [`sync::Arc`] stays valid after the objects are replaced.
+	pub fn current(&self) -> sync::Arc<T> {
+		self.current.clone()
+	}
+
+	/// Returns the [`ShaderWatcher`] of the shader.
+	pub fn shader_watcher(&mut self) -> &mut ShaderWatcher {
+		&mut self.shader_watcher
+	}
+
+	fn create(
+		device: &wgpu::Device,
+		shader_watcher: &mut ShaderWatcher,
+		create: &mut CreateFn<T>,
+	) -> Result<T, HotSwapError> {
+		device.push_error_scope(wgpu::ErrorFilter::Validation);
+		let shader_module =
+			device.create_shader_module(shader_watcher.shader_builder.shader_module_descriptor());
+		let mut error_scope = pin::pin!(device.pop_error_scope());
+		match future::Future::poll(
+			error_scope.as_mut(),
+			&mut task::Context::from_waker(task::Waker::noop()),
+		) {
+			task::Poll::Ready(Some(error)) => Err(HotSwapError::Validation(error)),
+			_ => Ok(create(device, shader_module)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs, thread, time};
+
+	use crate::{Diagnostic, ShaderBuilder, ShaderWatcher, ShaderWatcherSet};
+
+	#[test]
+	fn shader_watcher() {
+		let module_path = env::temp_dir().join("wgsl_preprocessor_shader_watcher.wgsl");
+		fs::write(&module_path, "const A: u32 = 1u;\n").unwrap();
+		let mut shader_watcher =
+			ShaderWatcher::new(ShaderBuilder::new(module_path.to_str().unwrap()).unwrap()).unwrap();
+		assert!(shader_watcher.poll().is_none());
+
+		fs::write(&module_path, "const A: u32 = 2u;\n//!endif\n").unwrap();
+		bump_modification_time(&module_path, 1);
+		assert!(shader_watcher.poll().unwrap().is_err());
+		assert_eq!(
+			shader_watcher.shader_builder().source_string,
+			"const A: u32 = 1u;\n"
+		);
+		assert!(shader_watcher.poll().is_none());
+
+		fs::write(&module_path, "const A: u32 = 2u;\n").unwrap();
+		bump_modification_time(&module_path, 2);
+		assert_eq!(
+			shader_watcher.poll().unwrap().unwrap(),
+			"const A: u32 = 2u;\n"
+		);
+		fs::remove_file(&module_path).unwrap();
+	}
+
+	#[test]
+	fn last_error() {
+		let module_path = env::temp_dir().join("wgsl_preprocessor_last_error.wgsl");
+		fs::write(&module_path, "const A: u32 = 1u;\n").unwrap();
+		let mut shader_watcher =
+			ShaderWatcher::new(ShaderBuilder::new(module_path.to_str().unwrap()).unwrap()).unwrap();
+		assert!(shader_watcher.last_error().is_none());
+
+		fs::write(&module_path, "const A: u32 = 2u;\n//!endif\n").unwrap();
+		bump_modification_time(&module_path, 1);
+		let error = shader_watcher.poll().unwrap().unwrap_err();
+		assert_eq!(shader_watcher.last_good_source(), "const A: u32 = 1u;\n");
+		let last_error = shader_watcher.last_error().unwrap();
+		assert_eq!(last_error.to_string(), error.to_string());
+		assert_eq!(Diagnostic::of(last_error), Diagnostic::of(&error));
+		assert_eq!(Diagnostic::of(last_error).unwrap().location.line, 2);
+		assert!(shader_watcher.poll().is_none());
+		assert!(shader_watcher.last_error().is_some());
+
+		fs::write(&module_path, "const A: u32 = 3u;\n").unwrap();
+		bump_modification_time(&module_path, 2);
+		assert!(shader_watcher.poll().unwrap().is_ok());
+		assert_eq!(shader_watcher.last_good_source(), "const A: u32 = 3u;\n");
+		assert!(shader_watcher.last_error().is_none());
+		fs::remove_file(&module_path).unwrap();
+	}
+
+	#[test]
+	fn debounce() {
+		let module_path = env::temp_dir().join("wgsl_preprocessor_debounce.wgsl");
+		fs::write(&module_path, "const A: u32 = 1u;\n").unwrap();
+		let mut shader_watcher =
+			ShaderWatcher::new(ShaderBuilder::new(module_path.to_str().unwrap()).unwrap()).unwrap();
+		shader_watcher.debounce(time::Duration::from_millis(50));
+
+		fs::write(&module_path, "const A: u32 = 2u;\n").unwrap();
+		bump_modification_time(&module_path, 1);
+		assert!(shader_watcher.poll().is_none());
+		fs::write(&module_path, "const A: u32 = 3u;\n").unwrap();
+		bump_modification_time(&module_path, 2);
+		assert!(shader_watcher.poll().is_none());
+		thread::sleep(time::Duration::from_millis(60));
+		assert_eq!(
+			shader_watcher.poll().unwrap().unwrap(),
+			"const A: u32 = 3u;\n"
+		);
+		assert!(shader_watcher.poll().is_none());
+		fs::remove_file(&module_path).unwrap();
+	}
+
+	#[test]
+	fn shader_watcher_set() {
+		let directory = env::temp_dir().join("wgsl_preprocessor_shader_watcher_set");
+		fs::create_dir_all(&directory).unwrap();
+		let shared_path = directory.join("shared.wgsl");
+		fs::write(&shared_path, "const A: u32 = 1u;\n").unwrap();
+		let mut shader_watcher_set = ShaderWatcherSet::new();
+		for (name, body) in [
+			("first", "const B: u32 = A;\n"),
+			("second", "const C: u32 = A;\n"),
+			("third", "const D: u32 = 1u;\n"),
+		] {
+			let shader_path = directory.join(format!("{name}.wgsl"));
+			let include = if name == "third" {
+				String::new()
+			} else {
+				format!("//!include {}\n", shared_path.display())
+			};
+			fs::write(&shader_path, include + body).unwrap();
+			shader_watcher_set.push(
+				ShaderWatcher::new(ShaderBuilder::new(shader_path.to_str().unwrap()).unwrap())
+					.unwrap(),
+			);
+		}
+		assert!(shader_watcher_set.poll().is_none());
+
+		fs::write(&shared_path, "const A: u32 = 2u;\n").unwrap();
+		bump_modification_time(&shared_path, 1);
+		let report = shader_watcher_set.poll().unwrap();
+		assert!(report.is_success());
+		assert_eq!(report.rebuilt, [0, 1]);
+		assert_eq!(
+			shader_watcher_set
+				.shader_watcher(1)
+				.shader_builder()
+				.source_string,
+			"const A: u32 = 2u;\nconst C: u32 = A;\n"
+		);
+
+		fs::write(&shared_path, "const A: u32 = 3u;\n//!endif\n").unwrap();
+		bump_modification_time(&shared_path, 2);
+		let report = shader_watcher_set.poll().unwrap();
+		assert!(report.rebuilt.is_empty());
+		assert_eq!(
+			report
+				.errors
+				.iter()
+				.map(|(index, _)| *index)
+				.collect::<Vec<usize>>(),
+			[0, 1]
+		);
+		assert!(shader_watcher_set.poll().is_none());
+		fs::remove_dir_all(&directory).unwrap();
+	}
+
+	/// Sets the modification time explicitly, since file systems may have a coarse resolution.
+	fn bump_modification_time(module_path: &std::path::Path, seconds: u64) {
+		fs::File::options()
+			.write(true)
+			.open(module_path)
+			.unwrap()
+			.set_modified(time::SystemTime::UNIX_EPOCH + time::Duration::from_secs(seconds))
+			.unwrap();
+	}
+}
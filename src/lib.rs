@@ -41,10 +41,72 @@ It is important to note that `test_shaders/main.wgsl` could also contain:
 //!include test_shaders/included2.wgsl
 ```
 The result would be the same.
+An `include_first` statement only includes the first of its modules that exists, for example to use
+an optimized module when it is available and fall back to a generic one otherwise:
+```wgsl
+//!include_first vendor/fast_blur.wgsl blur.wgsl
+```
+Circular includes fail the build with an error listing the cycle, like `a.wgsl → b.wgsl → a.wgsl`.
+A module with a `once` statement is only included once per build, so modules declaring common structs
+can be included by every module using them:
+```wgsl
+//!once
+struct Light {
+	intensity: f32,
+}
+```
+[`ShaderBuilder::deduplicate_includes`] applies this to every module.
+
+Include paths are relative to the directory of the including module, or to the working directory
+if no module exists there, which can be changed with [`ShaderBuilder::include_resolution`].
+Builds fail if different modules exist at both paths.
+Modules of shader libraries can be found in other directories added with [`ShaderBuilder::add_include_path`].
+Directories can also be named by aliases added with [`ShaderBuilder::add_path_alias`], like `@engine` in `//!include @engine/pbr.wgsl`.
+A single include path can be redirected to another module with [`ShaderBuilder::alias_module`],
+for example to use a platform-specific implementation behind a stable include path.
+
+Modules are read from the file system, unless the builder is created with [`ShaderBuilder::with_source_resolver`]
+to read them from another source, like [`MemoryResolver`] for shaders embedded in the binary.
+A root module that is already in memory, like generated code, can be built with [`ShaderBuilder::from_source`].
+To find which module introduced a problem, [`ShaderBuilder::dump_intermediates`] writes the content of every module
+after substitution, and the final source, to a directory.
+With [`ShaderBuilder::debug_markers`], included modules are surrounded by comments naming them in the built source.
+
+A single named section of a module can be included by following its path with a `section` argument:
+```wgsl
+//!include test_shaders/colors.wgsl section=palettes
+```
+Only the lines between `//!section palettes` and `//!endsection` in `test_shaders/colors.wgsl` are included,
+and building fails if the section is not found. Section statements are ignored when a whole module is included.
+
+Two modules using each other's declarations do not need to include each other, since WGSL declarations
+can be used before they appear. Instead, a module can state what it expects another module of the build to define:
+```wgsl
+//!declare struct Light;
+fn intensity(light: Light) -> f32 { return light.intensity; }
+```
+`declare` statements emit nothing, but building fails if no module of the build defines the declaration.
+Structs, functions, constants, variables, overrides and aliases can be declared.
+With [`ShaderBuilder::order_declarations`], top-level declarations are reordered so types and constants
+come before the code using them, whatever the order of the `include` statements.
+
+Shared modules can state their name and version with a `module` statement, and the modules using them
+can state the versions they are compatible with in `requires` statements:
+```wgsl
+//!module lighting 2.1
+```
+```wgsl
+//!include lighting.wgsl
+//!requires lighting >= 2
+//!requires lighting < 3
+```
+Versions have up to three numbers, like `2`, `2.1` or `2.1.3`, and can be compared with `>=` (the default), `>`, `<=`, `<` or `=`.
+Building fails if a required module is not part of the build, if its version is not compatible,
+or if a module is declared with different versions.
 
 # Example: Define Macros
 
-Non-function-like macro definitions are supported, for example:
+Object-like macro definitions are supported, for example:
 ```wgsl
 //!define u3 vec3<u32>
 @compute
@@ -61,7 +123,119 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
 	// ...
 }
 ```
-Multi-line macros are not yet supported.
+Macros only replace whole identifiers, so `u3` is not replaced in `u32`.
+Macros can use other macros, like `//!define SIZE WIDTH * HEIGHT`, and building fails if macros expand to themselves.
+
+Function-like macros take arguments, which replace their parameters in the body of the macro:
+```wgsl
+//!define SQUARE(x) ((x) * (x))
+const area: f32 = SQUARE(side + 1.0);
+```
+Arguments are split at the commas that are not nested in parentheses or brackets, so `MAX(f(a, b), c)` has two arguments,
+and invocations can be nested, like `SQUARE(SQUARE(x))`.
+Wrap parameters in parentheses in the body, like above, so arguments with operators keep their precedence.
+The size of every expansion can be limited with [`ShaderBuilder::max_expansion_size`], and expansions are counted in [`BuildStats::macro_expansions`].
+
+Macros span several lines when their lines end with `\`, which is removed, so longer snippets can be defined as a block:
+```wgsl
+//!define LUMINANCE \
+fn luminance(color: vec3<f32>) -> f32 { \
+	return dot(color, vec3(0.2126, 0.7152, 0.0722)); \
+}
+```
+
+Definitions can also be used through `${NAME:default}` templates, which are replaced by the value of `NAME`,
+or by `default` if `NAME` is not defined:
+```wgsl
+const light_count: u32 = ${LIGHT_COUNT:1u};
+const exposure: f32 = ${EXPOSURE};
+```
+Building fails if a template without a default value, like `${EXPOSURE}`, is not defined.
+Templates are only replaced if enabled with [`ShaderBuilder::templates`].
+
+Modules can declare the constants they expect from Rust code with `expect` statements:
+```wgsl
+//!expect MAX_LIGHTS: u32
+```
+Building fails if `MAX_LIGHTS` is not put from Rust code, or if it is put with methods like [`ShaderBuilder::put_constant`]
+as a value of another type.
+
+A name can be defined in several layers, and the value of the highest one is used:
+defaults put by presets (lowest), then `define` statements in modules, then definitions put from Rust code,
+then overlays passed to a single build with [`ShaderBuilder::build_source_with_overlay`] (highest).
+[`ShaderBuilder::definition`] returns the value of a definition and the layer it comes from.
+A `define_default` statement, like `//!define_default SAMPLES 4u`, only defines a name that is not defined yet,
+so modules can provide defaults that Rust code can override without a redefinition warning.
+Defaults can be put from Rust code with [`ShaderBuilder::put_default_constant`].
+Modules can also register values across includes with `define_add` statements, which add an integer
+to a definition, and `define_append` statements, which append an item to a comma-separated list:
+```wgsl
+//!define_add LIGHT_TYPE_COUNT 1u
+//!define_append LIGHT_TYPES POINT
+```
+These definitions start undefined and are substituted with their final values once all modules are loaded.
+
+Building also fails if a declaration, like `let` or `fn`, is named by a keyword or a reserved word of WGSL
+after macros are expanded, for example by `//!define NAME class`. The error names the macros expanding to the word.
+
+Lines between `preserve` and `endpreserve` statements are kept untouched: macros and templates are not replaced
+and statements are not processed, which is useful for license texts or vendor-specific pragmas:
+```wgsl
+//!preserve
+// Licensed under the terms of the SCALE License.
+//!endpreserve
+```
+
+With [`ShaderBuilder::record_substitutions`], builds record which definitions were substituted in every line of the built source,
+and with what values, in [`BuildStats::line_substitutions`].
+
+# Example: Conditional Compilation
+
+Lines between `ifdef` and `endif` statements are only kept if the symbol is defined, either in the shader
+or from Rust code with methods like [`ShaderBuilder::put_constant`]:
+```wgsl
+//!define SHADOWS
+//!ifdef SHADOWS
+//!include test_shaders/shadows.wgsl
+//!else
+fn shadow(position: vec3<f32>) -> f32 { return 1.0; }
+//!endif
+```
+`ifndef` keeps the lines only if the symbol is not defined, and conditional statements can be nested.
+A symbol defined as `false`, for example with `put_constant("SHADOWS", false)`, is tested as not defined,
+so boolean constants can be used both as values and as conditions.
+Symbols defined without a value are also substituted, by nothing, wherever their name appears in code.
+With [`ShaderBuilder::separate_symbols`], they are only tested by conditional statements,
+and definitions with a value are only substituted.
+Symbols describing the compilation target, like `TARGET_WEB`, can be defined with [`ShaderBuilder::target_symbols`].
+`if included("path")` keeps the lines only if a module whose path ends with `path` was included earlier in the build,
+so modules can adapt to optional modules:
+```wgsl
+//!if included("shadows.wgsl")
+let visibility = shadow(position);
+//!endif
+```
+`if` statements can also test expressions of the definitions, put from Rust code or defined in the shader:
+```wgsl
+//!if WIDTH > 256 && (defined(FOG) || !defined(SHADOWS))
+```
+Expressions support `defined(NAME)`, `included("path")`, `&&`, `||`, `!`, comparisons like `==` or `>=`,
+arithmetic, and parentheses. Integers are true unless they are 0,
+and the right operand of `&&` and `||` is only evaluated if it decides the result, like in C.
+
+`elifdef`, `elifndef` and `elif` statements select one of several branches, keeping only the first branch whose condition is true:
+```wgsl
+//!ifdef LOW_QUALITY
+const samples: u32 = 1u;
+//!elif QUALITY > 2
+const samples: u32 = 16u;
+//!else
+const samples: u32 = 4u;
+//!endif
+```
+Conditions are only evaluated until a branch is kept, and not at all in blocks nested in a skipped branch,
+so they can use definitions that only exist where the block is active.
+An `else` statement must be the last branch of its block.
 
 # Example: Defining a Constant Struct Array
 
@@ -126,13 +300,60 @@ ShaderBuilder::new("main.wgsl")
 			}
 		]
 	)
-	.build();
+	.build()
+	.unwrap();
 ```
 The compiled contents would be identical to:
 ```wgsl
 var<private> STRUCT_ARRAY: array<Struct, 2> = array<Struct, 2>(Struct(vec4<f32>(1.0, 2.0, 3.0, 4.0)),Struct(vec4<f32>(1.5, 2.1, 3.7, 4.9)),);
 ```
 
+# Warnings
+
+Builds report suspicious statements as warnings (see [`ShaderBuilder::warnings`]), each with a code:
+`W001` for macros that are never used, `W002` for macros that are defined again,
+optionally `W003` for symbols tested by conditional statements that are never defined,
+`W005` for `read_write` storage buffers declared from Rust code that are never written,
+`W006` for modules that are not valid UTF-8 and are decoded lossily,
+`W007` for modules declaring a color space, like `//!color_space srgb`, that differs from the one of the colors put from Rust code,
+and `W004` for uses of symbols marked as deprecated by a `deprecated` statement:
+```wgsl
+//!deprecated legacy_blend "use blend instead"
+```
+Warnings can be allowed or denied from Rust code with [`ShaderBuilder::allow`] and [`ShaderBuilder::deny`],
+or allowed in a single module with an `allow` statement:
+```wgsl
+//!allow(W001, W002)
+```
+
+# Example: Hot Reloading
+
+A [`ShaderWatcher`] rebuilds a shader when one of its modules is modified, and a [`HotSwap`] recreates
+the shader module and the pipelines using it, keeping the previous ones if the new build fails:
+```no_run
+# fn example(device: &wgpu::Device) {
+# let create_pipeline = |_: &wgpu::Device, _: &wgpu::ShaderModule| ();
+let mut hot_swap = wgsl_preprocessor::HotSwap::new(
+	device,
+	wgsl_preprocessor::ShaderBuilder::new("test_shaders/multiple_includes.wgsl").unwrap(),
+	move |device, shader_module| create_pipeline(device, &shader_module),
+)
+.unwrap();
+// Every frame:
+if let Some(Err(error)) = hot_swap.update(device) {
+	eprintln!("{error}");
+}
+let pipeline = hot_swap.current();
+# }
+```
+With [`ShaderWatcher::debounce`], rebuilds wait until the modules stop changing, so editors writing temporary files
+or saving in several steps trigger a single rebuild.
+A [`ShaderWatcherSet`] watches several shaders and rebuilds the ones affected by a save together, in parallel,
+reporting a single result for all of them.
+Failed rebuilds keep the last successfully built source, from [`ShaderWatcher::last_good_source`],
+and the newest error, from [`ShaderWatcher::last_error`] or [`HotSwap::last_error`],
+so applications can keep rendering with the previous shader while showing the error.
+
 # Crate features
 
 ### Inserting Arrays of Suitable Lengths as Vectors
@@ -144,14 +365,222 @@ By default, none of the following features are enabled.
 * **cgmath_vectors** -
   This feature is similar to **array_vectors** but with [`cgmath`] vector objects like [`cgmath::Vector3<u32>`]
   which would be translated to `vec3<u32>`.
+
+Single vector constants can be put without these features with [`ShaderBuilder::put_vec3`] and the like.
+
+### Serialization
+
+* **serde** -
+  When enabled, [`BuilderConfig`] implements `Serialize` and `Deserialize` from [`serde`](https://docs.rs/serde),
+  so build configurations can be stored and replayed with [`ShaderBuilder::from_config`].
+
+### GLSL Includes
+
+* **glsl_includes** -
+  When enabled, included modules with a GLSL shader stage extension (`.vert`, `.frag` or `.comp`) are converted
+  to WGSL with [`naga`](https://docs.rs/naga) before being spliced in, for example `//!include legacy/blur.frag`,
+  easing incremental ports of GLSL shaders.
+
+### SPIR-V Includes
+
+* **spirv_includes** -
+  When enabled, `include_spirv` statements convert the entry point of a compiled SPIR-V module to a WGSL function
+  with [`naga`](https://docs.rs/naga), for example `//!include_spirv legacy/tint.spv as fn tint`,
+  easing migrations from Vulkan toolchains. The other functions and the global variables of the module
+  are prefixed with the name of the function.
+
+### Diagnostics
+
+* **diagnostics** -
+  When enabled, [`Diagnostic::render`] renders errors caused by statements with the offending source line
+  and the chain of `include` statements, using [`codespan-reporting`](https://docs.rs/codespan-reporting).
+  Errors of [`naga`](https://docs.rs/naga) in built sources are not mapped back to the modules, and are reported
+  on the lines of the built source by naga itself.
+
+### Reflection
+
+* **reflection** -
+  When enabled, the [`reflection`] module parses built sources with [`naga`](https://docs.rs/naga),
+  for example to check that the outputs of a vertex stage match the inputs of a fragment stage,
+  to compute the bind group layouts required by shaders sharing a pipeline,
+  to validate the values of `override` constants before creating a pipeline,
+  to hash the interface of a shader for caching pipeline layouts,
+  or to build shaders into [`naga`](https://docs.rs/naga) modules with [`ShaderBuilder::build_naga_module`],
+  transformed by the passes added with [`ShaderBuilder::naga_pass`],
+  which wgpu creates shader modules from without parsing them again.
+
+### Memory-Mapped Output
+
+* **mmap** -
+  When enabled, `OutputTarget::MemoryMap` writes sources built with [`ShaderBuilder::build_output`] to a file
+  mapped in memory with [`memmap2`](https://docs.rs/memmap2) once they are built,
+  so they can be shared with other processes through the file.
+
+### Build Daemon
+
+* **daemon** -
+  When enabled, the [`daemon`] module answers build requests of other processes, naming a root module and its
+  definitions, with the built source or the error of the build as JSON, over a Unix socket or any other stream,
+  so tools that are not written in Rust, like DCC plugins or Python pipelines, can reuse the same preprocessor.
+
+### C API
+
+* **ffi** -
+  When enabled, the [`ffi`] module exports C functions, like [`ffi::wgsl_preprocessor_build`],
+  which preprocesses a shader with a list of definitions into a returned string,
+  so C and C++ engines can use the same shader preprocessing conventions.
+  The crate is not built as a dynamic library by default, build one with
+  `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+
+### Testing
+
+* **testing** -
+  When enabled, the [`testing`] module dispatches built compute shaders on a headless device and reads back
+  their buffers, so shader libraries can have GPU unit tests, for example of every permutation of their definitions.
+  [`testing::compare_golden`] compares read back values with golden files within a tolerance,
+  and writes the golden files instead when the `WGSL_PREPROCESSOR_UPDATE_GOLDENS` environment variable is set.
+
+### Instrumentation
+
+* **tracing** -
+  When enabled, builds are instrumented with [`tracing`](https://docs.rs/tracing) spans and events:
+  a span for every build and every loaded module, events for resolved includes and defined macros,
+  the number of substitutions of every definition, and the duration of every build.
 */
-use std::{any, borrow, collections::HashMap, path};
+#![allow(clippy::tabs_in_doc_comments)]
+
+use std::{
+	any, borrow, cell,
+	collections::{BTreeMap, HashMap, HashSet},
+	fmt, io, mem, path, sync, thread, time,
+};
+
+mod ast;
+mod bindings;
+mod color;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+mod diagnostics;
+mod expression;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod hot_reload;
+mod library;
+mod macros;
+mod module_cache;
+mod ordering;
+mod output;
+#[cfg(feature = "reflection")]
+pub mod reflection;
+mod reserved;
+mod source_resolver;
+mod sweep;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod units;
+mod warnings;
+
+pub use bindings::Bindings;
+pub use color::{Color, ColorSpace};
+pub use diagnostics::{Diagnostic, PreprocessError, Provenance, SourceLocation};
+pub use hot_reload::{BatchReport, HotSwap, HotSwapError, ShaderWatcher, ShaderWatcherSet};
+pub use library::ShaderLibrary;
+pub use module_cache::ModuleCache;
+pub use output::{BuildOutput, OutputTarget};
+pub use source_resolver::{FileSystemResolver, MemoryResolver, SourceResolver};
+pub use sweep::{Sweep, SweepFailure, SweepReport};
+pub use units::{AngleUnit, Degrees, LengthUnit, Millimeters, Radians, Units};
+pub use warnings::{Warning, WarningCode};
 
 const INSTRUCTION_PREFIX: &str = "//!";
+/// Maximum number of symbols of [`ShaderBuilder::build_permutations`], building 65536 permutations.
+const MAX_PERMUTATION_SYMBOLS: usize = 16;
 const INCLUDE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "include");
+const INCLUDE_SPIRV_INSTRUCTION: &str =
+	const_format::concatcp!(INSTRUCTION_PREFIX, "include_spirv");
+const INCLUDE_FIRST_INSTRUCTION: &str =
+	const_format::concatcp!(INSTRUCTION_PREFIX, "include_first");
 const DEFINE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "define");
+const DEFINE_DEFAULT_INSTRUCTION: &str =
+	const_format::concatcp!(INSTRUCTION_PREFIX, "define_default");
+const DEFINE_ADD_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "define_add");
+const DEFINE_APPEND_INSTRUCTION: &str =
+	const_format::concatcp!(INSTRUCTION_PREFIX, "define_append");
+const IFDEF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "ifdef");
+const IFNDEF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "ifndef");
+const IF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "if");
+const ELIF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "elif");
+const ELIFDEF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "elifdef");
+const ELIFNDEF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "elifndef");
+const ELSE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "else");
+const ENDIF_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "endif");
+const ALLOW_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "allow");
+const COLOR_SPACE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "color_space");
+const DEPRECATED_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "deprecated");
+const EXPECT_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "expect");
+const DECLARE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "declare");
+const ONCE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "once");
+const MODULE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "module");
+const REQUIRES_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "requires");
+const SECTION_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "section");
+const ENDSECTION_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "endsection");
+/// Prefix of the argument of an `include` statement that selects a section of the preceding module.
+const SECTION_ARGUMENT_PREFIX: &str = "section=";
+const PRESERVE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "preserve");
+const ENDPRESERVE_INSTRUCTION: &str = const_format::concatcp!(INSTRUCTION_PREFIX, "endpreserve");
+/// Delimits the index of a preserved region in the source until the region is restored.
+const PRESERVED_REGION_DELIMITER: char = '\u{1}';
+const TARGET_SYMBOLS: [&str; 4] = [
+	"TARGET_WEB",
+	"TARGET_NATIVE",
+	"TARGET_DEBUG",
+	"TARGET_RELEASE",
+];
 lazy_static::lazy_static! {
 	static ref MACRO_REGEX: regex::Regex = regex::Regex::new(&format!(r"{DEFINE_INSTRUCTION} (\S+) (.+)")).unwrap();
+	static ref FUNCTION_MACRO_REGEX: regex::Regex = regex::Regex::new(&format!(
+		r"^{DEFINE_INSTRUCTION}\s+([A-Za-z_][A-Za-z0-9_]*)\(([^)]*)\)(?:\s+(.*?))?\s*$"
+	))
+	.unwrap();
+	static ref ARRAY_SIZE_REGEX: regex::Regex =
+		regex::Regex::new(r"array\s*<(?:[^<>]|<(?:[^<>]|<[^<>]*>)*>)+?,\s*([A-Za-z_][A-Za-z0-9_]*)\s*>").unwrap();
+	static ref TEMPLATE_REGEX: regex::Regex =
+		regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(?::([^}]*))?\}").unwrap();
+	static ref DEPRECATED_REGEX: regex::Regex =
+		regex::Regex::new(&format!(r#"^{DEPRECATED_INSTRUCTION}\s+(\S+)(?:\s+"(.*)")?\s*$"#)).unwrap();
+	static ref EXPECT_REGEX: regex::Regex =
+		regex::Regex::new(&format!(r"^{EXPECT_INSTRUCTION}\s+([A-Za-z_][A-Za-z0-9_]*)\s*:\s*(\S.*?)\s*$")).unwrap();
+	static ref DECLARE_REGEX: regex::Regex = regex::Regex::new(&format!(
+		r"^{DECLARE_INSTRUCTION}\s+(struct|fn|const|var|override|alias)\s+([A-Za-z_][A-Za-z0-9_]*)\s*;?\s*$"
+	))
+	.unwrap();
+	static ref DEFINE_DEFAULT_REGEX: regex::Regex =
+		regex::Regex::new(&format!(r"^{DEFINE_DEFAULT_INSTRUCTION}\s+(\S+)(?:\s+(.+?))?\s*$")).unwrap();
+	static ref DEFINE_ADD_REGEX: regex::Regex =
+		regex::Regex::new(&format!(r"^{DEFINE_ADD_INSTRUCTION}\s+(\S+)\s+(-?[0-9]+)([ui]?)\s*$")).unwrap();
+	static ref DEFINE_APPEND_REGEX: regex::Regex =
+		regex::Regex::new(&format!(r"^{DEFINE_APPEND_INSTRUCTION}\s+(\S+)\s+(.+?)\s*$")).unwrap();
+	static ref INTEGER_REGEX: regex::Regex = regex::Regex::new(r"^(-?[0-9]+)([ui]?)$").unwrap();
+	static ref MODULE_REGEX: regex::Regex =
+		regex::Regex::new(&format!(r"^{MODULE_INSTRUCTION}\s+([A-Za-z_][A-Za-z0-9_.-]*)\s+(\S+)\s*$")).unwrap();
+	static ref REQUIRES_REGEX: regex::Regex = regex::Regex::new(&format!(
+		r"^{REQUIRES_INSTRUCTION}\s+([A-Za-z_][A-Za-z0-9_.-]*)\s*(>=|<=|>|<|=)?\s*([0-9]\S*)\s*$"
+	))
+	.unwrap();
+	static ref INCLUDE_SPIRV_REGEX: regex::Regex = regex::Regex::new(&format!(
+		r"^{INCLUDE_SPIRV_INSTRUCTION}\s+(\S+)\s+as\s+fn\s+([A-Za-z_][A-Za-z0-9_]*)\s*$"
+	))
+	.unwrap();
+	static ref NUMTHREADS_REGEX: regex::Regex =
+		regex::Regex::new(r"\[\s*numthreads\s*\(([^)]*)\)\s*\]").unwrap();
+	static ref SEMANTIC_REGEX: regex::Regex =
+		regex::Regex::new(r"([A-Za-z_][A-Za-z0-9_]*\s*:\s*[A-Za-z_][A-Za-z0-9_<>, ]*?)\s*:\s*(SV_[A-Za-z]+)").unwrap();
+	static ref POSITIVE_INTEGER_REGEX: regex::Regex =
+		regex::Regex::new(r"^(?:0[xX]([0-9a-fA-F]+)|([0-9]+))[ui]?$").unwrap();
+	static ref NUMERIC_LITERAL_REGEX: regex::Regex = regex::Regex::new(
+		r"(^|[^\w.])(?:0[xX]([0-9a-fA-F]+)|((?:[0-9]+\.[0-9]*|\.[0-9]+|[0-9]+)(?:[eE][+-]?[0-9]+)?))([fhiu]?)"
+	)
+	.unwrap();
 }
 
 /// Type for data types that can be defined in WGSL.
@@ -162,6 +591,13 @@ pub trait WGSLType {
 
 	/// Returns a string that creates an instance of the type in WGSL syntax.
 	fn string_definition(&self) -> String;
+
+	/// Returns a string that creates an instance of the type in the units of the shader,
+	/// see [`ShaderBuilder::units`]. Only types with a unit, like [`Degrees`], need to implement it.
+	fn string_definition_in(&self, units: Units) -> String {
+		let _ = units;
+		self.string_definition()
+	}
 }
 
 impl WGSLType for u32 {
@@ -174,14 +610,69 @@ impl WGSLType for u32 {
 	}
 }
 
-#[duplicate::duplicate_item(wgsl_type; [i32]; [f32])]
-impl WGSLType for wgsl_type {
+impl WGSLType for i32 {
+	fn type_name() -> String {
+		any::type_name::<i32>().to_string()
+	}
+
+	/// Returns a literal with the `i` suffix, like `-1i`.
+	/// The literal `2147483648i` does not fit in an `i32`, so [`i32::MIN`] is converted from an abstract integer.
+	fn string_definition(&self) -> String {
+		if *self == i32::MIN {
+			format!("i32({self})")
+		} else {
+			format!("{self}i")
+		}
+	}
+}
+
+/// Integer defined as a hexadecimal literal, for example masks like `put_constant("MASK", Hex(0xffu32))`,
+/// which is defined as `0xffu`.
+/// WGSL has no binary literals, so binary values like `Hex(0b1010u32)` are also defined in hexadecimal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hex<T>(pub T);
+
+impl WGSLType for Hex<u32> {
+	fn type_name() -> String {
+		u32::type_name()
+	}
+
+	fn string_definition(&self) -> String {
+		format!("{:#x}u", self.0)
+	}
+}
+
+impl WGSLType for Hex<i32> {
+	fn type_name() -> String {
+		i32::type_name()
+	}
+
+	/// Returns a literal with the `i` suffix, like `-0x1fi`, see [`i32::string_definition`].
+	fn string_definition(&self) -> String {
+		match self.0 {
+			i32::MIN => format!("i32(-{:#x})", self.0.unsigned_abs()),
+			value if value < 0 => format!("-{:#x}i", value.unsigned_abs()),
+			value => format!("{value:#x}i"),
+		}
+	}
+}
+
+impl WGSLType for f32 {
 	fn type_name() -> String {
-		any::type_name::<wgsl_type>().to_string()
+		any::type_name::<f32>().to_string()
 	}
 
+	/// Returns the shortest literal that converts back to the same value, always with a decimal point or
+	/// an exponent so it is never mistaken for an integer (`2.0`, `0.1`, `1e20`).
+	/// WGSL has no literals for NaN and infinities, so they are defined with their bits (`bitcast<f32>(0x7fc00000u)`).
+	/// Bit casts are not constant expressions, so these values can only be used in function bodies,
+	/// not in `const` declarations, override defaults or arrays put with [`ShaderBuilder::put_array_definition`].
 	fn string_definition(&self) -> String {
-		format!("{self}")
+		if self.is_finite() {
+			format!("{self:?}")
+		} else {
+			format!("bitcast<f32>({:#010x}u)", self.to_bits())
+		}
 	}
 }
 
@@ -200,7 +691,7 @@ impl WGSLType for wgsl_type {
 	}
 
 	fn string_definition(&self) -> String {
-		format!("{}({:?})", Self::type_name(), self).replace(&['[', ']'], "")
+		vector_string_definition(&Self::type_name(), self)
 	}
 }
 
@@ -219,19 +710,25 @@ impl WGSLType for wgsl_type {
 	}
 
 	fn string_definition(&self) -> String {
-		println!("{}", format!("{:?}", self));
-		format!(
-			"{}({})",
-			Self::type_name(),
-			format!("{:?}", self)
-				.replace("Vector", "")
-				.split(&['[', ']'])
-				.nth(1)
-				.unwrap()
-		)
+		let elements: Vec<_> = (0..<wgsl_type as cgmath::Array>::len())
+			.map(|index| self[index])
+			.collect();
+		vector_string_definition(&Self::type_name(), &elements)
 	}
 }
 
+#[cfg(any(feature = "array_vectors", feature = "cgmath_vectors"))]
+fn vector_string_definition<T: WGSLType>(type_name: &str, elements: &[T]) -> String {
+	format!(
+		"{type_name}({})",
+		elements
+			.iter()
+			.map(WGSLType::string_definition)
+			.collect::<Vec<String>>()
+			.join(", ")
+	)
+}
+
 impl WGSLType for bool {
 	fn type_name() -> String {
 		"bool".to_string()
@@ -242,287 +739,7066 @@ impl WGSLType for bool {
 	}
 }
 
+/// Type for Rust structs whose fields can be defined in WGSL as separate arrays.
+/// Used by [`ShaderBuilder::put_soa_definition`] to emit one array per field (struct-of-arrays).
+pub trait WGSLStruct {
+	/// Returns the name and the WGSL type name of every field, in declaration order.
+	fn field_types() -> Vec<(&'static str, String)>;
+
+	/// Returns a string that creates an instance of every field in WGSL syntax,
+	/// in the same order as [`WGSLStruct::field_types`].
+	fn field_definitions(&self) -> Vec<String>;
+}
+
 /// Wraps shader code, changes it and builds it into a [`wgpu::ShaderModuleDescriptor`].
 pub struct ShaderBuilder {
-	/// String with the current WGSL source.
+	/// String with the WGSL source of the last build.
 	/// It is marked public for debugging purposes.
 	pub source_string: String,
-	source_path: String,
+	config: BuilderConfig,
+	cancellation_token: Option<CancellationToken>,
+	module_cache: Option<sync::Arc<ModuleCache>>,
+	/// Modules loaded by [`ShaderBuilder::parse`], used instead of reading and parsing them again.
+	parsed_modules: Option<sync::Arc<ParsedModules>>,
+	source_resolver: sync::Arc<dyn SourceResolver>,
+	/// Libraries linked with [`ShaderBuilder::link_library`].
+	libraries: Vec<sync::Arc<ShaderLibrary>>,
+	label: String,
+	build_stats: BuildStats,
+	push_constant_ranges: Vec<wgpu::PushConstantRange>,
+	build_errors: Vec<ex::io::Error>,
+	warnings: Vec<Warning>,
+	definition_provenance: HashMap<String, Provenance>,
+	/// Definitions of the modules of the last successful build.
+	file_definitions: HashMap<String, String>,
+	/// Overlay of the last successful build.
+	overlay_definitions: HashMap<String, String>,
+	/// Names observed by the last successful build, if they are recorded,
+	/// see [`ShaderBuilder::build_permutations`].
+	observed_symbols: Option<ObservedSymbols>,
+	/// Passes added with [`ShaderBuilder::naga_pass`], with their names.
+	#[cfg(feature = "reflection")]
+	naga_passes: Vec<(String, reflection::NagaPass)>,
 }
 
-impl ShaderBuilder {
-	/// Creates a new [`ShaderBuilder`].
-	///
-	/// # Arguments
-	/// - `source_path` - Path to the root WGSL module.
-	///		All includes will be relative to the parent directory of the root WGSL module.
-	/// 	Code is generated recursively with attention to `include` and `define` statements.
-	/// 	See "Examples" for more details on include and macro functionality.
-	pub fn new(source_path: &str) -> Result<Self, ex::io::Error> {
-		let module_path = path::Path::new(&source_path);
-		let source_string = Self::load_shader_module(
-			module_path.parent().unwrap_or(path::Path::new("./")),
-			module_path,
-		)?
-		.0;
-		Ok(Self {
-			source_string,
-			source_path: source_path.to_string(),
-		})
+/// Configuration of a [`ShaderBuilder`], everything needed to replay a build.
+/// With the **serde** feature, it can be serialized and stored, for example in asset metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BuilderConfig {
+	/// Path to the root WGSL module.
+	pub source_path: String,
+	/// Constants put with methods like [`ShaderBuilder::put_constant`], as WGSL strings.
+	pub definitions: HashMap<String, String>,
+	/// Constants put with [`ShaderBuilder::put_constant_expr`], as WGSL expressions.
+	pub constant_expressions: HashMap<String, String>,
+	/// See [`ShaderBuilder::fold_constants`].
+	pub fold_constants: bool,
+	/// Constants of the [`DefinitionLayer::Default`] layer, put by presets and [`ShaderBuilder::put_default_constant`].
+	pub default_definitions: HashMap<String, String>,
+	/// Arrays put with methods like [`ShaderBuilder::put_array_definition`], as WGSL declarations.
+	pub array_definitions: HashMap<String, String>,
+	/// See [`ShaderBuilder::time_limit`].
+	pub time_limit: Option<time::Duration>,
+	/// See [`ShaderBuilder::label_hash`].
+	pub label_hash: bool,
+	/// See [`ShaderBuilder::deterministic`].
+	pub deterministic: bool,
+	/// See [`ShaderBuilder::target_symbols`].
+	pub target_symbols: bool,
+	/// See [`ShaderBuilder::substitution_scope`].
+	pub substitution_scope: SubstitutionScope,
+	/// See [`ShaderBuilder::separate_symbols`].
+	pub separate_symbols: bool,
+	/// See [`ShaderBuilder::error_recovery`].
+	pub error_recovery: bool,
+	/// See [`ShaderBuilder::hlsl_compatibility`].
+	pub hlsl_compatibility: bool,
+	/// See [`ShaderBuilder::templates`].
+	pub templates: bool,
+	/// See [`ShaderBuilder::undefined_symbol_lint`].
+	pub undefined_symbol_lint: bool,
+	/// Warnings allowed with [`ShaderBuilder::allow`].
+	pub allowed_warnings: Vec<WarningCode>,
+	/// Warnings denied with [`ShaderBuilder::deny`].
+	pub denied_warnings: Vec<WarningCode>,
+	/// Prefixes of include paths and their replacements, see [`ShaderBuilder::remap_include_prefix`].
+	pub include_prefixes: Vec<(String, String)>,
+	/// See [`ShaderBuilder::include_resolution`].
+	pub include_resolution: IncludeResolution,
+	/// Directories that include paths are searched in, see [`ShaderBuilder::add_include_path`].
+	pub include_paths: Vec<path::PathBuf>,
+	/// Include paths and the include paths they are redirected to, see [`ShaderBuilder::alias_module`].
+	pub module_aliases: Vec<(String, String)>,
+	/// See [`ShaderBuilder::order_declarations`].
+	pub order_declarations: bool,
+	/// See [`ShaderBuilder::record_substitutions`].
+	pub record_substitutions: bool,
+	/// See [`ShaderBuilder::deduplicate_includes`].
+	pub deduplicate_includes: bool,
+	/// See [`ShaderBuilder::debug_markers`].
+	pub debug_markers: bool,
+	/// See [`ShaderBuilder::normalize_paths`].
+	pub normalize_paths: bool,
+	/// See [`ShaderBuilder::invalid_utf8`].
+	pub invalid_utf8: InvalidUtf8,
+	/// See [`ShaderBuilder::max_module_size`].
+	pub max_module_size: Option<u64>,
+	/// See [`ShaderBuilder::max_output_size`].
+	pub max_output_size: Option<usize>,
+	/// See [`ShaderBuilder::max_expansion_size`].
+	pub max_expansion_size: Option<usize>,
+	/// See [`ShaderBuilder::output_target`].
+	pub output_target: OutputTarget,
+	/// See [`ShaderBuilder::literal_suffixes`].
+	pub literal_suffixes: LiteralSuffixes,
+	/// See [`ShaderBuilder::units`].
+	pub units: Units,
+	/// See [`ShaderBuilder::color_space`].
+	pub color_space: ColorSpace,
+	/// Names of the colors put with [`ShaderBuilder::put_color`], with the color spaces they were converted to.
+	pub color_constants: Vec<(String, ColorSpace)>,
+	/// WGSL type of the constants put with methods like [`ShaderBuilder::put_constant`], checked by `expect` statements.
+	pub definition_types: HashMap<String, String>,
+	/// Storage buffers declared by [`ShaderBuilder::put_bindings`], by name of the statement emitting them,
+	/// and whether they are read-only.
+	pub storage_accesses: HashMap<String, Vec<(String, bool)>>,
+}
+
+/// Parts of the source where definitions are substituted, see [`ShaderBuilder::substitution_scope`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SubstitutionScope {
+	/// Every occurrence of the name of a definition is replaced, unless it is part of a longer identifier.
+	#[default]
+	WholeFile,
+	/// Only matches of the regular expression are replaced.
+	/// If the expression has a capture group called `name`, it is the name of the definition,
+	/// for example `\$\{(?P<name>\w+)\}` replaces `${COUNT}` by the value of `COUNT`.
+	/// Otherwise the whole match is the name, for example `__[A-Z0-9_]+__` replaces `__COUNT__` by the value of `__COUNT__`.
+	/// Matches that are not defined are left untouched.
+	Placeholders(String),
+}
+
+/// Layer of a definition, see [`ShaderBuilder::definition`].
+/// When a name is defined in several layers, the value of the highest layer is used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DefinitionLayer {
+	/// Put by a [`Preset`], by [`ShaderBuilder::put_default_constant`] or by [`ShaderBuilder::target_symbols`].
+	Default,
+	/// Defined by a `define` statement in a module.
+	File,
+	/// Put from Rust code with methods like [`ShaderBuilder::put_constant`].
+	Builder,
+	/// Passed to a single build, see [`ShaderBuilder::build_source_with_overlay`].
+	Overlay,
+}
+
+/// Resolution of relative include paths, see [`ShaderBuilder::include_resolution`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IncludeResolution {
+	/// Paths are relative to the directory of the including module,
+	/// or to the working directory if no module exists there.
+	/// Including a path that names different modules relative to both fails.
+	#[default]
+	RelativeToModule,
+	/// Paths are relative to the working directory of the process.
+	WorkingDirectory,
+}
+
+/// Handling of modules that are not valid UTF-8, see [`ShaderBuilder::invalid_utf8`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InvalidUtf8 {
+	/// The build fails with the line and byte offset of the first invalid sequence.
+	#[default]
+	Error,
+	/// Invalid sequences are replaced by `U+FFFD`, with the warning `W006`.
+	Lossy,
+}
+
+/// Suffixes of the literals of [`WGSLType`] values, see [`ShaderBuilder::literal_suffixes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LiteralSuffixes {
+	/// Whether integers have the `u` or `i` suffix of their type, like `1u`. Enabled by default.
+	/// Without it, integers are abstract and become `i32` unless the context requires another type,
+	/// so integers that do not fit in an `i32` keep their suffix.
+	pub integers: bool,
+	/// Whether floats have the `f` suffix, like `1.0f`, instead of being abstract. Disabled by default.
+	pub floats: bool,
+}
+
+impl Default for LiteralSuffixes {
+	fn default() -> Self {
+		Self {
+			integers: true,
+			floats: false,
+		}
 	}
+}
 
-	/// Performs the WGSL's parallel to C's `#define` statement.
-	///
-	/// # Arguments
-	/// - `name` - Name of the constant; the string to replace in the code.
-	/// - `value` - Value of the constant.
-	pub fn put_constant(&mut self, name: &str, value: impl WGSLType) -> &mut Self {
-		self.source_string = self.source_string.replace(name, &value.string_definition());
-		self
+/// Bundle of definitions for a common rendering technique, see [`ShaderBuilder::preset`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+	/// Rendering to several array layers at once, for example for stereo or cube maps.
+	/// Requires [`wgpu::Features::MULTIVIEW`]. Defines:
+	/// - `MULTIVIEW` - Symbol for conditional compilation.
+	/// - `VIEW_COUNT` - Number of views, as a `u32`.
+	/// - `VIEW_INDEX` - The parameter of the view index, `@builtin(view_index) view_index: i32`.
+	Multiview {
+		/// Number of views.
+		views: u32,
+	},
+	/// Instanced rendering, where every instance reads its data by its index. Defines:
+	/// - `INSTANCED` - Symbol for conditional compilation.
+	/// - `MAX_INSTANCES` - Maximum number of instances, as a `u32`, for example the size of an instance array.
+	/// - `INSTANCE_INDEX` - The parameter of the instance index, `@builtin(instance_index) instance_index: u32`.
+	Instanced {
+		/// Maximum number of instances.
+		max_instances: u32,
+	},
+}
+
+/// Statistics of a build, see [`ShaderBuilder::build_stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildStats {
+	/// Paths of the modules read during the build, in the order they were read.
+	/// Paths are canonical if [`ShaderBuilder::normalize_paths`] is enabled.
+	pub files_read: Vec<path::PathBuf>,
+	/// Length of the built source in bytes.
+	pub bytes_emitted: usize,
+	/// Number of substitutions applied for every definition.
+	pub substitutions: HashMap<String, usize>,
+	/// Number of lines skipped because of conditional statements.
+	pub conditional_lines_skipped: usize,
+	/// Origin of every definition, for definitions put from Rust code and defined in the modules read.
+	/// A definition defined in several layers has the provenance of the value that is used,
+	/// see [`DefinitionLayer`].
+	pub definition_provenance: HashMap<String, Provenance>,
+	/// Definitions substituted in the lines of the built source, by line number starting at 1,
+	/// if [`ShaderBuilder::record_substitutions`] is enabled.
+	pub line_substitutions: BTreeMap<usize, Vec<Substitution>>,
+	/// Expansions of every macro that was expanded, see [`ShaderBuilder::max_expansion_size`].
+	pub macro_expansions: HashMap<String, MacroExpansion>,
+}
+
+/// Expansions of a macro during a build, see [`BuildStats::macro_expansions`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MacroExpansion {
+	/// Number of times the macro was expanded.
+	pub count: usize,
+	/// Total length of the expansions in bytes.
+	pub bytes_emitted: usize,
+	/// Length of the longest expansion in bytes.
+	pub largest_expansion: usize,
+}
+
+impl MacroExpansion {
+	/// Records `count` expansions of `length` bytes.
+	pub(crate) fn record(&mut self, count: usize, length: usize) {
+		self.count += count;
+		self.bytes_emitted += count * length;
+		self.largest_expansion = self.largest_expansion.max(length);
 	}
+}
 
-	/// Calls [`ShaderBuilder::put_constant`] for every (key, value) pair in a given [`HashMap`].
-	pub fn put_constant_map(
-		&mut self,
-		constant_map: &HashMap<&str, impl WGSLType + Copy>,
-	) -> &mut Self {
-		constant_map.iter().for_each(|(name, &value)| {
-			self.put_constant(name, value);
-		});
-		self
+/// Definition substituted in a line of the built source, see [`BuildStats::line_substitutions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+	/// Name of the definition.
+	pub name: String,
+	/// Value the name was replaced with.
+	pub value: String,
+}
+
+/// Specialization of a shader for an entry point, see [`ShaderBuilder::build_entry_points`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryPointBuild {
+	/// Name of the entry point.
+	pub entry_point: String,
+	/// Label of the specialization.
+	pub label: String,
+	/// Built WGSL source of the specialization.
+	pub source: String,
+}
+
+impl EntryPointBuild {
+	/// Returns a [`wgpu::ShaderModuleDescriptor`] of the specialization.
+	pub fn descriptor(&self) -> wgpu::ShaderModuleDescriptor<'_> {
+		wgpu::ShaderModuleDescriptor {
+			label: Some(&self.label),
+			source: wgpu::ShaderSource::Wgsl(borrow::Cow::Borrowed(&self.source)),
+		}
 	}
+}
 
-	/// Defines a constant array of elements.
-	///
-	/// # Arguments
-	/// - `name` - Name of the array in the WGSL source.
-	/// - `array` - Vector of [`WGSLType`] whose elements will be the elements in the array.
-	pub fn put_array_definition<'a, T: 'a + WGSLType>(
-		&'a mut self,
-		name: &str,
-		array: &Vec<&T>,
-	) -> &'a mut Self {
-		let type_name = T::type_name();
-		let array_length = array.len();
-		let mut string_definition = String::new();
+/// Bytes and directive trees of the modules loaded by [`ShaderBuilder::parse`], by path.
+type ParsedModules = HashMap<path::PathBuf, (sync::Arc<[u8]>, ast::Tree)>;
 
-		string_definition.push_str(&format!(
-			"var<private> {name}: array<{type_name}, {array_length}> = array<{type_name}, {array_length}>("
-		));
+/// Shader whose modules are loaded and parsed, to be rendered cheaply for many sets of definitions,
+/// see [`ShaderBuilder::parse`].
+pub struct ParsedShader {
+	/// Builder evaluating the directive trees of the parsed modules,
+	/// and reading the other modules from `module_cache`.
+	shader_builder: ShaderBuilder,
+	module_cache: sync::Arc<ModuleCache>,
+	includes: Vec<(path::PathBuf, Vec<path::PathBuf>)>,
+}
 
-		for value in array.iter() {
-			string_definition.push_str(&value.string_definition());
-			string_definition.push(',');
+impl ParsedShader {
+	/// Renders the shader with definitions in addition to those of the builder,
+	/// like [`ShaderBuilder::build_source_with_overlay`], only evaluating the directive trees of the parsed modules
+	/// and substituting the definitions, without reading or parsing the modules again.
+	pub fn render(&self, definitions: &HashMap<String, String>) -> Result<String, ex::io::Error> {
+		let mut shader_builder = self.shader_builder.specialization(&self.module_cache)?;
+		shader_builder.build_source_with_overlay(definitions)?;
+		Ok(shader_builder.source_string)
+	}
+
+	/// Returns the include graph: every loaded module, starting with the root module,
+	/// with the modules it includes, in every branch of its conditional blocks.
+	/// Modules of `include_first` statements are the first existing ones.
+	pub fn includes(&self) -> &[(path::PathBuf, Vec<path::PathBuf>)] {
+		&self.includes
+	}
+}
+
+/// Distinct sources of the permutations of a shader, see [`ShaderBuilder::build_permutations`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Permutations {
+	/// Every distinct built source.
+	pub variants: Vec<Variant>,
+	/// Every combination of defined symbols, with the index of its source in [`Permutations::variants`].
+	pub mapping: Vec<(Vec<String>, usize)>,
+}
+
+impl Permutations {
+	/// Returns the manifest of the permutations, mapping every combination of symbols to its variant,
+	/// so a runtime can pick a precompiled variant without running the preprocessor.
+	pub fn manifest(&self) -> VariantManifest {
+		VariantManifest {
+			entries: self
+				.mapping
+				.iter()
+				.map(|(symbols, variant_index)| {
+					let variant = &self.variants[*variant_index];
+					ManifestEntry {
+						symbols: symbols.clone(),
+						label: variant.label.clone(),
+						content_hash: fnv1a_hash(variant.source.as_bytes()),
+						#[cfg(feature = "reflection")]
+						interface_hash: reflection::interface_hash(&variant.source).ok(),
+						#[cfg(not(feature = "reflection"))]
+						interface_hash: None,
+					}
+				})
+				.collect(),
 		}
+	}
+}
 
-		string_definition.push_str(");");
+/// Mapping of combinations of symbols to precompiled variants, see [`Permutations::manifest`].
+/// With the **serde** feature it can be written as JSON, or it can be written as Rust code
+/// with [`VariantManifest::to_rust`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VariantManifest {
+	/// Entry of every combination of symbols.
+	pub entries: Vec<ManifestEntry>,
+}
 
-		self.source_string = self
-			.source_string
-			.replace(&format!("{DEFINE_INSTRUCTION} {name}"), &string_definition);
-		self
+/// Combination of symbols in a [`VariantManifest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestEntry {
+	/// Symbols defined by the combination.
+	pub symbols: Vec<String>,
+	/// Label of the variant built for the combination, identifying it, see [`Variant::label`].
+	pub label: String,
+	/// Hash of the source of the variant, see [`ShaderBuilder::content_hash`].
+	pub content_hash: u64,
+	/// Hash of the interface of the variant with the **reflection** feature, see [`reflection::interface_hash`].
+	pub interface_hash: Option<u64>,
+}
+
+impl VariantManifest {
+	/// Returns the entry of a combination of symbols, in any order.
+	pub fn entry(&self, symbols: &[&str]) -> Option<&ManifestEntry> {
+		self.entries.iter().find(|entry| {
+			entry.symbols.len() == symbols.len()
+				&& symbols
+					.iter()
+					.all(|symbol| entry.symbols.iter().any(|defined| defined == symbol))
+		})
 	}
 
-	/// Builds a [`wgpu::ShaderModuleDescriptor`] from the shader.
-	/// The `label` member of the built [`wgpu::ShaderModuleDescriptor`] is the name of the shader file without the postfix.
-	pub fn build(&self) -> wgpu::ShaderModuleDescriptor {
-		wgpu::ShaderModuleDescriptor {
-			label: Some(
-				&self
-					.source_path
-					.rsplit(['/', '.'])
-					.nth(1)
-					.unwrap_or(&self.source_path),
-			),
-			source: wgpu::ShaderSource::Wgsl(borrow::Cow::Borrowed(&self.source_string)),
+	/// Returns Rust code declaring the manifest as a constant array of
+	/// `(symbols, label, content hash, interface hash)` tuples, to be written by a build script
+	/// and included with [`include!`].
+	///
+	/// # Arguments
+	/// - `name` - Name of the constant.
+	pub fn to_rust(&self, name: &str) -> String {
+		let mut rust = format!("pub const {name}: &[(&[&str], &str, u64, Option<u64>)] = &[\n");
+		for entry in self.entries.iter() {
+			let interface_hash = entry
+				.interface_hash
+				.map_or_else(|| "None".to_string(), |hash| format!("Some({hash:#x})"));
+			rust.push_str(&format!(
+				"\t(&{:?}, {:?}, {:#x}, {interface_hash}),\n",
+				entry.symbols, entry.label, entry.content_hash
+			));
 		}
+		rust.push_str("];\n");
+		rust
 	}
+}
 
-	fn load_shader_module(
-		base_path: &path::Path,
-		module_path: &path::Path,
-	) -> Result<(String, HashMap<String, String>), ex::io::Error> {
-		let module_source = ex::fs::read_to_string(module_path)?;
-		let mut module_string = String::new();
-		let mut definitions: HashMap<String, String> = HashMap::new();
-		for line in module_source.lines() {
-			if line.starts_with(INCLUDE_INSTRUCTION) {
-				for include in line.split_whitespace().skip(1) {
-					let (included_module_string, included_definitions) =
-						Self::load_shader_module(base_path, &path::Path::new(include))?;
-					module_string.push_str(&included_module_string);
-					definitions.extend(included_definitions);
-				}
-			} else if let Some(captures) = MACRO_REGEX.captures(line) {
-				definitions.insert(captures[1].to_string(), captures[2].to_string());
-			} else {
-				module_string.push_str(line);
-				module_string.push('\n');
-			}
+/// Progress of [`ShaderBuilder::build_permutations_with_progress`], reported when a permutation is built.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermutationProgress<'a> {
+	/// Symbols defined by the permutation that was built.
+	pub symbols: &'a [String],
+	/// Number of permutations built so far, including this one.
+	pub completed: usize,
+	/// Number of permutations.
+	pub total: usize,
+	/// Time taken to build this permutation.
+	pub elapsed: time::Duration,
+}
+
+/// Distinct built source of permutations, see [`Permutations::variants`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+	/// Label of the variant: the label of the shader followed by the symbols of the first permutation
+	/// that produced it, for example `material:FOG+SHADOWS`.
+	pub label: String,
+	/// Built WGSL source of the variant.
+	pub source: String,
+}
+
+impl Variant {
+	/// Returns a [`wgpu::ShaderModuleDescriptor`] of the variant.
+	pub fn descriptor(&self) -> wgpu::ShaderModuleDescriptor<'_> {
+		wgpu::ShaderModuleDescriptor {
+			label: Some(&self.label),
+			source: wgpu::ShaderSource::Wgsl(borrow::Cow::Borrowed(&self.source)),
 		}
-		definitions.iter().for_each(|(name, value)| {
-			module_string = module_string.replace(name, value);
-		});
-		Ok((module_string, definitions))
 	}
 }
 
-#[cfg(test)]
-mod tests {
-	use crate::{ShaderBuilder, WGSLType};
-	use std::{collections::HashMap, io};
+/// Token for cancelling builds from another thread.
+/// Clones of a token share its state, so cancelling one of them cancels builds using any of them.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(sync::Arc<sync::atomic::AtomicBool>);
 
-	#[test]
-	fn nonexistent() {
-		assert_eq!(
-			ShaderBuilder::new("test_shaders/nonexistent.wgsl")
-				.err()
-				.unwrap()
-				.kind(),
-			io::ErrorKind::NotFound
-		);
+impl CancellationToken {
+	/// Creates a new [`CancellationToken`] that is not cancelled.
+	pub fn new() -> Self {
+		Self::default()
 	}
 
-	#[test]
-	fn standard_include() {
-		assert_eq!(
-			ShaderBuilder::new("test_shaders/includer.wgsl")
-				.unwrap()
-				.source_string,
-			ShaderBuilder::new("test_shaders/included.wgsl")
-				.unwrap()
-				.source_string
-		);
+	/// Cancels all builds using this token.
+	/// Builds started after the cancellation fail immediately.
+	pub fn cancel(&self) {
+		self.0.store(true, sync::atomic::Ordering::Relaxed);
 	}
 
-	#[test]
-	fn missing_include() {
-		assert_eq!(
-			ShaderBuilder::new("test_shaders/missing_include.wgsl")
-				.err()
-				.unwrap()
-				.kind(),
-			io::ErrorKind::NotFound
-		);
+	/// Returns whether [`CancellationToken::cancel`] was called.
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(sync::atomic::Ordering::Relaxed)
 	}
+}
 
-	#[test]
-	fn nested_include() {
+/// Names that can change the source of a build, recorded to prune permutations,
+/// see [`ShaderBuilder::build_permutations`].
+#[derive(Clone, Debug, Default)]
+struct ObservedSymbols {
+	/// Names looked up in the definitions or substituted.
+	names: HashSet<String>,
+	/// Whether the build tested which modules were included, which may depend on any definition.
+	is_every_symbol: bool,
+}
+
+/// State of a single build.
+struct BuildState {
+	/// Definitions of the [`DefinitionLayer::Default`] layer.
+	default_definitions: HashMap<String, String>,
+	/// Definitions of the modules, the [`DefinitionLayer::File`] layer.
+	definitions: HashMap<String, String>,
+	/// Definitions of the [`DefinitionLayer::Builder`] layer, with the constant expressions resolved.
+	builder_definitions: HashMap<String, String>,
+	/// Definitions of the [`DefinitionLayer::Overlay`] layer.
+	overlay_definitions: HashMap<String, String>,
+	deadline: Option<time::Instant>,
+	placeholder_regex: Option<regex::Regex>,
+	stats: BuildStats,
+	errors: Vec<ex::io::Error>,
+	include_chain: Vec<SourceLocation>,
+	/// Size of the output rendered so far by the modules including the module being rendered.
+	enclosing_output_size: usize,
+	warnings: Vec<Warning>,
+	/// Location of the first definition of every macro defined in a module.
+	file_definitions: HashMap<String, SourceLocation>,
+	/// Location of the first conditional statement testing every symbol.
+	tested_symbols: HashMap<String, SourceLocation>,
+	/// Names observed by the build, if they are recorded, see [`ShaderBuilder::build_permutations`].
+	observed_symbols: Option<cell::RefCell<ObservedSymbols>>,
+	/// Warnings allowed by `allow` statements, by module.
+	file_allowed_warnings: HashSet<(path::PathBuf, WarningCode)>,
+	/// Symbols marked by `deprecated` statements, with a pattern matching their uses and declarations
+	/// (in the `declaration` group) and the message of the statement.
+	deprecations: HashMap<String, (regex::Regex, String)>,
+	/// Contents of the `preserve` regions, replaced by placeholders until the source is substituted.
+	preserved_regions: Vec<String>,
+	/// Kind and name of the declarations of `declare` statements, with the location of the statement.
+	forward_declarations: Vec<(String, String, SourceLocation)>,
+	/// Version of the modules named by `module` statements, with the location of the first statement.
+	module_versions: HashMap<String, (String, SourceLocation)>,
+	/// Name, comparison operator and version of the `requires` statements, with the location of the statement.
+	version_requirements: Vec<(String, String, String, SourceLocation)>,
+	/// Storage buffers of [`BuilderConfig::storage_accesses`] and patterns matching writes to them.
+	storage_patterns: Vec<(String, regex::Regex)>,
+	/// Storage buffers declared by [`ShaderBuilder::put_bindings`], with whether they are read-only,
+	/// and the location of the statement emitting them.
+	storage_declarations: HashMap<String, (bool, SourceLocation)>,
+	/// Storage buffers that are written by the modules.
+	written_storage: HashSet<String>,
+	/// Errors of the writes to storage buffers, reported once the build is done for the buffers declared as read-only,
+	/// since buffers can be written before the statement declaring them.
+	storage_write_errors: Vec<(String, ex::io::Error)>,
+	/// Directory that the content of every module is written to, with the number of modules written,
+	/// see [`ShaderBuilder::dump_intermediates`].
+	intermediates: Option<(path::PathBuf, usize)>,
+	/// Contents of the modules that were read, for the lines of diagnostics.
+	module_sources: HashMap<path::PathBuf, sync::Arc<[u8]>>,
+	/// Definitions substituted in the lines of the last loaded modules, by line index,
+	/// if [`ShaderBuilder::record_substitutions`] is enabled.
+	line_substitutions: BTreeMap<usize, Vec<Substitution>>,
+	/// Names of the definitions of `define_add` and `define_append` statements,
+	/// substituted once all modules are loaded instead of in every module.
+	accumulated_definitions: HashSet<String>,
+	/// Function-like macros defined by `define` statements, like `//!define SQUARE(x) ((x) * (x))`.
+	function_macros: HashMap<String, macros::FunctionMacro>,
+	/// Normalized paths of the modules with a `once` statement.
+	once_modules: HashSet<path::PathBuf>,
+	/// Normalized paths of the included modules, with the included section,
+	/// see [`ShaderBuilder::deduplicate_includes`].
+	included_modules: HashSet<(path::PathBuf, Option<String>)>,
+}
+
+/// State of the loading of a module, see [`ShaderBuilder::render_nodes`].
+struct ModuleRender<'a> {
+	module_path: &'a path::Path,
+	/// Section to include, or [`None`] to include the whole module.
+	section: Option<&'a str>,
+	/// Name and line number of the open `section` statement.
+	open_section: Option<(&'a str, usize)>,
+	is_section_found: bool,
+	/// Line numbers of the conditional statements without a matching `endif`.
+	unclosed_conditionals: Vec<usize>,
+	/// Line number of the `preserve` statement without a matching `endpreserve`.
+	unclosed_preserve: Option<usize>,
+	module_string: String,
+	/// Definitions substituted in the lines of the included modules, by line index in the module string.
+	line_substitutions: BTreeMap<usize, Vec<Substitution>>,
+}
+
+/// State of a conditional block while its branches are evaluated in order, see [`ShaderBuilder::render_nodes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConditionalState {
+	/// No branch was kept yet, so the condition of the next branch is evaluated.
+	NotTaken,
+	/// A branch was kept, so the following branches are skipped.
+	Taken,
+	/// The block is in a skipped branch, so every branch is skipped.
+	ParentInactive,
+}
+
+impl ShaderBuilder {
+	/// Creates a new [`ShaderBuilder`].
+	///
+	/// # Arguments
+	/// - `source_path` - Path to the root WGSL module.
+	///   Includes are relative to the directory of the module containing them, see [`ShaderBuilder::include_resolution`].
+	///   Code is generated recursively with attention to `include` and `define` statements when the shader is built.
+	///   See "Examples" for more details on include and macro functionality.
+	pub fn new(source_path: &str) -> Result<Self, ex::io::Error> {
+		Self::from_config(BuilderConfig {
+			source_path: source_path.to_string(),
+			..BuilderConfig::default()
+		})
+	}
+
+	/// Creates a new [`ShaderBuilder`] from a [`BuilderConfig`],
+	/// for example one that was returned by [`ShaderBuilder::config`] and stored.
+	pub fn from_config(config: BuilderConfig) -> Result<Self, ex::io::Error> {
+		ex::fs::metadata(&config.source_path)?;
+		Ok(Self::with_config(
+			config,
+			sync::Arc::new(FileSystemResolver),
+		))
+	}
+
+	/// Creates a new [`ShaderBuilder`] of a root module held in memory, for example generated code or an embedded asset.
+	/// It is built like a root module read from a file, and its includes are read from the file system.
+	///
+	/// # Arguments
+	/// - `name` - Name of the root module, used as its path in errors, labels and include resolution,
+	///   for example `generated.wgsl`.
+	/// - `source` - WGSL source of the root module.
+	pub fn from_source(name: &str, source: &str) -> Self {
+		Self::with_config(
+			BuilderConfig {
+				source_path: name.to_string(),
+				..BuilderConfig::default()
+			},
+			sync::Arc::new(source_resolver::RootSourceResolver {
+				path: path::PathBuf::from(name),
+				source: source.as_bytes().to_vec(),
+			}),
+		)
+	}
+
+	/// Creates a new [`ShaderBuilder`] reading the root module and every included module through a [`SourceResolver`]
+	/// instead of the file system, see [`MemoryResolver`].
+	///
+	/// # Arguments
+	/// - `source_path` - Path to the root WGSL module, as understood by `source_resolver`.
+	/// - `source_resolver` - Source of the modules.
+	pub fn with_source_resolver(
+		source_path: &str,
+		source_resolver: impl SourceResolver + 'static,
+	) -> Result<Self, ex::io::Error> {
+		if !source_resolver.exists(path::Path::new(source_path)) {
+			return Err(ex::io::Error::Filesystem(
+				path::PathBuf::from(source_path),
+				"resolving",
+				io::Error::new(io::ErrorKind::NotFound, "module not found"),
+			));
+		}
+		Ok(Self::with_config(
+			BuilderConfig {
+				source_path: source_path.to_string(),
+				..BuilderConfig::default()
+			},
+			sync::Arc::new(source_resolver),
+		))
+	}
+
+	fn with_config(config: BuilderConfig, source_resolver: sync::Arc<dyn SourceResolver>) -> Self {
+		Self {
+			source_string: String::new(),
+			config,
+			cancellation_token: None,
+			module_cache: None,
+			parsed_modules: None,
+			source_resolver,
+			libraries: Vec::new(),
+			label: String::new(),
+			build_stats: BuildStats::default(),
+			push_constant_ranges: Vec::new(),
+			build_errors: Vec::new(),
+			warnings: Vec::new(),
+			definition_provenance: HashMap::new(),
+			file_definitions: HashMap::new(),
+			overlay_definitions: HashMap::new(),
+			observed_symbols: None,
+			#[cfg(feature = "reflection")]
+			naga_passes: Vec::new(),
+		}
+	}
+
+	/// Returns the configuration of the builder.
+	pub fn config(&self) -> &BuilderConfig {
+		&self.config
+	}
+
+	/// Performs the WGSL's parallel to C's `#define` statement.
+	/// Boolean constants can also be tested by conditional statements: `//!ifdef {name}` is only true
+	/// if the value is `true`.
+	///
+	/// # Arguments
+	/// - `name` - Name of the constant; the token to replace in the code.
+	///   Identifiers that only contain the name, like `NORMAL` for `N`, are left untouched.
+	/// - `value` - Value of the constant.
+	#[track_caller]
+	pub fn put_constant<T: WGSLType>(&mut self, name: &str, value: T) -> &mut Self {
+		self.set_definition(name.to_string(), Some(self.string_definition(&value)));
+		self.config
+			.definition_types
+			.insert(name.to_string(), T::type_name());
+		self
+	}
+
+	/// Defines a constant as a WGSL expression of literals and other constants, for example
+	/// `put_constant_expr("TILE_COUNT", "(WIDTH + TILE_SIZE - 1u) / TILE_SIZE")`.
+	/// Constants of the expression are replaced by their values put from Rust code when the shader is built,
+	/// and the expression is emitted in parentheses, or folded to a literal if enabled with
+	/// [`ShaderBuilder::fold_constants`].
+	#[track_caller]
+	pub fn put_constant_expr(&mut self, name: &str, expression: &str) -> &mut Self {
+		self.set_definition(name.to_string(), None);
+		let caller = std::panic::Location::caller();
+		self.definition_provenance.insert(
+			name.to_string(),
+			Provenance::Rust(SourceLocation {
+				path: path::PathBuf::from(caller.file()),
+				line: caller.line() as usize,
+			}),
+		);
+		self.config
+			.constant_expressions
+			.insert(name.to_string(), expression.to_string());
+		self
+	}
+
+	/// Sets whether constants put with [`ShaderBuilder::put_constant_expr`] are folded to literals,
+	/// like `2048u` instead of `((1024u) * 2u)`, which keeps the output simple and avoids corner cases of
+	/// constant evaluation in WGSL. Expressions of `+`, `-`, `*`, `/`, `%`, parentheses and the conversions
+	/// `i32(...)`, `u32(...)` and `f32(...)` can be folded.
+	/// Builds fail if an expression can not be folded, divides by zero, or overflows its type.
+	pub fn fold_constants(&mut self, fold_constants: bool) -> &mut Self {
+		self.config.fold_constants = fold_constants;
+		self
+	}
+
+	/// Defines a `vec2` constant, like `vec2<f32>(0.5, 1.0)`, without the **array_vectors** or **cgmath_vectors** features.
+	#[track_caller]
+	pub fn put_vec2<T: WGSLType>(&mut self, name: &str, vector: [T; 2]) -> &mut Self {
+		self.put_vector(name, &vector);
+		self
+	}
+
+	/// Defines a `vec3` constant, like `vec3<f32>(0.2, -1.0, 0.3)`, see [`ShaderBuilder::put_vec2`].
+	#[track_caller]
+	pub fn put_vec3<T: WGSLType>(&mut self, name: &str, vector: [T; 3]) -> &mut Self {
+		self.put_vector(name, &vector);
+		self
+	}
+
+	/// Defines a `vec4` constant, like `vec4<f32>(1.0, 0.5, 0.0, 1.0)`, see [`ShaderBuilder::put_vec2`].
+	#[track_caller]
+	pub fn put_vec4<T: WGSLType>(&mut self, name: &str, vector: [T; 4]) -> &mut Self {
+		self.put_vector(name, &vector);
+		self
+	}
+
+	/// Defines a color as a `vec4<f32>`, converted to the color space of the shader
+	/// (see [`ShaderBuilder::color_space`]), so colors can be given as they appear in a color picker:
+	/// ```
+	/// # use wgsl_preprocessor::{Color, ShaderBuilder};
+	/// let mut shader_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+	/// shader_builder.put_color("FOG_COLOR", Color::Srgb8([255, 255, 255, 128]));
+	/// assert_eq!(
+	/// 	shader_builder.config().definitions["FOG_COLOR"],
+	/// 	"vec4<f32>(1.0, 1.0, 1.0, 0.5019608)"
+	/// );
+	/// ```
+	#[track_caller]
+	pub fn put_color(&mut self, name: &str, color: Color) -> &mut Self {
+		let string_definition = format!(
+			"vec4<f32>({})",
+			color
+				.components(self.config.color_space)
+				.iter()
+				.map(|component| self.string_definition(component))
+				.collect::<Vec<String>>()
+				.join(", ")
+		);
+		self.set_definition(name.to_string(), Some(string_definition));
+		self.config
+			.definition_types
+			.insert(name.to_string(), "vec4<f32>".to_string());
+		self.config
+			.color_constants
+			.retain(|(color_name, _)| color_name != name);
+		self.config
+			.color_constants
+			.push((name.to_string(), self.config.color_space));
+		self
+	}
+
+	/// Like [`ShaderBuilder::put_constant`], but in the [`DefinitionLayer::Default`] layer,
+	/// so the constant can be overridden by a `define` statement in a module.
+	/// Useful for libraries of shaders providing fallback values.
+	#[track_caller]
+	pub fn put_default_constant(&mut self, name: &str, value: impl WGSLType) -> &mut Self {
+		self.set_default_definition(name.to_string(), self.string_definition(&value));
+		self
+	}
+
+	/// Calls [`ShaderBuilder::put_constant`] for every (key, value) pair in a given [`HashMap`].
+	#[track_caller]
+	pub fn put_constant_map(
+		&mut self,
+		constant_map: &HashMap<&str, impl WGSLType + Copy>,
+	) -> &mut Self {
+		for (name, &value) in constant_map.iter() {
+			self.put_constant(name, value);
+		}
+		self
+	}
+
+	/// Defines a constant array of elements.
+	/// If `array` is empty, no array is declared and `{name}_EMPTY` is defined instead,
+	/// so usages of the array can be guarded with `//!ifndef {name}_EMPTY`.
+	/// The array is initialized with a constant expression, so it can not contain NaN or infinite floats,
+	/// see the [`WGSLType`] implementation of `f32`.
+	///
+	/// # Arguments
+	/// - `name` - Name of the array in the WGSL source.
+	/// - `array` - Vector of [`WGSLType`] whose elements will be the elements in the array.
+	#[track_caller]
+	pub fn put_array_definition<'a, T: 'a + WGSLType>(
+		&'a mut self,
+		name: &str,
+		array: &Vec<&T>,
+	) -> &'a mut Self {
+		let string_definition = Self::array_string_definition(
+			name,
+			&T::type_name(),
+			array
+				.iter()
+				.map(|value| self.string_definition(*value))
+				.collect(),
+		);
+		self.insert_array_definition(name, array.is_empty(), string_definition);
+		self
+	}
+
+	/// Defines a pseudo-random `u32` constant generated from `seed`, for example the seed of a noise function.
+	/// The value only depends on the seed, so it is reproducible across builds, runs and platforms.
+	#[track_caller]
+	pub fn put_seeded_random(&mut self, name: &str, seed: u64) -> &mut Self {
+		let value = (SplitMix64(seed).next() >> 32) as u32;
+		self.put_constant(name, value)
+	}
+
+	/// Defines a constant array of `length` pseudo-random `f32`s in `[0, 1)` generated from `seed`,
+	/// for example a noise table, like [`ShaderBuilder::put_array_definition`].
+	/// The values only depend on the seed, so they are reproducible across builds, runs and platforms.
+	#[track_caller]
+	pub fn put_seeded_array(&mut self, name: &str, seed: u64, length: usize) -> &mut Self {
+		let mut random = SplitMix64(seed);
+		let values: Vec<f32> = (0..length)
+			.map(|_| (random.next() >> 40) as f32 / (1u32 << 24) as f32)
+			.collect();
+		self.put_array_definition(name, &values.iter().collect())
+	}
+
+	/// Defines a constant array for every field of a struct (struct-of-arrays).
+	/// The array of each field is named `{name}_{FIELD}`, where `FIELD` is the field name in upper case.
+	/// Empty slices are handled like in [`ShaderBuilder::put_array_definition`].
+	///
+	/// # Arguments
+	/// - `name` - Prefix of the arrays' names in the WGSL source.
+	/// - `array` - Slice of [`WGSLStruct`] whose fields will be the elements in the arrays.
+	///
+	/// # Panics
+	/// Panics if [`WGSLStruct::field_definitions`] and [`WGSLStruct::field_types`] return different numbers of fields.
+	#[track_caller]
+	pub fn put_soa_definition<T: WGSLStruct>(&mut self, name: &str, array: &[&T]) -> &mut Self {
+		let field_types = T::field_types();
+		let field_definitions: Vec<Vec<String>> = array
+			.iter()
+			.map(|value| {
+				let field_definitions = value.field_definitions();
+				assert_eq!(
+					field_definitions.len(),
+					field_types.len(),
+					"`{}` defines a different number of fields than it has field types",
+					short_type_name::<T>()
+				);
+				field_definitions
+					.into_iter()
+					.map(|field_definition| self.literal(field_definition))
+					.collect()
+			})
+			.collect();
+		let string_definition = field_types
+			.iter()
+			.enumerate()
+			.map(|(index, (field_name, type_name))| {
+				Self::array_string_definition(
+					&format!("{name}_{}", field_name.to_uppercase()),
+					type_name,
+					field_definitions
+						.iter()
+						.map(|fields| fields[index].clone())
+						.collect(),
+				)
+			})
+			.collect::<Vec<String>>()
+			.join("\n");
+		self.insert_array_definition(name, array.is_empty(), string_definition);
+		self
+	}
+
+	/// Declares a push constant block of type `T`, emitted at the `//!define {name}` statement like arrays
+	/// (see [`ShaderBuilder::put_array_definition`]):
+	/// a struct named like `T` with the fields of [`WGSLStruct::field_types`], and `var<push_constant> {name}: T;`.
+	/// The range of the block is recorded for [`ShaderBuilder::push_constant_ranges`].
+	///
+	/// # Arguments
+	/// - `name` - Name of the push constant variable in the WGSL source.
+	/// - `offset` - Offset of the block in bytes. Fields before the offset are declared as padding.
+	/// - `stages` - Shader stages that use the block.
+	///
+	/// # Panics
+	/// Panics if the WGSL layout of the fields does not match the size of `T`, if a field type is not
+	/// host-shareable, or if `offset` is not aligned to the alignment of the struct.
+	#[track_caller]
+	pub fn put_push_constants<T: WGSLStruct>(
+		&mut self,
+		name: &str,
+		offset: u32,
+		stages: wgpu::ShaderStages,
+	) -> &mut Self {
+		let type_name = short_type_name::<T>();
+		let StructLayout { size, align, .. } = struct_layout::<T>(type_name);
+		assert_eq!(
+			offset % align,
+			0,
+			"push constant offset {offset} is not aligned to the alignment of `{type_name}` ({align})"
+		);
+		let mut string_definition = format!("struct {type_name} {{\n");
+		if offset > 0 {
+			string_definition.push_str(&format!("\t_offset: array<u32, {}>,\n", offset / 4));
+		}
+		for (field_name, field_type) in T::field_types().iter() {
+			string_definition.push_str(&format!("\t{field_name}: {field_type},\n"));
+		}
+		string_definition.push_str(&format!("}}\nvar<push_constant> {name}: {type_name};"));
+		self.insert_array_definition(name, false, string_definition);
+		self.push_constant_ranges.push(wgpu::PushConstantRange {
+			stages,
+			range: offset..offset + size,
+		});
+		self
+	}
+
+	/// Returns the ranges of the push constant blocks declared with [`ShaderBuilder::put_push_constants`],
+	/// for building a [`wgpu::PipelineLayoutDescriptor`].
+	pub fn push_constant_ranges(&self) -> &[wgpu::PushConstantRange] {
+		&self.push_constant_ranges
+	}
+
+	/// Declares the bindings of a [`Bindings`] and the structs they use, emitted at the `//!define {name}` statement
+	/// like arrays (see [`ShaderBuilder::put_array_definition`]).
+	/// The matching layout entries are returned by [`Bindings::layout_entries`].
+	#[track_caller]
+	pub fn put_bindings(&mut self, name: &str, bindings: &Bindings) -> &mut Self {
+		self.insert_array_definition(name, false, bindings.declarations());
+		self.config
+			.storage_accesses
+			.insert(name.to_string(), bindings.storage_accesses());
+		self
+	}
+
+	/// Declares the byte layout of `T` as WGSL constants, emitted at the `//!define {name}` statement
+	/// like arrays (see [`ShaderBuilder::put_array_definition`]):
+	/// `const {name}_SIZE: u32` with the size of `T`, and `const {name}_{FIELD}_OFFSET: u32` with the offset
+	/// of every field, where `FIELD` is the field name in upper case.
+	/// Useful for shaders addressing buffers of `T` by bytes, for example through `array<u32>`.
+	///
+	/// # Panics
+	/// Panics if the WGSL layout of the fields does not match the size of `T`,
+	/// or if a field type is not host-shareable.
+	#[track_caller]
+	pub fn put_layout_constants<T: WGSLStruct>(&mut self, name: &str) -> &mut Self {
+		let layout = struct_layout::<T>(short_type_name::<T>());
+		let mut string_definition = format!("const {name}_SIZE: u32 = {}u;", layout.size);
+		for ((field_name, _), offset) in T::field_types().iter().zip(layout.offsets) {
+			string_definition.push_str(&format!(
+				"\nconst {name}_{}_OFFSET: u32 = {offset}u;",
+				field_name.to_uppercase()
+			));
+		}
+		self.insert_array_definition(name, false, string_definition);
+		self
+	}
+
+	/// Defines constants and symbols describing a texture format chosen at runtime, for example the surface format:
+	/// - `{name}_COMPONENTS` - Number of components of the format, as a `u32`.
+	/// - `{name}_SRGB` - Symbol defined if the format is sRGB.
+	/// - `{name}_DEPTH` and `{name}_STENCIL` - Symbols defined if the format has a depth or stencil aspect.
+	/// - `{name}_STORAGE` - WGSL texel format (for example `rgba8unorm`), defined if the format can be used
+	///   for storage textures, so annotations like `texture_storage_2d<{name}_STORAGE, write>` follow the format.
+	///
+	/// # Arguments
+	/// - `name` - Prefix of the names of the definitions.
+	/// - `format` - The texture format.
+	#[track_caller]
+	pub fn put_texture_format(&mut self, name: &str, format: wgpu::TextureFormat) -> &mut Self {
+		let storage_format = match format {
+			wgpu::TextureFormat::Rgba8Unorm
+			| wgpu::TextureFormat::Rgba8Snorm
+			| wgpu::TextureFormat::Rgba8Uint
+			| wgpu::TextureFormat::Rgba8Sint
+			| wgpu::TextureFormat::Rgba16Uint
+			| wgpu::TextureFormat::Rgba16Sint
+			| wgpu::TextureFormat::Rgba16Float
+			| wgpu::TextureFormat::R32Uint
+			| wgpu::TextureFormat::R32Sint
+			| wgpu::TextureFormat::R32Float
+			| wgpu::TextureFormat::Rg32Uint
+			| wgpu::TextureFormat::Rg32Sint
+			| wgpu::TextureFormat::Rg32Float
+			| wgpu::TextureFormat::Rgba32Uint
+			| wgpu::TextureFormat::Rgba32Sint
+			| wgpu::TextureFormat::Rgba32Float
+			| wgpu::TextureFormat::Bgra8Unorm => Some(format!("{format:?}").to_lowercase()),
+			_ => None,
+		};
+		self.put_constant(&format!("{name}_COMPONENTS"), format.components() as u32);
+		for (suffix, value) in [
+			("SRGB", format.is_srgb().then(String::new)),
+			("DEPTH", format.has_depth_aspect().then(String::new)),
+			("STENCIL", format.has_stencil_aspect().then(String::new)),
+			("STORAGE", storage_format),
+		] {
+			let definition_name = format!("{name}_{suffix}");
+			self.set_definition(definition_name, value);
+		}
+		self
+	}
+
+	/// Defines constants and symbols describing a surface configuration, so output shaders can specialize
+	/// for the swapchain:
+	/// - `SURFACE_WIDTH` and `SURFACE_HEIGHT` - Size of the surface, as `u32`s.
+	/// - `SURFACE_SRGB` - Symbol defined if the surface format is sRGB.
+	/// - `SURFACE_FORMAT_*` - Definitions of the surface format, see [`ShaderBuilder::put_texture_format`].
+	#[track_caller]
+	pub fn define_from_surface(
+		&mut self,
+		surface_configuration: &wgpu::SurfaceConfiguration,
+	) -> &mut Self {
+		self.put_constant("SURFACE_WIDTH", surface_configuration.width)
+			.put_constant("SURFACE_HEIGHT", surface_configuration.height)
+			.put_texture_format("SURFACE_FORMAT", surface_configuration.format);
+		self.set_definition(
+			"SURFACE_SRGB".to_string(),
+			surface_configuration.format.is_srgb().then(String::new),
+		);
+		self
+	}
+
+	/// Defines the standard set of symbols, constants and attributes of a [`Preset`],
+	/// so entry points can be written once for a technique:
+	/// ```wgsl
+	/// @vertex
+	/// fn main(VIEW_INDEX, @location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+	/// 	return cameras[view_index].view_projection * vec4<f32>(position, 1.0);
+	/// }
+	/// ```
+	#[track_caller]
+	pub fn preset(&mut self, preset: Preset) -> &mut Self {
+		let (symbol, count, parameter) = match preset {
+			Preset::Multiview { views } => (
+				"MULTIVIEW",
+				("VIEW_COUNT", views),
+				("VIEW_INDEX", "@builtin(view_index) view_index: i32"),
+			),
+			Preset::Instanced { max_instances } => (
+				"INSTANCED",
+				("MAX_INSTANCES", max_instances),
+				(
+					"INSTANCE_INDEX",
+					"@builtin(instance_index) instance_index: u32",
+				),
+			),
+		};
+		self.set_default_definition(symbol.to_string(), String::new());
+		self.put_default_constant(count.0, count.1);
+		self.set_default_definition(parameter.0.to_string(), parameter.1.to_string());
+		self
+	}
+
+	/// Limits the time a single build may take.
+	/// Builds exceeding the limit fail with [`io::ErrorKind::TimedOut`].
+	pub fn time_limit(&mut self, time_limit: time::Duration) -> &mut Self {
+		self.config.time_limit = Some(time_limit);
+		self
+	}
+
+	/// Limits the size of a single module in bytes, so including a large file by mistake,
+	/// like `//!include model.obj`, fails before the file is read.
+	/// Builds including a larger module fail with [`io::ErrorKind::InvalidData`].
+	pub fn max_module_size(&mut self, max_module_size: u64) -> &mut Self {
+		self.config.max_module_size = Some(max_module_size);
+		self
+	}
+
+	/// Limits the size of the built source in bytes, checked while modules are expanded and after every module is processed,
+	/// so builds fail before a runaway include tree produces the whole output.
+	/// Builds exceeding the limit fail with [`io::ErrorKind::InvalidData`].
+	pub fn max_output_size(&mut self, max_output_size: usize) -> &mut Self {
+		self.config.max_output_size = Some(max_output_size);
+		self
+	}
+
+	/// Limits the size of the expansion of a single macro in bytes,
+	/// so macros expanding exponentially, like `//!define QUAD(x) DOUBLE(DOUBLE(x))`, fail before they exhaust memory.
+	/// The values of macros using other macros are limited too.
+	/// Builds with a larger expansion fail with [`io::ErrorKind::InvalidData`].
+	/// The expansions of every macro are counted in [`BuildStats::macro_expansions`].
+	pub fn max_expansion_size(&mut self, max_expansion_size: usize) -> &mut Self {
+		self.config.max_expansion_size = Some(max_expansion_size);
+		self
+	}
+
+	/// Sets where [`ShaderBuilder::build_output`] puts the built source, [`OutputTarget::String`] by default.
+	/// The source can be written to a file, or to a file mapped in memory with the **mmap** feature,
+	/// instead of being kept in the builder.
+	/// The whole source is still built in memory first, since it is processed as a whole once every module is loaded,
+	/// so targets only change where the source ends up, not the memory used by the build.
+	pub fn output_target(&mut self, output_target: OutputTarget) -> &mut Self {
+		self.config.output_target = output_target;
+		self
+	}
+
+	/// Sets a [`CancellationToken`] that aborts builds when cancelled.
+	/// Cancelled builds fail with [`io::ErrorKind::Interrupted`].
+	pub fn cancellation_token(&mut self, cancellation_token: CancellationToken) -> &mut Self {
+		self.cancellation_token = Some(cancellation_token);
+		self
+	}
+
+	/// Sets a [`ModuleCache`] that modules are read through.
+	/// The same cache can be shared by many builders, so common modules are only read once.
+	pub fn module_cache(&mut self, module_cache: sync::Arc<ModuleCache>) -> &mut Self {
+		self.module_cache = Some(module_cache);
+		self
+	}
+
+	/// Links a library built with [`ShaderBuilder::build_library`] into the shader:
+	/// ```
+	/// # use std::sync::Arc;
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// let library = ShaderBuilder::new("test_shaders/link_library.wgsl")
+	/// 	.unwrap()
+	/// 	.put_constant("SCALE", 2.0f32)
+	/// 	.build_library()
+	/// 	.unwrap();
+	/// let library = Arc::new(library);
+	/// let source = ShaderBuilder::new("test_shaders/declare_lighting.wgsl")
+	/// 	.unwrap()
+	/// 	.link_library(library.clone())
+	/// 	.build_source()
+	/// 	.unwrap()
+	/// 	.to_string();
+	/// assert!(source.starts_with(library.source()));
+	/// ```
+	/// The source of linked libraries is placed before the source of the shader, in the order they were linked,
+	/// and their declarations satisfy `declare` statements. Definitions of the shader are not substituted in libraries.
+	/// Builds fail if a name is declared by several libraries, or by a library and a module of the shader.
+	pub fn link_library(&mut self, library: sync::Arc<ShaderLibrary>) -> &mut Self {
+		self.libraries.push(library);
+		self
+	}
+
+	/// Builds the WGSL source of the shader.
+	/// The result is also stored in [`ShaderBuilder::source_string`].
+	///
+	/// Fails if a module can not be read, if conditional statements are unbalanced, if a definition
+	/// used as the size of an array (for example `array<f32, NUM_SAMPLES>`) is not a positive integer,
+	/// or if the build is cancelled or exceeds its time limit.
+	/// By default the build stops at the first error, see [`ShaderBuilder::error_recovery`] to find all of them.
+	/// Errors caused by a statement are [`PreprocessError::Statement`]s, with the module and the line of the statement,
+	/// the name of the statement and the text of the line.
+	pub fn build_source(&mut self) -> Result<&str, PreprocessError> {
+		self.build_source_with_overlay(&HashMap::new())
+	}
+
+	/// Like [`ShaderBuilder::build_source`], with definitions that only apply to this build,
+	/// for example to specialize a shader for a single draw call.
+	/// Definitions of the overlay take precedence over all the others, see [`DefinitionLayer`].
+	///
+	/// # Arguments
+	/// - `overlay` - Definitions of the build, as WGSL strings.
+	pub fn build_source_with_overlay(
+		&mut self,
+		overlay: &HashMap<String, String>,
+	) -> Result<&str, PreprocessError> {
+		Ok(self.build_source_dumping(overlay, None)?)
+	}
+
+	/// Builds the WGSL source of the shader like [`ShaderBuilder::build_source`], and writes the content of every module
+	/// after its definitions are substituted and the final source to a directory, so the module that introduced a problem can be found.
+	/// Modules are written as they are loaded, included modules before the modules including them,
+	/// to files named with their loading order, like `000-included.wgsl`, and the final source is written to `final.wgsl`.
+	/// If the build fails, the modules loaded before the error are still written.
+	///
+	/// # Arguments
+	/// - `directory` - Directory of the files, created if it does not exist.
+	pub fn dump_intermediates(
+		&mut self,
+		directory: impl AsRef<path::Path>,
+	) -> Result<(), ex::io::Error> {
+		let directory = directory.as_ref();
+		ex::fs::create_dir_all(directory)?;
+		let source_string =
+			self.build_source_dumping(&HashMap::new(), Some(directory.to_path_buf()))?;
+		ex::fs::write(directory.join("final.wgsl"), source_string)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(skip(self, overlay, intermediates), fields(source_path = %self.config.source_path))
+	)]
+	fn build_source_dumping(
+		&mut self,
+		overlay: &HashMap<String, String>,
+		intermediates: Option<path::PathBuf>,
+	) -> Result<&str, ex::io::Error> {
+		#[cfg(feature = "tracing")]
+		let started = time::Instant::now();
+		let source_path = path::Path::new(&self.config.source_path);
+		if self.config.deterministic && self.config.target_symbols {
+			return Err(preprocess_error_with_kind(
+				source_path,
+				io::ErrorKind::InvalidInput,
+				"target symbols depend on the platform and are not allowed in deterministic mode"
+					.to_string(),
+			));
+		}
+		let mut state = BuildState {
+			default_definitions: self.default_definitions(),
+			definitions: HashMap::new(),
+			builder_definitions: self.config.definitions.clone(),
+			overlay_definitions: overlay.clone(),
+			deadline: self
+				.config
+				.time_limit
+				.map(|time_limit| time::Instant::now() + time_limit),
+			placeholder_regex: match &self.config.substitution_scope {
+				SubstitutionScope::WholeFile => None,
+				SubstitutionScope::Placeholders(pattern) => {
+					Some(regex::Regex::new(pattern).map_err(|error| {
+						preprocess_error_with_kind(
+							source_path,
+							io::ErrorKind::InvalidInput,
+							format!("invalid placeholder pattern: {error}"),
+						)
+					})?)
+				}
+			},
+			stats: BuildStats {
+				definition_provenance: self.definition_provenance.clone(),
+				..BuildStats::default()
+			},
+			errors: Vec::new(),
+			include_chain: Vec::new(),
+			enclosing_output_size: 0,
+			warnings: Vec::new(),
+			file_definitions: HashMap::new(),
+			tested_symbols: HashMap::new(),
+			observed_symbols: self
+				.observed_symbols
+				.as_ref()
+				.map(|_| cell::RefCell::default()),
+			file_allowed_warnings: HashSet::new(),
+			deprecations: HashMap::new(),
+			preserved_regions: Vec::new(),
+			forward_declarations: Vec::new(),
+			module_versions: HashMap::new(),
+			version_requirements: Vec::new(),
+			storage_patterns: self
+				.config
+				.storage_accesses
+				.values()
+				.flatten()
+				.map(|(variable, _)| variable)
+				.collect::<HashSet<&String>>()
+				.into_iter()
+				.map(|variable| (variable.clone(), storage_write_regex(variable)))
+				.collect(),
+			storage_declarations: HashMap::new(),
+			written_storage: HashSet::new(),
+			storage_write_errors: Vec::new(),
+			intermediates: intermediates.map(|directory| (directory, 0)),
+			module_sources: HashMap::new(),
+			line_substitutions: BTreeMap::new(),
+			accumulated_definitions: HashSet::new(),
+			function_macros: HashMap::new(),
+			once_modules: HashSet::new(),
+			included_modules: HashSet::new(),
+		};
+		self.build_errors.clear();
+		self.warnings.clear();
+		self.resolve_constant_expressions(source_path, &mut state)?;
+		let source_string = self.load_shader_module(source_path, None, &mut state)?;
+		// Definitions of the modules were substituted in every module unless shadowed by a higher layer,
+		// except accumulated definitions, which are substituted with their final values.
+		let mut definitions: HashMap<String, String> = state
+			.default_definitions
+			.iter()
+			.filter(|(name, _)| !state.definitions.contains_key(*name))
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect();
+		definitions.extend(
+			state
+				.accumulated_definitions
+				.iter()
+				.map(|name| (name.clone(), state.definitions[name].clone())),
+		);
+		definitions.extend(state.builder_definitions.clone());
+		definitions.extend(state.overlay_definitions.clone());
+		for error in Self::array_size_errors(source_path, &source_string, &definitions) {
+			self.recover(error, &mut state)?;
+		}
+		for (variable, error) in mem::take(&mut state.storage_write_errors) {
+			if state
+				.storage_declarations
+				.get(&variable)
+				.is_some_and(|(read_only, _)| *read_only)
+			{
+				self.recover(error, &mut state)?;
+			}
+		}
+		self.warnings = self.report_warnings(&mut state)?;
+		let source_string =
+			self.substitute(source_path, source_string, &definitions, &mut state)?;
+		for (kind, name, location) in mem::take(&mut state.forward_declarations) {
+			let declaration_regex =
+				regex::Regex::new(&format!(r"\b{kind}\b(?:\s*<[^>]*>)?\s+{name}\b")).unwrap();
+			let is_linked = self.libraries.iter().any(|library| {
+				library
+					.declarations
+					.iter()
+					.any(|declaration| *declaration == (kind.clone(), name.clone()))
+			});
+			if !is_linked && !declaration_regex.is_match(&source_string) {
+				let error = located_error(
+					&location.path,
+					location.line,
+					format!("`{kind} {name}` is declared but no module of the build defines it"),
+					&state,
+				);
+				self.recover(error, &mut state)?;
+			}
+		}
+		for (name, operator, version, location) in mem::take(&mut state.version_requirements) {
+			let message = match state.module_versions.get(&name) {
+				None => Some(format!(
+					"`{name} {operator} {version}` is required but no module of the build is named `{name}`"
+				)),
+				Some((module_version, module_location)) => {
+					let is_compatible = match (
+						version_components(module_version),
+						version_components(&version),
+					) {
+						(Some(module_version), Some(version)) => match operator.as_str() {
+							">=" => module_version >= version,
+							">" => module_version > version,
+							"<=" => module_version <= version,
+							"<" => module_version < version,
+							_ => module_version == version,
+						},
+						_ => false,
+					};
+					(!is_compatible).then(|| {
+						format!(
+							"`{name} {operator} {version}` is required but `{name}` is version {module_version} at {module_location}"
+						)
+					})
+				}
+			};
+			if let Some(message) = message {
+				let error = located_error(&location.path, location.line, message, &state);
+				self.recover(error, &mut state)?;
+			}
+		}
+		for reserved_declaration in reserved::reserved_declarations(&source_string) {
+			let error = self.reserved_declaration_error(source_path, reserved_declaration, &state);
+			self.recover(error, &mut state)?;
+		}
+		let mut line_substitutions = mem::take(&mut state.line_substitutions);
+		if self.config.record_substitutions {
+			// Preserved regions replace single placeholder lines.
+			let mut line_index = 0;
+			let line_indices: Vec<usize> = source_string
+				.lines()
+				.map(|line| {
+					let index = line_index;
+					line_index += line
+						.strip_prefix(PRESERVED_REGION_DELIMITER)
+						.and_then(|line| line.strip_suffix(PRESERVED_REGION_DELIMITER))
+						.and_then(|index| state.preserved_regions.get(index.parse::<usize>().ok()?))
+						.map_or(1, |region| region.lines().count());
+					index
+				})
+				.collect();
+			line_substitutions = remapped_lines(line_substitutions, &line_indices);
+		}
+		let mut source_string =
+			Self::restore_preserved_regions(source_string, &state.preserved_regions);
+		if !self.libraries.is_empty() {
+			for error in self.library_conflicts(source_path, &source_string) {
+				self.recover(error, &mut state)?;
+			}
+			let library_sources: String = self
+				.libraries
+				.iter()
+				.map(|library| {
+					let mut source = library.source.clone();
+					if !source.is_empty() && !source.ends_with('\n') {
+						source.push('\n');
+					}
+					source
+				})
+				.collect();
+			let library_lines = library_sources.lines().count();
+			line_substitutions = line_substitutions
+				.into_iter()
+				.map(|(index, substitutions)| (index + library_lines, substitutions))
+				.collect();
+			source_string = library_sources + &source_string;
+		}
+		if self.config.order_declarations {
+			let line_indices;
+			(source_string, line_indices) = ordering::order_declarations(&source_string);
+			line_substitutions = remapped_lines(line_substitutions, &line_indices);
+		}
+		self.check_output_size(source_path, source_string.len())?;
+		if !state.errors.is_empty() {
+			let message = format!(
+				"{} errors:\n{}",
+				state.errors.len(),
+				state
+					.errors
+					.iter()
+					.map(ToString::to_string)
+					.collect::<Vec<String>>()
+					.join("\n")
+			);
+			self.build_errors = state.errors;
+			return Err(preprocess_error(source_path, message));
+		}
+		#[cfg(feature = "tracing")]
+		tracing::debug!(
+			elapsed = ?started.elapsed(),
+			bytes = source_string.len(),
+			"built shader"
+		);
+		state.stats.bytes_emitted = source_string.len();
+		state.stats.line_substitutions = line_substitutions
+			.into_iter()
+			.map(|(index, substitutions)| (index + 1, substitutions))
+			.collect();
+		self.build_stats = state.stats;
+		self.file_definitions = state.definitions;
+		self.overlay_definitions = state.overlay_definitions;
+		if let Some(observed_symbols) = state.observed_symbols {
+			self.observed_symbols = Some(observed_symbols.into_inner());
+		}
+		self.source_string = source_string;
+		Ok(&self.source_string)
+	}
+
+	/// Returns the value of a definition and the layer it comes from, taking the highest layer
+	/// if it is defined in several of them:
+	/// [`DefinitionLayer::Default`] < [`DefinitionLayer::File`] < [`DefinitionLayer::Builder`] < [`DefinitionLayer::Overlay`].
+	/// Definitions of modules and overlays are those of the last successful build.
+	pub fn definition(&self, name: &str) -> Option<(&str, DefinitionLayer)> {
+		let is_target_symbol =
+			self.config.target_symbols && Self::target_symbol_definitions().contains_key(name);
+		layered_definition(
+			name,
+			[
+				(DefinitionLayer::Overlay, &self.overlay_definitions),
+				(DefinitionLayer::Builder, &self.config.definitions),
+				(DefinitionLayer::Builder, &self.config.constant_expressions),
+				(DefinitionLayer::File, &self.file_definitions),
+				(DefinitionLayer::Default, &self.config.default_definitions),
+			],
+		)
+		.or_else(|| is_target_symbol.then_some(("", DefinitionLayer::Default)))
+	}
+
+	/// Returns a human-readable summary of the configuration of the builder, for logging or bug reports:
+	/// the root module, the remapped include prefixes, the include paths, every definition with its value, layer and provenance,
+	/// the arrays, and the options.
+	/// Definitions of modules and overlays are those of the last successful build.
+	pub fn describe(&self) -> String {
+		let config = &self.config;
+		let mut description = format!("root: {}\n", config.source_path);
+		if !config.include_prefixes.is_empty() {
+			description.push_str("include prefixes:\n");
+			for (prefix, replacement) in config.include_prefixes.iter() {
+				description.push_str(&format!("\t{prefix} -> {replacement}\n"));
+			}
+		}
+		if !config.include_paths.is_empty() {
+			description.push_str("include paths:\n");
+			for include_path in config.include_paths.iter() {
+				description.push_str(&format!("\t{}\n", include_path.display()));
+			}
+		}
+		if !config.module_aliases.is_empty() {
+			description.push_str("module aliases:\n");
+			for (include, target) in config.module_aliases.iter() {
+				description.push_str(&format!("\t{include} -> {target}\n"));
+			}
+		}
+		let mut names: Vec<&String> = config
+			.definitions
+			.keys()
+			.chain(config.constant_expressions.keys())
+			.chain(config.default_definitions.keys())
+			.chain(self.file_definitions.keys())
+			.chain(self.overlay_definitions.keys())
+			.collect();
+		names.sort();
+		names.dedup();
+		if !names.is_empty() {
+			description.push_str("definitions:\n");
+		}
+		for name in names {
+			let Some((value, layer)) = self.definition(name) else {
+				continue;
+			};
+			description.push_str(&format!("\t{name} = `{value}` ({layer:?}"));
+			if let Some(provenance) = self
+				.build_stats
+				.definition_provenance
+				.get(name)
+				.or_else(|| self.definition_provenance.get(name))
+			{
+				description.push_str(&format!(", {provenance}"));
+			}
+			description.push_str(")\n");
+		}
+		if !self.libraries.is_empty() {
+			description.push_str(&format!(
+				"libraries: {}\n",
+				self.libraries
+					.iter()
+					.map(|library| library.name.as_str())
+					.collect::<Vec<&str>>()
+					.join(", ")
+			));
+		}
+		let mut arrays: Vec<&String> = config.array_definitions.keys().collect();
+		arrays.sort();
+		if !arrays.is_empty() {
+			description.push_str(&format!(
+				"arrays: {}\n",
+				arrays
+					.iter()
+					.map(|name| name.as_str())
+					.collect::<Vec<&str>>()
+					.join(", ")
+			));
+		}
+		description.push_str("options:\n");
+		let options: [(&str, &dyn fmt::Debug); 28] = [
+			("fold_constants", &config.fold_constants),
+			("time_limit", &config.time_limit),
+			("label_hash", &config.label_hash),
+			("deterministic", &config.deterministic),
+			("target_symbols", &config.target_symbols),
+			("substitution_scope", &config.substitution_scope),
+			("separate_symbols", &config.separate_symbols),
+			("error_recovery", &config.error_recovery),
+			("hlsl_compatibility", &config.hlsl_compatibility),
+			("templates", &config.templates),
+			("undefined_symbol_lint", &config.undefined_symbol_lint),
+			("allowed_warnings", &config.allowed_warnings),
+			("denied_warnings", &config.denied_warnings),
+			("include_resolution", &config.include_resolution),
+			("order_declarations", &config.order_declarations),
+			("record_substitutions", &config.record_substitutions),
+			("deduplicate_includes", &config.deduplicate_includes),
+			("debug_markers", &config.debug_markers),
+			("normalize_paths", &config.normalize_paths),
+			("invalid_utf8", &config.invalid_utf8),
+			("max_module_size", &config.max_module_size),
+			("max_output_size", &config.max_output_size),
+			("max_expansion_size", &config.max_expansion_size),
+			("output_target", &config.output_target),
+			("literal_suffixes", &config.literal_suffixes),
+			("units", &config.units),
+			("color_space", &config.color_space),
+			("module_cache", &self.module_cache.is_some()),
+		];
+		for (name, value) in options {
+			description.push_str(&format!("\t{name}: {value:?}\n"));
+		}
+		description
+	}
+
+	/// Returns the warnings of the last build, without the allowed ones.
+	pub fn warnings(&self) -> &[Warning] {
+		&self.warnings
+	}
+
+	/// Replaces a prefix of include paths, so modules can include others by stable logical paths
+	/// independent of where the shaders are on a given machine:
+	/// ```no_run
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// ShaderBuilder::new("assets/shaders/main.wgsl")
+	/// 	.unwrap()
+	/// 	.remap_include_prefix("engine:/", "/opt/engine/shaders/")
+	/// 	.remap_include_prefix("game:/", "assets/shaders/");
+	/// ```
+	/// With these rules, `//!include engine:/lighting.wgsl` includes `/opt/engine/shaders/lighting.wgsl`.
+	/// When several prefixes match an include path, the longest one is replaced.
+	pub fn remap_include_prefix(&mut self, prefix: &str, replacement: &str) -> &mut Self {
+		self.config
+			.include_prefixes
+			.retain(|(existing_prefix, _)| existing_prefix != prefix);
+		self.config
+			.include_prefixes
+			.push((prefix.to_string(), replacement.to_string()));
+		self
+	}
+
+	/// Sets how relative include paths are resolved. By default, they are relative to the directory
+	/// of the module containing the `include` statement, so shader trees can be moved around freely,
+	/// and relative to the working directory of the process if no module exists there.
+	/// Builds fail if different modules exist at both paths, instead of silently picking one.
+	/// Include paths are resolved after their prefixes are replaced (see [`ShaderBuilder::remap_include_prefix`]).
+	pub fn include_resolution(&mut self, include_resolution: IncludeResolution) -> &mut Self {
+		self.config.include_resolution = include_resolution;
+		self
+	}
+
+	/// Adds a directory that include paths are searched in, like the `-I` option of C compilers,
+	/// for example to include modules of a shader library vendored in another crate or asset directory.
+	/// Relative include paths are searched in the directories in the order they were added
+	/// if they do not lead to a module from the directory of the including module
+	/// (see [`ShaderBuilder::include_resolution`]) or from the working directory.
+	pub fn add_include_path(&mut self, directory: impl AsRef<path::Path>) -> &mut Self {
+		self.config
+			.include_paths
+			.push(directory.as_ref().to_path_buf());
+		self
+	}
+
+	/// Adds an alias of a directory, so modules can include others by project-wide names:
+	/// ```no_run
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// ShaderBuilder::new("assets/shaders/main.wgsl")
+	/// 	.unwrap()
+	/// 	.add_path_alias("@engine", "/opt/engine/shaders");
+	/// ```
+	/// With this alias, `//!include @engine/pbr.wgsl` includes `/opt/engine/shaders/pbr.wgsl`.
+	/// An alias is a prefix of include paths ending with the first `/`, replaced by the directory
+	/// like with [`ShaderBuilder::remap_include_prefix`]. Adding an alias again replaces its directory.
+	pub fn add_path_alias(&mut self, alias: &str, directory: impl AsRef<path::Path>) -> &mut Self {
+		self.remap_include_prefix(
+			&format!("{alias}/"),
+			&directory.as_ref().join("").to_string_lossy(),
+		)
+	}
+
+	/// Redirects an include path to another module, so platform-specific implementations can be chosen
+	/// behind a stable include path without changing the modules:
+	/// ```no_run
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// ShaderBuilder::new("assets/shaders/main.wgsl")
+	/// 	.unwrap()
+	/// 	.alias_module("lighting.wgsl", "lighting_mobile.wgsl");
+	/// ```
+	/// With this alias, `//!include lighting.wgsl` includes `lighting_mobile.wgsl`, which is resolved like any include path.
+	/// Only include paths written exactly like `include` are redirected. Aliasing an include path again replaces its target.
+	pub fn alias_module(&mut self, include: &str, target: &str) -> &mut Self {
+		self.config
+			.module_aliases
+			.retain(|(existing_include, _)| existing_include != include);
+		self.config
+			.module_aliases
+			.push((include.to_string(), target.to_string()));
+		self
+	}
+
+	/// Sets whether every module is only included once per build, as if all modules had a `once` statement.
+	/// Further `include` statements of a module, or of the same section of a module, are ignored.
+	/// Modules are compared by their canonical paths.
+	pub fn deduplicate_includes(&mut self, deduplicate_includes: bool) -> &mut Self {
+		self.config.deduplicate_includes = deduplicate_includes;
+		self
+	}
+
+	/// Sets whether top-level declarations are reordered so every declaration comes before the code using it,
+	/// regardless of the order of the `include` statements. Dependencies are found by a lightweight scan
+	/// of identifiers, and items that do not depend on each other keep their order.
+	pub fn order_declarations(&mut self, order_declarations: bool) -> &mut Self {
+		self.config.order_declarations = order_declarations;
+		self
+	}
+
+	/// Sets whether builds record the definitions substituted in every line of the built source, with their values,
+	/// for tools highlighting substitutions, see [`BuildStats::line_substitutions`].
+	/// Substitutions are recorded once per line, including the substitutions of templates and placeholders.
+	pub fn record_substitutions(&mut self, record_substitutions: bool) -> &mut Self {
+		self.config.record_substitutions = record_substitutions;
+		self
+	}
+
+	/// Sets whether included modules are surrounded by comments in the built source, so dumped shaders are easier to navigate:
+	/// ```wgsl
+	/// // --- begin include: shaders/lighting.wgsl ---
+	/// struct Light {
+	/// 	intensity: f32,
+	/// }
+	/// // --- end include ---
+	/// ```
+	/// Included sections are named after their module, like `test_shaders/colors.wgsl section=palettes`.
+	/// Definitions are not substituted in the comments.
+	pub fn debug_markers(&mut self, debug_markers: bool) -> &mut Self {
+		self.config.debug_markers = debug_markers;
+		self
+	}
+
+	/// Sets whether the paths of modules are normalized when comparing them, so that different paths
+	/// to the same file, like `shaders/./Common.wgsl` and `shaders/common.wgsl` on a case-insensitive
+	/// file system, are recognized as the same module on every platform.
+	/// Paths are canonicalized by the file system, or normalized lexically (resolving `.` and `..`)
+	/// if the file does not exist. This affects [`BuildStats::files_read`] and `if included(...)` statements.
+	pub fn normalize_paths(&mut self, normalize_paths: bool) -> &mut Self {
+		self.config.normalize_paths = normalize_paths;
+		self
+	}
+
+	/// Sets whether the literals of [`WGSLType`] values put after this call have explicit type suffixes,
+	/// to match the style of a project or to avoid surprises of abstract numbers in `const` expressions:
+	/// ```
+	/// # use wgsl_preprocessor::{LiteralSuffixes, ShaderBuilder};
+	/// let mut shader_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+	/// shader_builder
+	/// 	.literal_suffixes(LiteralSuffixes {
+	/// 		integers: false,
+	/// 		floats: true,
+	/// 	})
+	/// 	.put_constant("COUNT", 4u32)
+	/// 	.put_constant("SCALE", 0.5f32);
+	/// assert_eq!(shader_builder.config().definitions["COUNT"], "4");
+	/// assert_eq!(shader_builder.config().definitions["SCALE"], "0.5f");
+	/// ```
+	pub fn literal_suffixes(&mut self, literal_suffixes: LiteralSuffixes) -> &mut Self {
+		self.config.literal_suffixes = literal_suffixes;
+		self
+	}
+
+	/// Sets the units that the shader expects, so constants of unit types like [`Degrees`] or [`Millimeters`]
+	/// put after this call are converted to them. By default angles are in radians and lengths in meters.
+	pub fn units(&mut self, units: Units) -> &mut Self {
+		self.config.units = units;
+		self
+	}
+
+	/// Sets the color space that the shader expects colors in, so colors put with [`ShaderBuilder::put_color`]
+	/// after this call are converted to it. Colors are linear by default.
+	/// A module can declare the color space it expects with a statement like `//!color_space srgb`,
+	/// causing the warning `W007` if colors are put in another one.
+	pub fn color_space(&mut self, color_space: ColorSpace) -> &mut Self {
+		self.config.color_space = color_space;
+		self
+	}
+
+	/// Sets how modules that are not valid UTF-8 are handled, for example binary files included by mistake.
+	/// By default, the build fails with the location of the first invalid byte.
+	pub fn invalid_utf8(&mut self, invalid_utf8: InvalidUtf8) -> &mut Self {
+		self.config.invalid_utf8 = invalid_utf8;
+		self
+	}
+
+	/// Sets whether common HLSL syntax is rewritten to WGSL, to ease porting compute shaders:
+	/// - `[numthreads(8, 8, 1)]` becomes `@compute @workgroup_size(8, 8, 1)`.
+	/// - Parameters with system value semantics, like `id: vec3<u32> : SV_DispatchThreadID`,
+	///   become parameters with the matching built-in, like `@builtin(global_invocation_id) id: vec3<u32>`.
+	///   `SV_DispatchThreadID`, `SV_GroupThreadID`, `SV_GroupID` and `SV_GroupIndex` are supported.
+	pub fn hlsl_compatibility(&mut self, hlsl_compatibility: bool) -> &mut Self {
+		self.config.hlsl_compatibility = hlsl_compatibility;
+		self
+	}
+
+	/// Sets whether `${NAME:default}` templates are replaced by the value of `NAME`, or by `default` if `NAME`
+	/// is not defined. Building fails if a template without a default value, like `${NAME}`, is not defined.
+	/// Templates are left untouched by default, so sources that contain `${...}` text are not affected.
+	pub fn templates(&mut self, templates: bool) -> &mut Self {
+		self.config.templates = templates;
+		self
+	}
+
+	/// Sets whether symbols tested by conditional statements that are never defined, neither from Rust code
+	/// nor in any module of the build, are reported with the warning `W003`. They are usually typos.
+	pub fn undefined_symbol_lint(&mut self, undefined_symbol_lint: bool) -> &mut Self {
+		self.config.undefined_symbol_lint = undefined_symbol_lint;
+		self
+	}
+
+	/// Allows a warning, so it is not reported by [`ShaderBuilder::warnings`].
+	/// Warnings can also be allowed in a single module with a statement like `//!allow(W001, W002)`.
+	pub fn allow(&mut self, code: WarningCode) -> &mut Self {
+		self.config.denied_warnings.retain(|&denied| denied != code);
+		if !self.config.allowed_warnings.contains(&code) {
+			self.config.allowed_warnings.push(code);
+		}
+		self
+	}
+
+	/// Denies a warning, so builds with it fail unless it is allowed by an `allow` statement in the module.
+	pub fn deny(&mut self, code: WarningCode) -> &mut Self {
+		self.config
+			.allowed_warnings
+			.retain(|&allowed| allowed != code);
+		if !self.config.denied_warnings.contains(&code) {
+			self.config.denied_warnings.push(code);
+		}
+		self
+	}
+
+	/// Returns the errors of the last build in error recovery mode, see [`ShaderBuilder::error_recovery`].
+	pub fn build_errors(&self) -> &[ex::io::Error] {
+		&self.build_errors
+	}
+
+	/// Returns the statistics of the last successful build.
+	pub fn build_stats(&self) -> &BuildStats {
+		&self.build_stats
+	}
+
+	/// Returns a hash of the source of the last build.
+	/// The hash only depends on the source, so it is identical across runs and platforms.
+	pub fn content_hash(&self) -> u64 {
+		fnv1a_hash(self.source_string.as_bytes())
+	}
+
+	/// Returns a hash of the external interface of the source of the last build,
+	/// see [`reflection::interface_hash`].
+	#[cfg(feature = "reflection")]
+	pub fn interface_hash(&self) -> Result<u64, reflection::ParseError> {
+		reflection::interface_hash(&self.source_string)
+	}
+
+	/// Sets whether builds are reproducible.
+	/// In deterministic mode, the output only depends on the modules and the configuration of the builder,
+	/// and is byte-identical across runs and platforms: byte order marks are stripped from modules.
+	/// Definitions are always substituted in a fixed order (longest name first, then alphabetically),
+	/// and line endings are always normalized to `\n`.
+	pub fn deterministic(&mut self, deterministic: bool) -> &mut Self {
+		self.config.deterministic = deterministic;
+		self
+	}
+
+	/// Sets whether symbols describing the compilation target are defined, so shaders can select code paths
+	/// with statements like `//!ifdef TARGET_WEB`:
+	/// - `TARGET_WEB` when compiled to WebAssembly, `TARGET_NATIVE` otherwise.
+	/// - `TARGET_DEBUG` when compiled with debug assertions, `TARGET_RELEASE` otherwise.
+	///
+	/// Target symbols are not allowed in deterministic mode (see [`ShaderBuilder::deterministic`]).
+	pub fn target_symbols(&mut self, target_symbols: bool) -> &mut Self {
+		self.config.target_symbols = target_symbols;
+		self
+	}
+
+	/// Sets the parts of the source where definitions are substituted.
+	/// By default every occurrence of a definition's name is replaced, which can replace parts of
+	/// unrelated identifiers. With [`SubstitutionScope::Placeholders`], only explicit placeholders are replaced.
+	pub fn substitution_scope(&mut self, substitution_scope: SubstitutionScope) -> &mut Self {
+		self.config.substitution_scope = substitution_scope;
+		self
+	}
+
+	/// Sets whether conditional symbols and substituted definitions are kept apart.
+	/// When enabled, definitions without a value, like `//!define USE_FOG`, are only tested by conditional statements
+	/// and never substituted, so `USE_FOG` is left untouched where it appears as an identifier.
+	/// Definitions with a value, including `bool` constants, are only substituted,
+	/// and conditional statements consider them undefined.
+	pub fn separate_symbols(&mut self, separate_symbols: bool) -> &mut Self {
+		self.config.separate_symbols = separate_symbols;
+		self
+	}
+
+	/// Sets whether builds continue after errors in statements, unreadable modules and invalid array sizes,
+	/// so all of them are found in a single build.
+	/// A build with errors still fails, with an error listing all of them,
+	/// and the errors are available separately from [`ShaderBuilder::build_errors`].
+	/// Cancellations and time limits always stop the build.
+	pub fn error_recovery(&mut self, error_recovery: bool) -> &mut Self {
+		self.config.error_recovery = error_recovery;
+		self
+	}
+
+	/// Sets whether the labels of built [`wgpu::ShaderModuleDescriptor`]s end with a short content hash,
+	/// for example `shadow_pass@a1b2c3`.
+	/// See [`ShaderBuilder::content_hash`].
+	pub fn label_hash(&mut self, label_hash: bool) -> &mut Self {
+		self.config.label_hash = label_hash;
+		self
+	}
+
+	/// Builds a [`wgpu::ShaderModuleDescriptor`] from the shader.
+	/// The `label` member of the built [`wgpu::ShaderModuleDescriptor`] is the name of the shader file without the postfix,
+	/// followed by a short content hash if enabled with [`ShaderBuilder::label_hash`].
+	/// Fails like [`ShaderBuilder::build_source`].
+	pub fn build(&mut self) -> Result<wgpu::ShaderModuleDescriptor<'_>, PreprocessError> {
+		self.build_source()?;
+		Ok(self.shader_module_descriptor())
+	}
+
+	/// Builds the WGSL source of the shader like [`ShaderBuilder::build_source`], and puts it in the target set
+	/// with [`ShaderBuilder::output_target`].
+	/// Except for [`OutputTarget::String`], the source is moved to the target, and [`ShaderBuilder::source_string`] is left empty,
+	/// so the builder does not hold the source once it is written.
+	/// The target receives a copy of the whole built source, it is not written while modules are loaded.
+	/// Fails like [`ShaderBuilder::build_source`], or with the error of the target if it can not be written.
+	pub fn build_output(&mut self) -> Result<BuildOutput, ex::io::Error> {
+		self.build_source()?;
+		let source_string = match self.config.output_target {
+			OutputTarget::String => self.source_string.clone(),
+			_ => mem::take(&mut self.source_string),
+		};
+		output::write(&self.config.output_target, source_string).map_err(|error| {
+			preprocess_error_with_kind(
+				path::Path::new(&self.config.source_path),
+				error.kind(),
+				error.to_string(),
+			)
+		})
+	}
+
+	/// Builds the shader into a [`ShaderLibrary`] named with its label, to be linked into other shaders
+	/// with [`ShaderBuilder::link_library`] without reading its modules again.
+	/// Libraries linked into this builder are not part of the built library.
+	/// Fails like [`ShaderBuilder::build_source`].
+	pub fn build_library(&mut self) -> Result<ShaderLibrary, ex::io::Error> {
+		let libraries = mem::take(&mut self.libraries);
+		let source_string = self.build_source().map(str::to_string);
+		self.libraries = libraries;
+		let source = source_string?;
+		Ok(ShaderLibrary {
+			name: self.label(None),
+			declarations: ordering::declarations(&source),
+			source,
+		})
+	}
+
+	/// Builds the shader and parses it into a [`naga::Module`], so engines working on [`naga`] IR can run their own passes.
+	/// The module can be passed to wgpu without parsing the WGSL source again when the shader module is created:
+	/// ```
+	/// # use std::borrow::Cow;
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// let module = ShaderBuilder::new("test_shaders/compute_double.wgsl")
+	/// 	.unwrap()
+	/// 	.put_constant("FACTOR", 2.0f32)
+	/// 	.build_naga_module()
+	/// 	.unwrap();
+	/// let shader_module_descriptor = wgpu::ShaderModuleDescriptor {
+	/// 	label: Some("compute_double"),
+	/// 	source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+	/// };
+	/// ```
+	/// The passes added with [`ShaderBuilder::naga_pass`] are run on the module in order.
+	/// Fails like [`ShaderBuilder::build_source`], if the built source is not valid WGSL, or if a pass fails.
+	#[cfg(feature = "reflection")]
+	pub fn build_naga_module(&mut self) -> Result<naga::Module, ex::io::Error> {
+		self.build_source()?;
+		let source_path = path::Path::new(&self.config.source_path);
+		let mut module = naga::front::wgsl::parse_str(&self.source_string).map_err(|error| {
+			preprocess_error(source_path, error.emit_to_string(&self.source_string))
+		})?;
+		for (name, pass) in self.naga_passes.iter() {
+			pass(&mut module).map_err(|message| {
+				preprocess_error(source_path, format!("naga pass `{name}` failed: {message}"))
+			})?;
+		}
+		Ok(module)
+	}
+
+	/// Adds a pass transforming the [`naga::Module`] built by [`ShaderBuilder::build_naga_module`],
+	/// for transforms that can not be expressed safely on the WGSL source, like rewriting bindings:
+	/// ```
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// let mut shader_builder = ShaderBuilder::new("test_shaders/compute_double.wgsl").unwrap();
+	/// shader_builder
+	/// 	.put_constant("FACTOR", 2.0f32)
+	/// 	.naga_pass("bind to group 1", |module| {
+	/// 		for (_, global_variable) in module.global_variables.iter_mut() {
+	/// 			if let Some(binding) = &mut global_variable.binding {
+	/// 				binding.group = 1;
+	/// 			}
+	/// 		}
+	/// 		Ok(())
+	/// 	});
+	/// let module = shader_builder.build_naga_module().unwrap();
+	/// ```
+	/// Passes run in the order they were added, and a pass fails the build by returning an error message.
+	///
+	/// # Arguments
+	/// - `name` - Name of the pass, used in its errors.
+	/// - `pass` - The pass.
+	#[cfg(feature = "reflection")]
+	pub fn naga_pass(
+		&mut self,
+		name: &str,
+		pass: impl Fn(&mut naga::Module) -> Result<(), String> + Send + Sync + 'static,
+	) -> &mut Self {
+		self.naga_passes
+			.push((name.to_string(), sync::Arc::new(pass)));
+		self
+	}
+
+	/// Returns a [`wgpu::ShaderModuleDescriptor`] of the last build, see [`ShaderBuilder::build`].
+	pub(crate) fn shader_module_descriptor(&mut self) -> wgpu::ShaderModuleDescriptor<'_> {
+		self.label = self.label(None);
+		wgpu::ShaderModuleDescriptor {
+			label: Some(&self.label),
+			source: wgpu::ShaderSource::Wgsl(borrow::Cow::Borrowed(&self.source_string)),
+		}
+	}
+
+	/// Builds a specialization of the shader for every entry point, each with its own definitions
+	/// in addition to the definitions of the builder.
+	/// Modules are read once for all specializations, through the [`ModuleCache`] of the builder if it has one.
+	/// The label of every specialization is the label of the shader followed by the name of the entry point,
+	/// for example `material:fs_main_masked`.
+	///
+	/// # Arguments
+	/// - `entry_points` - Name of every entry point, with its definitions as WGSL strings.
+	pub fn build_entry_points(
+		&self,
+		entry_points: &[(&str, HashMap<String, String>)],
+	) -> Result<Vec<EntryPointBuild>, ex::io::Error> {
+		let module_cache = self.shared_module_cache();
+		entry_points
+			.iter()
+			.map(|(entry_point, definitions)| {
+				let mut shader_builder = self.specialization(&module_cache)?;
+				shader_builder.build_source_with_overlay(definitions)?;
+				Ok(EntryPointBuild {
+					entry_point: entry_point.to_string(),
+					label: shader_builder.label(Some(entry_point)),
+					source: shader_builder.source_string,
+				})
+			})
+			.collect()
+	}
+
+	/// Builds every combination of defining or not defining the given symbols, in addition to
+	/// the definitions of the builder, and deduplicates the combinations producing identical sources,
+	/// for example when a symbol is only tested inside a branch excluded by another one.
+	/// Modules are read once for all permutations, through the [`ModuleCache`] of the builder if it has one.
+	///
+	/// Combinations are pruned without being built when they only differ from a built combination
+	/// in symbols that its build never looked up or substituted and that do not appear in its source,
+	/// since such symbols can not change the source.
+	/// Builds testing `if included(...)` are never used for pruning.
+	///
+	/// Fails with [`io::ErrorKind::InvalidInput`] if there are more than 16 symbols,
+	/// since every symbol doubles the number of builds.
+	///
+	/// # Arguments
+	/// - `symbols` - Symbols tested by conditional statements.
+	pub fn build_permutations(&self, symbols: &[&str]) -> Result<Permutations, ex::io::Error> {
+		self.build_permutations_with_progress(symbols, |_| {})
+	}
+
+	/// Like [`ShaderBuilder::build_permutations`], reporting progress after every permutation is built,
+	/// for example to show a progress bar.
+	/// Permutations are built on a thread per available core, so `progress` is called from these threads.
+	/// The result does not depend on the order in which permutations are built.
+	pub fn build_permutations_with_progress(
+		&self,
+		symbols: &[&str],
+		progress: impl Fn(PermutationProgress) + Sync,
+	) -> Result<Permutations, ex::io::Error> {
+		if symbols.len() > MAX_PERMUTATION_SYMBOLS {
+			return Err(preprocess_error_with_kind(
+				path::Path::new(&self.config.source_path),
+				io::ErrorKind::InvalidInput,
+				format!(
+					"{} symbols exceed the maximum of {MAX_PERMUTATION_SYMBOLS} symbols of permutations",
+					symbols.len()
+				),
+			));
+		}
+		let module_cache = self.shared_module_cache();
+		let total = 1usize << symbols.len();
+		let combination_symbols = |combination: usize| -> Vec<String> {
+			symbols
+				.iter()
+				.enumerate()
+				.filter(|(index, _)| combination & (1 << index) != 0)
+				.map(|(_, symbol)| symbol.to_string())
+				.collect()
+		};
+		let symbol_regexes: Vec<regex::Regex> = symbols
+			.iter()
+			.map(|symbol| regex::Regex::new(&format!(r"\b{}\b", regex::escape(symbol))).unwrap())
+			.collect();
+		/// Build of a combination, the builders are dropped as soon as they are built.
+		enum Build {
+			/// Label and source of a built combination.
+			Source(String, String),
+			/// Earlier combination that the combination was pruned in favor of.
+			SameAs(usize),
+		}
+		let next_combination = sync::atomic::AtomicUsize::new(0);
+		let completed = sync::atomic::AtomicUsize::new(0);
+		let is_failed = sync::atomic::AtomicBool::new(false);
+		// Built combinations, with a mask of the symbols that can change their sources.
+		let observations: sync::Mutex<Vec<(usize, usize)>> = sync::Mutex::new(Vec::new());
+		let builds: sync::Mutex<Vec<Option<Result<Build, ex::io::Error>>>> =
+			sync::Mutex::new((0..total).map(|_| None).collect());
+		let threads = thread::available_parallelism().map_or(1, usize::from);
+		thread::scope(|scope| {
+			for _ in 0..threads.min(total) {
+				scope.spawn(|| loop {
+					let combination =
+						next_combination.fetch_add(1, sync::atomic::Ordering::Relaxed);
+					if combination >= total || is_failed.load(sync::atomic::Ordering::Relaxed) {
+						break;
+					}
+					let started = time::Instant::now();
+					let defined_symbols = combination_symbols(combination);
+					// Only earlier combinations are candidates, so every variant is built by its first combination.
+					let pruned = pruned_combination(&observations.lock().unwrap(), combination);
+					let build = match pruned {
+						Some(built) => Ok(Build::SameAs(built)),
+						None => self
+							.build_observed_permutation(
+								&module_cache,
+								symbols,
+								&symbol_regexes,
+								&defined_symbols,
+							)
+							.map(|(label, source, observed)| {
+								observations.lock().unwrap().push((combination, observed));
+								Build::Source(label, source)
+							}),
+					};
+					if build.is_err() {
+						is_failed.store(true, sync::atomic::Ordering::Relaxed);
+					} else {
+						progress(PermutationProgress {
+							symbols: &defined_symbols,
+							completed: completed.fetch_add(1, sync::atomic::Ordering::Relaxed) + 1,
+							total,
+							elapsed: started.elapsed(),
+						});
+					}
+					builds.lock().unwrap()[combination] = Some(build);
+				});
+			}
+		});
+		let mut permutations = Permutations::default();
+		let mut variant_indices: HashMap<String, usize> = HashMap::new();
+		let mut combination_variants = vec![0; total];
+		for (combination, build) in builds.into_inner().unwrap().into_iter().enumerate() {
+			// Builds after a failure may be skipped, the failure is reported below.
+			let Some(build) = build else {
+				continue;
+			};
+			let variant_index = match build? {
+				Build::SameAs(built) => combination_variants[built],
+				Build::Source(label, source) => match variant_indices.get(&source) {
+					Some(&variant_index) => variant_index,
+					None => {
+						let variant_index = permutations.variants.len();
+						variant_indices.insert(source.clone(), variant_index);
+						permutations.variants.push(Variant { label, source });
+						variant_index
+					}
+				},
+			};
+			combination_variants[combination] = variant_index;
+			permutations
+				.mapping
+				.push((combination_symbols(combination), variant_index));
+		}
+		Ok(permutations)
+	}
+
+	/// Builds a combination of permutation symbols, defining `defined_symbols` as empty strings,
+	/// and returns its label, its source, and a mask of the `symbols` that can change its source:
+	/// the symbols observed by the build and the ones present in its source, matched by `symbol_regexes`.
+	fn build_observed_permutation(
+		&self,
+		module_cache: &sync::Arc<ModuleCache>,
+		symbols: &[&str],
+		symbol_regexes: &[regex::Regex],
+		defined_symbols: &[String],
+	) -> Result<(String, String, usize), ex::io::Error> {
+		let definitions = defined_symbols
+			.iter()
+			.map(|symbol| (symbol.clone(), String::new()))
+			.collect();
+		let mut shader_builder = self.specialization(module_cache)?;
+		shader_builder.observed_symbols = Some(ObservedSymbols::default());
+		shader_builder.build_source_with_overlay(&definitions)?;
+		let observed_symbols = shader_builder.observed_symbols.take().unwrap_or_default();
+		let observed = symbols
+			.iter()
+			.zip(symbol_regexes.iter())
+			.enumerate()
+			.filter(|(_, (symbol, symbol_regex))| {
+				observed_symbols.is_every_symbol
+					|| observed_symbols.names.contains(**symbol)
+					|| symbol_regex.is_match(&shader_builder.source_string)
+			})
+			.fold(0, |observed, (index, _)| observed | 1 << index);
+		let label = if defined_symbols.is_empty() {
+			shader_builder.label(None)
+		} else {
+			shader_builder.label(Some(&defined_symbols.join("+")))
+		};
+		Ok((label, shader_builder.source_string, observed))
+	}
+
+	/// Builds every configuration of a sweep, in addition to the definitions of the builder,
+	/// and reports the configurations that fail to build, or to validate if [`Sweep::validate`] is enabled.
+	/// Modules are read once for all configurations, through the [`ModuleCache`] of the builder if it has one.
+	pub fn sweep(&self, sweep: &Sweep) -> SweepReport {
+		let module_cache = self.shared_module_cache();
+		let mut report = SweepReport {
+			configurations: sweep.configurations(),
+			failures: Vec::new(),
+		};
+		for configuration in 0..report.configurations {
+			let mut definitions = sweep.configuration(configuration);
+			for (_, value) in definitions.iter_mut() {
+				*value = self.literal(mem::take(value));
+			}
+			let overlay = definitions.iter().cloned().collect();
+			let result = self
+				.specialization(&module_cache)
+				.and_then(|mut shader_builder| {
+					shader_builder.build_source_with_overlay(&overlay)?;
+					Ok(shader_builder.source_string)
+				})
+				.map_err(|error| error.to_string());
+			#[cfg(feature = "reflection")]
+			let result = result.and_then(|source| {
+				if sweep.validate {
+					reflection::validate(&source)?;
+				}
+				Ok(source)
+			});
+			if let Err(message) = result {
+				report.failures.push(SweepFailure {
+					definitions,
+					message,
+				});
+			}
+		}
+		report
+	}
+
+	/// Loads and parses the root module and every module it includes, directly or not, so the shader can be rendered
+	/// for many sets of definitions with [`ParsedShader::render`] by only evaluating their directive trees:
+	/// ```
+	/// # use std::collections::HashMap;
+	/// # use wgsl_preprocessor::ShaderBuilder;
+	/// let parsed_shader = ShaderBuilder::new("test_shaders/conditional.wgsl").unwrap().parse().unwrap();
+	/// for quality in 1..=4 {
+	/// 	let definitions = HashMap::from([("QUALITY".to_string(), format!("{quality}u"))]);
+	/// 	parsed_shader.render(&definitions).unwrap();
+	/// }
+	/// ```
+	/// Modules included in every branch of conditional blocks are loaded, resolved like builds resolve them,
+	/// and modules that can not be read are skipped, so they only fail the renders including them.
+	/// Modules are read through the [`ModuleCache`] of the builder if it has one.
+	pub fn parse(&self) -> Result<ParsedShader, ex::io::Error> {
+		let module_cache = self.shared_module_cache();
+		let mut parsed_modules = ParsedModules::new();
+		let mut includes: Vec<(path::PathBuf, Vec<path::PathBuf>)> = Vec::new();
+		let mut pending = vec![path::PathBuf::from(&self.config.source_path)];
+		while let Some(module_path) = pending.pop() {
+			if includes
+				.iter()
+				.any(|(loaded_path, _)| *loaded_path == module_path)
+			{
+				continue;
+			}
+			let module_bytes = match module_cache.read(&module_path, &*self.source_resolver) {
+				Ok(module_bytes) => module_bytes,
+				Err(error) if includes.is_empty() => return Err(error),
+				Err(_) => continue,
+			};
+			let mut included_paths = Vec::new();
+			match self.parsed_source(&module_path, &module_bytes) {
+				Some(module_source) => {
+					let tree = ast::Tree::new(module_source);
+					self.included_paths(&module_path, tree.nodes(), &mut included_paths);
+					parsed_modules.insert(module_path.clone(), (module_bytes, tree));
+				}
+				None => {
+					let module_source = String::from_utf8_lossy(&module_bytes);
+					let nodes = ast::parse(&module_source);
+					self.included_paths(&module_path, &nodes, &mut included_paths);
+				}
+			}
+			pending.extend(included_paths.iter().rev().cloned());
+			includes.push((module_path, included_paths));
+		}
+		let mut shader_builder = self.specialization(&module_cache)?;
+		shader_builder.parsed_modules = Some(sync::Arc::new(parsed_modules));
+		Ok(ParsedShader {
+			shader_builder,
+			module_cache,
+			includes,
+		})
+	}
+
+	/// Appends the paths of the modules included by the nodes of a module, in every branch of its conditional blocks,
+	/// resolved like [`ShaderBuilder::include`] resolves them.
+	fn included_paths(
+		&self,
+		module_path: &path::Path,
+		nodes: &[ast::Node],
+		included_paths: &mut Vec<path::PathBuf>,
+	) {
+		for node in nodes {
+			match node {
+				ast::Node::Include(line) | ast::Node::IncludeFirst(line) => {
+					let mut paths = line
+						.text
+						.split_whitespace()
+						.skip(1)
+						.filter(|argument| !argument.starts_with(SECTION_ARGUMENT_PREFIX))
+						// Ambiguous includes are reported when the module is built.
+						.filter_map(|include| self.resolved_include(module_path, include).ok());
+					match node {
+						ast::Node::IncludeFirst(_) => {
+							included_paths.extend(paths.find(|path| self.is_module(path)))
+						}
+						_ => included_paths.extend(paths),
+					}
+				}
+				ast::Node::Conditional(conditional) => {
+					for branch in conditional.branches.iter() {
+						self.included_paths(module_path, &branch.nodes, included_paths);
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+
+	/// Returns the module cache of the builder, or a new one to share between specializations.
+	fn shared_module_cache(&self) -> sync::Arc<ModuleCache> {
+		self.module_cache
+			.clone()
+			.unwrap_or_else(|| sync::Arc::new(ModuleCache::new()))
+	}
+
+	/// Returns a copy of the builder reading modules through `module_cache`,
+	/// to be built with the definitions of a specialization as overlay.
+	fn specialization(&self, module_cache: &sync::Arc<ModuleCache>) -> Result<Self, ex::io::Error> {
+		let mut shader_builder =
+			Self::with_config(self.config.clone(), self.source_resolver.clone());
+		shader_builder.cancellation_token = self.cancellation_token.clone();
+		shader_builder.module_cache = Some(module_cache.clone());
+		shader_builder.parsed_modules = self.parsed_modules.clone();
+		shader_builder.libraries = self.libraries.clone();
+		shader_builder.definition_provenance = self.definition_provenance.clone();
+		#[cfg(feature = "reflection")]
+		{
+			shader_builder.naga_passes = self.naga_passes.clone();
+		}
+		Ok(shader_builder)
+	}
+
+	/// Returns the label of the shader: the name of the shader file without the postfix,
+	/// followed by the entry point if there is one, and by a short content hash if enabled.
+	fn label(&self, entry_point: Option<&str>) -> String {
+		let mut label = self
+			.config
+			.source_path
+			.rsplit(['/', '.'])
+			.nth(1)
+			.unwrap_or(&self.config.source_path)
+			.to_string();
+		if let Some(entry_point) = entry_point {
+			label = format!("{label}:{entry_point}");
+		}
+		if self.config.label_hash {
+			label = format!("{label}@{:06x}", self.content_hash() >> 40);
+		}
+		label
+	}
+
+	/// Returns the string definition of a value in the units of the shader,
+	/// with the suffixes of [`ShaderBuilder::literal_suffixes`].
+	fn string_definition(&self, value: &impl WGSLType) -> String {
+		self.literal(value.string_definition_in(self.config.units))
+	}
+
+	/// Applies [`ShaderBuilder::literal_suffixes`] to the numeric literals of a [`WGSLType`] string definition.
+	fn literal(&self, string_definition: String) -> String {
+		let literal_suffixes = self.config.literal_suffixes;
+		if literal_suffixes == LiteralSuffixes::default() {
+			return string_definition;
+		}
+		NUMERIC_LITERAL_REGEX
+			.replace_all(&string_definition, |captures: &regex::Captures| {
+				let suffix = match &captures[4] {
+					"i" | "u" if !literal_suffixes.integers => {
+						let value = match captures.get(2) {
+							Some(hexadecimal) => u64::from_str_radix(hexadecimal.as_str(), 16).ok(),
+							None => captures[3].parse::<u64>().ok(),
+						};
+						if value.is_some_and(|value| value <= i32::MAX as u64) {
+							""
+						} else {
+							&captures[4]
+						}
+					}
+					"" if literal_suffixes.floats
+						&& captures
+							.get(3)
+							.is_some_and(|decimal| decimal.as_str().contains(['.', 'e', 'E'])) =>
+					{
+						"f"
+					}
+					suffix => suffix,
+				};
+				format!(
+					"{}{}{suffix}",
+					&captures[1],
+					&captures[0][captures[1].len()..captures[0].len() - captures[4].len()]
+				)
+			})
+			.into_owned()
+	}
+
+	fn array_string_definition(name: &str, type_name: &str, values: Vec<String>) -> String {
+		let array_length = values.len();
+		let mut string_definition = String::new();
+
+		string_definition.push_str(&format!(
+			"var<private> {name}: array<{type_name}, {array_length}> = array<{type_name}, {array_length}>("
+		));
+
+		for value in values.iter() {
+			string_definition.push_str(value);
+			string_definition.push(',');
+		}
+
+		string_definition.push_str(");");
+		string_definition
+	}
+
+	/// Returns the definitions of the [`DefinitionLayer::Default`] layer.
+	fn default_definitions(&self) -> HashMap<String, String> {
+		let mut default_definitions = if self.config.target_symbols {
+			Self::target_symbol_definitions()
+		} else {
+			HashMap::new()
+		};
+		default_definitions.extend(self.config.default_definitions.clone());
+		default_definitions
+	}
+
+	/// Resolves the constants put with [`ShaderBuilder::put_constant_expr`] into the builder definitions of a build.
+	fn resolve_constant_expressions(
+		&self,
+		source_path: &path::Path,
+		state: &mut BuildState,
+	) -> Result<(), ex::io::Error> {
+		let lookup = |name: &str| {
+			observe_symbol(name, state);
+			layered_definition(
+				name,
+				[
+					(DefinitionLayer::Overlay, &state.overlay_definitions),
+					(DefinitionLayer::Builder, &self.config.definitions),
+					(DefinitionLayer::Builder, &self.config.constant_expressions),
+					(DefinitionLayer::Default, &state.default_definitions),
+				],
+			)
+			.map(|(value, _)| value.to_string())
+		};
+		let mut names: Vec<&String> = self.config.constant_expressions.keys().collect();
+		names.sort();
+		let mut resolved_expressions = Vec::new();
+		let mut errors = Vec::new();
+		for name in names {
+			let expression = &self.config.constant_expressions[name];
+			if !self.config.fold_constants {
+				resolved_expressions.push((
+					name.clone(),
+					format!("({})", expression::expand(expression, &lookup)),
+				));
+				continue;
+			}
+			match expression::fold(expression, &lookup) {
+				Ok(literal) => resolved_expressions.push((name.clone(), literal)),
+				Err(message) => errors.push(preprocess_error(
+					source_path,
+					format!(
+						"constant `{name}` defined as `{expression}` can not be folded: {message}"
+					),
+				)),
+			}
+		}
+		state.builder_definitions.extend(resolved_expressions);
+		for error in errors {
+			self.recover(error, state)?;
+		}
+		Ok(())
+	}
+
+	/// Returns the value and the layer of a definition during a build, see [`ShaderBuilder::definition`].
+	fn build_definition<'a>(
+		&'a self,
+		name: &str,
+		state: &'a BuildState,
+	) -> Option<(&'a str, DefinitionLayer)> {
+		observe_symbol(name, state);
+		layered_definition(
+			name,
+			[
+				(DefinitionLayer::Overlay, &state.overlay_definitions),
+				(DefinitionLayer::Builder, &state.builder_definitions),
+				(DefinitionLayer::File, &state.definitions),
+				(DefinitionLayer::Default, &state.default_definitions),
+			],
+		)
+	}
+
+	/// Returns whether a definition of a module is shadowed by a higher layer.
+	fn is_shadowed(&self, name: &str, state: &BuildState) -> bool {
+		observe_symbol(name, state);
+		state.builder_definitions.contains_key(name) || state.overlay_definitions.contains_key(name)
+	}
+
+	fn target_symbol_definitions() -> HashMap<String, String> {
+		let platform = if cfg!(target_arch = "wasm32") {
+			"TARGET_WEB"
+		} else {
+			"TARGET_NATIVE"
+		};
+		let profile = if cfg!(debug_assertions) {
+			"TARGET_DEBUG"
+		} else {
+			"TARGET_RELEASE"
+		};
+		HashMap::from([
+			(platform.to_string(), String::new()),
+			(profile.to_string(), String::new()),
+		])
+	}
+
+	/// Returns the definitions in the order they are substituted: longest name first, then alphabetically,
+	/// so definitions whose values contain the names of others are substituted the same way in every build.
+	fn ordered_definitions(definitions: &HashMap<String, String>) -> Vec<(&String, &String)> {
+		let mut ordered_definitions: Vec<(&String, &String)> = definitions.iter().collect();
+		ordered_definitions.sort_by(|(name, _), (other_name, _)| {
+			other_name
+				.len()
+				.cmp(&name.len())
+				.then_with(|| name.cmp(other_name))
+		});
+		ordered_definitions
+	}
+
+	#[track_caller]
+	fn put_vector<T: WGSLType>(&mut self, name: &str, elements: &[T]) {
+		let string_definition = format!(
+			"vec{}<{}>({})",
+			elements.len(),
+			T::type_name(),
+			elements
+				.iter()
+				.map(|element| self.string_definition(element))
+				.collect::<Vec<String>>()
+				.join(", ")
+		);
+		self.set_definition(name.to_string(), Some(string_definition));
+		self.config.definition_types.insert(
+			name.to_string(),
+			format!("vec{}<{}>", elements.len(), T::type_name()),
+		);
+	}
+
+	/// Inserts a definition of the [`DefinitionLayer::Default`] layer,
+	/// recording the location of the caller as its provenance.
+	#[track_caller]
+	fn set_default_definition(&mut self, name: String, value: String) {
+		let caller = std::panic::Location::caller();
+		self.definition_provenance.insert(
+			name.clone(),
+			Provenance::Rust(SourceLocation {
+				path: path::PathBuf::from(caller.file()),
+				line: caller.line() as usize,
+			}),
+		);
+		self.config.default_definitions.insert(name, value);
+	}
+
+	/// Inserts a definition put from Rust code, or removes it if `value` is [`None`],
+	/// recording the location of the caller as its provenance.
+	#[track_caller]
+	fn set_definition(&mut self, name: String, value: Option<String>) {
+		self.config.definition_types.remove(&name);
+		match value {
+			Some(value) => {
+				let caller = std::panic::Location::caller();
+				self.definition_provenance.insert(
+					name.clone(),
+					Provenance::Rust(SourceLocation {
+						path: path::PathBuf::from(caller.file()),
+						line: caller.line() as usize,
+					}),
+				);
+				self.config.constant_expressions.remove(&name);
+				self.config.definitions.insert(name, value);
+			}
+			None => {
+				self.definition_provenance.remove(&name);
+				self.config.constant_expressions.remove(&name);
+				self.config.definitions.remove(&name);
+			}
+		}
+	}
+
+	#[track_caller]
+	fn insert_array_definition(&mut self, name: &str, is_empty: bool, string_definition: String) {
+		let empty_name = format!("{name}_EMPTY");
+		if is_empty {
+			self.set_definition(empty_name, Some(String::new()));
+			self.config
+				.array_definitions
+				.insert(name.to_string(), String::new());
+		} else {
+			self.set_definition(empty_name, None);
+			self.config
+				.array_definitions
+				.insert(name.to_string(), string_definition);
+		}
+	}
+
+	fn array_size_errors(
+		module_path: &path::Path,
+		module_string: &str,
+		definitions: &HashMap<String, String>,
+	) -> Vec<ex::io::Error> {
+		ARRAY_SIZE_REGEX
+			.captures_iter(module_string)
+			.filter_map(|captures| {
+				let value = definitions.get(&captures[1])?;
+				let is_positive = POSITIVE_INTEGER_REGEX
+					.captures(value.trim())
+					.and_then(|integer| match integer.get(1) {
+						Some(hexadecimal) => u64::from_str_radix(hexadecimal.as_str(), 16).ok(),
+						None => integer[2].parse::<u64>().ok(),
+					})
+					.is_some_and(|integer| integer > 0);
+				(!is_positive).then(|| {
+					preprocess_error(
+						module_path,
+						format!(
+							"array size `{}` is defined as `{value}`, which is not a positive integer",
+							&captures[1]
+						),
+					)
+				})
+			})
+			.collect()
+	}
+
+	/// Records a module read by the build, for its diagnostics and [`BuildStats::files_read`].
+	fn record_module_read(
+		&self,
+		module_path: &path::Path,
+		module_bytes: sync::Arc<[u8]>,
+		state: &mut BuildState,
+	) {
+		state
+			.module_sources
+			.insert(module_path.to_path_buf(), module_bytes);
+		state.stats.files_read.push(if self.config.normalize_paths {
+			normalized_path(module_path)
+		} else {
+			module_path.to_path_buf()
+		});
+	}
+
+	/// Returns the source of a module as it is parsed by builds, or [`None`] if decoding the module reports
+	/// an error or a warning, in which case it is decoded again by every build to report them.
+	fn parsed_source(&self, module_path: &path::Path, module_bytes: &[u8]) -> Option<String> {
+		let module_source = std::str::from_utf8(module_bytes).ok()?;
+		#[cfg(feature = "glsl_includes")]
+		let module_source: borrow::Cow<str> = match Self::glsl_to_wgsl(module_path, module_source) {
+			Some(Ok(wgsl_source)) => wgsl_source.into(),
+			Some(Err(_)) => return None,
+			None => module_source.into(),
+		};
+		#[cfg(not(feature = "glsl_includes"))]
+		let module_source: borrow::Cow<str> = {
+			let _ = module_path;
+			module_source.into()
+		};
+		let module_source = if self.config.deterministic {
+			module_source.trim_start_matches('\u{feff}')
+		} else {
+			&module_source
+		};
+		Some(module_source.to_string())
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(level = "debug", skip(self, state), err(Display))
+	)]
+	fn load_shader_module(
+		&self,
+		module_path: &path::Path,
+		section: Option<&str>,
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		if let Some(max_module_size) = self.config.max_module_size {
+			let module_size = self.source_resolver.size(module_path).unwrap_or_default();
+			if module_size > max_module_size {
+				let message = format!(
+					"module of {module_size} bytes exceeds the maximum module size of {max_module_size} bytes"
+				);
+				let error = if state.include_chain.is_empty() {
+					preprocess_error(module_path, message)
+				} else {
+					include_error(module_path, io::ErrorKind::InvalidData, message, state)
+				};
+				self.recover(error, state)?;
+				return Ok(String::new());
+			}
+		}
+		if let Some((module_bytes, tree)) = self
+			.parsed_modules
+			.as_ref()
+			.and_then(|parsed_modules| parsed_modules.get(module_path))
+		{
+			self.record_module_read(module_path, module_bytes.clone(), state);
+			return self.render_module(module_path, section, tree.nodes(), state);
+		}
+		let module_bytes: sync::Arc<[u8]> = match match &self.module_cache {
+			Some(module_cache) => module_cache.read(module_path, &*self.source_resolver),
+			None => source_resolver::read_module(&*self.source_resolver, module_path),
+		} {
+			Ok(module_bytes) => module_bytes,
+			Err(error) => {
+				let error = if state.include_chain.is_empty() {
+					error
+				} else {
+					include_error(module_path, error.kind(), error.to_string(), state)
+				};
+				self.recover(error, state)?;
+				return Ok(String::new());
+			}
+		};
+		self.record_module_read(module_path, module_bytes.clone(), state);
+		let module_source = match std::str::from_utf8(&module_bytes) {
+			Ok(module_source) => borrow::Cow::Borrowed(module_source),
+			Err(error) => {
+				let offset = error.valid_up_to();
+				let line_number = module_bytes[..offset]
+					.iter()
+					.filter(|&&byte| byte == b'\n')
+					.count() + 1;
+				let message =
+					format!("invalid UTF-8 at byte {offset}, the module may be a binary file");
+				match self.config.invalid_utf8 {
+					InvalidUtf8::Error => {
+						self.recover(
+							located_error(module_path, line_number, message, state),
+							state,
+						)?;
+						return Ok(String::new());
+					}
+					InvalidUtf8::Lossy => {
+						state.warnings.push(Warning {
+							code: WarningCode::InvalidUtf8,
+							message: format!("{message}, invalid sequences are replaced"),
+							location: SourceLocation {
+								path: module_path.to_path_buf(),
+								line: line_number,
+							},
+						});
+						String::from_utf8_lossy(&module_bytes)
+					}
+				}
+			}
+		};
+		#[cfg(feature = "glsl_includes")]
+		let module_source = match Self::glsl_to_wgsl(module_path, &module_source) {
+			Some(Ok(wgsl_source)) => wgsl_source.into(),
+			Some(Err(error)) => {
+				self.recover(error, state)?;
+				return Ok(String::new());
+			}
+			None => module_source,
+		};
+		let module_source = if self.config.deterministic {
+			module_source.trim_start_matches('\u{feff}')
+		} else {
+			&module_source
+		};
+		let nodes = ast::parse(module_source);
+		self.render_module(module_path, section, &nodes, state)
+	}
+
+	/// Evaluates the directive tree of a loaded module and substitutes its definitions, see [`ShaderBuilder::render_nodes`].
+	fn render_module(
+		&self,
+		module_path: &path::Path,
+		section: Option<&str>,
+		nodes: &[ast::Node],
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		let mut render = ModuleRender {
+			module_path,
+			section,
+			open_section: None,
+			is_section_found: false,
+			unclosed_conditionals: Vec::new(),
+			unclosed_preserve: None,
+			module_string: String::new(),
+			line_substitutions: BTreeMap::new(),
+		};
+		self.render_nodes(nodes, true, &mut render, state)?;
+		if let Some((name, line_number)) = render.open_section {
+			self.recover(
+				located_error(
+					module_path,
+					line_number,
+					format!("section `{name}` without a matching `{ENDSECTION_INSTRUCTION}`"),
+					state,
+				),
+				state,
+			)?;
+		}
+		if let Some(section) = section.filter(|_| !render.is_section_found) {
+			self.recover(
+				include_error(
+					module_path,
+					io::ErrorKind::NotFound,
+					format!("section `{section}` not found in {}", module_path.display()),
+					state,
+				),
+				state,
+			)?;
+		}
+		if let Some(line_number) = render.unclosed_preserve {
+			self.recover(
+				located_error(
+					module_path,
+					line_number,
+					format!(
+						"`{PRESERVE_INSTRUCTION}` without a matching `{ENDPRESERVE_INSTRUCTION}`"
+					),
+					state,
+				),
+				state,
+			)?;
+		}
+		for &line_number in render.unclosed_conditionals.iter() {
+			self.recover(
+				located_error(
+					module_path,
+					line_number,
+					format!("`{IFDEF_INSTRUCTION}` without a matching `{ENDIF_INSTRUCTION}`"),
+					state,
+				),
+				state,
+			)?;
+		}
+		let module_string = render.module_string;
+		let definitions: HashMap<String, String> = state
+			.definitions
+			.iter()
+			.filter(|(name, _)| {
+				!self.is_shadowed(name, state) && !state.accumulated_definitions.contains(*name)
+			})
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect();
+		for error in Self::array_size_errors(module_path, &module_string, &definitions) {
+			self.recover(error, state)?;
+		}
+		state.line_substitutions = render.line_substitutions;
+		let module_string = self.expand_templates(module_path, module_string, state)?;
+		let module_string = self.expand_function_macros(module_path, module_string, state)?;
+		let module_string = self.substitute(module_path, module_string, &definitions, state)?;
+		self.check_output_size(
+			module_path,
+			state.enclosing_output_size + module_string.len(),
+		)?;
+		if let Some((directory, count)) = &mut state.intermediates {
+			let file_name = format!(
+				"{count:03}-{}",
+				module_path
+					.file_name()
+					.unwrap_or_default()
+					.to_string_lossy()
+			);
+			ex::fs::write(
+				directory.join(file_name),
+				Self::restore_preserved_regions(module_string.clone(), &state.preserved_regions),
+			)?;
+			*count += 1;
+		}
+		Ok(module_string)
+	}
+
+	/// Evaluates the nodes of a module in order, appending the lines that are kept to the module string.
+	/// Nodes of skipped branches are still visited, so their sections and errors are processed,
+	/// but the conditions of a block are only evaluated until a branch is kept,
+	/// and not at all if the block is in a skipped branch.
+	fn render_nodes<'a>(
+		&self,
+		nodes: &'a [ast::Node<'a>],
+		is_kept: bool,
+		render: &mut ModuleRender<'a>,
+		state: &mut BuildState,
+	) -> Result<(), ex::io::Error> {
+		let module_path = render.module_path;
+		for node in nodes {
+			self.check_interruption(module_path, state)?;
+			self.check_output_size(
+				module_path,
+				state.enclosing_output_size + render.module_string.len(),
+			)?;
+			let is_outside_section = render.section.is_some()
+				&& render.open_section.map(|(open_name, _)| open_name) != render.section;
+			match node {
+				ast::Node::Section(line, name) => {
+					let Some(name) = *name else {
+						self.recover(
+							located_error(
+								module_path,
+								line.number,
+								format!("missing name in `{}`", line.text.trim()),
+								state,
+							),
+							state,
+						)?;
+						continue;
+					};
+					if let Some((open_name, _)) = render.open_section {
+						self.recover(
+							located_error(
+								module_path,
+								line.number,
+								format!("section `{name}` is nested in section `{open_name}`"),
+								state,
+							),
+							state,
+						)?;
+					}
+					render.is_section_found |= render.section == Some(name);
+					render.open_section = Some((name, line.number));
+				}
+				ast::Node::EndSection(line) => {
+					if render.open_section.take().is_none() {
+						self.recover(
+							located_error(
+								module_path,
+								line.number,
+								format!(
+									"`{ENDSECTION_INSTRUCTION}` without a matching `{SECTION_INSTRUCTION}`"
+								),
+								state,
+							),
+							state,
+						)?;
+					}
+				}
+				// Statements outside the included section are ignored, but the section may be nested in a block.
+				ast::Node::Conditional(conditional) if is_outside_section => {
+					for branch in conditional.branches.iter() {
+						self.render_nodes(&branch.nodes, is_kept, render, state)?;
+					}
+				}
+				_ if is_outside_section => {}
+				ast::Node::Conditional(conditional) => {
+					if !conditional.is_closed {
+						render.unclosed_conditionals.push(conditional.line.number);
+					}
+					let mut conditional_state = if is_kept {
+						ConditionalState::NotTaken
+					} else {
+						ConditionalState::ParentInactive
+					};
+					let mut else_line = None;
+					for branch in conditional.branches.iter() {
+						if let Some(else_line) = else_line {
+							let instruction = branch
+								.line
+								.text
+								.split_whitespace()
+								.next()
+								.unwrap_or_default();
+							self.recover(
+								located_error(
+									module_path,
+									branch.line.number,
+									format!(
+										"`{instruction}` after the `{ELSE_INSTRUCTION}` on line {else_line}"
+									),
+									state,
+								),
+								state,
+							)?;
+						}
+						let is_branch_kept = match (conditional_state, branch.condition) {
+							(ConditionalState::NotTaken, Some(condition)) => {
+								self.condition(module_path, &branch.line, condition, state)?
+							}
+							(ConditionalState::NotTaken, None) => true,
+							// Expressions of inactive branches may use symbols that are only defined
+							// in the active ones, so only the symbols of `ifdef` statements are checked.
+							(_, Some(condition @ ast::Condition::Defined { .. })) => {
+								self.condition(module_path, &branch.line, condition, state)?;
+								false
+							}
+							_ => false,
+						};
+						if is_branch_kept {
+							conditional_state = ConditionalState::Taken;
+						}
+						if branch.condition.is_none() {
+							else_line = Some(branch.line.number);
+						}
+						self.render_nodes(&branch.nodes, is_branch_kept, render, state)?;
+					}
+				}
+				ast::Node::Preserve {
+					line,
+					region,
+					is_closed,
+				} => {
+					if !is_closed {
+						render.unclosed_preserve = Some(line.number);
+					} else if is_kept {
+						render
+							.module_string
+							.push_str(&Self::preserve_region(region.clone(), state));
+					}
+				}
+				ast::Node::Unmatched(line) => {
+					let instruction = line.text.split_whitespace().next().unwrap_or_default();
+					let opening_instruction = if instruction == ENDPRESERVE_INSTRUCTION {
+						PRESERVE_INSTRUCTION
+					} else {
+						IFDEF_INSTRUCTION
+					};
+					self.recover(
+						located_error(
+							module_path,
+							line.number,
+							format!("`{instruction}` without a matching `{opening_instruction}`"),
+							state,
+						),
+						state,
+					)?;
+				}
+				_ if !is_kept => state.stats.conditional_lines_skipped += 1,
+				ast::Node::Statement(statement, line) => {
+					self.apply_statement(module_path, *statement, line, state)?;
+				}
+				ast::Node::Include(line) | ast::Node::IncludeFirst(line) => {
+					let first_existing = matches!(node, ast::Node::IncludeFirst(_));
+					state.enclosing_output_size += render.module_string.len();
+					let included_module_string =
+						self.include(module_path, line, first_existing, state);
+					state.enclosing_output_size -= render.module_string.len();
+					let included_module_string = included_module_string?;
+					if self.config.record_substitutions {
+						splice_line_substitutions(
+							&mut render.line_substitutions,
+							&render.module_string,
+							mem::take(&mut state.line_substitutions),
+						);
+					}
+					render.module_string.push_str(&included_module_string);
+				}
+				ast::Node::IncludeSpirv(line) => {
+					let spirv_string = self.include_spirv(module_path, line, state)?;
+					render.module_string.push_str(&spirv_string);
+				}
+				ast::Node::Define {
+					line,
+					name,
+					value: Some(value),
+				} => {
+					#[cfg(feature = "tracing")]
+					tracing::trace!(name, value = value.as_ref(), "defining macro");
+					self.define(
+						name,
+						value.to_string(),
+						Self::location(module_path, line),
+						state,
+					);
+				}
+				ast::Node::DefineFunction {
+					line,
+					name,
+					parameters,
+					body,
+				} => match macros::FunctionMacro::new(parameters, body) {
+					Ok(function_macro) => {
+						#[cfg(feature = "tracing")]
+						tracing::trace!(
+							name,
+							parameters,
+							body = body.as_ref(),
+							"defining function-like macro"
+						);
+						state
+							.function_macros
+							.insert(name.to_string(), function_macro);
+					}
+					Err(message) => {
+						self.recover(
+							located_error(module_path, line.number, message, state),
+							state,
+						)?;
+					}
+				},
+				ast::Node::Define {
+					line,
+					name,
+					value: None,
+				} => match self.config.array_definitions.get(*name) {
+					Some(array_definition) if array_definition.is_empty() => {}
+					Some(array_definition) => {
+						for (variable, read_only) in self
+							.config
+							.storage_accesses
+							.get(*name)
+							.into_iter()
+							.flatten()
+						{
+							state.storage_declarations.insert(
+								variable.clone(),
+								(*read_only, Self::location(module_path, line)),
+							);
+						}
+						render.module_string.push_str(array_definition);
+						render.module_string.push('\n');
+					}
+					None if !name.is_empty() => {
+						self.define(
+							name,
+							String::new(),
+							Self::location(module_path, line),
+							state,
+						);
+					}
+					None => {
+						render.module_string.push_str(line.text);
+						render.module_string.push('\n');
+					}
+				},
+				ast::Node::Text(line) => {
+					let location = Self::location(module_path, line);
+					Self::warn_deprecated_uses(line.text, &location, state);
+					Self::record_storage_writes(line.text, &location, state);
+					if self.config.hlsl_compatibility {
+						let text = match Self::hlsl_compatible_line(line.text) {
+							Ok(text) => text,
+							Err(message) => {
+								self.recover(
+									located_error(module_path, line.number, message, state),
+									state,
+								)?;
+								line.text.to_string()
+							}
+						};
+						render.module_string.push_str(&text);
+					} else {
+						render.module_string.push_str(line.text);
+					}
+					render.module_string.push('\n');
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn location(module_path: &path::Path, line: &ast::Line) -> SourceLocation {
+		SourceLocation {
+			path: module_path.to_path_buf(),
+			line: line.number,
+		}
+	}
+
+	/// Evaluates the condition of a branch of a conditional block, recording the tested symbols.
+	/// Invalid conditions are false.
+	fn condition(
+		&self,
+		module_path: &path::Path,
+		line: &ast::Line,
+		condition: ast::Condition,
+		state: &mut BuildState,
+	) -> Result<bool, ex::io::Error> {
+		match condition {
+			ast::Condition::Defined { symbol: None, .. } => {
+				self.recover(
+					located_error(
+						module_path,
+						line.number,
+						format!("missing symbol in `{}`", line.text.trim()),
+						state,
+					),
+					state,
+				)?;
+				Ok(false)
+			}
+			ast::Condition::Defined {
+				symbol: Some(name),
+				is_negated,
+			} => {
+				let location = Self::location(module_path, line);
+				Self::warn_deprecated_uses(name, &location, state);
+				state
+					.tested_symbols
+					.entry(name.to_string())
+					.or_insert(location);
+				Ok(self.is_symbol_defined(name, state) != is_negated)
+			}
+			ast::Condition::If(condition) => {
+				let lookup = |name: &str| {
+					self.build_definition(name, state)
+						.map(|(value, _)| value.to_string())
+				};
+				let is_defined = |name: &str| self.is_symbol_defined(name, state);
+				let is_included = |included_path: &str| {
+					observe_every_symbol(state);
+					let included_path = if self.config.normalize_paths {
+						lexically_normalized_path(path::Path::new(included_path))
+					} else {
+						path::PathBuf::from(included_path)
+					};
+					state
+						.stats
+						.files_read
+						.iter()
+						.any(|file_read| file_read.ends_with(&included_path))
+				};
+				let tests = expression::Tests {
+					is_defined: &is_defined,
+					is_included: &is_included,
+				};
+				match expression::condition(condition, &lookup, &tests) {
+					Ok((value, symbols)) => {
+						let location = Self::location(module_path, line);
+						for symbol in symbols {
+							Self::warn_deprecated_uses(&symbol, &location, state);
+							state
+								.tested_symbols
+								.entry(symbol)
+								.or_insert_with(|| location.clone());
+						}
+						Ok(value)
+					}
+					Err(message) => {
+						self.recover(
+							located_error(
+								module_path,
+								line.number,
+								format!("invalid condition `{condition}`: {message}"),
+								state,
+							),
+							state,
+						)?;
+						Ok(false)
+					}
+				}
+			}
+		}
+	}
+
+	/// Returns whether a symbol tested by a conditional statement is defined.
+	/// Symbols defined as `false` are not defined, unless [`ShaderBuilder::separate_symbols`] is enabled,
+	/// in which case only symbols without a value are.
+	fn is_symbol_defined(&self, name: &str, state: &BuildState) -> bool {
+		self.build_definition(name, state)
+			.is_some_and(|(value, _)| match self.config.separate_symbols {
+				true => value.is_empty(),
+				false => value.trim() != "false",
+			})
+	}
+
+	/// Applies a statement like `allow` or `deprecated` where it appears.
+	fn apply_statement(
+		&self,
+		module_path: &path::Path,
+		statement: ast::Statement,
+		line: &ast::Line,
+		state: &mut BuildState,
+	) -> Result<(), ex::io::Error> {
+		let trimmed = line.text.trim();
+		match statement {
+			ast::Statement::Allow => {
+				for code in trimmed[ALLOW_INSTRUCTION.len()..]
+					.trim()
+					.trim_start_matches('(')
+					.trim_end_matches(')')
+					.split(',')
+					.map(str::trim)
+					.filter(|code| !code.is_empty())
+				{
+					match code.parse() {
+						Ok(code) => {
+							state
+								.file_allowed_warnings
+								.insert((module_path.to_path_buf(), code));
+						}
+						Err(message) => self.recover(
+							located_error(module_path, line.number, message, state),
+							state,
+						)?,
+					}
+				}
+			}
+			ast::Statement::ColorSpace => {
+				match trimmed[COLOR_SPACE_INSTRUCTION.len()..]
+					.trim()
+					.parse::<ColorSpace>()
+				{
+					Ok(color_space) => {
+						for (name, color_constant_space) in self.config.color_constants.iter() {
+							if *color_constant_space != color_space {
+								state.warnings.push(Warning {
+									code: WarningCode::ColorSpaceMismatch,
+									message: format!(
+										"the module expects {color_space} colors, but `{name}` is put as a {color_constant_space} color"
+									),
+									location: Self::location(module_path, line),
+								});
+							}
+						}
+					}
+					Err(message) => self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?,
+				}
+			}
+			ast::Statement::Deprecated => {
+				let Some(captures) = DEPRECATED_REGEX.captures(trimmed) else {
+					return self.recover(
+						located_error(
+							module_path,
+							line.number,
+							format!("missing symbol in `{trimmed}`"),
+							state,
+						),
+						state,
+					);
+				};
+				let name = &captures[1];
+				state.deprecations.insert(
+					name.to_string(),
+					(
+						regex::Regex::new(&format!(
+							r"(?P<declaration>fn\s+)?\b{}\b",
+							regex::escape(name)
+						))
+						.unwrap(),
+						captures
+							.get(2)
+							.map_or_else(String::new, |message| message.as_str().to_string()),
+					),
+				);
+			}
+			ast::Statement::Declare => match DECLARE_REGEX.captures(trimmed) {
+				Some(captures) => state.forward_declarations.push((
+					captures[1].to_string(),
+					captures[2].to_string(),
+					Self::location(module_path, line),
+				)),
+				None => self.recover(
+					located_error(
+						module_path,
+						line.number,
+						format!("expected `{DECLARE_INSTRUCTION} KIND NAME;`, found `{trimmed}`"),
+						state,
+					),
+					state,
+				)?,
+			},
+			ast::Statement::Once => {
+				state.once_modules.insert(normalized_path(module_path));
+			}
+			ast::Statement::DefineAdd => {
+				let Some(captures) = DEFINE_ADD_REGEX.captures(trimmed) else {
+					return self.recover(
+						located_error(
+							module_path,
+							line.number,
+							format!("expected `{DEFINE_ADD_INSTRUCTION} NAME INTEGER`, found `{trimmed}`"),
+							state,
+						),
+						state,
+					);
+				};
+				let name = &captures[1];
+				let sum = match self.accumulated_definition(name, state) {
+					None => Ok(format!("{}{}", &captures[2], &captures[3])),
+					Some(value) => INTEGER_REGEX
+						.captures(value)
+						.and_then(|value_captures| {
+							let sum = value_captures[1]
+								.parse::<i64>()
+								.ok()?
+								.checked_add(captures[2].parse().ok()?)?;
+							let suffix = match &value_captures[2] {
+								"" => &captures[3],
+								suffix => suffix,
+							};
+							Some(format!("{sum}{suffix}"))
+						})
+						.ok_or_else(|| format!("`{name}` is `{value}`, which is not an integer")),
+				};
+				match sum {
+					Ok(sum) => self.accumulate(name, sum, Self::location(module_path, line), state),
+					Err(message) => self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?,
+				}
+			}
+			ast::Statement::DefineAppend => match DEFINE_APPEND_REGEX.captures(trimmed) {
+				Some(captures) => {
+					let list = match self.accumulated_definition(&captures[1], state) {
+						Some(value) if !value.is_empty() => format!("{value}, {}", &captures[2]),
+						_ => captures[2].to_string(),
+					};
+					self.accumulate(&captures[1], list, Self::location(module_path, line), state);
+				}
+				None => self.recover(
+					located_error(
+						module_path,
+						line.number,
+						format!(
+							"expected `{DEFINE_APPEND_INSTRUCTION} NAME ITEM`, found `{trimmed}`"
+						),
+						state,
+					),
+					state,
+				)?,
+			},
+			ast::Statement::DefineDefault => match DEFINE_DEFAULT_REGEX.captures(trimmed) {
+				Some(captures) => {
+					if self.build_definition(&captures[1], state).is_none() {
+						self.define(
+							&captures[1],
+							captures
+								.get(2)
+								.map_or_else(String::new, |value| value.as_str().to_string()),
+							Self::location(module_path, line),
+							state,
+						);
+					}
+				}
+				None => self.recover(
+					located_error(
+						module_path,
+						line.number,
+						format!("missing name in `{trimmed}`"),
+						state,
+					),
+					state,
+				)?,
+			},
+			ast::Statement::Module => {
+				let message = match MODULE_REGEX.captures(trimmed) {
+					Some(captures) if version_components(&captures[2]).is_none() => {
+						Some(format!("invalid version `{}`", &captures[2]))
+					}
+					Some(captures) => match state.module_versions.get(&captures[1]) {
+						Some((version, location)) if *version != captures[2] => Some(format!(
+							"module `{}` is version {} here but version {version} at {location}",
+							&captures[1], &captures[2]
+						)),
+						Some(_) => None,
+						None => {
+							state.module_versions.insert(
+								captures[1].to_string(),
+								(captures[2].to_string(), Self::location(module_path, line)),
+							);
+							None
+						}
+					},
+					None => Some(format!(
+						"expected `{MODULE_INSTRUCTION} NAME VERSION`, found `{trimmed}`"
+					)),
+				};
+				if let Some(message) = message {
+					self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?;
+				}
+			}
+			ast::Statement::Requires => match REQUIRES_REGEX.captures(trimmed) {
+				Some(captures) if version_components(&captures[3]).is_some() => {
+					state.version_requirements.push((
+						captures[1].to_string(),
+						captures
+							.get(2)
+							.map_or(">=", |operator| operator.as_str())
+							.to_string(),
+						captures[3].to_string(),
+						Self::location(module_path, line),
+					))
+				}
+				_ => self.recover(
+					located_error(
+						module_path,
+						line.number,
+						format!(
+							"expected `{REQUIRES_INSTRUCTION} NAME OPERATOR VERSION`, found `{trimmed}`"
+						),
+						state,
+					),
+					state,
+				)?,
+			},
+			ast::Statement::Expect => {
+				let message = match EXPECT_REGEX.captures(trimmed) {
+					Some(captures) => self.unmet_expectation(&captures[1], &captures[2], state),
+					None => Some(format!(
+						"expected `{EXPECT_INSTRUCTION} NAME: TYPE`, found `{trimmed}`"
+					)),
+				};
+				if let Some(message) = message {
+					self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Loads the modules of an `include` statement, returning their concatenated strings,
+	/// or only the first existing module of an `include_first` statement if `first_existing` is set.
+	fn include(
+		&self,
+		module_path: &path::Path,
+		line: &ast::Line,
+		first_existing: bool,
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		let mut includes: Vec<(&str, Option<&str>)> = Vec::new();
+		for argument in line.text.split_whitespace().skip(1) {
+			match (
+				argument.strip_prefix(SECTION_ARGUMENT_PREFIX),
+				includes.last_mut(),
+			) {
+				(Some(name), Some((_, section @ None))) => *section = Some(name),
+				(Some(_), _) => self.recover(
+					located_error(
+						module_path,
+						line.number,
+						format!("`{argument}` does not follow a module"),
+						state,
+					),
+					state,
+				)?,
+				(None, _) => includes.push((argument, None)),
+			}
+		}
+		if first_existing {
+			match includes.iter().find(|(include, _)| {
+				// Ambiguous includes exist, and are reported below.
+				self.resolved_include(module_path, include)
+					.map_or(true, |include_path| self.is_module(&include_path))
+			}) {
+				Some(include) => includes = vec![*include],
+				None => {
+					let error = diagnostic_error(
+						module_path,
+						io::ErrorKind::NotFound,
+						diagnostic(
+							format!("no module of `{}` exists", line.text.trim()),
+							Self::location(module_path, line),
+							state.include_chain.clone(),
+							state,
+						),
+					);
+					self.recover(error, state)?;
+					return Ok(String::new());
+				}
+			}
+		}
+		let mut module_string = String::new();
+		let mut line_substitutions = BTreeMap::new();
+		for (include, section) in includes {
+			let include_path = match self.resolved_include(module_path, include) {
+				Ok(include_path) => include_path,
+				Err(message) => {
+					self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?;
+					continue;
+				}
+			};
+			#[cfg(feature = "tracing")]
+			tracing::debug!(include, path = %include_path.display(), section, "resolving include");
+			let normalized_include_path = normalized_path(&include_path);
+			let included_module = (normalized_include_path.clone(), section.map(str::to_string));
+			if state.once_modules.contains(&normalized_include_path)
+				|| (self.config.deduplicate_includes
+					&& state.included_modules.contains(&included_module))
+			{
+				continue;
+			}
+			state.included_modules.insert(included_module);
+			state.include_chain.push(Self::location(module_path, line));
+			if let Some(cycle_start) = state
+				.include_chain
+				.iter()
+				.position(|include| normalized_path(&include.path) == normalized_include_path)
+			{
+				let cycle: Vec<String> = state.include_chain[cycle_start..]
+					.iter()
+					.map(|include| include.path.display().to_string())
+					.chain([include_path.display().to_string()])
+					.collect();
+				let error = include_error(
+					&include_path,
+					io::ErrorKind::InvalidData,
+					format!("circular include: {}", cycle.join(" → ")),
+					state,
+				);
+				state.include_chain.pop();
+				self.recover(error, state)?;
+				continue;
+			}
+			let mut included_module_string =
+				self.load_shader_module(&include_path, section, state)?;
+			state.include_chain.pop();
+			if self.config.debug_markers {
+				let section_argument = section
+					.map(|section| format!(" {SECTION_ARGUMENT_PREFIX}{section}"))
+					.unwrap_or_default();
+				module_string.push_str(&Self::preserve_region(
+					format!(
+						"// --- begin include: {}{section_argument} ---\n",
+						include_path.display()
+					),
+					state,
+				));
+				if !included_module_string.is_empty() && !included_module_string.ends_with('\n') {
+					included_module_string.push('\n');
+				}
+				included_module_string.push_str(&Self::preserve_region(
+					"// --- end include ---\n".to_string(),
+					state,
+				));
+			}
+			if self.config.record_substitutions {
+				splice_line_substitutions(
+					&mut line_substitutions,
+					&module_string,
+					mem::take(&mut state.line_substitutions),
+				);
+			}
+			module_string.push_str(&included_module_string);
+		}
+		state.line_substitutions = line_substitutions;
+		Ok(module_string)
+	}
+
+	/// Loads the SPIR-V module of an `include_spirv` statement, returning its entry point as a WGSL function.
+	fn include_spirv(
+		&self,
+		module_path: &path::Path,
+		line: &ast::Line,
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		let trimmed = line.text.trim();
+		let Some(captures) = INCLUDE_SPIRV_REGEX.captures(trimmed) else {
+			self.recover(
+				located_error(
+					module_path,
+					line.number,
+					format!(
+						"expected `{INCLUDE_SPIRV_INSTRUCTION} PATH as fn NAME`, found `{trimmed}`"
+					),
+					state,
+				),
+				state,
+			)?;
+			return Ok(String::new());
+		};
+		#[cfg(feature = "spirv_includes")]
+		{
+			let spirv_path = match self.resolved_include(module_path, &captures[1]) {
+				Ok(spirv_path) => spirv_path,
+				Err(message) => {
+					self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?;
+					return Ok(String::new());
+				}
+			};
+			let spirv_string = match &self.module_cache {
+				Some(module_cache) => module_cache.read(&spirv_path, &*self.source_resolver),
+				None => source_resolver::read_module(&*self.source_resolver, &spirv_path),
+			}
+			.map_err(|error| error.to_string())
+			.and_then(|module_bytes| Self::spirv_to_wgsl(&module_bytes, &captures[2]));
+			match spirv_string {
+				Ok(spirv_string) => {
+					state.stats.files_read.push(if self.config.normalize_paths {
+						normalized_path(&spirv_path)
+					} else {
+						spirv_path
+					});
+					Ok(spirv_string)
+				}
+				Err(message) => {
+					self.recover(
+						located_error(module_path, line.number, message, state),
+						state,
+					)?;
+					Ok(String::new())
+				}
+			}
+		}
+		#[cfg(not(feature = "spirv_includes"))]
+		{
+			let _ = captures;
+			self.recover(
+				located_error(
+					module_path,
+					line.number,
+					format!(
+						"`{INCLUDE_SPIRV_INSTRUCTION}` requires the **spirv_includes** feature"
+					),
+					state,
+				),
+				state,
+			)?;
+			Ok(String::new())
+		}
+	}
+
+	/// Converts a SPIR-V module to WGSL with [`naga`], turning its entry point into a function named `function_name`.
+	/// The other functions and the global variables of the module are prefixed with the name of the function,
+	/// and entry point attributes are removed.
+	#[cfg(feature = "spirv_includes")]
+	fn spirv_to_wgsl(module_bytes: &[u8], function_name: &str) -> Result<String, String> {
+		let mut module =
+			naga::front::spv::parse_u8_slice(module_bytes, &naga::front::spv::Options::default())
+				.map_err(|error| format!("invalid SPIR-V module: {error}"))?;
+		if module.entry_points.len() != 1 {
+			return Err(format!(
+				"SPIR-V module has {} entry points instead of 1",
+				module.entry_points.len()
+			));
+		}
+		let prefix = |name: &mut Option<String>| {
+			if let Some(name) = name {
+				*name = format!("{function_name}_{name}");
+			}
+		};
+		for (_, function) in module.functions.iter_mut() {
+			prefix(&mut function.name);
+		}
+		for (_, global_variable) in module.global_variables.iter_mut() {
+			prefix(&mut global_variable.name);
+		}
+		let mut function = module.entry_points.remove(0).function;
+		function.name = Some(function_name.to_string());
+		for argument in function.arguments.iter_mut() {
+			argument.binding = None;
+		}
+		if let Some(result) = &mut function.result {
+			result.binding = None;
+		}
+		module.functions.append(function, naga::Span::UNDEFINED);
+		let module_info = naga::valid::Validator::new(
+			naga::valid::ValidationFlags::all(),
+			naga::valid::Capabilities::all(),
+		)
+		.validate(&module)
+		.map_err(|error| format!("invalid SPIR-V module: {}", error.into_inner()))?;
+		naga::back::wgsl::write_string(
+			&module,
+			&module_info,
+			naga::back::wgsl::WriterFlags::empty(),
+		)
+		.map_err(|error| format!("SPIR-V conversion failed: {error}"))
+	}
+
+	/// Returns an error for every name declared by several linked libraries,
+	/// or by a linked library and the source of the shader.
+	fn library_conflicts(&self, source_path: &path::Path, source: &str) -> Vec<ex::io::Error> {
+		let conflict = |kind: &str, name: &str, declarer: &str, declaration: &(String, String)| {
+			let (declared_kind, other_declarer) = declaration;
+			preprocess_error(
+				source_path,
+				format!(
+					"`{kind} {name}` is declared by {declarer}, \
+					but {other_declarer} already declares `{declared_kind} {name}`"
+				),
+			)
+		};
+		let mut declarations: HashMap<&str, (String, String)> = HashMap::new();
+		let mut errors = Vec::new();
+		for library in self.libraries.iter() {
+			let declarer = format!("library `{}`", library.name);
+			for (kind, name) in library.declarations.iter() {
+				match declarations.get(name.as_str()) {
+					Some(declaration) => errors.push(conflict(kind, name, &declarer, declaration)),
+					None => {
+						declarations.insert(name, (kind.clone(), declarer.clone()));
+					}
+				}
+			}
+		}
+		for (kind, name) in ordering::declarations(source) {
+			if let Some(declaration) = declarations.get(name.as_str()) {
+				errors.push(conflict(&kind, &name, "the shader", declaration));
+			}
+		}
+		errors
+	}
+
+	/// Fails if a built string exceeds [`ShaderBuilder::max_output_size`].
+	fn check_output_size(
+		&self,
+		module_path: &path::Path,
+		size: usize,
+	) -> Result<(), ex::io::Error> {
+		match self.config.max_output_size {
+			Some(max_output_size) if size > max_output_size => Err(preprocess_error(
+				module_path,
+				format!(
+					"output of {size} bytes exceeds the maximum output size of {max_output_size} bytes"
+				),
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	/// Returns an include path with its longest matching prefix replaced,
+	/// see [`ShaderBuilder::remap_include_prefix`].
+	fn remapped_include(&self, include: &str) -> String {
+		self.config
+			.include_prefixes
+			.iter()
+			.filter(|(prefix, _)| include.starts_with(prefix.as_str()))
+			.max_by_key(|(prefix, _)| prefix.len())
+			.map_or_else(
+				|| include.to_string(),
+				|(prefix, replacement)| format!("{replacement}{}", &include[prefix.len()..]),
+			)
+	}
+
+	/// Returns whether a module exists at `module_path`, in the module cache or through the source resolver.
+	fn is_module(&self, module_path: &path::Path) -> bool {
+		self.module_cache
+			.as_ref()
+			.is_some_and(|module_cache| module_cache.contains(module_path))
+			|| self.source_resolver.exists(module_path)
+	}
+
+	/// Returns the path of an included module, see [`ShaderBuilder::include_resolution`].
+	/// Fails if different modules exist relative to the including module and to the working directory.
+	fn resolved_include(
+		&self,
+		module_path: &path::Path,
+		include: &str,
+	) -> Result<path::PathBuf, String> {
+		let include = self
+			.config
+			.module_aliases
+			.iter()
+			.find(|(aliased_include, _)| aliased_include == include)
+			.map_or(include, |(_, target)| target.as_str());
+		let include_path = path::PathBuf::from(self.remapped_include(include));
+		let is_module = |path: &path::Path| self.is_module(path);
+		if self.config.include_resolution == IncludeResolution::RelativeToModule {
+			if let Some(module_directory) = module_path.parent() {
+				let relative_path = module_directory.join(&include_path);
+				if relative_path != include_path && is_module(&relative_path) {
+					let is_same_module = |other_path: &path::Path| {
+						normalized_path(&relative_path) == normalized_path(other_path)
+							|| relative_path.canonicalize().is_ok_and(|canonical_path| {
+								other_path.canonicalize().ok() == Some(canonical_path)
+							})
+					};
+					if is_module(&include_path) && !is_same_module(&include_path) {
+						return Err(format!(
+							"`{include}` is ambiguous: both {} and {} exist, relative to the including module and to the working directory",
+							relative_path.display(),
+							include_path.display()
+						));
+					}
+					return Ok(relative_path);
+				}
+			}
+		}
+		if include_path.is_relative() && !is_module(&include_path) {
+			if let Some(searched_path) = self
+				.config
+				.include_paths
+				.iter()
+				.map(|include_directory| include_directory.join(&include_path))
+				.find(|searched_path| is_module(searched_path))
+			{
+				return Ok(searched_path);
+			}
+		}
+		Ok(include_path)
+	}
+
+	/// Records the contents of a `preserve` region and returns the line that replaces it
+	/// until [`ShaderBuilder::restore_preserved_regions`].
+	fn preserve_region(region: String, state: &mut BuildState) -> String {
+		state.preserved_regions.push(region);
+		format!(
+			"{PRESERVED_REGION_DELIMITER}{}{PRESERVED_REGION_DELIMITER}\n",
+			state.preserved_regions.len() - 1
+		)
+	}
+
+	/// Replaces the placeholders of `preserve` regions by their untouched contents.
+	fn restore_preserved_regions(
+		mut source_string: String,
+		preserved_regions: &[String],
+	) -> String {
+		for (index, region) in preserved_regions.iter().enumerate() {
+			source_string = source_string.replace(
+				&format!("{PRESERVED_REGION_DELIMITER}{index}{PRESERVED_REGION_DELIMITER}\n"),
+				region,
+			);
+		}
+		source_string
+	}
+
+	/// Converts a GLSL module to WGSL with [`naga`], if the module has a GLSL shader stage extension
+	/// (`.vert`, `.frag` or `.comp`).
+	#[cfg(feature = "glsl_includes")]
+	fn glsl_to_wgsl(
+		module_path: &path::Path,
+		module_source: &str,
+	) -> Option<Result<String, ex::io::Error>> {
+		let stage = match module_path.extension()?.to_str()? {
+			"vert" => naga::ShaderStage::Vertex,
+			"frag" => naga::ShaderStage::Fragment,
+			"comp" => naga::ShaderStage::Compute,
+			_ => return None,
+		};
+		let module =
+			match naga::front::glsl::Frontend::default().parse(&stage.into(), module_source) {
+				Ok(module) => module,
+				Err(error) => {
+					return Some(Err(preprocess_error(
+						module_path,
+						error.emit_to_string(module_source),
+					)))
+				}
+			};
+		let module_info = match naga::valid::Validator::new(
+			naga::valid::ValidationFlags::all(),
+			naga::valid::Capabilities::all(),
+		)
+		.validate(&module)
+		{
+			Ok(module_info) => module_info,
+			Err(error) => {
+				return Some(Err(preprocess_error(
+					module_path,
+					error.emit_to_string(module_source),
+				)))
+			}
+		};
+		Some(
+			naga::back::wgsl::write_string(
+				&module,
+				&module_info,
+				naga::back::wgsl::WriterFlags::empty(),
+			)
+			.map_err(|error| {
+				preprocess_error(module_path, format!("GLSL conversion failed: {error}"))
+			}),
+		)
+	}
+
+	fn expand_templates(
+		&self,
+		module_path: &path::Path,
+		module_string: String,
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		if !self.config.templates {
+			return Ok(module_string);
+		}
+		let mut undefined_templates = Vec::new();
+		let mut substitutions = Vec::new();
+		if self.config.record_substitutions {
+			for (index, line) in module_string.lines().enumerate() {
+				for captures in TEMPLATE_REGEX.captures_iter(line) {
+					if let Some((value, _)) = self.build_definition(&captures[1], state) {
+						let value = value.to_string();
+						record_substitution(
+							&mut state.line_substitutions,
+							index,
+							&captures[1],
+							&value,
+						);
+					}
+				}
+			}
+		}
+		let module_string = TEMPLATE_REGEX
+			.replace_all(&module_string, |captures: &regex::Captures| {
+				let name = &captures[1];
+				match self.build_definition(name, state) {
+					Some((value, _)) => {
+						substitutions.push(name.to_string());
+						value.to_string()
+					}
+					None => captures.get(2).map_or_else(
+						|| {
+							undefined_templates.push(captures[0].to_string());
+							String::new()
+						},
+						|default| default.as_str().to_string(),
+					),
+				}
+			})
+			.into_owned();
+		for name in substitutions {
+			*state.stats.substitutions.entry(name).or_default() += 1;
+		}
+		for template in undefined_templates {
+			self.recover(
+				preprocess_error(
+					module_path,
+					format!("`{template}` is not defined and has no default value"),
+				),
+				state,
+			)?;
+		}
+		Ok(module_string)
+	}
+
+	/// Expands the invocations of the function-like macros defined so far, see [`macros::expand`].
+	fn expand_function_macros(
+		&self,
+		module_path: &path::Path,
+		module_string: String,
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		if state.function_macros.is_empty() {
+			return Ok(module_string);
+		}
+		let expanded = macros::expand(
+			&module_string,
+			&state.function_macros,
+			self.config.max_expansion_size,
+			&mut state.stats.macro_expansions,
+		);
+		match expanded {
+			Ok(module_string) => Ok(module_string),
+			Err(message) => {
+				self.recover(preprocess_error(module_path, message), state)?;
+				Ok(module_string)
+			}
+		}
+	}
+
+	fn substitute(
+		&self,
+		module_path: &path::Path,
+		mut string: String,
+		definitions: &HashMap<String, String>,
+		state: &mut BuildState,
+	) -> Result<String, ex::io::Error> {
+		let substituted_definitions: HashMap<String, String>;
+		let definitions = if self.config.separate_symbols {
+			substituted_definitions = definitions
+				.iter()
+				.filter(|(_, value)| !value.is_empty())
+				.map(|(name, value)| (name.clone(), value.clone()))
+				.collect();
+			&substituted_definitions
+		} else {
+			definitions
+		};
+		if let Some(placeholder_regex) = &state.placeholder_regex {
+			self.check_interruption(module_path, state)?;
+			if self.config.record_substitutions {
+				for (index, line) in string.lines().enumerate() {
+					for captures in placeholder_regex.captures_iter(line) {
+						let name = captures
+							.name("name")
+							.map_or(&captures[0], |name| name.as_str());
+						if let Some(value) = definitions.get(name) {
+							record_substitution(&mut state.line_substitutions, index, name, value);
+						}
+					}
+				}
+			}
+			let mut substitutions: HashMap<String, usize> = HashMap::new();
+			string = placeholder_regex
+				.replace_all(&string, |captures: &regex::Captures| {
+					let placeholder = &captures[0];
+					let name = captures
+						.name("name")
+						.map_or(placeholder, |name| name.as_str());
+					observe_symbol(name, state);
+					match definitions.get(name) {
+						Some(value) => {
+							*substitutions.entry(name.to_string()).or_default() += 1;
+							value.clone()
+						}
+						None => placeholder.to_string(),
+					}
+				})
+				.into_owned();
+			for (name, value) in definitions {
+				let substitutions = substitutions.get(name).copied().unwrap_or_default();
+				#[cfg(feature = "tracing")]
+				tracing::trace!(name, substitutions, "substituting definition");
+				*state.stats.substitutions.entry(name.clone()).or_default() += substitutions;
+				if substitutions > 0 {
+					state
+						.stats
+						.macro_expansions
+						.entry(name.clone())
+						.or_default()
+						.record(substitutions, value.len());
+				}
+			}
+			return Ok(string);
+		}
+		if state.observed_symbols.is_some() {
+			// Definitions may be substituted directly or through the values of other definitions.
+			for name in definitions.keys() {
+				if identifier_occurrences(&string, name).next().is_some()
+					|| definitions
+						.values()
+						.any(|value| identifier_occurrences(value, name).next().is_some())
+				{
+					observe_symbol(name, state);
+				}
+			}
+		}
+		let definitions = self.expanded_definitions(module_path, definitions, state)?;
+		for (name, value) in Self::ordered_definitions(&definitions) {
+			self.check_interruption(module_path, state)?;
+			let occurrences: Vec<usize> = identifier_occurrences(&string, name).collect();
+			let substitutions = occurrences.len();
+			#[cfg(feature = "tracing")]
+			tracing::trace!(name, value, substitutions, "substituting definition");
+			if self.config.record_substitutions && substitutions > 0 {
+				for (index, line) in string.lines().enumerate() {
+					if identifier_occurrences(line, name).next().is_some() {
+						record_substitution(&mut state.line_substitutions, index, name, value);
+					}
+				}
+			}
+			*state.stats.substitutions.entry(name.clone()).or_default() += substitutions;
+			if substitutions > 0 {
+				state
+					.stats
+					.macro_expansions
+					.entry(name.clone())
+					.or_default()
+					.record(substitutions, value.len());
+				string = replaced_occurrences(&string, name, value, occurrences);
+			}
+		}
+		Ok(string)
+	}
+
+	/// Returns the definitions with the names of other definitions in their values expanded, recursively,
+	/// so definitions can build on each other like `//!define SIZE WIDTH * HEIGHT`.
+	/// Definitions that are part of a cycle are reported and kept as they are.
+	fn expanded_definitions(
+		&self,
+		module_path: &path::Path,
+		definitions: &HashMap<String, String>,
+		state: &mut BuildState,
+	) -> Result<HashMap<String, String>, ex::io::Error> {
+		let ordered_definitions = Self::ordered_definitions(definitions);
+		let mut expanded_definitions = HashMap::new();
+		for (name, _) in ordered_definitions.iter() {
+			if let Err(cycle) = expanded_definition(
+				name,
+				&ordered_definitions,
+				&mut expanded_definitions,
+				&mut Vec::new(),
+			) {
+				self.recover(
+					preprocess_error(
+						module_path,
+						format!("circular definition: {}", cycle.join(" → ")),
+					),
+					state,
+				)?;
+				for name in cycle {
+					let value = definitions[&name].clone();
+					expanded_definitions.insert(name, value);
+				}
+				if let Ok(value) = expanded_definition(
+					name,
+					&ordered_definitions,
+					&mut expanded_definitions,
+					&mut Vec::new(),
+				) {
+					expanded_definitions.insert(name.to_string(), value);
+				}
+			}
+		}
+		if let Some(max_expansion_size) = self.config.max_expansion_size {
+			let mut oversized: Vec<(String, usize)> = expanded_definitions
+				.iter()
+				.filter(|(_, value)| value.len() > max_expansion_size)
+				.map(|(name, value)| (name.clone(), value.len()))
+				.collect();
+			oversized.sort();
+			for (name, length) in oversized {
+				self.recover(
+					preprocess_error(
+						module_path,
+						expansion_size_error(&name, length, max_expansion_size),
+					),
+					state,
+				)?;
+				let value = definitions[&name].clone();
+				expanded_definitions.insert(name, value);
+			}
+		}
+		Ok(expanded_definitions)
+	}
+
+	/// Returns why an `expect` statement is not met: the name must be put from Rust code,
+	/// with the expected type if its type is known.
+	fn unmet_expectation(&self, name: &str, ty: &str, state: &BuildState) -> Option<String> {
+		match self.build_definition(name, state) {
+			None | Some((_, DefinitionLayer::File)) => Some(format!(
+				"`{name}` is expected to be put from Rust code as `{ty}`, but it is not"
+			)),
+			Some((_, DefinitionLayer::Builder)) => {
+				let put_type = self.config.definition_types.get(name)?;
+				let normalized = |ty: &str| ty.split_whitespace().collect::<String>();
+				(normalized(put_type) != normalized(ty)).then(|| {
+					format!("`{name}` is expected to be `{ty}`, but it is put as `{put_type}`")
+				})
+			}
+			Some(_) => None,
+		}
+	}
+
+	/// Defines a macro from a module, warning about redefinitions.
+	/// Definitions from Rust code of the [`DefinitionLayer::Default`] layer are overridden silently.
+	fn define(&self, name: &str, value: String, location: SourceLocation, state: &mut BuildState) {
+		let is_shadowed = self.is_shadowed(name, state);
+		if is_shadowed || state.definitions.contains_key(name) {
+			state.warnings.push(Warning {
+				code: WarningCode::Redefinition,
+				message: match state.stats.definition_provenance.get(name) {
+					Some(provenance) => format!("`{name}` is already defined at {provenance}"),
+					None => format!("`{name}` is already defined"),
+				},
+				location: location.clone(),
+			});
+		}
+		if !is_shadowed {
+			state
+				.stats
+				.definition_provenance
+				.insert(name.to_string(), Provenance::Module(location.clone()));
+		}
+		state
+			.file_definitions
+			.entry(name.to_string())
+			.or_insert(location);
+		state.definitions.insert(name.to_string(), value);
+	}
+
+	/// Returns the value that `define_add` and `define_append` statements accumulate on,
+	/// from the modules or the [`DefinitionLayer::Default`] layer.
+	fn accumulated_definition<'a>(&self, name: &str, state: &'a BuildState) -> Option<&'a str> {
+		state
+			.definitions
+			.get(name)
+			.or_else(|| state.default_definitions.get(name))
+			.map(String::as_str)
+	}
+
+	/// Sets the value of a definition accumulated by `define_add` and `define_append` statements,
+	/// keeping the provenance of its first definition without a redefinition warning.
+	fn accumulate(
+		&self,
+		name: &str,
+		value: String,
+		location: SourceLocation,
+		state: &mut BuildState,
+	) {
+		match state.definitions.get_mut(name) {
+			Some(accumulated_value) => *accumulated_value = value,
+			None => self.define(name, value, location, state),
+		}
+		state.accumulated_definitions.insert(name.to_string());
+	}
+
+	/// Returns the error of a declaration named by a keyword or a reserved word of WGSL,
+	/// naming the macros that expand to it.
+	fn reserved_declaration_error(
+		&self,
+		source_path: &path::Path,
+		reserved_declaration: reserved::ReservedDeclaration,
+		state: &BuildState,
+	) -> ex::io::Error {
+		let mut message = format!(
+			"`{}` declares `{}`, which is {} of WGSL and can not be used as an identifier",
+			reserved_declaration.declaration, reserved_declaration.name, reserved_declaration.kind
+		);
+		let mut macro_names: Vec<&String> = state
+			.default_definitions
+			.iter()
+			.chain(state.definitions.iter())
+			.chain(state.builder_definitions.iter())
+			.chain(state.overlay_definitions.iter())
+			.filter(|(_, value)| value.trim() == reserved_declaration.name)
+			.map(|(name, _)| name)
+			.collect();
+		macro_names.sort();
+		macro_names.dedup();
+		for name in macro_names {
+			message += &match state.stats.definition_provenance.get(name) {
+				Some(provenance) => format!(", `{name}` defined at {provenance} expands to it"),
+				None => format!(", `{name}` expands to it"),
+			};
+		}
+		preprocess_error(source_path, message)
+	}
+
+	/// Rewrites the HLSL syntax of a line, see [`ShaderBuilder::hlsl_compatibility`].
+	fn hlsl_compatible_line(line: &str) -> Result<String, String> {
+		let line = NUMTHREADS_REGEX.replace_all(line, "@compute @workgroup_size($1)");
+		let mut unsupported_semantic = None;
+		let line = SEMANTIC_REGEX.replace_all(&line, |captures: &regex::Captures| {
+			let built_in = match &captures[2] {
+				"SV_DispatchThreadID" => "global_invocation_id",
+				"SV_GroupThreadID" => "local_invocation_id",
+				"SV_GroupID" => "workgroup_id",
+				"SV_GroupIndex" => "local_invocation_index",
+				semantic => {
+					unsupported_semantic.get_or_insert_with(|| semantic.to_string());
+					return captures[0].to_string();
+				}
+			};
+			format!("@builtin({built_in}) {}", &captures[1])
+		});
+		match unsupported_semantic {
+			Some(semantic) => Err(format!("unsupported HLSL semantic `{semantic}`")),
+			None => Ok(line.into_owned()),
+		}
+	}
+
+	/// Warns about uses of deprecated symbols in a line, ignoring the declarations of deprecated functions.
+	fn warn_deprecated_uses(line: &str, location: &SourceLocation, state: &mut BuildState) {
+		let mut deprecated_uses: Vec<Warning> = state
+			.deprecations
+			.iter()
+			.filter(|(_, (pattern, _))| {
+				pattern
+					.captures_iter(line)
+					.any(|captures| captures.name("declaration").is_none())
+			})
+			.map(|(name, (_, message))| Warning {
+				code: WarningCode::Deprecated,
+				message: if message.is_empty() {
+					format!("`{name}` is deprecated")
+				} else {
+					format!("`{name}` is deprecated: {message}")
+				},
+				location: location.clone(),
+			})
+			.collect();
+		deprecated_uses.sort_by(|warning, other| warning.message.cmp(&other.message));
+		state.warnings.extend(deprecated_uses);
+	}
+
+	/// Records writes to storage buffers declared by [`ShaderBuilder::put_bindings`] in a line,
+	/// with the errors reported if the buffers are declared as read-only.
+	fn record_storage_writes(line: &str, location: &SourceLocation, state: &mut BuildState) {
+		let mut written_storage: Vec<String> = state
+			.storage_patterns
+			.iter()
+			.filter(|(_, pattern)| pattern.is_match(line))
+			.map(|(variable, _)| variable.clone())
+			.collect();
+		written_storage.sort();
+		for variable in written_storage {
+			let error = located_error(
+				&location.path,
+				location.line,
+				format!("`{variable}` is written but is declared as a read-only storage buffer"),
+				state,
+			);
+			state.storage_write_errors.push((variable.clone(), error));
+			state.written_storage.insert(variable);
+		}
+	}
+
+	/// Adds warnings that need the whole build, removes allowed warnings,
+	/// and reports denied warnings as errors.
+	fn report_warnings(&self, state: &mut BuildState) -> Result<Vec<Warning>, ex::io::Error> {
+		let mut unused_definitions: Vec<(&String, &SourceLocation)> = state
+			.file_definitions
+			.iter()
+			.filter(|(name, _)| {
+				state
+					.stats
+					.substitutions
+					.get(*name)
+					.copied()
+					.unwrap_or_default()
+					== 0 && !state.tested_symbols.contains_key(*name)
+			})
+			.collect();
+		unused_definitions.sort_by_key(|(_, location)| (&location.path, location.line));
+		let unused_definition_warnings: Vec<Warning> = unused_definitions
+			.into_iter()
+			.map(|(name, location)| Warning {
+				code: WarningCode::UnusedDefine,
+				message: format!("`{name}` is never used"),
+				location: location.clone(),
+			})
+			.collect();
+		state.warnings.extend(unused_definition_warnings);
+		let mut unwritten_storage: Vec<(&String, &SourceLocation)> = state
+			.storage_declarations
+			.iter()
+			.filter(|(variable, (read_only, _))| {
+				!read_only && !state.written_storage.contains(*variable)
+			})
+			.map(|(variable, (_, location))| (variable, location))
+			.collect();
+		unwritten_storage.sort_by_key(|(variable, _)| *variable);
+		state.warnings.extend(
+			unwritten_storage
+				.into_iter()
+				.map(|(variable, location)| Warning {
+					code: WarningCode::UnwrittenStorage,
+					message: format!(
+					"`{variable}` is declared as a `read_write` storage buffer but is never written"
+				),
+					location: location.clone(),
+				}),
+		);
+		if self.config.undefined_symbol_lint {
+			let mut undefined_symbols: Vec<(&String, &SourceLocation)> = state
+				.tested_symbols
+				.iter()
+				.filter(|(name, _)| {
+					let is_target_symbol =
+						self.config.target_symbols && TARGET_SYMBOLS.contains(&name.as_str());
+					!(is_target_symbol
+						|| state.file_definitions.contains_key(*name)
+						|| self.build_definition(name, state).is_some())
+				})
+				.collect();
+			undefined_symbols.sort_by_key(|(_, location)| (&location.path, location.line));
+			let undefined_symbol_warnings: Vec<Warning> = undefined_symbols
+				.into_iter()
+				.map(|(name, location)| Warning {
+					code: WarningCode::UndefinedSymbol,
+					message: format!("`{name}` is never defined"),
+					location: location.clone(),
+				})
+				.collect();
+			state.warnings.extend(undefined_symbol_warnings);
+		}
+		let mut warnings = mem::take(&mut state.warnings);
+		warnings.retain(|warning| {
+			!self.config.allowed_warnings.contains(&warning.code)
+				&& !state
+					.file_allowed_warnings
+					.contains(&(warning.location.path.clone(), warning.code))
+		});
+		for warning in warnings.iter() {
+			#[cfg(feature = "tracing")]
+			tracing::warn!(code = warning.code.code(), location = %warning.location, "{}", warning.message);
+			if self.config.denied_warnings.contains(&warning.code) {
+				let error = located_error(
+					&warning.location.path,
+					warning.location.line,
+					format!("{} (denied warning {})", warning.message, warning.code),
+					state,
+				);
+				self.recover(error, state)?;
+			}
+		}
+		Ok(warnings)
+	}
+
+	/// Records an error in error recovery mode, or returns it otherwise.
+	fn recover(&self, error: ex::io::Error, state: &mut BuildState) -> Result<(), ex::io::Error> {
+		if !self.config.error_recovery {
+			return Err(error);
+		}
+		state.errors.push(error);
+		Ok(())
+	}
+
+	fn check_interruption(
+		&self,
+		module_path: &path::Path,
+		state: &BuildState,
+	) -> Result<(), ex::io::Error> {
+		if self
+			.cancellation_token
+			.as_ref()
+			.is_some_and(CancellationToken::is_cancelled)
+		{
+			return Err(preprocess_error_with_kind(
+				module_path,
+				io::ErrorKind::Interrupted,
+				"build was cancelled".to_string(),
+			));
+		}
+		if state
+			.deadline
+			.is_some_and(|deadline| time::Instant::now() >= deadline)
+		{
+			return Err(preprocess_error_with_kind(
+				module_path,
+				io::ErrorKind::TimedOut,
+				"build exceeded its time limit".to_string(),
+			));
+		}
+		Ok(())
+	}
+}
+
+/// Returns the size and alignment in bytes of a host-shareable WGSL type,
+/// or [`None`] if the type is not supported.
+fn type_layout(type_name: &str) -> Option<(u32, u32)> {
+	let type_name = type_name.replace(char::is_whitespace, "");
+	match type_name.as_str() {
+		"f32" | "i32" | "u32" => return Some((4, 4)),
+		"f16" => return Some((2, 2)),
+		_ => {}
+	}
+	let (constructor, parameters) = type_name.strip_suffix('>')?.split_once('<')?;
+	if constructor == "array" {
+		let (element_type, length) = parameters.rsplit_once(',')?;
+		let (element_size, element_align) = type_layout(element_type)?;
+		let stride = element_size.next_multiple_of(element_align);
+		return Some((
+			stride * length.trim_end_matches(['u', 'i']).parse::<u32>().ok()?,
+			element_align,
+		));
+	}
+	let (scalar_size, _) = type_layout(parameters)?;
+	let vector_layout = |components: u32| {
+		let size = scalar_size * components;
+		(
+			size,
+			if components == 3 {
+				size + scalar_size
+			} else {
+				size
+			},
+		)
+	};
+	match constructor.strip_prefix("vec") {
+		Some(components) => Some(vector_layout(components.parse().ok()?)),
+		None => {
+			let (columns, rows) = constructor.strip_prefix("mat")?.split_once('x')?;
+			let (_, column_align) = vector_layout(rows.parse().ok()?);
+			Some((column_align * columns.parse::<u32>().ok()?, column_align))
+		}
+	}
+}
+
+/// Layout in bytes of a WGSL struct.
+struct StructLayout {
+	offsets: Vec<u32>,
+	size: u32,
+	align: u32,
+}
+
+/// Returns the WGSL layout of `T`, which must match its Rust layout.
+///
+/// # Panics
+/// Panics if a field type is not host-shareable or if the size of `T` does not match the WGSL layout.
+fn struct_layout<T: WGSLStruct>(type_name: &str) -> StructLayout {
+	let mut layout = StructLayout {
+		offsets: Vec::new(),
+		size: 0,
+		align: 1,
+	};
+	for (field_name, field_type) in T::field_types().iter() {
+		let (field_size, field_align) = type_layout(field_type).unwrap_or_else(|| {
+			panic!("field `{field_name}` of `{type_name}` has a type that is not host-shareable")
+		});
+		let offset = layout.size.next_multiple_of(field_align);
+		layout.offsets.push(offset);
+		layout.size = offset + field_size;
+		layout.align = layout.align.max(field_align);
+	}
+	layout.size = layout.size.next_multiple_of(layout.align);
+	assert_eq!(
+		layout.size as usize,
+		mem::size_of::<T>(),
+		"WGSL layout of `{type_name}` does not match its Rust layout"
+	);
+	layout
+}
+
+/// Returns the name of a Rust type without its path.
+fn short_type_name<T>() -> &'static str {
+	any::type_name::<T>().rsplit("::").next().unwrap()
+}
+
+/// Returns the value of a definition in the first layer defining it, with the layer.
+fn layered_definition<'a, const LAYERS: usize>(
+	name: &str,
+	layers: [(DefinitionLayer, &'a HashMap<String, String>); LAYERS],
+) -> Option<(&'a str, DefinitionLayer)> {
+	layers
+		.into_iter()
+		.find_map(|(layer, definitions)| Some((definitions.get(name)?.as_str(), layer)))
+}
+
+/// SplitMix64 pseudo-random number generator, which is small and whose output is stable.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+		let mut value = self.0;
+		value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+		value ^ (value >> 31)
+	}
+}
+
+/// Returns the byte offsets of the occurrences of a definition name that are whole tokens,
+/// so that the name `N` is not replaced in `NORMAL` or `in_N`.
+/// Names starting or ending with characters that can not be part of identifiers match next to any character there.
+fn identifier_occurrences<'a>(string: &'a str, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+	let is_identifier_character =
+		|character: Option<char>| character.is_some_and(|c| c.is_alphanumeric() || c == '_');
+	let is_bounded_start = is_identifier_character(name.chars().next());
+	let is_bounded_end = is_identifier_character(name.chars().next_back());
+	string
+		.match_indices(name)
+		.map(|(start, _)| start)
+		.filter(move |start| {
+			let is_joined_before =
+				is_bounded_start && is_identifier_character(string[..*start].chars().next_back());
+			let is_joined_after = is_bounded_end
+				&& is_identifier_character(string[start + name.len()..].chars().next());
+			!is_joined_before && !is_joined_after
+		})
+}
+
+/// Returns a string with the occurrences of `name` at `occurrences` replaced by `value`.
+fn replaced_occurrences(
+	string: &str,
+	name: &str,
+	value: &str,
+	occurrences: impl IntoIterator<Item = usize>,
+) -> String {
+	let mut replaced_string = String::with_capacity(string.len());
+	let mut end = 0;
+	for start in occurrences {
+		replaced_string.push_str(&string[end..start]);
+		replaced_string.push_str(value);
+		end = start + name.len();
+	}
+	replaced_string.push_str(&string[end..]);
+	replaced_string
+}
+
+/// Expands the names of definitions in the value of a definition, see [`ShaderBuilder::expanded_definitions`].
+/// Returns the names of the definitions of the cycle if the definition expands to itself.
+fn expanded_definition(
+	name: &str,
+	definitions: &[(&String, &String)],
+	expanded_definitions: &mut HashMap<String, String>,
+	expansion_chain: &mut Vec<String>,
+) -> Result<String, Vec<String>> {
+	if let Some(value) = expanded_definitions.get(name) {
+		return Ok(value.clone());
+	}
+	if let Some(cycle_start) = expansion_chain.iter().position(|expanded| expanded == name) {
+		let mut cycle = expansion_chain[cycle_start..].to_vec();
+		cycle.push(name.to_string());
+		return Err(cycle);
+	}
+	expansion_chain.push(name.to_string());
+	let mut value = definitions
+		.iter()
+		.find(|(defined_name, _)| *defined_name == name)
+		.unwrap()
+		.1
+		.clone();
+	for (other_name, _) in definitions.iter() {
+		let occurrences: Vec<usize> = identifier_occurrences(&value, other_name).collect();
+		if !occurrences.is_empty() {
+			let other_value = expanded_definition(
+				other_name,
+				definitions,
+				expanded_definitions,
+				expansion_chain,
+			)?;
+			value = replaced_occurrences(&value, other_name, &other_value, occurrences);
+		}
+	}
+	expansion_chain.pop();
+	expanded_definitions.insert(name.to_string(), value.clone());
+	Ok(value)
+}
+
+/// Returns the message of an expansion exceeding [`ShaderBuilder::max_expansion_size`].
+fn expansion_size_error(name: &str, length: usize, max_expansion_size: usize) -> String {
+	format!(
+		"expansion of macro `{name}` is {length} bytes, \
+		exceeding the maximum expansion size of {max_expansion_size} bytes"
+	)
+}
+
+/// Returns the major, minor and patch numbers of a version like `2.1`, missing numbers being 0.
+fn version_components(version: &str) -> Option<[u64; 3]> {
+	let mut components = [0; 3];
+	let mut numbers = version.split('.');
+	for component in components.iter_mut() {
+		if let Some(number) = numbers.next() {
+			*component = number.parse().ok()?;
+		}
+	}
+	numbers.next().is_none().then_some(components)
+}
+
+/// 64-bit FNV-1a hash, which unlike [`std::hash::DefaultHasher`] is stable across Rust versions.
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+		(hash ^ byte as u64).wrapping_mul(0x100000001b3)
+	})
+}
+
+/// Records a definition substituted in a line, once per line.
+fn record_substitution(
+	line_substitutions: &mut BTreeMap<usize, Vec<Substitution>>,
+	index: usize,
+	name: &str,
+	value: &str,
+) {
+	let substitutions = line_substitutions.entry(index).or_default();
+	if !substitutions
+		.iter()
+		.any(|substitution| substitution.name == name && substitution.value == value)
+	{
+		substitutions.push(Substitution {
+			name: name.to_string(),
+			value: value.to_string(),
+		});
+	}
+}
+
+/// Moves values keyed by line index to the new indices of their lines.
+fn remapped_lines<T>(lines: BTreeMap<usize, T>, line_indices: &[usize]) -> BTreeMap<usize, T> {
+	lines
+		.into_iter()
+		.filter_map(|(index, value)| Some((*line_indices.get(index)?, value)))
+		.collect()
+}
+
+/// Adds the substitutions of the lines of a string appended to another string.
+fn splice_line_substitutions(
+	line_substitutions: &mut BTreeMap<usize, Vec<Substitution>>,
+	string: &str,
+	appended_line_substitutions: BTreeMap<usize, Vec<Substitution>>,
+) {
+	let offset = string.matches('\n').count();
+	for (index, substitutions) in appended_line_substitutions {
+		line_substitutions.insert(offset + index, substitutions);
+	}
+}
+
+fn preprocess_error(module_path: &path::Path, message: String) -> ex::io::Error {
+	preprocess_error_with_kind(module_path, io::ErrorKind::InvalidData, message)
+}
+
+/// Returns an error caused by a statement, carrying a [`Diagnostic`].
+fn located_error(
+	module_path: &path::Path,
+	line: usize,
+	message: String,
+	state: &BuildState,
+) -> ex::io::Error {
+	diagnostic_error(
+		module_path,
+		io::ErrorKind::InvalidData,
+		diagnostic(
+			message,
+			SourceLocation {
+				path: module_path.to_path_buf(),
+				line,
+			},
+			state.include_chain.clone(),
+			state,
+		),
+	)
+}
+
+/// Records a name looked up in the definitions of a build or substituted, if names are recorded.
+fn observe_symbol(name: &str, state: &BuildState) {
+	if let Some(observed_symbols) = &state.observed_symbols {
+		observed_symbols.borrow_mut().names.insert(name.to_string());
+	}
+}
+
+/// Records that the source of a build may depend on any definition, if names are recorded.
+fn observe_every_symbol(state: &BuildState) {
+	if let Some(observed_symbols) = &state.observed_symbols {
+		observed_symbols.borrow_mut().is_every_symbol = true;
+	}
+}
+
+/// Returns the earlier built combination that a combination of permutation symbols can be pruned in favor of,
+/// if they only differ in symbols that can not change the source of the built combination.
+/// `observations` holds the built combinations, with a mask of the symbols that can change their sources.
+fn pruned_combination(observations: &[(usize, usize)], combination: usize) -> Option<usize> {
+	observations
+		.iter()
+		.find(|(built, observed)| *built < combination && (built ^ combination) & observed == 0)
+		.map(|(built, _)| *built)
+}
+
+/// Returns the [`Diagnostic`] of an error on a line, with the text and the statement of the line.
+fn diagnostic(
+	message: String,
+	location: SourceLocation,
+	include_chain: Vec<SourceLocation>,
+	state: &BuildState,
+) -> Diagnostic {
+	let line_text = |location: &SourceLocation| {
+		state
+			.module_sources
+			.get(&location.path)
+			.and_then(|module_source| {
+				String::from_utf8_lossy(module_source)
+					.lines()
+					.nth(location.line - 1)
+					.map(str::to_string)
+			})
+			.unwrap_or_default()
+	};
+	let text = line_text(&location);
+	let include_texts = include_chain.iter().map(line_text).collect();
+	let directive = text
+		.trim()
+		.strip_prefix(INSTRUCTION_PREFIX)
+		.map(|statement| {
+			statement
+				.split(|character: char| !character.is_alphanumeric() && character != '_')
+				.next()
+				.unwrap_or_default()
+				.to_string()
+		})
+		.filter(|directive| !directive.is_empty());
+	Diagnostic {
+		message,
+		location,
+		directive,
+		text,
+		include_chain,
+		include_texts,
+	}
+}
+
+/// Returns a pattern matching writes to a variable: assignments to it or to its members and elements,
+/// increments and decrements, and atomic operations.
+fn storage_write_regex(variable: &str) -> regex::Regex {
+	let variable = regex::escape(variable);
+	regex::Regex::new(&format!(
+		r"atomic\w*\(\s*&\s*{variable}\b|\b{variable}\b(?:\s*(?:\.\s*\w+|\[[^\]]*\]))*\s*(?:(?:<<|>>|[-+*/%&|^])?=(?:[^=]|$)|\+\+|--)"
+	))
+	.unwrap()
+}
+
+/// Returns the canonical path of a file, or its lexically normalized path if it can not be canonicalized.
+fn normalized_path(module_path: &path::Path) -> path::PathBuf {
+	std::fs::canonicalize(module_path).unwrap_or_else(|_| lexically_normalized_path(module_path))
+}
+
+/// Returns a path without `.` components, and with `..` components removing the preceding ones.
+fn lexically_normalized_path(module_path: &path::Path) -> path::PathBuf {
+	let mut normalized_path = path::PathBuf::new();
+	for component in module_path.components() {
+		match component {
+			path::Component::CurDir => {}
+			path::Component::ParentDir
+				if matches!(
+					normalized_path.components().next_back(),
+					Some(path::Component::Normal(_))
+				) =>
+			{
+				normalized_path.pop();
+			}
+			component => normalized_path.push(component),
+		}
+	}
+	normalized_path
+}
+
+/// Returns an error located at the `include` statement of the module being loaded.
+fn include_error(
+	module_path: &path::Path,
+	kind: io::ErrorKind,
+	message: String,
+	state: &BuildState,
+) -> ex::io::Error {
+	let (include, include_chain) = state
+		.include_chain
+		.split_last()
+		.expect("module is not included");
+	diagnostic_error(
+		module_path,
+		kind,
+		diagnostic(message, include.clone(), include_chain.to_vec(), state),
+	)
+}
+
+fn diagnostic_error(
+	module_path: &path::Path,
+	kind: io::ErrorKind,
+	diagnostic: Diagnostic,
+) -> ex::io::Error {
+	ex::io::Error::Filesystem(
+		module_path.to_path_buf(),
+		"preprocessing",
+		io::Error::new(kind, diagnostic),
+	)
+}
+
+fn preprocess_error_with_kind(
+	module_path: &path::Path,
+	kind: io::ErrorKind,
+	message: String,
+) -> ex::io::Error {
+	ex::io::Error::Filesystem(
+		module_path.to_path_buf(),
+		"preprocessing",
+		io::Error::new(kind, message),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		Bindings, BuildOutput, CancellationToken, Color, ColorSpace, DefinitionLayer, Diagnostic,
+		Hex, IncludeResolution, InvalidUtf8, LiteralSuffixes, MacroExpansion, MemoryResolver,
+		OutputTarget, PreprocessError, Preset, Provenance, ShaderBuilder, SourceLocation,
+		Substitution, SubstitutionScope, Sweep, SweepReport, WGSLStruct, WGSLType, Warning,
+		WarningCode,
+	};
+	use std::{
+		collections::{BTreeMap, HashMap},
+		env, fs, io, path, sync, time,
+	};
+
+	#[test]
+	fn scalar_string_definitions() {
+		assert_eq!(1u32.string_definition(), "1u");
+		assert_eq!((-1i32).string_definition(), "-1i");
+		assert_eq!(i32::MIN.string_definition(), "i32(-2147483648)");
+		assert_eq!(Hex(0xffu32).string_definition(), "0xffu");
+		assert_eq!(Hex(0b1010u32).string_definition(), "0xau");
+		assert_eq!(Hex(-0x1fi32).string_definition(), "-0x1fi");
+		assert_eq!(Hex(i32::MIN).string_definition(), "i32(-0x80000000)");
+		assert_eq!(2.0f32.string_definition(), "2.0");
+		assert_eq!((-0.0f32).string_definition(), "-0.0");
+		assert_eq!(0.1f32.string_definition(), "0.1");
+		assert_eq!(1e20f32.string_definition(), "1e20");
+		assert_eq!(1e-7f32.string_definition(), "1e-7");
+		assert_eq!(f32::NAN.string_definition(), "bitcast<f32>(0x7fc00000u)");
+		assert_eq!(
+			f32::NEG_INFINITY.string_definition(),
+			"bitcast<f32>(0xff800000u)"
+		);
+		assert_eq!(true.string_definition(), "true");
+	}
+
+	#[cfg(feature = "reflection")]
+	#[test]
+	fn non_finite_floats() {
+		// Bit casts are not constant expressions, so non-finite floats can only be used in function bodies.
+		let build = |source: &str| {
+			let mut shader_builder = ShaderBuilder::from_source("non_finite_floats.wgsl", source);
+			shader_builder
+				.put_constant("NAN", f32::NAN)
+				.put_array_definition("VALUES", &vec![&f32::INFINITY]);
+			naga::front::wgsl::parse_str(shader_builder.build_source().unwrap()).map(|_| ())
+		};
+		assert!(build("fn f() -> f32 {\n\tlet nan = NAN;\n\treturn nan;\n}\n").is_ok());
+		assert!(build("const nan = NAN;\n").is_err());
+		assert!(build("//!define VALUES\n").is_err());
+	}
+
+	#[test]
+	fn literal_suffixes() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+		shader_builder
+			.literal_suffixes(LiteralSuffixes {
+				integers: false,
+				floats: true,
+			})
+			.put_constant("OFFSET", -0x1fi32)
+			.put_constant("MASK", Hex(0xff800000u32))
+			.put_constant("EXPONENT", 1e-7f32)
+			.put_constant("NEG_INFINITY", f32::NEG_INFINITY)
+			.put_constant("NAN", f32::NAN);
+		let definitions = &shader_builder.config().definitions;
+		assert_eq!(definitions["OFFSET"], "-31");
+		assert_eq!(definitions["MASK"], "0xff800000u");
+		assert_eq!(definitions["EXPONENT"], "1e-7f");
+		assert_eq!(definitions["NEG_INFINITY"], "bitcast<f32>(0xff800000u)");
+		assert_eq!(definitions["NAN"], "bitcast<f32>(0x7fc00000)");
+	}
+
+	#[test]
+	fn nonexistent() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/nonexistent.wgsl")
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::NotFound
+		);
+	}
+
+	#[test]
+	fn standard_include() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/includer.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/included.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn missing_include() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/missing_include.wgsl")
+				.unwrap()
+				.build_source()
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::NotFound
+		);
+	}
+
+	#[test]
+	fn include_first() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/include_first.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/included.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		let error = ShaderBuilder::new("test_shaders/include_first_missing.wgsl")
+			.unwrap()
+			.build_source()
+			.unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::NotFound);
+		assert_eq!(
+			error.diagnostic().unwrap().message,
+			"no module of `//!include_first test_shaders/vendor_missing.wgsl test_shaders/generic_missing.wgsl` exists"
+		);
+	}
+
+	#[test]
+	fn build_permutations() {
+		let permutations = ShaderBuilder::new("test_shaders/permutations.wgsl")
+			.unwrap()
+			.build_permutations(&["FOG", "FOG_HEIGHT"])
+			.unwrap();
+		assert_eq!(
+			permutations
+				.variants
+				.iter()
+				.map(|variant| variant.label.as_str())
+				.collect::<Vec<&str>>(),
+			[
+				"permutations",
+				"permutations:FOG",
+				"permutations:FOG+FOG_HEIGHT"
+			]
+		);
+		assert_eq!(
+			permutations.mapping,
+			[
+				(Vec::new(), 0),
+				(vec!["FOG".to_string()], 1),
+				(vec!["FOG_HEIGHT".to_string()], 0),
+				(vec!["FOG".to_string(), "FOG_HEIGHT".to_string()], 2),
+			]
+		);
+	}
+
+	#[test]
+	fn pruned_permutations() {
+		// Symbols only looked up by statements or only present in the source can not be pruned.
+		let source = "//!define_default QUALITY 1u\n\
+			const quality: u32 = QUALITY;\n\
+			const tint: f32 = 1.0 TINT;\n";
+		let permutations = ShaderBuilder::from_source("pruned_permutations.wgsl", source)
+			.build_permutations(&["QUALITY", "TINT", "UNUSED"])
+			.unwrap();
+		assert_eq!(permutations.variants.len(), 4);
+		assert_eq!(permutations.mapping.len(), 8);
+		for (symbols, variant_index) in permutations.mapping.iter() {
+			let definitions = symbols
+				.iter()
+				.map(|symbol| (symbol.clone(), String::new()))
+				.collect();
+			assert_eq!(
+				permutations.variants[*variant_index].source,
+				ShaderBuilder::from_source("pruned_permutations.wgsl", source)
+					.build_source_with_overlay(&definitions)
+					.unwrap()
+			);
+		}
+	}
+
+	#[test]
+	fn pruned_permutations_in_any_order() {
+		// Permutations are built on several threads, so the combination `S` may be built before the empty one,
+		// and the combination `A` must not be pruned in favor of it.
+		let symbols = ["S", "A"];
+		let symbol_regexes: Vec<regex::Regex> = symbols
+			.iter()
+			.map(|symbol| regex::Regex::new(&format!(r"\b{symbol}\b")).unwrap())
+			.collect();
+		let observed_permutation = |source: &str, defined_symbols: &[&str]| {
+			let shader_builder = ShaderBuilder::from_source("pruned_permutations.wgsl", source);
+			let defined_symbols: Vec<String> = defined_symbols
+				.iter()
+				.map(|symbol| symbol.to_string())
+				.collect();
+			shader_builder
+				.build_observed_permutation(
+					&shader_builder.shared_module_cache(),
+					&symbols,
+					&symbol_regexes,
+					&defined_symbols,
+				)
+				.unwrap()
+		};
+		let (_, source, observed) =
+			observed_permutation("fn f() -> u32 {\n\treturn 1u S;\n}\n", &["S"]);
+		assert_eq!(source, "fn f() -> u32 {\n\treturn 1u ;\n}\n");
+		assert_eq!(observed, 0b01);
+		assert_eq!(super::pruned_combination(&[(0b01, observed)], 0b10), None);
+		assert_eq!(
+			super::pruned_combination(&[(0b01, observed)], 0b11),
+			Some(0b01)
+		);
+		let (_, _, observed) = observed_permutation(
+			"//!if included(\"missing.wgsl\")\nconst a: u32 = 1u;\n//!endif\n",
+			&[],
+		);
+		assert_eq!(observed, 0b11);
+	}
+
+	#[test]
+	fn sweep() {
+		let mut sweep = Sweep::new();
+		sweep.symbol("FOG").constant("COUNT", 0u32..=2);
+		let mut shader_builder = ShaderBuilder::new("test_shaders/sweep.wgsl").unwrap();
+		shader_builder.templates(true);
+		let report = shader_builder.sweep(&sweep);
+		assert_eq!(report.configurations, 6);
+		let failed_configurations = |report: &SweepReport| -> Vec<String> {
+			report
+				.failures
+				.iter()
+				.map(|failure| failure.to_string())
+				.map(|failure| failure[..failure.find(':').unwrap()].to_string())
+				.collect()
+		};
+		assert_eq!(
+			failed_configurations(&report),
+			[
+				"[COUNT=0u]",
+				"[FOG, COUNT=0u]",
+				"[FOG, COUNT=1u]",
+				"[FOG, COUNT=2u]"
+			]
+		);
+		#[cfg(feature = "reflection")]
+		{
+			sweep.validate(true);
+			let report = shader_builder.sweep(&sweep);
+			assert_eq!(
+				failed_configurations(&report),
+				[
+					"[COUNT=0u]",
+					"[FOG, COUNT=0u]",
+					"[COUNT=1u]",
+					"[FOG, COUNT=1u]",
+					"[FOG, COUNT=2u]"
+				]
+			);
+		}
+	}
+
+	#[test]
+	fn parse() {
+		let parsed_shader = ShaderBuilder::new("test_shaders/nested_include.wgsl")
+			.unwrap()
+			.parse()
+			.unwrap();
+		assert_eq!(
+			parsed_shader.includes(),
+			[
+				(
+					"test_shaders/nested_include.wgsl".into(),
+					vec!["test_shaders/includer.wgsl".into()]
+				),
+				(
+					"test_shaders/includer.wgsl".into(),
+					vec!["test_shaders/included.wgsl".into()]
+				),
+				("test_shaders/included.wgsl".into(), Vec::new()),
+			]
+		);
+		assert_eq!(
+			parsed_shader.render(&HashMap::new()).unwrap(),
+			ShaderBuilder::new("test_shaders/nested_include.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+
+		let mut shader_builder = ShaderBuilder::from_source(
+			"test_shaders/parse.wgsl",
+			"//!ifdef FOG\n//!include fog\n//!else\n\
+			//!include_first test_shaders/vendor_missing.wgsl test_shaders/included2.wgsl\n//!endif\n",
+		);
+		shader_builder.alias_module("fog", "test_shaders/included.wgsl");
+		let parsed_shader = shader_builder.parse().unwrap();
+		assert_eq!(
+			parsed_shader.includes()[0].1,
+			[
+				path::PathBuf::from("test_shaders/included.wgsl"),
+				path::PathBuf::from("test_shaders/included2.wgsl")
+			]
+		);
+		for symbols in [
+			HashMap::new(),
+			HashMap::from([("FOG".to_string(), String::new())]),
+		] {
+			assert_eq!(
+				parsed_shader.render(&symbols).unwrap(),
+				shader_builder.build_source_with_overlay(&symbols).unwrap()
+			);
+		}
+	}
+
+	#[test]
+	fn build_permutations_with_progress() {
+		let reports = std::sync::Mutex::new(Vec::new());
+		let permutations = ShaderBuilder::new("test_shaders/permutations.wgsl")
+			.unwrap()
+			.build_permutations_with_progress(&["FOG", "FOG_HEIGHT"], |progress| {
+				reports.lock().unwrap().push((
+					progress.completed,
+					progress.total,
+					progress.symbols.len(),
+				));
+			})
+			.unwrap();
+		assert_eq!(permutations.variants.len(), 3);
+		let mut reports = reports.into_inner().unwrap();
+		reports.sort();
+		assert_eq!(
+			reports
+				.iter()
+				.map(|report| report.0)
+				.collect::<Vec<usize>>(),
+			[1, 2, 3, 4]
+		);
+		assert!(reports.iter().all(|report| report.1 == 4));
+		assert_eq!(reports.iter().map(|report| report.2).sum::<usize>(), 4);
+	}
+
+	#[test]
+	fn too_many_permutation_symbols() {
+		let symbols: Vec<String> = (0..64).map(|index| format!("SYMBOL_{index}")).collect();
+		let error = ShaderBuilder::new("test_shaders/permutations.wgsl")
+			.unwrap()
+			.build_permutations(&symbols.iter().map(String::as_str).collect::<Vec<&str>>())
+			.err()
+			.unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+	}
+
+	#[test]
+	fn variant_manifest() {
+		let permutations = ShaderBuilder::new("test_shaders/permutations.wgsl")
+			.unwrap()
+			.build_permutations(&["FOG", "FOG_HEIGHT"])
+			.unwrap();
+		let manifest = permutations.manifest();
+		assert_eq!(manifest.entries.len(), 4);
+		assert_eq!(
+			manifest.entry(&["FOG_HEIGHT", "FOG"]).unwrap().label,
+			"permutations:FOG+FOG_HEIGHT"
+		);
+		assert_eq!(
+			manifest.entry(&["FOG_HEIGHT"]).unwrap().content_hash,
+			manifest.entry(&[]).unwrap().content_hash
+		);
+		assert_eq!(
+			manifest.entry(&[]).unwrap().interface_hash.is_some(),
+			cfg!(feature = "reflection")
+		);
+		assert!(manifest.to_rust("SHADER_VARIANTS").starts_with(
+			"pub const SHADER_VARIANTS: &[(&[&str], &str, u64, Option<u64>)] = &[\n\t(&[], \"permutations\", 0x"
+		));
+		#[cfg(feature = "reflection")]
+		assert!(manifest.to_rust("SHADER_VARIANTS").contains(&format!(
+			", Some({:#x})),\n",
+			manifest.entries[0].interface_hash.unwrap()
+		)));
+		#[cfg(not(feature = "reflection"))]
+		assert!(manifest.to_rust("SHADER_VARIANTS").contains(", None),\n"));
+		#[cfg(feature = "serde")]
+		assert_eq!(
+			serde_json::from_str::<crate::VariantManifest>(
+				&serde_json::to_string(&manifest).unwrap()
+			)
+			.unwrap(),
+			manifest
+		);
+	}
+
+	#[cfg(feature = "glsl_includes")]
+	#[test]
+	fn glsl_include() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/glsl_include.wgsl").unwrap();
+		let source = shader_builder.build_source().unwrap();
+		assert!(source.contains("@fragment"));
+		assert!(source.contains("fn main("));
+	}
+
+	#[test]
+	fn hlsl_compatibility() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/hlsl_compatibility.wgsl")
+				.unwrap()
+				.hlsl_compatibility(true)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/hlsl_compatibility_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn nested_include() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/nested_include.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/includer.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn multiple_includes() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			format!(
+				"{}{}",
+				ShaderBuilder::new("test_shaders/included.wgsl")
+					.unwrap()
+					.build_source()
+					.unwrap(),
+				ShaderBuilder::new("test_shaders/included2.wgsl")
+					.unwrap()
+					.build_source()
+					.unwrap()
+			)
+		)
+	}
+
+	#[test]
+	fn multiple_inline_includes() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/multiple_inline_includes.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn define() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/definer.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/defined.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn reused_builder() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/definer.wgsl").unwrap();
+		let first_source = shader_builder.build_source().unwrap().to_string();
+		let first_warnings = shader_builder.warnings().to_vec();
+		assert!(shader_builder.config().definitions.is_empty());
+		assert_eq!(shader_builder.build_source().unwrap(), first_source);
+		assert_eq!(shader_builder.warnings(), first_warnings);
+
+		let mut config = shader_builder.config().clone();
+		config.source_path = "test_shaders/conditional.wgsl".to_string();
+		let mut other_builder = ShaderBuilder::from_config(config).unwrap();
+		assert_eq!(
+			other_builder.build_source().unwrap(),
+			ShaderBuilder::new("test_shaders/conditional.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn include_define() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/included_define.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/included_define_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+		)
+	}
+
+	#[test]
+	fn define_with_spaces() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/define_with_spaces.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/define_with_spaces_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+		)
+	}
+
+	#[test]
+	fn put_constant() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/set_constants.wgsl")
+				.unwrap()
+				.put_constant("ONE", 1u32)
+				.put_constant("TWO", 2u32)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/set_constants_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn whole_identifier_substitution() {
+		let mut shader_builder = ShaderBuilder::from_source(
+			"identifiers",
+			"const NORMAL_N: u32 = N;\nfn in_N() -> u32 { return N+N; }\n",
+		);
+		shader_builder.put_constant("N", 3u32);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const NORMAL_N: u32 = 3u;\nfn in_N() -> u32 { return 3u+3u; }\n"
+		);
+		assert_eq!(shader_builder.build_stats().substitutions["N"], 3);
+	}
+
+	#[test]
+	fn substitution_order() {
+		for _ in 0..8 {
+			let mut shader_builder = ShaderBuilder::from_source(
+				"substitution_order",
+				"//!define AB A + 3u\n//!define A 4u\nconst a: u32 = FOO + FOO_BAR + AB;\n",
+			);
+			shader_builder
+				.put_constant("FOO", 1u32)
+				.put_constant("FOO_BAR", 2u32);
+			assert_eq!(
+				shader_builder.build_source().unwrap(),
+				"const a: u32 = 1u + 2u + 4u + 3u;\n"
+			);
+		}
+	}
+
+	#[test]
+	fn placeholder_substitution_scope() {
+		for (pattern, expected_path) in [
+			(
+				r"\$\{(?P<name>[A-Za-z_][A-Za-z0-9_]*)\}",
+				"test_shaders/placeholders_named_processed.wgsl",
+			),
+			(
+				r"__[A-Z0-9_]+__",
+				"test_shaders/placeholders_whole_processed.wgsl",
+			),
+		] {
+			assert_eq!(
+				ShaderBuilder::new("test_shaders/placeholders.wgsl")
+					.unwrap()
+					.substitution_scope(SubstitutionScope::Placeholders(pattern.to_string()))
+					.put_constant("COUNT", 4u32)
+					.put_constant("__COUNT__", 8u32)
+					.build_source()
+					.unwrap(),
+				ShaderBuilder::new(expected_path)
+					.unwrap()
+					.build_source()
+					.unwrap()
+			);
+		}
+	}
+
+	#[test]
+	fn templates() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/templates.wgsl")
+				.unwrap()
+				.templates(true)
+				.put_constant("LIGHT_COUNT", 4u32)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/templates_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/undefined_template.wgsl")
+				.unwrap()
+				.templates(true)
+				.build_source()
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::InvalidData
+		);
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/undefined_template.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			"const exposure: f32 = ${EXPOSURE};\n"
+		);
+	}
+
+	#[test]
+	fn constant_expressions() {
+		let mut shader_builder =
+			ShaderBuilder::new("test_shaders/constant_expressions.wgsl").unwrap();
+		shader_builder
+			.put_constant("WIDTH", 1000u32)
+			.put_constant("TILE_SIZE", 16u32)
+			.put_constant_expr("TILE_COUNT", "(WIDTH + TILE_SIZE - 1u) / TILE_SIZE")
+			.put_constant_expr("ASPECT", "f32(WIDTH) / 500.0");
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const tile_count: u32 = (((1000u) + (16u) - 1u) / (16u));\n\
+			const aspect: f32 = (f32((1000u)) / 500.0);\n"
+		);
+		assert_eq!(
+			shader_builder.fold_constants(true).build_source().unwrap(),
+			"const tile_count: u32 = 63u;\nconst aspect: f32 = 2.0f;\n"
+		);
+		let mut errors = Vec::new();
+		for expression in [
+			"1u / (TILE_SIZE - 16u)",
+			"4294967295u + WIDTH",
+			"WIDTH + 1i",
+			"f32(1e39)",
+		] {
+			errors.push(
+				shader_builder
+					.put_constant_expr("ASPECT", expression)
+					.build_source()
+					.err()
+					.unwrap()
+					.to_string(),
+			);
+		}
+		assert_eq!(
+			errors,
+			[
+				"error preprocessing \"test_shaders/constant_expressions.wgsl\": \
+				constant `ASPECT` defined as `1u / (TILE_SIZE - 16u)` can not be folded: division by zero",
+				"error preprocessing \"test_shaders/constant_expressions.wgsl\": \
+				constant `ASPECT` defined as `4294967295u + WIDTH` can not be folded: `4294968295` overflows `u32`",
+				"error preprocessing \"test_shaders/constant_expressions.wgsl\": \
+				constant `ASPECT` defined as `WIDTH + 1i` can not be folded: `+` mixes `1000u` and `1i`",
+				"error preprocessing \"test_shaders/constant_expressions.wgsl\": \
+				constant `ASPECT` defined as `f32(1e39)` can not be folded: `1e39` overflows `f32`",
+			]
+		);
+	}
+
+	#[test]
+	fn put_color() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/color_space.wgsl").unwrap();
+		assert_eq!(
+			shader_builder
+				.put_color("TINT", Color::Srgb8([255, 128, 0, 255]))
+				.build_source()
+				.unwrap(),
+			"const tint: vec4<f32> = vec4<f32>(1.0, 0.21586053, 0.0, 1.0);\n"
+		);
+		assert_eq!(
+			shader_builder.warnings(),
+			[Warning {
+				code: WarningCode::ColorSpaceMismatch,
+				message: "the module expects srgb colors, but `TINT` is put as a linear color"
+					.to_string(),
+				location: SourceLocation {
+					path: path::PathBuf::from("test_shaders/color_space.wgsl"),
+					line: 1,
+				},
+			}]
+		);
+		shader_builder
+			.color_space(ColorSpace::Srgb)
+			.build_source()
+			.unwrap();
+		assert_eq!(shader_builder.warnings().len(), 1);
+		assert_eq!(
+			shader_builder
+				.put_color("TINT", Color::Linear([0.0, 0.21586053, 0.0, 1.0]))
+				.build_source()
+				.unwrap(),
+			"const tint: vec4<f32> = vec4<f32>(0.0, 0.5019608, 0.0, 1.0);\n"
+		);
+		assert!(shader_builder.warnings().is_empty());
+	}
+
+	#[test]
+	fn put_seeded_random() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+		shader_builder
+			.put_seeded_random("NOISE_SEED", 42)
+			.put_seeded_array("NOISE", 42, 4);
+		let mut other_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+		other_builder
+			.put_seeded_random("NOISE_SEED", 42)
+			.put_seeded_array("NOISE", 42, 4);
+		assert_eq!(shader_builder.config(), other_builder.config());
+		let noise_seed = shader_builder.config().definitions["NOISE_SEED"].clone();
+		assert_ne!(
+			other_builder
+				.put_seeded_random("NOISE_SEED", 43)
+				.config()
+				.definitions["NOISE_SEED"],
+			noise_seed
+		);
+		let noise = &shader_builder.config().array_definitions["NOISE"];
+		let values = noise
+			.split_once(">(")
+			.unwrap()
+			.1
+			.trim_end_matches(",);")
+			.split(',')
+			.map(|value| value.parse::<f32>().unwrap())
+			.collect::<Vec<f32>>();
+		assert_eq!(values.len(), 4);
+		assert!(values.iter().all(|value| (0.0..1.0).contains(value)));
+	}
+
+	#[test]
+	fn put_vectors() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/put_vectors.wgsl")
+				.unwrap()
+				.put_vec3("SUN_DIR", [0.2f32, -1.0, 0.3])
+				.put_vec2("TILE_SIZE", [16u32, 8])
+				.put_vec4("TINT", [1.0f32, 0.5, 0.0, 1.0])
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/put_vectors_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn put_constant_map() {
+		let mut constants = HashMap::new();
+		constants.insert("ONE", 1u32);
+		constants.insert("TWO", 2u32);
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/set_constants.wgsl")
+				.unwrap()
+				.put_constant_map(&constants)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/set_constants_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn build_entry_points() {
+		let entry_point_builds = ShaderBuilder::new("test_shaders/entry_points.wgsl")
+			.unwrap()
+			.templates(true)
+			.build_entry_points(&[
+				("fs_main", HashMap::new()),
+				(
+					"fs_main_masked",
+					HashMap::from([
+						("MASKED".to_string(), String::new()),
+						("ALPHA_CUTOFF".to_string(), 0.5f32.string_definition()),
+					]),
+				),
+			])
+			.unwrap();
+		assert_eq!(
+			entry_point_builds
+				.iter()
+				.map(|entry_point_build| (
+					entry_point_build.label.as_str(),
+					entry_point_build.source.as_str()
+				))
+				.collect::<Vec<(&str, &str)>>(),
+			[
+				(
+					"entry_points:fs_main",
+					"const masked: bool = false;\nconst alpha_cutoff: f32 = 0.0;\n"
+				),
+				(
+					"entry_points:fs_main_masked",
+					"const masked: bool = true;\nconst alpha_cutoff: f32 = 0.5;\n"
+				)
+			]
+		);
+	}
+
+	#[test]
+	fn load_proper_label() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/included.wgsl")
+				.unwrap()
+				.build()
+				.unwrap()
+				.label
+				.unwrap(),
+			"included"
+		);
+	}
+
+	#[test]
+	fn conditional() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/conditional.wgsl")
+				.unwrap()
+				.put_constant("QUALITY", 2u32)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/conditional_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn bool_conditional() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/bool_conditional.wgsl").unwrap();
+		assert_eq!(
+			shader_builder
+				.put_constant("SHADOWS", true)
+				.put_constant("FOG", false)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/bool_conditional_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		assert_eq!(
+			shader_builder
+				.put_constant("SHADOWS", false)
+				.build_source()
+				.unwrap(),
+			"const fog: bool = false;\n"
+		);
+	}
+
+	#[test]
+	fn describe() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/included_define.wgsl").unwrap();
+		shader_builder
+			.put_constant("FOG", true)
+			.remap_include_prefix("shaders/", "test_shaders/")
+			.deterministic(true);
+		let description = shader_builder.describe();
+		assert!(description.starts_with(
+			"root: test_shaders/included_define.wgsl\ninclude prefixes:\n\tshaders/ -> test_shaders/\n"
+		));
+		assert!(description.contains(&format!(
+			"\tFOG = `true` (Builder, Rust code at {}:",
+			file!()
+		)));
+		assert!(description.contains("\tdeterministic: true\n"));
+	}
+
+	#[test]
+	fn add_include_path() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/include_path.wgsl").unwrap();
+		assert!(shader_builder.build_source().is_err());
+		assert_eq!(
+			shader_builder
+				.add_include_path("test_shaders/relative")
+				.add_include_path("test_shaders/library")
+				.build_source()
+				.unwrap(),
+			"const library_light: f32 = 1.0;\n"
+		);
+		assert!(shader_builder
+			.describe()
+			.contains("include paths:\n\ttest_shaders/relative\n\ttest_shaders/library\n"));
+	}
+
+	#[test]
+	fn add_path_alias() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/path_alias.wgsl").unwrap();
+		assert!(shader_builder.build_source().is_err());
+		assert_eq!(
+			shader_builder
+				.add_path_alias("@library", "missing")
+				.add_path_alias("@library", "test_shaders/library")
+				.build_source()
+				.unwrap(),
+			"const library_light: f32 = 1.0;\n"
+		);
+		assert_eq!(
+			shader_builder.build_stats().files_read[1],
+			path::Path::new("test_shaders/library/library_lighting.wgsl")
+		);
+		assert!(shader_builder
+			.describe()
+			.contains("include prefixes:\n\t@library/ -> test_shaders/library/\n"));
+	}
+
+	#[test]
+	fn debug_markers() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/multiple_includes.wgsl").unwrap();
+		shader_builder
+			.debug_markers(true)
+			.record_substitutions(true);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"// --- begin include: test_shaders/included.wgsl ---\n\
+			struct Included {\n    color: vec4<f32>;\n};\n\
+			// --- end include ---\n\
+			// --- begin include: test_shaders/included2.wgsl ---\n\
+			struct Included2 {\n    color: vec4<f32>;\n};\n\
+			// --- end include ---\n"
+		);
+		assert!(shader_builder
+			.describe()
+			.contains("\tdebug_markers: true\n"));
+		let source = ShaderBuilder::new("test_shaders/include_section.wgsl")
+			.unwrap()
+			.debug_markers(true)
+			.build_source()
+			.unwrap()
+			.to_string();
+		assert!(source
+			.contains("// --- begin include: test_shaders/colors.wgsl section=palettes ---\n"));
+	}
+
+	#[test]
+	fn alias_module() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/multiple_includes.wgsl").unwrap();
+		assert_eq!(
+			shader_builder
+				.alias_module("test_shaders/included2.wgsl", "missing.wgsl")
+				.alias_module("test_shaders/included2.wgsl", "test_shaders/included.wgsl")
+				.build_source()
+				.unwrap(),
+			"struct Included {\n    color: vec4<f32>;\n};\nstruct Included {\n    color: vec4<f32>;\n};\n"
+		);
+		assert!(shader_builder.describe().contains(
+			"module aliases:\n\ttest_shaders/included2.wgsl -> test_shaders/included.wgsl\n"
+		));
+	}
+
+	#[test]
+	fn include_resolution() {
+		let mut shader_builder =
+			ShaderBuilder::new("test_shaders/relative/relative_includer.wgsl").unwrap();
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const relative: bool = true;\n"
+		);
+		assert_eq!(
+			shader_builder
+				.include_resolution(IncludeResolution::WorkingDirectory)
+				.build_source()
+				.unwrap_err()
+				.kind(),
+			io::ErrorKind::NotFound
+		);
+
+		let mut source_resolver = MemoryResolver::new();
+		source_resolver
+			.insert("shaders/main.wgsl", "//!include common.wgsl\n")
+			.insert("shaders/common.wgsl", "const relative: bool = true;\n")
+			.insert("common.wgsl", "const relative: bool = false;\n");
+		let mut shader_builder =
+			ShaderBuilder::with_source_resolver("shaders/main.wgsl", source_resolver).unwrap();
+		let error = shader_builder.build_source().unwrap_err();
+		assert_eq!(
+			error.diagnostic().unwrap().message,
+			"`common.wgsl` is ambiguous: both shaders/common.wgsl and common.wgsl exist, \
+				relative to the including module and to the working directory"
+		);
+		assert_eq!(
+			shader_builder
+				.include_resolution(IncludeResolution::WorkingDirectory)
+				.build_source()
+				.unwrap(),
+			"const relative: bool = false;\n"
+		);
+	}
+
+	#[test]
+	fn forward_declaration() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/declare_root.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			"fn intensity(light: Light) -> f32 {\n\treturn light.intensity;\n}\nstruct Light {\n\tintensity: f32,\n}\n"
+		);
+		let error = ShaderBuilder::new("test_shaders/declare_lighting.wgsl")
+			.unwrap()
+			.build_source()
+			.unwrap_err();
+		assert_eq!(
+			error.diagnostic().unwrap().message,
+			"`struct Light` is declared but no module of the build defines it"
+		);
+	}
+
+	#[test]
+	fn link_library() {
+		let mut library_builder = ShaderBuilder::new("test_shaders/link_library.wgsl").unwrap();
+		let library = library_builder
+			.put_constant("SCALE", 2.0f32)
+			.build_library()
+			.unwrap();
+		assert_eq!(library.name(), "link_library");
+		assert_eq!(
+			library.declarations(),
+			[
+				("struct".to_string(), "Light".to_string()),
+				("const".to_string(), "LIGHT_INTENSITY".to_string())
+			]
+		);
+		let library = sync::Arc::new(library);
+		let mut shader_builder = ShaderBuilder::new("test_shaders/declare_lighting.wgsl").unwrap();
+		shader_builder.link_library(library.clone());
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"struct Light {\n\tintensity: f32,\n}\nconst LIGHT_INTENSITY: f32 = 2.0;\n\
+			fn intensity(light: Light) -> f32 {\n\treturn light.intensity;\n}\n"
+		);
+		assert_eq!(
+			shader_builder.build_stats().files_read,
+			[path::PathBuf::from("test_shaders/declare_lighting.wgsl")]
+		);
+		assert!(shader_builder
+			.describe()
+			.contains("libraries: link_library\n"));
+		let error =
+			ShaderBuilder::from_source("conflicting", "const LIGHT_INTENSITY: f32 = 1.0;\n")
+				.link_library(library.clone())
+				.build_source()
+				.unwrap_err();
+		assert!(error.to_string().contains(
+			"`const LIGHT_INTENSITY` is declared by the shader, but library `link_library` already declares `const LIGHT_INTENSITY`"
+		));
+		let error = ShaderBuilder::from_source("twice", "")
+			.link_library(library.clone())
+			.link_library(library)
+			.build_source()
+			.unwrap_err();
+		assert!(error
+			.to_string()
+			.contains("`struct Light` is declared by library `link_library`, but library `link_library` already declares `struct Light`"));
+	}
+
+	#[cfg(feature = "reflection")]
+	#[test]
+	fn validate_library() {
+		ShaderBuilder::new("test_shaders/link_library.wgsl")
+			.unwrap()
+			.put_constant("SCALE", 2.0f32)
+			.build_library()
+			.unwrap()
+			.validate()
+			.unwrap();
+		assert!(ShaderBuilder::new("test_shaders/link_library.wgsl")
+			.unwrap()
+			.build_library()
+			.unwrap()
+			.validate()
+			.is_err());
+	}
+
+	#[test]
+	fn module_versions() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/versions/compatible.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			"const lighting_version: u32 = 2u;\n"
+		);
+		let mut shader_builder =
+			ShaderBuilder::new("test_shaders/versions/incompatible.wgsl").unwrap();
+		shader_builder.error_recovery(true);
+		assert!(shader_builder.build_source().is_err());
+		assert_eq!(
+			shader_builder
+				.build_errors()
+				.iter()
+				.map(|error| Diagnostic::of(error).unwrap().message.as_str())
+				.collect::<Vec<&str>>(),
+			[
+				"module `lighting` is version 2.2 here but version 2.1 at test_shaders/versions/lighting.wgsl:1",
+				"`lighting >= 3` is required but `lighting` is version 2.1 at test_shaders/versions/lighting.wgsl:1",
+				"`shadows = 1` is required but no module of the build is named `shadows`"
+			]
+		);
+	}
+
+	#[test]
+	fn from_source() {
+		let mut shader_builder = ShaderBuilder::from_source(
+			"generated.wgsl",
+			"//!include test_shaders/included.wgsl\nconst generated: u32 = COUNT;",
+		);
+		assert_eq!(
+			shader_builder
+				.put_constant("COUNT", 2u32)
+				.build_source()
+				.unwrap(),
+			format!(
+				"{}const generated: u32 = 2u;\n",
+				ShaderBuilder::new("test_shaders/included.wgsl")
+					.unwrap()
+					.build_source()
+					.unwrap()
+			)
+		);
+		assert_eq!(shader_builder.label(None), "generated");
+	}
+
+	#[test]
+	fn record_substitutions() {
+		let mut shader_builder =
+			ShaderBuilder::new("test_shaders/record_substitutions.wgsl").unwrap();
+		shader_builder
+			.put_constant("FACTOR", 3.0f32)
+			.record_substitutions(true);
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/nested_include.wgsl")
+			shader_builder.build_source().unwrap(),
+			"// SCALE is kept\n// in preserved regions.\nconst factor: f32 = 3.0;\nconst scaled: f32 = 2.0 * 3.0;\n"
+		);
+		let substitution = |name: &str, value: &str| Substitution {
+			name: name.to_string(),
+			value: value.to_string(),
+		};
+		assert_eq!(
+			shader_builder.build_stats().line_substitutions,
+			BTreeMap::from([
+				(3, vec![substitution("FACTOR", "3.0")]),
+				(
+					4,
+					vec![substitution("SCALE", "2.0"), substitution("FACTOR", "3.0")]
+				),
+			])
+		);
+	}
+
+	#[cfg(feature = "reflection")]
+	#[test]
+	fn build_naga_module() {
+		let module = ShaderBuilder::new("test_shaders/compute_double.wgsl")
+			.unwrap()
+			.put_constant("FACTOR", 2.0f32)
+			.build_naga_module()
+			.unwrap();
+		assert_eq!(module.entry_points[0].name, "main");
+		assert!(ShaderBuilder::new("test_shaders/compute_double.wgsl")
+			.unwrap()
+			.build_naga_module()
+			.is_err());
+	}
+
+	#[cfg(feature = "reflection")]
+	#[test]
+	fn naga_pass() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/compute_double.wgsl").unwrap();
+		shader_builder
+			.put_constant("FACTOR", 2.0f32)
+			.naga_pass("rename", |module| {
+				module.entry_points[0].name = "double".to_string();
+				Ok(())
+			});
+		assert_eq!(
+			shader_builder.build_naga_module().unwrap().entry_points[0].name,
+			"double"
+		);
+		let error = shader_builder
+			.naga_pass("reject", |_| Err("not supported".to_string()))
+			.build_naga_module()
+			.unwrap_err();
+		assert!(error
+			.to_string()
+			.contains("naga pass `reject` failed: not supported"));
+	}
+
+	#[test]
+	fn once() {
+		let common = "struct Light {\n\tintensity: f32,\n}\n";
+		let shading = "fn shade(light: Light) -> f32 { return light.intensity; }\n";
+		let included = "struct Included {\n    color: vec4<f32>;\n};\n";
+		let mut shader_builder = ShaderBuilder::new("test_shaders/once.wgsl").unwrap();
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			format!("{common}{shading}{included}{included}")
+		);
+		assert_eq!(
+			shader_builder
+				.deduplicate_includes(true)
+				.build_source()
+				.unwrap(),
+			format!("{common}{shading}{included}")
+		);
+	}
+
+	#[cfg(feature = "spirv_includes")]
+	#[test]
+	fn include_spirv() {
+		let source = ShaderBuilder::new("test_shaders/include_spirv.wgsl")
+			.unwrap()
+			.build_source()
+			.unwrap()
+			.to_string();
+		assert!(source.contains("fn tint() -> vec4<f32> {"));
+		assert!(source.contains("fn tint_main() {"));
+		naga::front::wgsl::parse_str(&source).unwrap();
+	}
+
+	#[test]
+	fn circular_include() {
+		let error = ShaderBuilder::new("test_shaders/cycle_a.wgsl")
+			.unwrap()
+			.build_source()
+			.unwrap_err();
+		let diagnostic = error.diagnostic().unwrap();
+		assert_eq!(
+			diagnostic.message,
+			"circular include: test_shaders/cycle_a.wgsl → test_shaders/cycle_b.wgsl → test_shaders/cycle_a.wgsl"
+		);
+		assert_eq!(
+			diagnostic.location,
+			SourceLocation {
+				path: path::PathBuf::from("test_shaders/cycle_b.wgsl"),
+				line: 1,
+			}
+		);
+	}
+
+	#[test]
+	fn dump_intermediates() {
+		let directory = env::temp_dir().join("wgsl_preprocessor_dump_intermediates");
+		let _ = fs::remove_dir_all(&directory);
+		ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+			.unwrap()
+			.dump_intermediates(&directory)
+			.unwrap();
+		let mut file_names: Vec<String> = fs::read_dir(&directory)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+			.collect();
+		file_names.sort();
+		assert_eq!(
+			file_names,
+			[
+				"000-included.wgsl",
+				"001-included2.wgsl",
+				"002-multiple_includes.wgsl",
+				"final.wgsl"
+			]
+		);
+		assert_eq!(
+			fs::read_to_string(directory.join("final.wgsl")).unwrap(),
+			ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
 				.unwrap()
-				.source_string,
-			ShaderBuilder::new("test_shaders/includer.wgsl")
+				.build_source()
 				.unwrap()
-				.source_string
-		)
+		);
+		fs::remove_dir_all(&directory).unwrap();
 	}
 
 	#[test]
-	fn multiple_includes() {
+	fn build_output() {
+		let source = "const SCALE: f32 = 2.0;\n";
+		let mut shader_builder = ShaderBuilder::from_source("build_output", source);
+		match shader_builder.build_output().unwrap() {
+			BuildOutput::String(source_string) => assert_eq!(source_string, source),
+			output => panic!("unexpected output {output:?}"),
+		}
+		assert_eq!(shader_builder.source_string, source);
+		match shader_builder
+			.output_target(OutputTarget::Bytes)
+			.build_output()
+			.unwrap()
+		{
+			BuildOutput::Bytes(bytes) => assert_eq!(bytes, source.as_bytes()),
+			output => panic!("unexpected output {output:?}"),
+		}
+		assert!(shader_builder.source_string.is_empty());
+		let file_path = env::temp_dir().join("wgsl_preprocessor_build_output.wgsl");
+		let output = shader_builder
+			.output_target(OutputTarget::File(file_path.clone()))
+			.build_output()
+			.unwrap();
+		assert_eq!(output.len(), source.len());
+		assert_eq!(fs::read_to_string(&file_path).unwrap(), source);
+		#[cfg(feature = "mmap")]
+		match shader_builder
+			.output_target(OutputTarget::MemoryMap(file_path.clone()))
+			.build_output()
+			.unwrap()
+		{
+			BuildOutput::MemoryMap(memory_map) => assert_eq!(&memory_map[..], source.as_bytes()),
+			output => panic!("unexpected output {output:?}"),
+		}
+		fs::remove_file(&file_path).unwrap();
+		let error = shader_builder
+			.output_target(OutputTarget::File(file_path.join("missing.wgsl")))
+			.build_output()
+			.unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::NotFound);
+	}
+
+	#[test]
+	fn order_declarations() {
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+			ShaderBuilder::new("test_shaders/order_declarations.wgsl")
 				.unwrap()
-				.source_string,
-			format!(
-				"{}{}",
-				ShaderBuilder::new("test_shaders/included.wgsl")
-					.unwrap()
-					.source_string,
-				ShaderBuilder::new("test_shaders/included2.wgsl")
-					.unwrap()
-					.source_string
+				.order_declarations(true)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/order_declarations_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn expect() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/expect.wgsl").unwrap();
+		shader_builder
+			.put_constant("MAX_LIGHTS", 8u32)
+			.put_color("FOG_COLOR", Color::Linear([0.0; 4]));
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const max_lights: u32 = 8u;\nconst fog_color: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);\n"
+		);
+		let error = shader_builder
+			.put_constant("MAX_LIGHTS", 8i32)
+			.build_source()
+			.unwrap_err();
+		assert!(error
+			.to_string()
+			.contains("`MAX_LIGHTS` is expected to be `u32`, but it is put as `i32`"));
+		let error = ShaderBuilder::new("test_shaders/expect.wgsl")
+			.unwrap()
+			.put_constant("MAX_LIGHTS", 8u32)
+			.build_source()
+			.unwrap_err();
+		assert!(error.to_string().contains(
+			"`FOG_COLOR` is expected to be put from Rust code as `vec4<f32>`, but it is not"
+		));
+	}
+
+	#[test]
+	fn separate_symbols() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/separate_symbols.wgsl").unwrap();
+		shader_builder.put_constant("DENSITY", 0.5f32);
+		assert!(shader_builder
+			.build_source()
+			.unwrap()
+			.contains("fn () -> bool"));
+		assert_eq!(
+			shader_builder
+				.separate_symbols(true)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/separate_symbols_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn included_condition() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/included_condition.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/included_condition_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn if_expressions() {
+		let source = "//!define SAMPLES 4\n\
+			//!if WIDTH > 256 && (defined(FOG) || !defined(SHADOWS))\nwide();\n//!endif\n\
+			//!if SAMPLES * 2 == 8 && WIDTH % 2u == 0u\nsampled();\n//!endif\n\
+			//!if defined(BLUR) && BLUR_RADIUS > 2\nblur();\n//!endif\n\
+			//!if !(SAMPLES >= 4) || LOW_QUALITY\nlow();\n//!endif\n";
+		let mut shader_builder = ShaderBuilder::from_source("if_expressions", source);
+		shader_builder
+			.put_constant("WIDTH", 512u32)
+			.put_constant("LOW_QUALITY", false);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"wide();\nsampled();\n"
+		);
+		shader_builder
+			.put_constant("WIDTH", 128u32)
+			.put_constant("BLUR", true)
+			.put_constant("BLUR_RADIUS", 3i32)
+			.put_constant("LOW_QUALITY", true);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"sampled();\nblur();\nlow();\n"
+		);
+		let mut shader_builder =
+			ShaderBuilder::from_source("invalid_if", "//!if 2 >\nwide();\n//!endif\n");
+		let error = shader_builder.build_source().unwrap_err();
+		assert!(error
+			.to_string()
+			.contains("invalid condition `2 >`: unexpected end of expression"));
+		assert_eq!(error.diagnostic().unwrap().location.line, 1);
+		let error = ShaderBuilder::from_source(
+			"negation_overflow",
+			"//!if -(0 - 9223372036854775807 - 1) < 0\nwide();\n//!endif\n",
+		)
+		.build_source()
+		.unwrap_err();
+		assert!(error
+			.to_string()
+			.contains("`-(-9223372036854775808)` overflows"));
+	}
+
+	#[test]
+	fn elif() {
+		let source = "//!ifdef LOW\nconst samples: u32 = 1u;\n\
+			//!elif QUALITY > 2\nconst samples: u32 = 16u;\n\
+			//!elifndef MEDIUM\nconst samples: u32 = 8u;\n\
+			//!elifdef MEDIUM\nconst samples: u32 = 4u;\n\
+			//!else\nconst samples: u32 = 0u;\n//!endif\n";
+		let samples = |symbols: &[&str], quality: i32| {
+			let mut shader_builder = ShaderBuilder::from_source("elif", source);
+			shader_builder.put_constant("QUALITY", quality);
+			for symbol in symbols {
+				shader_builder.put_constant(symbol, true);
+			}
+			shader_builder.build_source().unwrap().to_string()
+		};
+		assert_eq!(samples(&[], 1), "const samples: u32 = 8u;\n");
+		assert_eq!(samples(&["LOW"], 3), "const samples: u32 = 1u;\n");
+		assert_eq!(samples(&[], 3), "const samples: u32 = 16u;\n");
+		assert_eq!(samples(&["MEDIUM"], 1), "const samples: u32 = 4u;\n");
+		assert_eq!(
+			ShaderBuilder::from_source(
+				"else",
+				"//!ifdef A\n//!elifdef B\n//!else\nc();\n//!endif\n"
+			)
+			.build_source()
+			.unwrap(),
+			"c();\n"
+		);
+	}
+
+	#[test]
+	fn nested_else() {
+		let source = "//!ifdef OUTER\n\
+			//!ifdef INNER\na();\n//!elif WIDTH > 2\nb();\n//!else\nc();\n//!endif\n\
+			//!else\n\
+			//!ifdef INNER\nd();\n//!else\ne();\n//!endif\n\
+			//!endif\n";
+		let build = |symbols: &[&str]| {
+			let mut shader_builder = ShaderBuilder::from_source("nested_else", source);
+			for symbol in symbols {
+				shader_builder.put_constant(symbol, true);
+			}
+			// `WIDTH` is only defined when the block using it is active.
+			if symbols.contains(&"OUTER") {
+				shader_builder.put_constant("WIDTH", 1);
+			}
+			shader_builder.build_source().unwrap().to_string()
+		};
+		assert_eq!(build(&[]), "e();\n");
+		assert_eq!(build(&["INNER"]), "d();\n");
+		assert_eq!(build(&["OUTER"]), "c();\n");
+		assert_eq!(build(&["OUTER", "INNER"]), "a();\n");
+
+		assert_eq!(
+			ShaderBuilder::from_source(
+				"defined_in_block",
+				"//!ifndef A\n//!define A\na();\n//!elifdef A\nb();\n//!else\nc();\n//!endif\n\
+				//!ifdef A\nd();\n//!else\ne();\n//!endif\n"
 			)
+			.build_source()
+			.unwrap(),
+			"a();\nd();\n"
+		);
+
+		let error = ShaderBuilder::from_source(
+			"duplicate_else",
+			"//!ifdef A\na();\n//!else\nb();\n//!else\nc();\n//!endif\n",
 		)
+		.build_source()
+		.unwrap_err();
+		assert_eq!(
+			error.diagnostic().unwrap().message,
+			"`//!else` after the `//!else` on line 3"
+		);
 	}
 
 	#[test]
-	fn multiple_inline_includes() {
+	fn unbalanced_conditional() {
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/multiple_inline_includes.wgsl")
+			ShaderBuilder::new("test_shaders/unbalanced_conditional.wgsl")
 				.unwrap()
-				.source_string,
-			ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+				.build_source()
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::InvalidData
+		);
+	}
+
+	#[test]
+	fn error_recovery() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/error_recovery.wgsl").unwrap();
+		shader_builder.templates(true);
+		assert_eq!(
+			shader_builder.build_source().err().unwrap().kind(),
+			io::ErrorKind::NotFound
+		);
+		assert!(shader_builder.build_errors().is_empty());
+		shader_builder.error_recovery(true);
+		assert_eq!(
+			shader_builder.build_source().err().unwrap().kind(),
+			io::ErrorKind::InvalidData
+		);
+		assert_eq!(
+			shader_builder
+				.build_errors()
+				.iter()
+				.map(|error| error.kind())
+				.collect::<Vec<io::ErrorKind>>(),
+			[
+				io::ErrorKind::NotFound,
+				io::ErrorKind::InvalidData,
+				io::ErrorKind::InvalidData,
+				io::ErrorKind::InvalidData
+			]
+		);
+	}
+
+	#[test]
+	fn remap_include_prefix() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/remapped_include.wgsl")
+				.unwrap()
+				.remap_include_prefix("shaders:/", "missing/")
+				.remap_include_prefix("shaders:/", "test_shaders/")
+				.remap_include_prefix("shaders", "missing/")
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/included.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn normalize_paths() {
+		let mut shader_builder =
+			ShaderBuilder::new("test_shaders/normalized_include.wgsl").unwrap();
+		assert!(!shader_builder
+			.build_source()
+			.unwrap()
+			.contains("normalized"));
+		assert!(shader_builder
+			.normalize_paths(true)
+			.build_source()
+			.unwrap()
+			.contains("normalized"));
+		assert_eq!(
+			shader_builder.build_stats().files_read[1],
+			fs::canonicalize("test_shaders/included.wgsl").unwrap()
+		);
+	}
+
+	#[test]
+	fn invalid_utf8() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/invalid_utf8.wgsl").unwrap();
+		let error = shader_builder.build_source().err().unwrap();
+		assert_eq!(
+			error.diagnostic().unwrap(),
+			&Diagnostic {
+				message: "invalid UTF-8 at byte 23, the module may be a binary file".to_string(),
+				location: SourceLocation {
+					path: path::PathBuf::from("test_shaders/invalid_utf8.wgsl"),
+					line: 2,
+				},
+				directive: None,
+				text: "// \u{fffd}\u{fffd}".to_string(),
+				include_chain: Vec::new(),
+				include_texts: Vec::new(),
+			}
+		);
+		assert_eq!(
+			shader_builder
+				.invalid_utf8(InvalidUtf8::Lossy)
+				.build_source()
+				.unwrap(),
+			"const a: f32 = 1.0;\n// \u{fffd}\u{fffd}\nconst b: f32 = 2.0;\n"
+		);
+		assert_eq!(
+			shader_builder
+				.warnings()
+				.iter()
+				.map(|warning| warning.code)
+				.collect::<Vec<WarningCode>>(),
+			[WarningCode::InvalidUtf8]
+		);
+	}
+
+	#[test]
+	fn size_limits() {
+		let error = ShaderBuilder::new("test_shaders/include_section.wgsl")
+			.unwrap()
+			.max_module_size(100)
+			.build_source()
+			.err()
+			.unwrap();
+		assert_eq!(
+			error.diagnostic().unwrap().location,
+			SourceLocation {
+				path: path::PathBuf::from("test_shaders/include_section.wgsl"),
+				line: 1,
+			}
+		);
+		let mut shader_builder = ShaderBuilder::new("test_shaders/include_section.wgsl").unwrap();
+		shader_builder.max_module_size(100).error_recovery(true);
+		assert!(shader_builder.build_source().is_err());
+		assert_eq!(shader_builder.build_errors().len(), 1);
+		assert!(shader_builder.build_errors()[0]
+			.to_string()
+			.contains("exceeds the maximum module size of 100 bytes"));
+		let error = ShaderBuilder::new("test_shaders/included.wgsl")
+			.unwrap()
+			.max_output_size(10)
+			.build_source()
+			.err()
+			.unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+		assert!(error
+			.to_string()
+			.contains("exceeds the maximum output size of 10 bytes"));
+	}
+
+	#[test]
+	fn include_section() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/include_section.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/include_section_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		let error = ShaderBuilder::new("test_shaders/include_missing_section.wgsl")
+			.unwrap()
+			.build_source()
+			.err()
+			.unwrap();
+		assert!(matches!(
+			&error,
+			PreprocessError::Statement {
+				path,
+				line: 2,
+				directive: Some(directive),
+				text,
+				..
+			} if path == path::Path::new("test_shaders/include_missing_section.wgsl")
+				&& directive == "include"
+				&& text == "//!include test_shaders/colors.wgsl section=shadows"
+		));
+		assert_eq!(
+			error.diagnostic().unwrap(),
+			&Diagnostic {
+				message: "section `shadows` not found in test_shaders/colors.wgsl".to_string(),
+				location: SourceLocation {
+					path: path::PathBuf::from("test_shaders/include_missing_section.wgsl"),
+					line: 2,
+				},
+				directive: Some("include".to_string()),
+				text: "//!include test_shaders/colors.wgsl section=shadows".to_string(),
+				include_chain: Vec::new(),
+				include_texts: Vec::new(),
+			}
+		);
+	}
+
+	#[test]
+	fn preserve() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/preserve.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			fs::read_to_string("test_shaders/preserve_processed.wgsl").unwrap()
+		);
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/unbalanced_preserve.wgsl")
+				.unwrap()
+				.build_source()
+				.err()
+				.unwrap()
+				.diagnostic()
+				.unwrap()
+				.message,
+			"`//!preserve` without a matching `//!endpreserve`"
+		);
+	}
+
+	#[test]
+	fn reserved_word() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/reserved_word.wgsl")
+				.unwrap()
+				.build_source()
+				.err()
+				.unwrap()
+				.to_string(),
+			"error preprocessing \"test_shaders/reserved_word.wgsl\": \
+			`let class` declares `class`, which is a reserved word of WGSL and can not be used as an identifier, \
+			`NAME` defined at test_shaders/reserved_word.wgsl:1 expands to it"
+		);
+	}
+
+	#[test]
+	fn warnings() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/warnings.wgsl").unwrap();
+		shader_builder.build_source().unwrap();
+		assert_eq!(
+			shader_builder.warnings(),
+			[
+				Warning {
+					code: WarningCode::Redefinition,
+					message: "`COUNT` is already defined at test_shaders/warnings.wgsl:2"
+						.to_string(),
+					location: SourceLocation {
+						path: path::PathBuf::from("test_shaders/warnings.wgsl"),
+						line: 3,
+					},
+				},
+				Warning {
+					code: WarningCode::UnusedDefine,
+					message: "`UNUSED` is never used".to_string(),
+					location: SourceLocation {
+						path: path::PathBuf::from("test_shaders/warnings.wgsl"),
+						line: 1,
+					},
+				}
+			]
+		);
+		shader_builder
+			.allow(WarningCode::UnusedDefine)
+			.build_source()
+			.unwrap();
+		assert_eq!(shader_builder.warnings().len(), 1);
+		assert_eq!(
+			shader_builder
+				.deny(WarningCode::Redefinition)
+				.build_source()
+				.err()
 				.unwrap()
-				.source_string
+				.kind(),
+			io::ErrorKind::InvalidData
+		);
+	}
+
+	#[test]
+	fn allow_statement() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/allowed_warnings.wgsl").unwrap();
+		shader_builder
+			.deny(WarningCode::Redefinition)
+			.build_source()
+			.unwrap();
+		assert_eq!(
+			shader_builder
+				.warnings()
+				.iter()
+				.map(|warning| warning.code)
+				.collect::<Vec<WarningCode>>(),
+			[WarningCode::UnusedDefine]
+		);
+	}
+
+	#[test]
+	fn allow_every_code() {
+		let codes = ["W001", "W002", "W003", "W004", "W005", "W006", "W007"];
+		for code in codes {
+			assert_eq!(code.parse::<WarningCode>().unwrap().code(), code);
+		}
+		assert!("W008".parse::<WarningCode>().is_err());
+		ShaderBuilder::from_source(
+			"allow_every_code.wgsl",
+			&format!("//!allow({})\nconst A: u32 = 1u;\n", codes.join(", ")),
 		)
+		.build_source()
+		.unwrap();
+	}
+
+	#[test]
+	fn undefined_symbol_lint() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/conditional.wgsl").unwrap();
+		shader_builder
+			.undefined_symbol_lint(true)
+			.build_source()
+			.unwrap();
+		assert_eq!(
+			shader_builder.warnings(),
+			[Warning {
+				code: WarningCode::UndefinedSymbol,
+				message: "`QUALITY` is never defined".to_string(),
+				location: SourceLocation {
+					path: path::PathBuf::from("test_shaders/conditional.wgsl"),
+					line: 4,
+				},
+			}]
+		);
+		shader_builder
+			.put_constant("QUALITY", 2u32)
+			.build_source()
+			.unwrap();
+		assert!(shader_builder.warnings().is_empty());
+	}
+
+	#[test]
+	fn deprecated() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/deprecated.wgsl").unwrap();
+		shader_builder.build_source().unwrap();
+		assert_eq!(
+			shader_builder.warnings(),
+			[
+				Warning {
+					code: WarningCode::Deprecated,
+					message: "`OLD_SCALE` is deprecated: use SCALE instead".to_string(),
+					location: SourceLocation {
+						path: path::PathBuf::from("test_shaders/deprecated.wgsl"),
+						line: 5,
+					},
+				},
+				Warning {
+					code: WarningCode::Deprecated,
+					message: "`legacy_blend` is deprecated".to_string(),
+					location: SourceLocation {
+						path: path::PathBuf::from("test_shaders/deprecated.wgsl"),
+						line: 9,
+					},
+				}
+			]
+		);
+	}
+
+	#[test]
+	fn definition_provenance() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/warnings.wgsl").unwrap();
+		shader_builder.put_constant("UNUSED", 0u32);
+		let put_constant_line = line!() as usize - 1;
+		shader_builder.build_source().unwrap();
+		let rust_provenance = Provenance::Rust(SourceLocation {
+			path: path::PathBuf::from(file!()),
+			line: put_constant_line,
+		});
+		assert_eq!(
+			shader_builder.warnings()[0].message,
+			format!("`UNUSED` is already defined at {rust_provenance}")
+		);
+		assert_eq!(
+			shader_builder.build_stats().definition_provenance["COUNT"],
+			Provenance::Module(SourceLocation {
+				path: path::PathBuf::from("test_shaders/warnings.wgsl"),
+				line: 3,
+			})
+		);
+	}
+
+	#[test]
+	fn build_stats() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/conditional.wgsl").unwrap();
+		let source_length = shader_builder
+			.put_constant("QUALITY", 2u32)
+			.build_source()
+			.unwrap()
+			.len();
+		let build_stats = shader_builder.build_stats();
+		assert_eq!(
+			build_stats.files_read,
+			vec![path::PathBuf::from("test_shaders/conditional.wgsl")]
+		);
+		assert_eq!(build_stats.bytes_emitted, source_length);
+		assert_eq!(build_stats.substitutions["QUALITY"], 1);
+		assert_eq!(build_stats.substitutions["FOG"], 0);
+		assert_eq!(build_stats.conditional_lines_skipped, 3);
+	}
+
+	#[test]
+	fn cancelled_build() {
+		let cancellation_token = CancellationToken::new();
+		cancellation_token.cancel();
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+				.unwrap()
+				.cancellation_token(cancellation_token.clone())
+				.build_source()
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::Interrupted
+		);
+	}
+
+	#[test]
+	fn time_limit() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/multiple_includes.wgsl").unwrap();
+		assert_eq!(
+			shader_builder
+				.time_limit(time::Duration::ZERO)
+				.build_source()
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::TimedOut
+		);
+		assert!(shader_builder
+			.time_limit(time::Duration::from_secs(60))
+			.build_source()
+			.is_ok());
 	}
 
+	#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
 	#[test]
-	fn define() {
+	fn target_symbols() {
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/definer.wgsl")
+			ShaderBuilder::new("test_shaders/target_symbols.wgsl")
 				.unwrap()
-				.source_string,
-			ShaderBuilder::new("test_shaders/defined.wgsl")
+				.target_symbols(true)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/target_symbols_processed.wgsl")
 				.unwrap()
-				.source_string
-		)
-	}
-
-	#[test]
-	fn include_define() {
+				.build_source()
+				.unwrap()
+		);
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/included_define.wgsl")
+			ShaderBuilder::new("test_shaders/target_symbols.wgsl")
 				.unwrap()
-				.source_string,
-			ShaderBuilder::new("test_shaders/included_define_processed.wgsl")
+				.target_symbols(true)
+				.deterministic(true)
+				.build_source()
+				.err()
 				.unwrap()
-				.source_string,
-		)
+				.kind(),
+			io::ErrorKind::InvalidInput
+		);
 	}
 
 	#[test]
-	fn define_with_spaces() {
+	fn array_size_constant() {
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/define_with_spaces.wgsl")
+			ShaderBuilder::new("test_shaders/array_size_constant.wgsl")
 				.unwrap()
-				.source_string,
-			ShaderBuilder::new("test_shaders/define_with_spaces_processed.wgsl")
+				.put_constant("NUM_SAMPLES", 4u32)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/array_size_constant_processed.wgsl")
+				.unwrap()
+				.build_source()
 				.unwrap()
-				.source_string,
 		)
 	}
 
 	#[test]
-	fn put_constant() {
+	fn invalid_array_size_constant() {
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/set_constants.wgsl")
+			ShaderBuilder::new("test_shaders/invalid_array_size_constant.wgsl")
 				.unwrap()
-				.put_constant("ONE", 1u32)
-				.put_constant("TWO", 2u32)
-				.source_string,
-			ShaderBuilder::new("test_shaders/set_constants_processed.wgsl")
+				.build_source()
+				.err()
 				.unwrap()
-				.source_string
-		)
+				.kind(),
+			io::ErrorKind::InvalidData
+		);
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/array_size_constant.wgsl")
+				.unwrap()
+				.put_constant("NUM_SAMPLES", 0u32)
+				.build_source()
+				.err()
+				.unwrap()
+				.kind(),
+			io::ErrorKind::InvalidData
+		);
 	}
 
 	#[test]
-	fn put_constant_map() {
-		let mut constants = HashMap::new();
-		constants.insert("ONE", 1u32);
-		constants.insert("TWO", 2u32);
+	fn deterministic() {
+		for _ in 0..8 {
+			assert_eq!(
+				ShaderBuilder::new("test_shaders/deterministic.wgsl")
+					.unwrap()
+					.deterministic(true)
+					.put_constant("LIGHT", 1u32)
+					.put_constant("LIGHT_COUNT", 4u32)
+					.put_constant("LIGHT_COUNT_MAX", 8u32)
+					.build_source()
+					.unwrap(),
+				ShaderBuilder::new("test_shaders/deterministic_processed.wgsl")
+					.unwrap()
+					.build_source()
+					.unwrap()
+			);
+		}
+	}
+
+	#[test]
+	fn label_hash() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+		let label = shader_builder
+			.label_hash(true)
+			.build()
+			.unwrap()
+			.label
+			.unwrap()
+			.to_string();
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/set_constants.wgsl")
-				.unwrap()
-				.put_constant_map(&constants)
-				.source_string,
-			ShaderBuilder::new("test_shaders/set_constants_processed.wgsl")
-				.unwrap()
-				.source_string
-		)
+			label,
+			format!("included@{:06x}", shader_builder.content_hash() >> 40)
+		);
+		let mut other_shader_builder = ShaderBuilder::new("test_shaders/included2.wgsl").unwrap();
+		other_shader_builder.build_source().unwrap();
+		assert_ne!(
+			shader_builder.content_hash(),
+			other_shader_builder.content_hash()
+		);
 	}
 
+	#[cfg(feature = "serde")]
 	#[test]
-	fn load_proper_label() {
+	fn serialized_config() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/set_constants.wgsl").unwrap();
+		shader_builder
+			.put_constant("ONE", 1u32)
+			.put_constant("TWO", 2u32)
+			.label_hash(true);
+		let serialized_config = serde_json::to_string(shader_builder.config()).unwrap();
 		assert_eq!(
-			ShaderBuilder::new("test_shaders/included.wgsl")
+			ShaderBuilder::from_config(serde_json::from_str(&serialized_config).unwrap())
 				.unwrap()
-				.build()
-				.label
+				.build_source()
 				.unwrap(),
-			"included"
+			shader_builder.build_source().unwrap()
 		);
 	}
 
@@ -532,10 +7808,12 @@ mod tests {
 			ShaderBuilder::new("test_shaders/put_array_definition_bools.wgsl")
 				.unwrap()
 				.put_array_definition("BOOL_ARRAY", &vec![&true, &false])
-				.source_string,
+				.build_source()
+				.unwrap(),
 			ShaderBuilder::new("test_shaders/put_array_definition_bools_processed.wgsl")
 				.unwrap()
-				.source_string
+				.build_source()
+				.unwrap()
 		)
 	}
 
@@ -545,10 +7823,12 @@ mod tests {
 			ShaderBuilder::new("test_shaders/put_array_definition_scalars.wgsl")
 				.unwrap()
 				.put_array_definition("SCALAR_ARRAY", &vec![&1, &0])
-				.source_string,
+				.build_source()
+				.unwrap(),
 			ShaderBuilder::new("test_shaders/put_array_definition_scalars_processed.wgsl")
 				.unwrap()
-				.source_string
+				.build_source()
+				.unwrap()
 		)
 	}
 
@@ -563,8 +7843,7 @@ mod tests {
 			}
 
 			fn string_definition(&self) -> String {
-				format!("{}(vec4<f32>({:?}))", Self::type_name(), self.data)
-					.replace(&['[', ']'], "")
+				format!("{}(vec4<f32>({:?}))", Self::type_name(), self.data).replace(['[', ']'], "")
 			}
 		}
 		assert_eq!(
@@ -581,10 +7860,521 @@ mod tests {
 						}
 					]
 				)
-				.source_string,
+				.build_source()
+				.unwrap(),
 			ShaderBuilder::new("test_shaders/put_array_definition_structs_processed.wgsl")
 				.unwrap()
-				.source_string
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn put_array_definition_empty() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/put_array_definition_empty.wgsl")
+				.unwrap()
+				.put_array_definition::<u32>("LIGHT_ARRAY", &vec![])
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/put_array_definition_empty_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn put_soa_definition() {
+		struct Particle {
+			pub position: [f32; 2],
+			pub mass: f32,
+		}
+		impl WGSLStruct for Particle {
+			fn field_types() -> Vec<(&'static str, String)> {
+				vec![
+					("position", "vec2<f32>".to_string()),
+					("mass", f32::type_name()),
+				]
+			}
+
+			fn field_definitions(&self) -> Vec<String> {
+				vec![
+					format!("vec2<f32>({:?})", self.position).replace(['[', ']'], ""),
+					self.mass.string_definition(),
+				]
+			}
+		}
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/put_soa_definition.wgsl")
+				.unwrap()
+				.put_soa_definition(
+					"PARTICLES",
+					&[
+						&Particle {
+							position: [1.0, 2.0],
+							mass: 0.5
+						},
+						&Particle {
+							position: [3.5, 4.0],
+							mass: 2.0
+						}
+					]
+				)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/put_soa_definition_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[repr(C)]
+	struct Transform {
+		pub translation: [f32; 3],
+		pub scale: f32,
+	}
+
+	impl WGSLStruct for Transform {
+		fn field_types() -> Vec<(&'static str, String)> {
+			vec![
+				("translation", "vec3<f32>".to_string()),
+				("scale", f32::type_name()),
+			]
+		}
+
+		fn field_definitions(&self) -> Vec<String> {
+			vec![
+				format!("vec3<f32>({:?})", self.translation).replace(['[', ']'], ""),
+				self.scale.string_definition(),
+			]
+		}
+	}
+
+	#[test]
+	fn put_push_constants() {
+		let mut shader_builder =
+			ShaderBuilder::new("test_shaders/put_push_constants.wgsl").unwrap();
+		shader_builder.put_push_constants::<Transform>(
+			"transform",
+			16,
+			wgpu::ShaderStages::VERTEX_FRAGMENT,
+		);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			ShaderBuilder::new("test_shaders/put_push_constants_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		assert_eq!(
+			shader_builder.push_constant_ranges(),
+			[wgpu::PushConstantRange {
+				stages: wgpu::ShaderStages::VERTEX_FRAGMENT,
+				range: 16..32,
+			}]
+		);
+	}
+
+	#[test]
+	#[should_panic(
+		expected = "`Transform` defines a different number of fields than it has field types"
+	)]
+	fn mismatched_soa_fields() {
+		struct Transform;
+		impl WGSLStruct for Transform {
+			fn field_types() -> Vec<(&'static str, String)> {
+				vec![("scale", "f32".to_string())]
+			}
+
+			fn field_definitions(&self) -> Vec<String> {
+				Vec::new()
+			}
+		}
+		ShaderBuilder::new("test_shaders/put_soa_definition.wgsl")
+			.unwrap()
+			.put_soa_definition("TRANSFORMS", &[&Transform]);
+	}
+
+	#[test]
+	#[should_panic(expected = "is not aligned")]
+	fn misaligned_push_constants() {
+		ShaderBuilder::new("test_shaders/put_push_constants.wgsl")
+			.unwrap()
+			.put_push_constants::<Transform>("transform", 8, wgpu::ShaderStages::VERTEX);
+	}
+
+	#[test]
+	fn preset() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/preset.wgsl")
+				.unwrap()
+				.preset(Preset::Multiview { views: 2 })
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/preset_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+	}
+
+	#[test]
+	fn definition_layers() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/definition_layers.wgsl").unwrap();
+		shader_builder
+			.preset(Preset::Multiview { views: 2 })
+			.put_constant("SAMPLES", 8u32)
+			.put_constant("QUALITY", 2u32);
+		assert_eq!(
+			shader_builder
+				.build_source_with_overlay(&HashMap::from([(
+					"QUALITY".to_string(),
+					"3u".to_string()
+				)]))
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/definition_layers_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		assert_eq!(
+			shader_builder.definition("MULTIVIEW"),
+			Some(("", DefinitionLayer::Default))
+		);
+		assert_eq!(
+			shader_builder.definition("VIEW_COUNT"),
+			Some(("4u", DefinitionLayer::File))
+		);
+		assert_eq!(
+			shader_builder.definition("SAMPLES"),
+			Some(("8u", DefinitionLayer::Builder))
+		);
+		assert_eq!(
+			shader_builder.definition("QUALITY"),
+			Some(("3u", DefinitionLayer::Overlay))
+		);
+		assert_eq!(shader_builder.definition("FOG"), None);
+		shader_builder.build_source().unwrap();
+		assert_eq!(
+			shader_builder.definition("QUALITY"),
+			Some(("2u", DefinitionLayer::Builder))
+		);
+	}
+
+	#[test]
+	fn accumulated_definitions() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/registry/registry.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap(),
+			"fn point_light() -> f32 { return 1.0; }\n\
+			fn spot_light() -> f32 { return 0.5; }\n\
+			const light_types = array<u32, 2u>(POINT, SPOT);\n"
+		);
+		let error = ShaderBuilder::from_source(
+			"invalid_accumulation",
+			"//!define COUNT many\n//!define_add COUNT 1\n",
+		)
+		.build_source()
+		.unwrap_err();
+		assert_eq!(
+			error.diagnostic().unwrap().message,
+			"`COUNT` is `many`, which is not an integer"
+		);
+	}
+
+	#[test]
+	fn recursive_definitions() {
+		let mut shader_builder = ShaderBuilder::from_source(
+			"recursive_definitions",
+			"//!define SIZE WIDTH * HEIGHT\n//!define WIDTH 4u\n//!define HEIGHT (WIDTH + 1u)\n\
+			const size: u32 = SIZE;\n",
+		);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const size: u32 = 4u * (4u + 1u);\n"
+		);
+		let mut shader_builder = ShaderBuilder::from_source(
+			"circular_definitions",
+			"//!define AREA SIDE * SIDE\n//!define SIDE EDGE\n//!define EDGE SIDE\n\
+			const area: u32 = AREA;\n",
+		);
+		shader_builder.error_recovery(true);
+		assert!(shader_builder.build_source().is_err());
+		assert_eq!(shader_builder.build_errors().len(), 1);
+		assert!(shader_builder.build_errors()[0]
+			.to_string()
+			.contains("circular definition: SIDE → EDGE → SIDE"));
+	}
+
+	#[test]
+	fn function_macros() {
+		let mut shader_builder = ShaderBuilder::from_source(
+			"function_macros",
+			"//!define SCALE 2.0\n//!define SQUARE(x) ((x) * (x))\n//!define LERP(a, b, t) mix(a, b, t)\n\
+			const a: f32 = SQUARE(SQUARE(SCALE + 1.0));\nconst b = LERP(vec2(0.0, 1.0), SQUARE(v[0]), 0.5);\n",
+		);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const a: f32 = ((((2.0 + 1.0) * (2.0 + 1.0))) * (((2.0 + 1.0) * (2.0 + 1.0))));\n\
+			const b = mix(vec2(0.0, 1.0), ((v[0]) * (v[0])), 0.5);\n"
+		);
+		assert_eq!(
+			shader_builder.build_stats().macro_expansions["SQUARE"].count,
+			3
+		);
+		let mut shader_builder = ShaderBuilder::from_source(
+			"function_macro_arguments",
+			"//!define SQUARE(x) ((x) * (x))\nconst a = SQUARE(1.0, 2.0);\n",
+		);
+		assert!(shader_builder
+			.build_source()
+			.unwrap_err()
+			.to_string()
+			.contains("macro `SQUARE` takes 1 arguments but 2 were given"));
+	}
+
+	#[test]
+	fn multi_line_macros() {
+		let source = "//!define LUMINANCE \\\n\
+			fn luminance(color: vec3<f32>) -> f32 { \\\n\
+			\treturn dot(color, vec3(0.2126, 0.7152, 0.0722)); \\\n\
+			}\n\
+			//!define CLAMPED(x, low, high) \\\n\
+			\tmin(max(x, low), \\\n\
+			\thigh)\n\
+			LUMINANCE\nconst a = CLAMPED(b, 0.0, 1.0);\n";
+		assert_eq!(
+			ShaderBuilder::from_source("multi_line_macros", source)
+				.build_source()
+				.unwrap(),
+			"fn luminance(color: vec3<f32>) -> f32 {\n\
+			\treturn dot(color, vec3(0.2126, 0.7152, 0.0722));\n\
+			}\nconst a = min(max(b, 0.0),\n\t1.0);\n"
+		);
+	}
+
+	#[test]
+	fn max_expansion_size() {
+		let source = "//!define DOUBLE(x) (x + x)\n//!define QUAD(x) DOUBLE(DOUBLE(x))\n\
+			//!define SIZE WIDTH * WIDTH\n//!define WIDTH 64u\n\
+			const a = QUAD(b);\nconst size = SIZE;\n";
+		let mut shader_builder = ShaderBuilder::from_source("max_expansion_size", source);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const a = ((b + b) + (b + b));\nconst size = 64u * 64u;\n"
+		);
+		let macro_expansions = &shader_builder.build_stats().macro_expansions;
+		assert_eq!(
+			macro_expansions["DOUBLE"],
+			MacroExpansion {
+				count: 2,
+				bytes_emitted: 26,
+				largest_expansion: 19,
+			}
+		);
+		assert_eq!(macro_expansions["QUAD"].count, 1);
+		assert_eq!(macro_expansions["SIZE"].bytes_emitted, 9);
+		let error = shader_builder
+			.max_expansion_size(18)
+			.build_source()
+			.unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+		assert!(error.to_string().contains(
+			"expansion of macro `DOUBLE` is 19 bytes, exceeding the maximum expansion size of 18 bytes"
+		));
+		let mut shader_builder = ShaderBuilder::from_source(
+			"max_definition_size",
+			"//!define SIZE WIDTH * WIDTH\n//!define WIDTH 64u\nconst size = SIZE;\n",
+		);
+		shader_builder.max_expansion_size(8);
+		assert!(shader_builder
+			.build_source()
+			.unwrap_err()
+			.to_string()
+			.contains("expansion of macro `SIZE` is 9 bytes"));
+	}
+
+	#[test]
+	fn define_default() {
+		let source = "//!define_default SAMPLES 4u\n\
+			//!define QUALITY 2u\n//!define_default QUALITY 3u\n\
+			const samples: u32 = SAMPLES;\nconst quality: u32 = QUALITY;\n";
+		let mut shader_builder = ShaderBuilder::from_source("define_default", source);
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const samples: u32 = 4u;\nconst quality: u32 = 2u;\n"
+		);
+		assert_eq!(
+			shader_builder.definition("SAMPLES"),
+			Some(("4u", DefinitionLayer::File))
+		);
+		assert_eq!(
+			shader_builder
+				.put_constant("SAMPLES", 8u32)
+				.build_source()
+				.unwrap(),
+			"const samples: u32 = 8u;\nconst quality: u32 = 2u;\n"
+		);
+		assert!(shader_builder
+			.warnings()
+			.iter()
+			.all(|warning| warning.code != WarningCode::Redefinition));
+	}
+
+	#[test]
+	fn put_bindings() {
+		let mut bindings = Bindings::new();
+		bindings
+			.uniform::<Transform>("transform")
+			.group(1)
+			.visibility(wgpu::ShaderStages::FRAGMENT)
+			.texture_2d("albedo")
+			.sampler("samp");
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/put_bindings.wgsl")
+				.unwrap()
+				.put_bindings("BINDINGS", &bindings)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/put_bindings_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		);
+		assert_eq!(bindings.groups(), [0, 1]);
+		assert_eq!(
+			bindings.layout_entries(0),
+			[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::all(),
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: std::num::NonZeroU64::new(16),
+				},
+				count: None,
+			}]
+		);
+		assert_eq!(
+			bindings
+				.layout_entries(1)
+				.iter()
+				.map(|entry| (entry.binding, entry.visibility))
+				.collect::<Vec<_>>(),
+			[
+				(0, wgpu::ShaderStages::FRAGMENT),
+				(1, wgpu::ShaderStages::FRAGMENT)
+			]
+		);
+	}
+
+	#[test]
+	fn storage_access() {
+		let mut bindings = Bindings::new();
+		bindings
+			.storage::<Transform>("transforms", true)
+			.storage::<Transform>("output", false)
+			.storage::<Transform>("unused", false);
+		let mut shader_builder = ShaderBuilder::new("test_shaders/storage_access.wgsl").unwrap();
+		shader_builder.put_bindings("BINDINGS", &bindings);
+		shader_builder.build_source().unwrap();
+		assert_eq!(
+			shader_builder.warnings(),
+			[Warning {
+				code: WarningCode::UnwrittenStorage,
+				message:
+					"`unused` is declared as a `read_write` storage buffer but is never written"
+						.to_string(),
+				location: SourceLocation {
+					path: path::PathBuf::from("test_shaders/storage_access.wgsl"),
+					line: 1,
+				},
+			}]
+		);
+
+		let mut bindings = Bindings::new();
+		bindings
+			.storage::<Transform>("transforms", true)
+			.storage::<Transform>("output", true)
+			.storage::<Transform>("unused", true);
+		let error = shader_builder
+			.put_bindings("BINDINGS", &bindings)
+			.build_source()
+			.err()
+			.unwrap();
+		assert_eq!(
+			error.diagnostic().unwrap().message,
+			"`output` is written but is declared as a read-only storage buffer"
+		);
+
+		let mut shader_builder = ShaderBuilder::from_source(
+			"storage_access_before_declaration.wgsl",
+			"fn store() {\n\toutput.scale = 1.0;\n}\n//!define BINDINGS\n",
+		);
+		let error = shader_builder
+			.put_bindings("BINDINGS", &bindings)
+			.build_source()
+			.err()
+			.unwrap();
+		assert_eq!(error.diagnostic().unwrap().location.line, 2);
+	}
+
+	#[test]
+	fn put_layout_constants() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/put_layout_constants.wgsl")
+				.unwrap()
+				.put_layout_constants::<Transform>("TRANSFORM")
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/put_layout_constants_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn put_texture_format() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/put_texture_format.wgsl")
+				.unwrap()
+				.put_texture_format("OUTPUT_FORMAT", wgpu::TextureFormat::Rgba8Unorm)
+				.put_texture_format("SHADOW_FORMAT", wgpu::TextureFormat::Depth32Float)
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/put_texture_format_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
+		)
+	}
+
+	#[test]
+	fn define_from_surface() {
+		assert_eq!(
+			ShaderBuilder::new("test_shaders/define_from_surface.wgsl")
+				.unwrap()
+				.define_from_surface(&wgpu::SurfaceConfiguration {
+					usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+					format: wgpu::TextureFormat::Bgra8UnormSrgb,
+					width: 1920,
+					height: 1080,
+					present_mode: wgpu::PresentMode::Fifo,
+					desired_maximum_frame_latency: 2,
+					alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+					view_formats: Vec::new(),
+				})
+				.build_source()
+				.unwrap(),
+			ShaderBuilder::new("test_shaders/define_from_surface_processed.wgsl")
+				.unwrap()
+				.build_source()
+				.unwrap()
 		)
 	}
 
@@ -598,10 +8388,12 @@ mod tests {
 					"VECTOR_ARRAY",
 					&vec![&[1.0, 2.0, 3.0, 4.0], &[1.5, 2.1, 3.7, 4.9]]
 				)
-				.source_string,
+				.build_source()
+				.unwrap(),
 			ShaderBuilder::new("test_shaders/put_array_definition_vectors_processed.wgsl")
 				.unwrap()
-				.source_string
+				.build_source()
+				.unwrap()
 		)
 	}
 
@@ -618,10 +8410,12 @@ mod tests {
 						&cgmath::Vector4::<f32>::new(1.5, 2.1, 3.7, 4.9)
 					]
 				)
-				.source_string,
+				.build_source()
+				.unwrap(),
 			ShaderBuilder::new("test_shaders/put_array_definition_vectors_processed.wgsl")
 				.unwrap()
-				.source_string
+				.build_source()
+				.unwrap()
 		)
 	}
 }
@@ -0,0 +1,45 @@
+/// Preprocessed shader library, built once with [`ShaderBuilder::build_library`](crate::ShaderBuilder::build_library)
+/// and linked into any number of shaders with [`ShaderBuilder::link_library`](crate::ShaderBuilder::link_library)
+/// without reading its modules again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderLibrary {
+	pub(crate) name: String,
+	pub(crate) source: String,
+	pub(crate) declarations: Vec<(String, String)>,
+}
+
+impl ShaderLibrary {
+	/// Returns the name of the library, the label of the shader it was built from.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Returns the built WGSL source of the library.
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	/// Returns the kind and the name of every top-level declaration of the library, like `("fn", "shade")`.
+	pub fn declarations(&self) -> &[(String, String)] {
+		&self.declarations
+	}
+
+	/// Returns whether the library declares `name`, whatever the kind of the declaration.
+	pub fn declares(&self, name: &str) -> bool {
+		self.declarations
+			.iter()
+			.any(|(_, declared_name)| declared_name == name)
+	}
+
+	/// Validates the source of the library with [`naga`], so errors are found once instead of in every shader linking it.
+	/// Requires the **reflection** feature.
+	#[cfg(feature = "reflection")]
+	pub fn validate(&self) -> Result<(), ex::io::Error> {
+		crate::reflection::validate(&self.source).map_err(|message| {
+			crate::preprocess_error(
+				std::path::Path::new(&self.name),
+				format!("invalid library: {message}"),
+			)
+		})
+	}
+}
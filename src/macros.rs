@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use crate::MacroExpansion;
+
+lazy_static::lazy_static! {
+	static ref IDENTIFIER_REGEX: regex::Regex = regex::Regex::new(r"\b[A-Za-z_][A-Za-z0-9_]*\b").unwrap();
+}
+
+/// Function-like macro, defined by a statement like `//!define SQUARE(x) ((x) * (x))`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FunctionMacro {
+	pub(crate) parameters: Vec<String>,
+	pub(crate) body: String,
+}
+
+impl FunctionMacro {
+	/// Parses the comma-separated parameters and the body of a macro, failing if a parameter is not an identifier.
+	pub(crate) fn new(parameters: &str, body: &str) -> Result<Self, String> {
+		let parameters: Vec<String> = if parameters.trim().is_empty() {
+			Vec::new()
+		} else {
+			parameters
+				.split(',')
+				.map(|parameter| parameter.trim().to_string())
+				.collect()
+		};
+		for (index, parameter) in parameters.iter().enumerate() {
+			if IDENTIFIER_REGEX
+				.find(parameter)
+				.map(|identifier| identifier.as_str())
+				!= Some(parameter.as_str())
+			{
+				return Err(format!("invalid macro parameter `{parameter}`"));
+			}
+			if parameters[..index].contains(parameter) {
+				return Err(format!("duplicate macro parameter `{parameter}`"));
+			}
+		}
+		Ok(Self {
+			parameters,
+			body: body.trim().to_string(),
+		})
+	}
+}
+
+/// Expands the invocations of function-like macros in a string, like `SQUARE(a + 1)`.
+/// Arguments are split at the commas that are not nested in parentheses or brackets,
+/// and invocations in arguments and in expansions are expanded too,
+/// except for invocations of a macro within its own expansion, which are kept like in C.
+/// Names of macros that are not followed by `(` are left untouched.
+///
+/// Returns the expanded string, recording the expansions of every macro in `expansions`,
+/// or fails as soon as an expansion is longer than `max_expansion_size` bytes.
+pub(crate) fn expand(
+	string: &str,
+	macros: &HashMap<String, FunctionMacro>,
+	max_expansion_size: Option<usize>,
+	expansions: &mut HashMap<String, MacroExpansion>,
+) -> Result<String, String> {
+	Expansion {
+		macros,
+		max_expansion_size,
+		expansions,
+		expanding: Vec::new(),
+	}
+	.expand(string)
+}
+
+/// State of an [`expand`] call.
+struct Expansion<'a> {
+	macros: &'a HashMap<String, FunctionMacro>,
+	max_expansion_size: Option<usize>,
+	expansions: &'a mut HashMap<String, MacroExpansion>,
+	/// Names of the macros being expanded, which are not expanded again in their own expansion.
+	expanding: Vec<String>,
+}
+
+impl Expansion<'_> {
+	fn expand(&mut self, string: &str) -> Result<String, String> {
+		let mut expanded_string = String::with_capacity(string.len());
+		let mut end = 0;
+		for identifier in IDENTIFIER_REGEX.find_iter(string) {
+			if identifier.start() < end {
+				continue;
+			}
+			let name = identifier.as_str();
+			let Some(function_macro) = self
+				.macros
+				.get(name)
+				.filter(|_| !self.expanding.iter().any(|expanded| expanded == name))
+			else {
+				continue;
+			};
+			let rest = string[identifier.end()..].trim_start();
+			if !rest.starts_with('(') {
+				continue;
+			}
+			let arguments_offset = string.len() - rest.len() + 1;
+			let (arguments, arguments_length) = arguments(&rest[1..])
+				.ok_or_else(|| format!("unterminated invocation of macro `{name}`"))?;
+			let arguments: Vec<String> = if function_macro.parameters.is_empty()
+				&& arguments.len() == 1
+				&& arguments[0].is_empty()
+			{
+				Vec::new()
+			} else {
+				arguments
+			};
+			if arguments.len() != function_macro.parameters.len() {
+				return Err(format!(
+					"macro `{name}` takes {} arguments but {} were given",
+					function_macro.parameters.len(),
+					arguments.len()
+				));
+			}
+			let arguments = arguments
+				.iter()
+				.map(|argument| self.expand(argument))
+				.collect::<Result<Vec<String>, String>>()?;
+			let body =
+				IDENTIFIER_REGEX.replace_all(&function_macro.body, |captures: &regex::Captures| {
+					match function_macro
+						.parameters
+						.iter()
+						.position(|parameter| *parameter == captures[0])
+					{
+						Some(index) => arguments[index].clone(),
+						None => captures[0].to_string(),
+					}
+				});
+			self.check_size(name, body.len())?;
+			self.expanding.push(name.to_string());
+			let expansion = self.expand(&body)?;
+			self.expanding.pop();
+			self.check_size(name, expansion.len())?;
+			self.expansions
+				.entry(name.to_string())
+				.or_default()
+				.record(1, expansion.len());
+			expanded_string.push_str(&string[end..identifier.start()]);
+			expanded_string.push_str(&expansion);
+			end = arguments_offset + arguments_length + 1;
+		}
+		expanded_string.push_str(&string[end..]);
+		Ok(expanded_string)
+	}
+
+	/// Fails if an expansion of a macro is longer than the maximum expansion size.
+	fn check_size(&self, name: &str, length: usize) -> Result<(), String> {
+		match self.max_expansion_size {
+			Some(max_expansion_size) if length > max_expansion_size => Err(
+				crate::expansion_size_error(name, length, max_expansion_size),
+			),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Splits the arguments of an invocation, starting after its `(`, at the commas that are not nested.
+/// Returns the trimmed arguments and the length of the arguments up to the closing `)`,
+/// or [`None`] if the invocation is not closed.
+fn arguments(string: &str) -> Option<(Vec<String>, usize)> {
+	let mut arguments = Vec::new();
+	let mut depth = 0usize;
+	let mut start = 0;
+	for (index, character) in string.char_indices() {
+		match character {
+			'(' | '[' => depth += 1,
+			')' if depth == 0 => {
+				arguments.push(string[start..index].trim().to_string());
+				return Some((arguments, index));
+			}
+			')' | ']' => depth = depth.saturating_sub(1),
+			',' if depth == 0 => {
+				arguments.push(string[start..index].trim().to_string());
+				start = index + 1;
+			}
+			_ => {}
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use crate::macros::{expand, FunctionMacro};
+
+	#[test]
+	fn expand_function_macros() {
+		let macros = HashMap::from([
+			(
+				"SQUARE".to_string(),
+				FunctionMacro::new("x", "((x) * (x))").unwrap(),
+			),
+			(
+				"MAX3".to_string(),
+				FunctionMacro::new("a, b, c", "max(a, max(b, c))").unwrap(),
+			),
+			("ZERO".to_string(), FunctionMacro::new("", "0.0").unwrap()),
+			(
+				"LOOP".to_string(),
+				FunctionMacro::new("x", "LOOP(x)").unwrap(),
+			),
+		]);
+		let mut expansions = HashMap::new();
+		assert_eq!(
+			expand(
+				"let a = SQUARE(SQUARE(f(b, c))) + MAX3(v[0], ZERO(), SQUARE (d)) + SQUARE_ROOT + LOOP(1);",
+				&macros,
+				None,
+				&mut expansions
+			)
+			.unwrap(),
+			"let a = ((((f(b, c)) * (f(b, c)))) * (((f(b, c)) * (f(b, c))))) + max(v[0], max(0.0, ((d) * (d)))) + SQUARE_ROOT + LOOP(1);"
+		);
+		assert_eq!(expansions["SQUARE"].count, 3);
+		assert_eq!(expansions["SQUARE"].largest_expansion, 55);
+		assert_eq!(
+			expand("SQUARE(a, b)", &macros, None, &mut expansions).unwrap_err(),
+			"macro `SQUARE` takes 1 arguments but 2 were given"
+		);
+		assert_eq!(
+			expand("SQUARE(a", &macros, None, &mut expansions).unwrap_err(),
+			"unterminated invocation of macro `SQUARE`"
+		);
+		assert!(FunctionMacro::new("a b", "a").is_err());
+		assert!(FunctionMacro::new("a, a", "a").is_err());
+	}
+}
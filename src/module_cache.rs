@@ -0,0 +1,147 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	path, sync,
+};
+
+use crate::source_resolver::{read_module, SourceResolver};
+
+/// Cache of module sources that can be shared between [`ShaderBuilder`](crate::ShaderBuilder)s,
+/// so modules included by many shaders are only read once per process.
+///
+/// Modules are cached by path and never reloaded from the disk on their own.
+/// Modules that changed on the disk should be evicted with [`ModuleCache::evict`] or [`ModuleCache::clear`].
+#[derive(Debug, Default)]
+pub struct ModuleCache {
+	max_modules: Option<usize>,
+	state: sync::Mutex<ModuleCacheState>,
+}
+
+#[derive(Debug, Default)]
+struct ModuleCacheState {
+	modules: HashMap<path::PathBuf, sync::Arc<[u8]>>,
+	/// Cached paths from the least to the most recently used.
+	usage: VecDeque<path::PathBuf>,
+}
+
+impl ModuleCache {
+	/// Creates a new empty [`ModuleCache`] without a limit on the number of cached modules.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Creates a new empty [`ModuleCache`] that holds at most `max_modules` modules.
+	/// When the cache is full, the least recently used module is evicted.
+	pub fn with_max_modules(max_modules: usize) -> Self {
+		Self {
+			max_modules: Some(max_modules),
+			..Self::default()
+		}
+	}
+
+	/// Removes the module at `module_path` from the cache.
+	/// Returns whether the module was cached.
+	pub fn evict(&self, module_path: impl AsRef<path::Path>) -> bool {
+		let mut state = self.state.lock().unwrap();
+		let module_path = module_path.as_ref();
+		state.usage.retain(|cached_path| cached_path != module_path);
+		state.modules.remove(module_path).is_some()
+	}
+
+	/// Removes all modules from the cache.
+	pub fn clear(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.modules.clear();
+		state.usage.clear();
+	}
+
+	/// Returns the number of cached modules.
+	pub fn len(&self) -> usize {
+		self.state.lock().unwrap().modules.len()
+	}
+
+	/// Returns whether no modules are cached.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns whether the module at `module_path` is cached.
+	pub fn contains(&self, module_path: impl AsRef<path::Path>) -> bool {
+		self.state
+			.lock()
+			.unwrap()
+			.modules
+			.contains_key(module_path.as_ref())
+	}
+
+	/// Returns the bytes of a module, which are decoded by the builder.
+	pub(crate) fn read(
+		&self,
+		module_path: &path::Path,
+		source_resolver: &dyn SourceResolver,
+	) -> Result<sync::Arc<[u8]>, ex::io::Error> {
+		let mut state = self.state.lock().unwrap();
+		if let Some(module_source) = state.modules.get(module_path).cloned() {
+			state.usage.retain(|cached_path| cached_path != module_path);
+			state.usage.push_back(module_path.to_path_buf());
+			return Ok(module_source);
+		}
+		let module_source = read_module(source_resolver, module_path)?;
+		if self.max_modules == Some(0) {
+			return Ok(module_source);
+		}
+		if self
+			.max_modules
+			.is_some_and(|max_modules| state.modules.len() >= max_modules)
+		{
+			if let Some(least_recently_used) = state.usage.pop_front() {
+				state.modules.remove(&least_recently_used);
+			}
+		}
+		state
+			.modules
+			.insert(module_path.to_path_buf(), module_source.clone());
+		state.usage.push_back(module_path.to_path_buf());
+		Ok(module_source)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{ModuleCache, ShaderBuilder};
+	use std::sync;
+
+	#[test]
+	fn shared_module_cache() {
+		let module_cache = sync::Arc::new(ModuleCache::new());
+		for source_path in [
+			"test_shaders/includer.wgsl",
+			"test_shaders/multiple_includes.wgsl",
+		] {
+			ShaderBuilder::new(source_path)
+				.unwrap()
+				.module_cache(module_cache.clone())
+				.build_source()
+				.unwrap();
+		}
+		assert_eq!(module_cache.len(), 4);
+		assert!(module_cache.contains("test_shaders/included.wgsl"));
+		assert!(module_cache.evict("test_shaders/included.wgsl"));
+		assert!(!module_cache.contains("test_shaders/included.wgsl"));
+		module_cache.clear();
+		assert!(module_cache.is_empty());
+	}
+
+	#[test]
+	fn least_recently_used_eviction() {
+		let module_cache = sync::Arc::new(ModuleCache::with_max_modules(2));
+		ShaderBuilder::new("test_shaders/multiple_includes.wgsl")
+			.unwrap()
+			.module_cache(module_cache.clone())
+			.build_source()
+			.unwrap();
+		assert_eq!(module_cache.len(), 2);
+		assert!(!module_cache.contains("test_shaders/multiple_includes.wgsl"));
+		assert!(module_cache.contains("test_shaders/included.wgsl"));
+		assert!(module_cache.contains("test_shaders/included2.wgsl"));
+	}
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+lazy_static::lazy_static! {
+	static ref DECLARED_NAME_REGEX: regex::Regex = regex::Regex::new(
+		r"^(?:\s+|//[^\n]*\n|/\*(?s:.*?)\*/|@[A-Za-z_][A-Za-z0-9_]*(?:\s*\([^)]*\))?)*(struct|fn|const|var|override|alias)\b(?:\s*<[^>]*>)?\s+([A-Za-z_][A-Za-z0-9_]*)"
+	)
+	.unwrap();
+	static ref IDENTIFIER_REGEX: regex::Regex =
+		regex::Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+}
+
+/// Splits a WGSL source into its top-level items, each with the comments and blank lines before it
+/// and the rest of its last line. Text after the last item is an item of its own.
+fn items(source: &str) -> Vec<&str> {
+	let bytes = source.as_bytes();
+	let mut items = Vec::new();
+	let mut start = 0;
+	let mut depth = 0usize;
+	let mut index = 0;
+	while index < bytes.len() {
+		match bytes[index] {
+			b'/' if bytes.get(index + 1) == Some(&b'/') => {
+				index = source[index..]
+					.find('\n')
+					.map_or(bytes.len(), |end| index + end);
+				continue;
+			}
+			b'/' if bytes.get(index + 1) == Some(&b'*') => {
+				index = source[index + 2..]
+					.find("*/")
+					.map_or(bytes.len(), |end| index + end + 4);
+				continue;
+			}
+			b'{' | b'(' | b'[' => depth += 1,
+			b'}' | b')' | b']' => depth = depth.saturating_sub(1),
+			_ => {}
+		}
+		let is_item_end = depth == 0 && matches!(bytes[index], b';' | b'}');
+		index += 1;
+		if is_item_end {
+			let rest_of_line = |index: usize| {
+				source[index..]
+					.find('\n')
+					.map_or(&source[index..], |end| &source[index..index + end + 1])
+			};
+			let rest = rest_of_line(index).trim_start();
+			if bytes[index - 1] == b'}' && rest.starts_with(';') {
+				index += rest_of_line(index).len() - rest.len() + 1;
+			}
+			let rest = rest_of_line(index).trim_start();
+			if rest.is_empty() || rest.starts_with("//") {
+				index += rest_of_line(index).len();
+			}
+			items.push(&source[start..index]);
+			start = index;
+		}
+	}
+	if start < source.len() {
+		items.push(&source[start..]);
+	}
+	items
+}
+
+/// Reorders the top-level items of a WGSL source so every declaration comes before the items using it,
+/// keeping the original order otherwise. Dependencies are found by scanning the identifiers of every item,
+/// so an item using a local variable named like a declaration is also placed after it,
+/// which is harmless since WGSL declarations can be in any order.
+///
+/// Returns the reordered source and the new index of every line of the source.
+pub(crate) fn order_declarations(source: &str) -> (String, Vec<usize>) {
+	let items = items(source);
+	let declarations: HashMap<&str, usize> = items
+		.iter()
+		.enumerate()
+		.filter_map(|(index, item)| {
+			Some((
+				DECLARED_NAME_REGEX.captures(item)?.get(2).unwrap().as_str(),
+				index,
+			))
+		})
+		.collect();
+	let dependencies: Vec<Vec<usize>> = items
+		.iter()
+		.enumerate()
+		.map(|(index, item)| {
+			let mut dependencies: Vec<usize> = IDENTIFIER_REGEX
+				.find_iter(item)
+				.filter_map(|identifier| declarations.get(identifier.as_str()).copied())
+				.filter(|dependency| *dependency != index)
+				.collect();
+			dependencies.sort_unstable();
+			dependencies.dedup();
+			dependencies
+		})
+		.collect();
+	let mut is_visited = vec![false; items.len()];
+	let mut order = Vec::with_capacity(items.len());
+	for index in 0..items.len() {
+		visit(index, &dependencies, &mut is_visited, &mut order);
+	}
+	let mut ordered_source = String::with_capacity(source.len() + items.len());
+	let mut line_indices = vec![0; source.lines().count()];
+	for index in order {
+		if !ordered_source.is_empty() && !ordered_source.ends_with('\n') {
+			ordered_source.push('\n');
+		}
+		let item = items[index];
+		let line_index = source[..item.as_ptr() as usize - source.as_ptr() as usize]
+			.matches('\n')
+			.count();
+		let ordered_line_index = ordered_source.matches('\n').count();
+		for offset in 0..item.lines().count() {
+			line_indices[line_index + offset] = ordered_line_index + offset;
+		}
+		ordered_source.push_str(item);
+	}
+	(ordered_source, line_indices)
+}
+
+/// Returns the kind and the name of every top-level declaration of a WGSL source, in order.
+pub(crate) fn declarations(source: &str) -> Vec<(String, String)> {
+	items(source)
+		.into_iter()
+		.filter_map(|item| {
+			let captures = DECLARED_NAME_REGEX.captures(item)?;
+			Some((captures[1].to_string(), captures[2].to_string()))
+		})
+		.collect()
+}
+
+/// Adds an item to the order after its dependencies, ignoring dependency cycles.
+fn visit(
+	index: usize,
+	dependencies: &[Vec<usize>],
+	is_visited: &mut [bool],
+	order: &mut Vec<usize>,
+) {
+	if is_visited[index] {
+		return;
+	}
+	is_visited[index] = true;
+	for dependency in dependencies[index].iter() {
+		visit(*dependency, dependencies, is_visited, order);
+	}
+	order.push(index);
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::ordering::order_declarations;
+
+	#[test]
+	fn ordered_declarations() {
+		let source = "enable f16;\n\
+			// Uses lights.\n\
+			fn shade(light: Light) -> f32 { return light.intensity * SCALE; }\n\
+			@group(0) @binding(0) var<uniform> sun: Light;\n\
+			struct Light {\n\tintensity: f32,\n};\n\
+			const SCALE: f32 = 2.0; // Scale.\n";
+		let (ordered_source, line_indices) = order_declarations(source);
+		assert_eq!(
+			ordered_source,
+			"enable f16;\n\
+			struct Light {\n\tintensity: f32,\n};\n\
+			const SCALE: f32 = 2.0; // Scale.\n\
+			// Uses lights.\n\
+			fn shade(light: Light) -> f32 { return light.intensity * SCALE; }\n\
+			@group(0) @binding(0) var<uniform> sun: Light;\n"
+		);
+		assert_eq!(line_indices, [0, 5, 6, 7, 1, 2, 3, 4]);
+		let ordered_source = "struct A { b: f32 }\nfn f(a: A) {}";
+		assert_eq!(order_declarations(ordered_source).0, ordered_source);
+	}
+}
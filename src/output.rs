@@ -0,0 +1,91 @@
+use std::{io, path};
+
+/// Destination of the source built by [`ShaderBuilder::build_output`](crate::ShaderBuilder::build_output),
+/// see [`ShaderBuilder::output_target`](crate::ShaderBuilder::output_target).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputTarget {
+	/// Returned as a [`String`], and kept in [`ShaderBuilder::source_string`](crate::ShaderBuilder::source_string).
+	#[default]
+	String,
+	/// Returned as the UTF-8 bytes of the source.
+	Bytes,
+	/// Written to a file, created or truncated.
+	File(path::PathBuf),
+	/// Written to a file, created or truncated, which is returned mapped in memory,
+	/// so it can be shared with other processes. Requires the **mmap** feature.
+	#[cfg(feature = "mmap")]
+	MemoryMap(path::PathBuf),
+}
+
+/// Source built by [`ShaderBuilder::build_output`](crate::ShaderBuilder::build_output), depending on its [`OutputTarget`].
+#[derive(Debug)]
+pub enum BuildOutput {
+	/// Source of an [`OutputTarget::String`].
+	String(String),
+	/// Source of an [`OutputTarget::Bytes`].
+	Bytes(Vec<u8>),
+	/// Path of the file of an [`OutputTarget::File`], and the length of the source in bytes.
+	File(path::PathBuf, usize),
+	/// Memory map of the file of an [`OutputTarget::MemoryMap`].
+	#[cfg(feature = "mmap")]
+	MemoryMap(memmap2::Mmap),
+}
+
+impl BuildOutput {
+	/// Returns the length of the source in bytes.
+	pub fn len(&self) -> usize {
+		match self {
+			Self::String(source) => source.len(),
+			Self::Bytes(bytes) => bytes.len(),
+			Self::File(_, length) => *length,
+			#[cfg(feature = "mmap")]
+			Self::MemoryMap(memory_map) => memory_map.len(),
+		}
+	}
+
+	/// Returns whether the source is empty.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+/// Moves a built source to its target.
+pub(crate) fn write(target: &OutputTarget, source: String) -> io::Result<BuildOutput> {
+	match target {
+		OutputTarget::String => Ok(BuildOutput::String(source)),
+		OutputTarget::Bytes => Ok(BuildOutput::Bytes(source.into_bytes())),
+		OutputTarget::File(file_path) => {
+			std::fs::write(file_path, &source).map_err(|error| file_error(file_path, error))?;
+			Ok(BuildOutput::File(file_path.clone(), source.len()))
+		}
+		#[cfg(feature = "mmap")]
+		OutputTarget::MemoryMap(file_path) => {
+			let map = || -> io::Result<memmap2::Mmap> {
+				let file = std::fs::OpenOptions::new()
+					.read(true)
+					.write(true)
+					.create(true)
+					.truncate(true)
+					.open(file_path)?;
+				file.set_len(source.len() as u64)?;
+				// Safety: the file was just truncated by this process, and is only modified through the map.
+				let mut memory_map = unsafe { memmap2::MmapMut::map_mut(&file)? };
+				memory_map.copy_from_slice(source.as_bytes());
+				memory_map.flush()?;
+				memory_map.make_read_only()
+			};
+			map()
+				.map(BuildOutput::MemoryMap)
+				.map_err(|error| file_error(file_path, error))
+		}
+	}
+}
+
+/// Returns an error of the file of a target, with its path.
+fn file_error(file_path: &path::Path, error: io::Error) -> io::Error {
+	io::Error::new(
+		error.kind(),
+		format!("failed to write {}: {error}", file_path.display()),
+	)
+}
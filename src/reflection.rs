@@ -0,0 +1,1078 @@
+/*!
+Reflection over built WGSL sources with [`naga`], enabled by the **reflection** feature.
+*/
+use std::{
+	collections::{BTreeMap, HashMap},
+	error, fmt, num, sync,
+};
+
+/// Pass transforming a module, see [`crate::ShaderBuilder::naga_pass`].
+pub(crate) type NagaPass = sync::Arc<dyn Fn(&mut naga::Module) -> Result<(), String> + Send + Sync>;
+
+/// Error returned when the interfaces of two shader stages are not compatible,
+/// see [`check_stage_interface`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterfaceError {
+	/// A source could not be parsed.
+	Parse {
+		/// Stage of the source that could not be parsed.
+		stage: naga::ShaderStage,
+		/// Message of the parsing error, with the offending source lines.
+		message: String,
+	},
+	/// A source has no entry point with the given name and stage.
+	MissingEntryPoint {
+		/// Stage of the missing entry point.
+		stage: naga::ShaderStage,
+		/// Name of the missing entry point.
+		name: String,
+	},
+	/// Inputs of the fragment stage are not outputs of the vertex stage.
+	Mismatches(Vec<InterfaceMismatch>),
+}
+
+impl fmt::Display for InterfaceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Parse { stage, message } => write!(f, "{stage:?} stage: {message}"),
+			Self::MissingEntryPoint { stage, name } => {
+				write!(f, "{stage:?} stage: missing entry point `{name}`")
+			}
+			Self::Mismatches(mismatches) => {
+				for (index, mismatch) in mismatches.iter().enumerate() {
+					if index > 0 {
+						writeln!(f)?;
+					}
+					write!(f, "{mismatch}")?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+impl error::Error for InterfaceError {}
+
+/// Fragment input without a matching vertex output, see [`InterfaceError::Mismatches`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceMismatch {
+	/// The `@location` of the fragment input.
+	pub location: u32,
+	/// Name of the fragment input.
+	pub fragment_input: String,
+	/// WGSL type of the fragment input.
+	pub fragment_type: String,
+	/// WGSL type of the vertex output at the same location, if there is one.
+	pub vertex_type: Option<String>,
+}
+
+impl fmt::Display for InterfaceMismatch {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.vertex_type {
+			Some(vertex_type) => write!(
+				f,
+				"@location({}) fragment input `{}` has type `{}`, but the vertex output has type `{vertex_type}`",
+				self.location, self.fragment_input, self.fragment_type
+			),
+			None => write!(
+				f,
+				"@location({}) fragment input `{}` of type `{}` is not an output of the vertex stage",
+				self.location, self.fragment_input, self.fragment_type
+			),
+		}
+	}
+}
+
+/// Checks that every `@location` input of a fragment entry point is an output of a vertex entry point
+/// with the same type.
+///
+/// # Arguments
+/// - `vertex_source` - Built WGSL source containing the vertex entry point.
+/// - `vertex_entry_point` - Name of the vertex entry point.
+/// - `fragment_source` - Built WGSL source containing the fragment entry point, may be the same as `vertex_source`.
+/// - `fragment_entry_point` - Name of the fragment entry point.
+pub fn check_stage_interface(
+	vertex_source: &str,
+	vertex_entry_point: &str,
+	fragment_source: &str,
+	fragment_entry_point: &str,
+) -> Result<(), InterfaceError> {
+	let vertex_module = parse(naga::ShaderStage::Vertex, vertex_source)?;
+	let fragment_module = parse(naga::ShaderStage::Fragment, fragment_source)?;
+	let vertex_function = entry_point_function(
+		&vertex_module,
+		naga::ShaderStage::Vertex,
+		vertex_entry_point,
+	)?;
+	let fragment_function = entry_point_function(
+		&fragment_module,
+		naga::ShaderStage::Fragment,
+		fragment_entry_point,
+	)?;
+
+	let mut vertex_outputs = BTreeMap::new();
+	if let Some(result) = &vertex_function.result {
+		collect_locations(
+			&vertex_module,
+			"",
+			result.ty,
+			result.binding.as_ref(),
+			&mut vertex_outputs,
+		);
+	}
+	let mut fragment_inputs = BTreeMap::new();
+	for argument in fragment_function.arguments.iter() {
+		collect_locations(
+			&fragment_module,
+			argument.name.as_deref().unwrap_or_default(),
+			argument.ty,
+			argument.binding.as_ref(),
+			&mut fragment_inputs,
+		);
+	}
+
+	let mismatches: Vec<InterfaceMismatch> = fragment_inputs
+		.into_iter()
+		.filter_map(|(location, (fragment_input, fragment_type))| {
+			let vertex_type = vertex_outputs
+				.get(&location)
+				.map(|(_, vertex_type): &(String, String)| vertex_type.clone());
+			(vertex_type.as_ref() != Some(&fragment_type)).then_some(InterfaceMismatch {
+				location,
+				fragment_input,
+				fragment_type,
+				vertex_type,
+			})
+		})
+		.collect();
+	if mismatches.is_empty() {
+		Ok(())
+	} else {
+		Err(InterfaceError::Mismatches(mismatches))
+	}
+}
+
+/// Merged bind group layout requirements of shaders sharing a pipeline, see [`pipeline_layout`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineLayoutSummary {
+	/// Entries of every bind group by group index, sorted by binding.
+	/// The visibility of an entry is the union of the stages of the entry points using it.
+	pub bind_groups: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>>,
+}
+
+/// Error returned when shaders can not share a pipeline layout, see [`pipeline_layout`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+	/// A source could not be parsed or validated.
+	Invalid {
+		/// Index of the source in the slice given to [`pipeline_layout`].
+		source_index: usize,
+		/// Message of the error, with the offending source lines.
+		message: String,
+	},
+	/// Bindings are declared with different types.
+	Conflicts(Vec<BindingConflict>),
+}
+
+impl fmt::Display for LayoutError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Invalid {
+				source_index,
+				message,
+			} => write!(f, "source {source_index}: {message}"),
+			Self::Conflicts(conflicts) => {
+				for (index, conflict) in conflicts.iter().enumerate() {
+					if index > 0 {
+						writeln!(f)?;
+					}
+					write!(f, "{conflict}")?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+impl error::Error for LayoutError {}
+
+/// Binding declared with different types by two shaders, see [`LayoutError::Conflicts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BindingConflict {
+	/// Index of the source in the slice given to [`pipeline_layout`] that conflicts with the previous ones.
+	pub source_index: usize,
+	/// Group index of the binding.
+	pub group: u32,
+	/// Entry required by the previous sources.
+	pub first: wgpu::BindGroupLayoutEntry,
+	/// Entry required by the conflicting source.
+	pub second: wgpu::BindGroupLayoutEntry,
+}
+
+impl fmt::Display for BindingConflict {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"source {}: @group({}) @binding({}) is declared as {:?}, but previously as {:?}",
+			self.source_index, self.group, self.first.binding, self.second.ty, self.first.ty
+		)
+	}
+}
+
+/// Computes the bind group layouts required by shaders that will share a pipeline,
+/// for building [`wgpu::BindGroupLayoutDescriptor`]s and a [`wgpu::PipelineLayoutDescriptor`].
+/// Only bindings used by an entry point are included.
+///
+/// # Arguments
+/// - `sources` - Built WGSL sources of the shaders.
+pub fn pipeline_layout(sources: &[&str]) -> Result<PipelineLayoutSummary, LayoutError> {
+	let mut summary = PipelineLayoutSummary::default();
+	let mut conflicts = Vec::new();
+	for (source_index, source) in sources.iter().enumerate() {
+		let invalid = |message| LayoutError::Invalid {
+			source_index,
+			message,
+		};
+		let module = naga::front::wgsl::parse_str(source)
+			.map_err(|error| invalid(error.emit_to_string(source)))?;
+		let module_info = naga::valid::Validator::new(
+			naga::valid::ValidationFlags::all(),
+			naga::valid::Capabilities::all(),
+		)
+		.validate(&module)
+		.map_err(|error| invalid(error.emit_to_string(source)))?;
+		for (handle, global) in module.global_variables.iter() {
+			let (Some(resource_binding), Some((ty, count))) =
+				(&global.binding, binding_type(&module, global))
+			else {
+				continue;
+			};
+			let visibility = module
+				.entry_points
+				.iter()
+				.enumerate()
+				.filter(|&(index, _)| !module_info.get_entry_point(index)[handle].is_empty())
+				.fold(wgpu::ShaderStages::NONE, |visibility, (_, entry_point)| {
+					visibility
+						| match entry_point.stage {
+							naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+							naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+							naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+						}
+				});
+			if visibility.is_empty() {
+				continue;
+			}
+			let entry = wgpu::BindGroupLayoutEntry {
+				binding: resource_binding.binding,
+				visibility,
+				ty,
+				count,
+			};
+			let entries = summary
+				.bind_groups
+				.entry(resource_binding.group)
+				.or_default();
+			match entries
+				.iter_mut()
+				.find(|existing| existing.binding == entry.binding)
+			{
+				Some(existing) if (existing.ty, existing.count) == (entry.ty, entry.count) => {
+					existing.visibility |= entry.visibility;
+				}
+				Some(existing) => conflicts.push(BindingConflict {
+					source_index,
+					group: resource_binding.group,
+					first: *existing,
+					second: entry,
+				}),
+				None => entries.push(entry),
+			}
+		}
+	}
+	if !conflicts.is_empty() {
+		return Err(LayoutError::Conflicts(conflicts));
+	}
+	for entries in summary.bind_groups.values_mut() {
+		entries.sort_by_key(|entry| entry.binding);
+	}
+	Ok(summary)
+}
+
+/// Error returned when a source can not be parsed, see [`interface_hash`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+	/// Message of the parsing error, with the offending source lines.
+	pub message: String,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl error::Error for ParseError {}
+
+/// Scalar type of an `override` constant, see [`OverrideConstant`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverrideType {
+	/// `bool`
+	Bool,
+	/// `i32`
+	I32,
+	/// `u32`
+	U32,
+	/// `f32`
+	F32,
+	/// `f16`
+	F16,
+}
+
+impl OverrideType {
+	/// Returns whether a value is representable by the type.
+	fn contains(&self, value: f64) -> bool {
+		match self {
+			Self::Bool => value == 0.0 || value == 1.0,
+			Self::I32 => {
+				value.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&value)
+			}
+			Self::U32 => value.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&value),
+			Self::F32 => value.abs() <= f32::MAX as f64,
+			Self::F16 => value.abs() <= 65504.0,
+		}
+	}
+}
+
+impl fmt::Display for OverrideType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", format!("{self:?}").to_lowercase())
+	}
+}
+
+/// Pipeline-overridable constant declared with `override`, see [`override_constants`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverrideConstant {
+	/// Name of the constant.
+	pub name: String,
+	/// Key of the constant in [`wgpu::PipelineCompilationOptions::constants`]:
+	/// its `@id` if it has one, or its name otherwise.
+	pub key: String,
+	/// Type of the constant.
+	pub ty: OverrideType,
+	/// Default value of the constant, if it has one that is a literal.
+	pub default: Option<f64>,
+	/// Whether the constant has a default value, so it may be left unset.
+	pub has_default: bool,
+}
+
+/// Returns the `override` constants declared by a shader.
+///
+/// # Arguments
+/// - `source` - Built WGSL source of the shader.
+pub fn override_constants(source: &str) -> Result<Vec<OverrideConstant>, ParseError> {
+	let module = naga::front::wgsl::parse_str(source).map_err(|error| ParseError {
+		message: error.emit_to_string(source),
+	})?;
+	Ok(module
+		.overrides
+		.iter()
+		.filter_map(|(_, r#override)| {
+			let name = r#override.name.clone()?;
+			let ty = match module.types[r#override.ty].inner {
+				naga::TypeInner::Scalar(scalar) => match (scalar.kind, scalar.width) {
+					(naga::ScalarKind::Bool, _) => OverrideType::Bool,
+					(naga::ScalarKind::Sint, _) => OverrideType::I32,
+					(naga::ScalarKind::Uint, _) => OverrideType::U32,
+					(naga::ScalarKind::Float, 2) => OverrideType::F16,
+					_ => OverrideType::F32,
+				},
+				_ => return None,
+			};
+			let default = r#override
+				.init
+				.and_then(|init| match module.global_expressions[init] {
+					naga::Expression::Literal(literal) => Some(match literal {
+						naga::Literal::F64(value) | naga::Literal::AbstractFloat(value) => value,
+						naga::Literal::F32(value) => value.into(),
+						naga::Literal::U32(value) => value.into(),
+						naga::Literal::I32(value) => value.into(),
+						naga::Literal::U64(value) => value as f64,
+						naga::Literal::I64(value) | naga::Literal::AbstractInt(value) => {
+							value as f64
+						}
+						naga::Literal::Bool(value) => f64::from(u8::from(value)),
+					}),
+					_ => None,
+				});
+			Some(OverrideConstant {
+				key: r#override
+					.id
+					.map_or_else(|| name.clone(), |id| id.to_string()),
+				name,
+				ty,
+				default,
+				has_default: r#override.init.is_some(),
+			})
+		})
+		.collect())
+}
+
+/// Error returned when setting `override` constants, see [`OverrideValues`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverrideError {
+	/// The shader declares no `override` constant with the name.
+	Unknown {
+		/// The name that was set.
+		name: String,
+		/// Names of the constants declared by the shader.
+		declared: Vec<String>,
+	},
+	/// The value can not be represented by the type of the constant.
+	OutOfRange {
+		/// Name of the constant.
+		name: String,
+		/// Type of the constant.
+		ty: OverrideType,
+		/// The value that was set.
+		value: f64,
+	},
+	/// Constants without a default value were not set.
+	Missing(Vec<String>),
+}
+
+impl fmt::Display for OverrideError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Unknown { name, declared } => write!(
+				f,
+				"unknown override constant `{name}`, declared constants are: {}",
+				declared.join(", ")
+			),
+			Self::OutOfRange { name, ty, value } => write!(
+				f,
+				"value {value} of override constant `{name}` is not a valid `{ty}`"
+			),
+			Self::Missing(names) => write!(
+				f,
+				"override constants without a default value are not set: {}",
+				names.join(", ")
+			),
+		}
+	}
+}
+
+impl error::Error for OverrideError {}
+
+/// Values of the `override` constants of a shader, validated against their declarations,
+/// for [`wgpu::PipelineCompilationOptions::constants`]:
+/// ```
+/// # use wgsl_preprocessor::reflection::OverrideValues;
+/// let mut override_values = OverrideValues::new("override brightness: f32 = 1.0;").unwrap();
+/// override_values.set("brightness", 0.5).unwrap();
+/// assert!(override_values.set("brightnes", 0.5).is_err());
+/// let constants = override_values.constants().unwrap();
+/// assert_eq!(constants.get("brightness"), Some(&0.5));
+/// ```
+/// Misspelled names fail when they are set instead of when the pipeline is created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverrideValues {
+	declarations: Vec<OverrideConstant>,
+	constants: HashMap<String, f64>,
+}
+
+impl OverrideValues {
+	/// Creates new [`OverrideValues`] for the `override` constants declared by a shader, all unset.
+	///
+	/// # Arguments
+	/// - `source` - Built WGSL source of the shader.
+	pub fn new(source: &str) -> Result<Self, ParseError> {
+		Ok(Self {
+			declarations: override_constants(source)?,
+			constants: HashMap::new(),
+		})
+	}
+
+	/// Returns the declarations of the `override` constants of the shader.
+	pub fn declarations(&self) -> &[OverrideConstant] {
+		&self.declarations
+	}
+
+	/// Sets the value of a constant, by its name or its `@id`.
+	/// Booleans are converted to `0.0` or `1.0`.
+	pub fn set(&mut self, name: &str, value: impl Into<f64>) -> Result<&mut Self, OverrideError> {
+		let value = value.into();
+		let Some(declaration) = self
+			.declarations
+			.iter()
+			.find(|declaration| declaration.name == name || declaration.key == name)
+		else {
+			return Err(OverrideError::Unknown {
+				name: name.to_string(),
+				declared: self
+					.declarations
+					.iter()
+					.map(|declaration| declaration.name.clone())
+					.collect(),
+			});
+		};
+		if !declaration.ty.contains(value) {
+			return Err(OverrideError::OutOfRange {
+				name: declaration.name.clone(),
+				ty: declaration.ty,
+				value,
+			});
+		}
+		self.constants.insert(declaration.key.clone(), value);
+		Ok(self)
+	}
+
+	/// Returns the values that were set by key, checking that every constant without a default value was set.
+	pub fn constants(&self) -> Result<&HashMap<String, f64>, OverrideError> {
+		let missing: Vec<String> = self
+			.declarations
+			.iter()
+			.filter(|declaration| {
+				!declaration.has_default && !self.constants.contains_key(&declaration.key)
+			})
+			.map(|declaration| declaration.name.clone())
+			.collect();
+		if missing.is_empty() {
+			Ok(&self.constants)
+		} else {
+			Err(OverrideError::Missing(missing))
+		}
+	}
+}
+
+/// Returns a hash of the external interface of a shader: its entry points with their inputs and outputs,
+/// its resource bindings with their types, and its `override` constants.
+/// Unlike [`crate::ShaderBuilder::content_hash`], the hash does not change when function bodies, comments
+/// or private declarations are edited, so it can key caches of pipeline layouts.
+/// Like the content hash, it is identical across runs and platforms.
+///
+/// # Arguments
+/// - `source` - Built WGSL source of the shader.
+pub fn interface_hash(source: &str) -> Result<u64, ParseError> {
+	let module = naga::front::wgsl::parse_str(source).map_err(|error| ParseError {
+		message: error.emit_to_string(source),
+	})?;
+	let mut interface = Vec::new();
+	for entry_point in module.entry_points.iter() {
+		interface.push(format!(
+			"entry point {:?} {} {:?}",
+			entry_point.stage, entry_point.name, entry_point.workgroup_size
+		));
+		for argument in entry_point.function.arguments.iter() {
+			interface.push(format!(
+				"{} input {:?} {}",
+				entry_point.name,
+				argument.binding,
+				type_signature(&module, argument.ty)
+			));
+		}
+		if let Some(result) = &entry_point.function.result {
+			interface.push(format!(
+				"{} output {:?} {}",
+				entry_point.name,
+				result.binding,
+				type_signature(&module, result.ty)
+			));
+		}
+	}
+	for (_, global) in module.global_variables.iter() {
+		if let Some(resource_binding) = &global.binding {
+			interface.push(format!(
+				"binding {} {} {:?} {}",
+				resource_binding.group,
+				resource_binding.binding,
+				global.space,
+				type_signature(&module, global.ty)
+			));
+		}
+	}
+	for (_, r#override) in module.overrides.iter() {
+		interface.push(format!(
+			"override {:?} {:?} {}",
+			r#override.name,
+			r#override.id,
+			type_signature(&module, r#override.ty)
+		));
+	}
+	interface.sort();
+	Ok(crate::fnv1a_hash(interface.join("\n").as_bytes()))
+}
+
+/// Returns the name of a type in WGSL syntax, followed by the layout of its members if it is a struct.
+fn type_signature(module: &naga::Module, ty: naga::Handle<naga::Type>) -> String {
+	match &module.types[ty].inner {
+		naga::TypeInner::Struct { members, span } => format!(
+			"{} {{ {}}} {span}",
+			type_name(module, ty),
+			members
+				.iter()
+				.map(|member| format!(
+					"{:?} {:?} {} {}, ",
+					member.name,
+					member.binding,
+					type_signature(module, member.ty),
+					member.offset
+				))
+				.collect::<String>()
+		),
+		naga::TypeInner::Array { base, size, stride } => format!(
+			"array<{}, {size:?}> {stride}",
+			type_signature(module, *base)
+		),
+		_ => type_name(module, ty),
+	}
+}
+
+/// Returns the binding type and the array length of a global resource variable.
+fn binding_type(
+	module: &naga::Module,
+	global: &naga::GlobalVariable,
+) -> Option<(wgpu::BindingType, Option<num::NonZeroU32>)> {
+	let (inner, count) = match &module.types[global.ty].inner {
+		naga::TypeInner::BindingArray { base, size } => (
+			&module.types[*base].inner,
+			match size {
+				naga::ArraySize::Constant(size) => Some(*size),
+				naga::ArraySize::Dynamic => None,
+			},
+		),
+		inner => (inner, None),
+	};
+	let ty = match (global.space, inner) {
+		(naga::AddressSpace::Uniform, _) => wgpu::BindingType::Buffer {
+			ty: wgpu::BufferBindingType::Uniform,
+			has_dynamic_offset: false,
+			min_binding_size: None,
+		},
+		(naga::AddressSpace::Storage { access }, _) => wgpu::BindingType::Buffer {
+			ty: wgpu::BufferBindingType::Storage {
+				read_only: !access.contains(naga::StorageAccess::STORE),
+			},
+			has_dynamic_offset: false,
+			min_binding_size: None,
+		},
+		(naga::AddressSpace::Handle, naga::TypeInner::Sampler { comparison }) => {
+			wgpu::BindingType::Sampler(if *comparison {
+				wgpu::SamplerBindingType::Comparison
+			} else {
+				wgpu::SamplerBindingType::Filtering
+			})
+		}
+		(
+			naga::AddressSpace::Handle,
+			naga::TypeInner::Image {
+				dim,
+				arrayed,
+				class,
+			},
+		) => {
+			let view_dimension = match (dim, arrayed) {
+				(naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+				(naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+				(naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+				(naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+				(naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+				(naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+			};
+			match class {
+				naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+					sample_type: match kind {
+						naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+						naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+						_ => wgpu::TextureSampleType::Float { filterable: true },
+					},
+					view_dimension,
+					multisampled: *multi,
+				},
+				naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Depth,
+					view_dimension,
+					multisampled: *multi,
+				},
+				naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+					access: if !access.contains(naga::StorageAccess::STORE) {
+						wgpu::StorageTextureAccess::ReadOnly
+					} else if access.contains(naga::StorageAccess::LOAD) {
+						wgpu::StorageTextureAccess::ReadWrite
+					} else {
+						wgpu::StorageTextureAccess::WriteOnly
+					},
+					format: texture_format(*format),
+					view_dimension,
+				},
+			}
+		}
+		(naga::AddressSpace::Handle, naga::TypeInner::AccelerationStructure) => {
+			wgpu::BindingType::AccelerationStructure
+		}
+		_ => return None,
+	};
+	Some((ty, count))
+}
+
+/// Converts a storage texture format, whose variants have the same names in [`naga`] and [`wgpu`].
+fn texture_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+	macro_rules! texture_formats {
+		($($format:ident),*) => {
+			match format {
+				$(naga::StorageFormat::$format => wgpu::TextureFormat::$format,)*
+			}
+		};
+	}
+	texture_formats!(
+		R8Unorm,
+		R8Snorm,
+		R8Uint,
+		R8Sint,
+		R16Uint,
+		R16Sint,
+		R16Float,
+		Rg8Unorm,
+		Rg8Snorm,
+		Rg8Uint,
+		Rg8Sint,
+		R32Uint,
+		R32Sint,
+		R32Float,
+		Rg16Uint,
+		Rg16Sint,
+		Rg16Float,
+		Rgba8Unorm,
+		Rgba8Snorm,
+		Rgba8Uint,
+		Rgba8Sint,
+		Bgra8Unorm,
+		Rgb10a2Uint,
+		Rgb10a2Unorm,
+		Rg11b10Float,
+		Rg32Uint,
+		Rg32Sint,
+		Rg32Float,
+		Rgba16Uint,
+		Rgba16Sint,
+		Rgba16Float,
+		Rgba32Uint,
+		Rgba32Sint,
+		Rgba32Float,
+		R16Unorm,
+		R16Snorm,
+		Rg16Unorm,
+		Rg16Snorm,
+		Rgba16Unorm,
+		Rgba16Snorm
+	)
+}
+
+fn parse(stage: naga::ShaderStage, source: &str) -> Result<naga::Module, InterfaceError> {
+	naga::front::wgsl::parse_str(source).map_err(|error| InterfaceError::Parse {
+		stage,
+		message: error.emit_to_string(source),
+	})
+}
+
+/// Parses and validates a WGSL source, returning the error as a string.
+pub(crate) fn validate(source: &str) -> Result<(), String> {
+	let module =
+		naga::front::wgsl::parse_str(source).map_err(|error| error.emit_to_string(source))?;
+	naga::valid::Validator::new(
+		naga::valid::ValidationFlags::all(),
+		naga::valid::Capabilities::all(),
+	)
+	.validate(&module)
+	.map_err(|error| error.emit_to_string(source))?;
+	Ok(())
+}
+
+fn entry_point_function<'a>(
+	module: &'a naga::Module,
+	stage: naga::ShaderStage,
+	name: &str,
+) -> Result<&'a naga::Function, InterfaceError> {
+	module
+		.entry_points
+		.iter()
+		.find(|entry_point| entry_point.stage == stage && entry_point.name == name)
+		.map(|entry_point| &entry_point.function)
+		.ok_or_else(|| InterfaceError::MissingEntryPoint {
+			stage,
+			name: name.to_string(),
+		})
+}
+
+/// Collects the name and type of every `@location` in a type, including members of structs.
+fn collect_locations(
+	module: &naga::Module,
+	name: &str,
+	ty: naga::Handle<naga::Type>,
+	binding: Option<&naga::Binding>,
+	locations: &mut BTreeMap<u32, (String, String)>,
+) {
+	match (binding, &module.types[ty].inner) {
+		(Some(naga::Binding::Location { location, .. }), _) => {
+			locations.insert(*location, (name.to_string(), type_name(module, ty)));
+		}
+		(None, naga::TypeInner::Struct { members, .. }) => {
+			for member in members.iter() {
+				collect_locations(
+					module,
+					member.name.as_deref().unwrap_or_default(),
+					member.ty,
+					member.binding.as_ref(),
+					locations,
+				);
+			}
+		}
+		_ => {}
+	}
+}
+
+/// Returns the name of a type in WGSL syntax.
+pub(crate) fn type_name(module: &naga::Module, ty: naga::Handle<naga::Type>) -> String {
+	let ty = &module.types[ty];
+	match &ty.inner {
+		naga::TypeInner::Scalar(scalar) => scalar_name(scalar),
+		naga::TypeInner::Vector { size, scalar } => {
+			format!("vec{}<{}>", *size as u8, scalar_name(scalar))
+		}
+		naga::TypeInner::Matrix {
+			columns,
+			rows,
+			scalar,
+		} => format!(
+			"mat{}x{}<{}>",
+			*columns as u8,
+			*rows as u8,
+			scalar_name(scalar)
+		),
+		naga::TypeInner::Atomic(scalar) => format!("atomic<{}>", scalar_name(scalar)),
+		naga::TypeInner::Array { base, size, .. } => match size {
+			naga::ArraySize::Constant(size) => {
+				format!("array<{}, {size}>", type_name(module, *base))
+			}
+			naga::ArraySize::Dynamic => format!("array<{}>", type_name(module, *base)),
+		},
+		inner => ty.name.clone().unwrap_or_else(|| format!("{inner:?}")),
+	}
+}
+
+fn scalar_name(scalar: &naga::Scalar) -> String {
+	match (scalar.kind, scalar.width) {
+		(naga::ScalarKind::Bool, _) => "bool".to_string(),
+		(naga::ScalarKind::Sint, 4) => "i32".to_string(),
+		(naga::ScalarKind::Uint, 4) => "u32".to_string(),
+		(naga::ScalarKind::Float, 2) => "f16".to_string(),
+		(naga::ScalarKind::Float, 4) => "f32".to_string(),
+		(kind, width) => format!("{kind:?}{}", width * 8),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use crate::{
+		reflection::{
+			self, InterfaceError, InterfaceMismatch, LayoutError, OverrideConstant, OverrideError,
+			OverrideType, OverrideValues,
+		},
+		ShaderBuilder,
+	};
+
+	fn build_source(source_path: &str) -> String {
+		ShaderBuilder::new(source_path)
+			.unwrap()
+			.build_source()
+			.unwrap()
+			.to_string()
+	}
+
+	#[test]
+	fn override_values() {
+		let source = build_source("test_shaders/overrides.wgsl");
+		assert_eq!(
+			reflection::override_constants(&source).unwrap(),
+			[
+				OverrideConstant {
+					name: "brightness".to_string(),
+					key: "brightness".to_string(),
+					ty: OverrideType::F32,
+					default: Some(1.5),
+					has_default: true,
+				},
+				OverrideConstant {
+					name: "sample_count".to_string(),
+					key: "7".to_string(),
+					ty: OverrideType::U32,
+					default: None,
+					has_default: false,
+				},
+				OverrideConstant {
+					name: "use_fog".to_string(),
+					key: "use_fog".to_string(),
+					ty: OverrideType::Bool,
+					default: Some(0.0),
+					has_default: true,
+				},
+			]
+		);
+
+		let mut override_values = OverrideValues::new(&source).unwrap();
+		assert_eq!(
+			override_values.constants(),
+			Err(OverrideError::Missing(vec!["sample_count".to_string()]))
+		);
+		assert_eq!(
+			override_values.set("sample_count", -1).err(),
+			Some(OverrideError::OutOfRange {
+				name: "sample_count".to_string(),
+				ty: OverrideType::U32,
+				value: -1.0,
+			})
+		);
+		assert_eq!(
+			override_values.set("use_fgo", true).err(),
+			Some(OverrideError::Unknown {
+				name: "use_fgo".to_string(),
+				declared: vec![
+					"brightness".to_string(),
+					"sample_count".to_string(),
+					"use_fog".to_string()
+				],
+			})
+		);
+		override_values
+			.set("sample_count", 4u32)
+			.unwrap()
+			.set("use_fog", true)
+			.unwrap();
+		assert_eq!(
+			override_values.constants().unwrap(),
+			&HashMap::from([("7".to_string(), 4.0), ("use_fog".to_string(), 1.0)])
+		);
+	}
+
+	#[test]
+	fn interface_hash() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/interface_hash.wgsl").unwrap();
+		shader_builder.build_source().unwrap();
+		let interface_hash = shader_builder.interface_hash().unwrap();
+		assert_eq!(
+			reflection::interface_hash(&build_source("test_shaders/interface_hash_cosmetic.wgsl")),
+			Ok(interface_hash)
+		);
+		assert_ne!(
+			reflection::interface_hash(&build_source("test_shaders/interface_hash_changed.wgsl")),
+			Ok(interface_hash)
+		);
+	}
+
+	#[test]
+	fn compatible_stage_interface() {
+		let vertex_source = build_source("test_shaders/interface_vertex.wgsl");
+		let fragment_source = build_source("test_shaders/interface_fragment.wgsl");
+		assert_eq!(
+			reflection::check_stage_interface(
+				&vertex_source,
+				"vs_main",
+				&fragment_source,
+				"fs_main"
+			),
+			Ok(())
+		);
+	}
+
+	#[test]
+	fn mismatched_stage_interface() {
+		let vertex_source = build_source("test_shaders/interface_vertex.wgsl");
+		let fragment_source = build_source("test_shaders/interface_fragment_mismatched.wgsl");
+		assert_eq!(
+			reflection::check_stage_interface(
+				&vertex_source,
+				"vs_main",
+				&fragment_source,
+				"fs_main"
+			),
+			Err(InterfaceError::Mismatches(vec![
+				InterfaceMismatch {
+					location: 0,
+					fragment_input: "color".to_string(),
+					fragment_type: "vec3<f32>".to_string(),
+					vertex_type: Some("vec4<f32>".to_string()),
+				},
+				InterfaceMismatch {
+					location: 2,
+					fragment_input: "normal".to_string(),
+					fragment_type: "vec3<f32>".to_string(),
+					vertex_type: None,
+				}
+			]))
+		);
+	}
+
+	#[test]
+	fn merged_pipeline_layout() {
+		let vertex_source = build_source("test_shaders/layout_vertex.wgsl");
+		let fragment_source = build_source("test_shaders/layout_fragment.wgsl");
+		let summary = reflection::pipeline_layout(&[&vertex_source, &fragment_source]).unwrap();
+		assert_eq!(
+			summary.bind_groups.get(&0).unwrap(),
+			&[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			}]
+		);
+		assert_eq!(
+			summary.bind_groups.get(&1).unwrap(),
+			&[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						sample_type: wgpu::TextureSampleType::Float { filterable: true },
+						view_dimension: wgpu::TextureViewDimension::D2,
+						multisampled: false,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					count: None,
+				}
+			]
+		);
+	}
+
+	#[test]
+	fn conflicting_pipeline_layout() {
+		let vertex_source = build_source("test_shaders/layout_vertex.wgsl");
+		let fragment_source = build_source("test_shaders/layout_fragment_conflicting.wgsl");
+		let Err(LayoutError::Conflicts(conflicts)) =
+			reflection::pipeline_layout(&[&vertex_source, &fragment_source])
+		else {
+			panic!("expected a conflict");
+		};
+		assert_eq!(conflicts.len(), 1);
+		assert_eq!((conflicts[0].source_index, conflicts[0].group), (1, 0));
+		assert_eq!(
+			conflicts[0].second.ty,
+			wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only: true },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			}
+		);
+	}
+}
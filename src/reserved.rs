@@ -0,0 +1,223 @@
+/// Keywords of WGSL.
+const KEYWORDS: [&str; 26] = [
+	"alias",
+	"break",
+	"case",
+	"const",
+	"const_assert",
+	"continue",
+	"continuing",
+	"default",
+	"diagnostic",
+	"discard",
+	"else",
+	"enable",
+	"false",
+	"fn",
+	"for",
+	"if",
+	"let",
+	"loop",
+	"override",
+	"requires",
+	"return",
+	"struct",
+	"switch",
+	"true",
+	"var",
+	"while",
+];
+
+/// Words reserved by WGSL for future use.
+const RESERVED_WORDS: [&str; 145] = [
+	"NULL",
+	"Self",
+	"abstract",
+	"active",
+	"alignas",
+	"alignof",
+	"as",
+	"asm",
+	"asm_fragment",
+	"async",
+	"attribute",
+	"auto",
+	"await",
+	"become",
+	"binding_array",
+	"cast",
+	"catch",
+	"class",
+	"co_await",
+	"co_return",
+	"co_yield",
+	"coherent",
+	"column_major",
+	"common",
+	"compile",
+	"compile_fragment",
+	"concept",
+	"const_cast",
+	"consteval",
+	"constexpr",
+	"constinit",
+	"crate",
+	"debugger",
+	"decltype",
+	"delete",
+	"demote",
+	"demote_to_helper",
+	"do",
+	"dynamic_cast",
+	"enum",
+	"explicit",
+	"export",
+	"extends",
+	"extern",
+	"external",
+	"fallthrough",
+	"filter",
+	"final",
+	"finally",
+	"friend",
+	"from",
+	"fxgroup",
+	"get",
+	"goto",
+	"groupshared",
+	"highp",
+	"impl",
+	"implements",
+	"import",
+	"inline",
+	"instanceof",
+	"interface",
+	"layout",
+	"lowp",
+	"macro",
+	"macro_rules",
+	"match",
+	"mediump",
+	"meta",
+	"mod",
+	"module",
+	"move",
+	"mut",
+	"mutable",
+	"namespace",
+	"new",
+	"nil",
+	"noexcept",
+	"noinline",
+	"nointerpolation",
+	"noperspective",
+	"null",
+	"nullptr",
+	"of",
+	"operator",
+	"package",
+	"packoffset",
+	"partition",
+	"pass",
+	"patch",
+	"pixelfragment",
+	"precise",
+	"precision",
+	"premerge",
+	"priv",
+	"protected",
+	"pub",
+	"public",
+	"readonly",
+	"ref",
+	"regardless",
+	"register",
+	"reinterpret_cast",
+	"require",
+	"resource",
+	"restrict",
+	"self",
+	"set",
+	"shared",
+	"sizeof",
+	"smooth",
+	"snorm",
+	"static",
+	"static_assert",
+	"static_cast",
+	"std",
+	"subroutine",
+	"super",
+	"target",
+	"template",
+	"this",
+	"thread_local",
+	"throw",
+	"trait",
+	"try",
+	"type",
+	"typedef",
+	"typeid",
+	"typename",
+	"typeof",
+	"union",
+	"unless",
+	"unorm",
+	"unsafe",
+	"unsized",
+	"use",
+	"using",
+	"varying",
+	"virtual",
+	"volatile",
+	"wgsl",
+	"where",
+	"with",
+	"writeonly",
+	"yield",
+];
+
+lazy_static::lazy_static! {
+	static ref DECLARATION_REGEX: regex::Regex = regex::Regex::new(
+		r"\b(let|var|const|override|fn|struct|alias)\b(?:\s*<[^>]*>)?\s+([A-Za-z_][A-Za-z0-9_]*)"
+	)
+	.unwrap();
+}
+
+/// Returns whether an identifier is a keyword or a reserved word of WGSL, as a description of it.
+fn reserved_kind(identifier: &str) -> Option<&'static str> {
+	if KEYWORDS.contains(&identifier) {
+		Some("a keyword")
+	} else if RESERVED_WORDS.contains(&identifier) {
+		Some("a reserved word")
+	} else {
+		None
+	}
+}
+
+/// Declaration in a WGSL source whose name can not be used as an identifier.
+pub(crate) struct ReservedDeclaration {
+	/// The declaration, for example `let class`.
+	pub declaration: String,
+	/// The declared name, for example `class`.
+	pub name: String,
+	/// Whether the name is a keyword or a reserved word.
+	pub kind: &'static str,
+}
+
+/// Returns the declarations in a WGSL source whose names are keywords or reserved words,
+/// ignoring line comments.
+pub(crate) fn reserved_declarations(source: &str) -> Vec<ReservedDeclaration> {
+	source
+		.lines()
+		.map(|line| line.split("//").next().unwrap_or_default())
+		.flat_map(|code| DECLARATION_REGEX.captures_iter(code))
+		.filter_map(|captures| {
+			Some(ReservedDeclaration {
+				kind: reserved_kind(&captures[2])?,
+				declaration: format!("{} {}", &captures[1], &captures[2]),
+				name: captures[2].to_string(),
+			})
+		})
+		.collect()
+}
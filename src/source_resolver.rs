@@ -0,0 +1,158 @@
+use std::{collections::HashMap, io, path, sync};
+
+/// Source of the modules of a shader, see [`crate::ShaderBuilder::with_source_resolver`].
+/// The root module and every included module are read through it, so shaders can be embedded
+/// in the binary, for example with [`include_str!`], or loaded over the network.
+pub trait SourceResolver: Send + Sync {
+	/// Returns the contents of the module at `path`, which are decoded as UTF-8 by the builder.
+	fn resolve(&self, path: &path::Path) -> io::Result<Vec<u8>>;
+
+	/// Returns whether a module exists at `path`, to check the root module when a builder is created
+	/// and to resolve include paths relative to the including module.
+	fn exists(&self, path: &path::Path) -> bool;
+
+	/// Returns the size of the module at `path` in bytes if it is known without reading the module,
+	/// so [`crate::ShaderBuilder::max_module_size`] is enforced before reading it.
+	fn size(&self, _path: &path::Path) -> Option<u64> {
+		None
+	}
+}
+
+/// [`SourceResolver`] reading modules from the file system, used by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileSystemResolver;
+
+impl SourceResolver for FileSystemResolver {
+	fn resolve(&self, path: &path::Path) -> io::Result<Vec<u8>> {
+		std::fs::read(path)
+	}
+
+	fn exists(&self, path: &path::Path) -> bool {
+		path.is_file()
+	}
+
+	fn size(&self, path: &path::Path) -> Option<u64> {
+		std::fs::metadata(path).ok().map(|metadata| metadata.len())
+	}
+}
+
+/// [`SourceResolver`] of modules held in memory, for example embedded with [`include_str!`]:
+/// ```
+/// # use wgsl_preprocessor::{MemoryResolver, ShaderBuilder};
+/// let mut source_resolver = MemoryResolver::new();
+/// source_resolver
+/// 	.insert("shaders/main.wgsl", "//!include common.wgsl\nconst a: f32 = SCALE;")
+/// 	.insert("shaders/common.wgsl", "//!define SCALE 2.0");
+/// let mut shader_builder =
+/// 	ShaderBuilder::with_source_resolver("shaders/main.wgsl", source_resolver).unwrap();
+/// assert_eq!(shader_builder.build_source().unwrap(), "const a: f32 = 2.0;\n");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryResolver {
+	modules: HashMap<path::PathBuf, Vec<u8>>,
+}
+
+impl MemoryResolver {
+	/// Creates a new [`MemoryResolver`] without modules.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a module, replacing the module at the same path if there is one.
+	pub fn insert(
+		&mut self,
+		path: impl Into<path::PathBuf>,
+		source: impl Into<Vec<u8>>,
+	) -> &mut Self {
+		self.modules.insert(path.into(), source.into());
+		self
+	}
+}
+
+impl SourceResolver for MemoryResolver {
+	fn resolve(&self, path: &path::Path) -> io::Result<Vec<u8>> {
+		self.modules.get(path).cloned().ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("no module at {}", path.display()),
+			)
+		})
+	}
+
+	fn exists(&self, path: &path::Path) -> bool {
+		self.modules.contains_key(path)
+	}
+
+	fn size(&self, path: &path::Path) -> Option<u64> {
+		self.modules.get(path).map(|source| source.len() as u64)
+	}
+}
+
+/// [`SourceResolver`] of a root module held in memory, reading the other modules from the file system,
+/// see [`crate::ShaderBuilder::from_source`].
+pub(crate) struct RootSourceResolver {
+	pub(crate) path: path::PathBuf,
+	pub(crate) source: Vec<u8>,
+}
+
+impl SourceResolver for RootSourceResolver {
+	fn resolve(&self, path: &path::Path) -> io::Result<Vec<u8>> {
+		if path == self.path {
+			Ok(self.source.clone())
+		} else {
+			FileSystemResolver.resolve(path)
+		}
+	}
+
+	fn exists(&self, path: &path::Path) -> bool {
+		path == self.path || FileSystemResolver.exists(path)
+	}
+
+	fn size(&self, path: &path::Path) -> Option<u64> {
+		if path == self.path {
+			Some(self.source.len() as u64)
+		} else {
+			FileSystemResolver.size(path)
+		}
+	}
+}
+
+/// Reads a module through a [`SourceResolver`], with the error of the file system functions of [`ex`].
+pub(crate) fn read_module(
+	source_resolver: &dyn SourceResolver,
+	module_path: &path::Path,
+) -> Result<sync::Arc<[u8]>, ex::io::Error> {
+	source_resolver
+		.resolve(module_path)
+		.map(Into::into)
+		.map_err(|error| ex::io::Error::Filesystem(module_path.to_path_buf(), "reading", error))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{MemoryResolver, ShaderBuilder};
+
+	#[test]
+	fn memory_resolver() {
+		let mut source_resolver = MemoryResolver::new();
+		source_resolver
+			.insert(
+				"shaders/main.wgsl",
+				"//!include lighting.wgsl\n//!include missing.wgsl\n",
+			)
+			.insert("shaders/lighting.wgsl", "const light: f32 = 1.0;");
+		assert!(ShaderBuilder::with_source_resolver("main.wgsl", source_resolver.clone()).is_err());
+		let mut shader_builder =
+			ShaderBuilder::with_source_resolver("shaders/main.wgsl", source_resolver.clone())
+				.unwrap();
+		let error = shader_builder.build_source().unwrap_err().to_string();
+		assert!(error.contains("missing.wgsl"), "{error}");
+		source_resolver.insert("missing.wgsl", "const missing: bool = false;");
+		let mut shader_builder =
+			ShaderBuilder::with_source_resolver("shaders/main.wgsl", source_resolver).unwrap();
+		assert_eq!(
+			shader_builder.build_source().unwrap(),
+			"const light: f32 = 1.0;\nconst missing: bool = false;\n"
+		);
+	}
+}
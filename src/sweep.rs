@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::WGSLType;
+
+/// Configuration space of a shader, built configuration by configuration with [`crate::ShaderBuilder::sweep`]
+/// to check that an uber-shader is valid across all of it:
+/// ```
+/// # use wgsl_preprocessor::Sweep;
+/// let mut sweep = Sweep::new();
+/// sweep
+/// 	.symbol("FOG")
+/// 	.symbol("SHADOWS")
+/// 	.constant("SAMPLES", 1u32..=4)
+/// 	.constant("BIAS", [0.0f32, 0.5]);
+/// assert_eq!(sweep.configurations(), 32);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Sweep {
+	/// Symbols that are either defined or not.
+	pub(crate) symbols: Vec<String>,
+	/// Constants with the string definition of every value they take.
+	pub(crate) constants: Vec<(String, Vec<String>)>,
+	#[cfg(feature = "reflection")]
+	pub(crate) validate: bool,
+}
+
+impl Sweep {
+	/// Creates a new empty [`Sweep`], which has a single configuration.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a symbol that is either defined or not, doubling the number of configurations.
+	pub fn symbol(&mut self, name: &str) -> &mut Self {
+		self.symbols.push(name.to_string());
+		self
+	}
+
+	/// Adds a constant taking every value of `values`, for example a range like `1u32..=4`,
+	/// multiplying the number of configurations by the number of values.
+	/// Values are defined with the literal suffixes of the builder, but unit types are always in their default units.
+	pub fn constant<T: WGSLType>(
+		&mut self,
+		name: &str,
+		values: impl IntoIterator<Item = T>,
+	) -> &mut Self {
+		self.constants.push((
+			name.to_string(),
+			values
+				.into_iter()
+				.map(|value| value.string_definition())
+				.collect(),
+		));
+		self
+	}
+
+	/// Validates the built source of every configuration with [`naga`], in addition to building it.
+	/// Requires the **reflection** feature.
+	#[cfg(feature = "reflection")]
+	pub fn validate(&mut self, validate: bool) -> &mut Self {
+		self.validate = validate;
+		self
+	}
+
+	/// Returns the number of configurations of the sweep.
+	pub fn configurations(&self) -> usize {
+		self.constants
+			.iter()
+			.fold(1 << self.symbols.len(), |configurations, (_, values)| {
+				configurations * values.len()
+			})
+	}
+
+	/// Returns the definitions of a configuration, every symbol defined as an empty string.
+	pub(crate) fn configuration(&self, mut index: usize) -> Vec<(String, String)> {
+		let mut definitions = Vec::new();
+		for symbol in self.symbols.iter() {
+			if index & 1 != 0 {
+				definitions.push((symbol.clone(), String::new()));
+			}
+			index >>= 1;
+		}
+		for (name, values) in self.constants.iter() {
+			definitions.push((name.clone(), values[index % values.len()].clone()));
+			index /= values.len();
+		}
+		definitions
+	}
+}
+
+/// Result of [`crate::ShaderBuilder::sweep`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SweepReport {
+	/// Number of configurations built.
+	pub configurations: usize,
+	/// Every configuration that failed to build or to validate.
+	pub failures: Vec<SweepFailure>,
+}
+
+/// Configuration of a [`Sweep`] that failed to build or to validate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SweepFailure {
+	/// Definitions of the configuration: the symbols that are defined, followed by the value of every constant.
+	pub definitions: Vec<(String, String)>,
+	/// Error of the build or of the validation.
+	pub message: String,
+}
+
+impl fmt::Display for SweepFailure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let definitions: Vec<String> = self
+			.definitions
+			.iter()
+			.map(|(name, value)| {
+				if value.is_empty() {
+					name.clone()
+				} else {
+					format!("{name}={value}")
+				}
+			})
+			.collect();
+		write!(f, "[{}]: {}", definitions.join(", "), self.message)
+	}
+}
@@ -0,0 +1,339 @@
+/*!
+Running built compute shaders on a headless device, for unit tests of shader libraries,
+enabled by the **testing** feature.
+*/
+use std::{env, error, fmt, future, io, path, pin, sync, task};
+
+use wgpu::util::DeviceExt;
+
+use crate::{PreprocessError, ShaderBuilder};
+
+/// Environment variable that makes [`compare_golden`] write golden files instead of comparing with them,
+/// for example `WGSL_PREPROCESSOR_UPDATE_GOLDENS=1 cargo test`.
+pub const UPDATE_GOLDENS_VARIABLE: &str = "WGSL_PREPROCESSOR_UPDATE_GOLDENS";
+
+/// Error of [`ComputeHarness::dispatch`].
+#[derive(Debug)]
+pub enum DispatchError {
+	/// The shader could not be built.
+	Build(PreprocessError),
+	/// The device reported an error, for example because the built shader is not valid WGSL.
+	Device(wgpu::Error),
+	/// The buffers could not be read back.
+	Readback(wgpu::BufferAsyncError),
+}
+
+impl fmt::Display for DispatchError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Build(error) => write!(f, "{error}"),
+			Self::Device(error) => write!(f, "{error}"),
+			Self::Readback(error) => write!(f, "{error}"),
+		}
+	}
+}
+
+impl error::Error for DispatchError {}
+
+/// Headless device dispatching built compute shaders and reading back their buffers,
+/// so shader libraries can have GPU unit tests, for example of every permutation of their definitions:
+/// ```no_run
+/// # use wgsl_preprocessor::{testing::ComputeHarness, ShaderBuilder};
+/// let Some(harness) = ComputeHarness::new() else {
+/// 	return; // No adapter, for example on a CI machine without a GPU.
+/// };
+/// let mut shader_builder = ShaderBuilder::new("shaders/double.wgsl").unwrap();
+/// let input: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|value| value.to_ne_bytes()).collect();
+/// let buffers = harness
+/// 	.dispatch(shader_builder.put_constant("FACTOR", 2.0f32), "main", &[&input], (2, 1, 1))
+/// 	.unwrap();
+/// ```
+/// Futures of the device are polled once, so the harness only works on native platforms.
+pub struct ComputeHarness {
+	device: wgpu::Device,
+	queue: wgpu::Queue,
+}
+
+impl ComputeHarness {
+	/// Creates a harness on the default adapter, which may be a software one,
+	/// or returns [`None`] if there is no adapter, so tests can be skipped.
+	pub fn new() -> Option<Self> {
+		let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+		let adapter = ready(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))??;
+		let (device, queue) = ready(adapter.request_device(
+			&wgpu::DeviceDescriptor {
+				label: Some("wgsl_preprocessor compute harness"),
+				required_features: wgpu::Features::empty(),
+				required_limits: wgpu::Limits::downlevel_defaults(),
+			},
+			None,
+		))?
+		.ok()?;
+		Some(Self { device, queue })
+	}
+
+	/// Builds a shader and dispatches one of its compute entry points, returning the contents of the buffers afterwards.
+	///
+	/// # Arguments
+	/// - `shader_builder` - Builder of the shader.
+	/// - `entry_point` - Name of the compute entry point.
+	/// - `buffers` - Initial contents of the buffers, bound in order to group 0 as
+	///   `var<storage, read_write>` buffers, starting at binding 0.
+	/// - `workgroups` - Number of workgroups in every dimension.
+	pub fn dispatch(
+		&self,
+		shader_builder: &mut ShaderBuilder,
+		entry_point: &str,
+		buffers: &[&[u8]],
+		workgroups: (u32, u32, u32),
+	) -> Result<Vec<Vec<u8>>, DispatchError> {
+		let shader_module_descriptor = shader_builder.build().map_err(DispatchError::Build)?;
+		self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+		let shader_module = self.device.create_shader_module(shader_module_descriptor);
+		let layout_entries: Vec<wgpu::BindGroupLayoutEntry> = (0..buffers.len() as u32)
+			.map(|binding| wgpu::BindGroupLayoutEntry {
+				binding,
+				visibility: wgpu::ShaderStages::COMPUTE,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Storage { read_only: false },
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			})
+			.collect();
+		let bind_group_layout =
+			self.device
+				.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+					label: None,
+					entries: &layout_entries,
+				});
+		let pipeline_layout = self
+			.device
+			.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: None,
+				bind_group_layouts: &[&bind_group_layout],
+				push_constant_ranges: &[],
+			});
+		let pipeline = self
+			.device
+			.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: None,
+				layout: Some(&pipeline_layout),
+				module: &shader_module,
+				entry_point,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+			});
+		let storage_buffers: Vec<wgpu::Buffer> = buffers
+			.iter()
+			.map(|contents| {
+				self.device
+					.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+						label: None,
+						contents,
+						usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+					})
+			})
+			.collect();
+		let bind_group_entries: Vec<wgpu::BindGroupEntry> = storage_buffers
+			.iter()
+			.enumerate()
+			.map(|(binding, buffer)| wgpu::BindGroupEntry {
+				binding: binding as u32,
+				resource: buffer.as_entire_binding(),
+			})
+			.collect();
+		let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: None,
+			layout: &bind_group_layout,
+			entries: &bind_group_entries,
+		});
+		let readback_buffers: Vec<wgpu::Buffer> = storage_buffers
+			.iter()
+			.map(|buffer| {
+				self.device.create_buffer(&wgpu::BufferDescriptor {
+					label: None,
+					size: buffer.size(),
+					usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+					mapped_at_creation: false,
+				})
+			})
+			.collect();
+		let mut encoder = self
+			.device
+			.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+		{
+			let mut compute_pass =
+				encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+			compute_pass.set_pipeline(&pipeline);
+			compute_pass.set_bind_group(0, &bind_group, &[]);
+			compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
+		}
+		for (storage_buffer, readback_buffer) in storage_buffers.iter().zip(&readback_buffers) {
+			encoder.copy_buffer_to_buffer(
+				storage_buffer,
+				0,
+				readback_buffer,
+				0,
+				storage_buffer.size(),
+			);
+		}
+		self.queue.submit([encoder.finish()]);
+		if let Some(Some(error)) = ready(self.device.pop_error_scope()) {
+			return Err(DispatchError::Device(error));
+		}
+		let (sender, receiver) = sync::mpsc::channel();
+		for readback_buffer in readback_buffers.iter() {
+			let sender = sender.clone();
+			readback_buffer
+				.slice(..)
+				.map_async(wgpu::MapMode::Read, move |result| {
+					let _ = sender.send(result);
+				});
+		}
+		self.device.poll(wgpu::Maintain::Wait);
+		for result in receiver.try_iter() {
+			result.map_err(DispatchError::Readback)?;
+		}
+		Ok(readback_buffers
+			.iter()
+			.map(|readback_buffer| readback_buffer.slice(..).get_mapped_range().to_vec())
+			.collect())
+	}
+}
+
+/// Reinterprets a buffer returned by [`ComputeHarness::dispatch`] as `f32` values.
+pub fn f32_values(buffer: &[u8]) -> Vec<f32> {
+	buffer
+		.chunks_exact(4)
+		.map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+		.collect()
+}
+
+/// Compares values, usually read back with [`f32_values`], with a golden file of one value per line.
+/// Fails if the golden file is missing or has a different number of values,
+/// or if a value differs from its golden value by more than `tolerance`; `NaN` only matches `NaN`.
+///
+/// If the [`UPDATE_GOLDENS_VARIABLE`] environment variable is set, the golden file is written instead.
+pub fn compare_golden(
+	golden_path: impl AsRef<path::Path>,
+	values: &[f32],
+	tolerance: f32,
+) -> Result<(), ex::io::Error> {
+	compare_or_update_golden(
+		golden_path.as_ref(),
+		values,
+		tolerance,
+		env::var_os(UPDATE_GOLDENS_VARIABLE).is_some(),
+	)
+}
+
+/// Compares values with a golden file like [`compare_golden`], or writes the golden file if `update` is set.
+fn compare_or_update_golden(
+	golden_path: &path::Path,
+	values: &[f32],
+	tolerance: f32,
+	update: bool,
+) -> Result<(), ex::io::Error> {
+	if update {
+		return ex::fs::write(
+			golden_path,
+			values
+				.iter()
+				.map(|value| format!("{value:?}\n"))
+				.collect::<String>(),
+		);
+	}
+	let golden_error = |message: String| {
+		ex::io::Error::Filesystem(
+			golden_path.to_path_buf(),
+			"comparing with",
+			io::Error::new(io::ErrorKind::InvalidData, message),
+		)
+	};
+	let golden = ex::fs::read_to_string(golden_path)?;
+	let golden_values = golden
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			line.trim()
+				.parse::<f32>()
+				.map_err(|_| golden_error(format!("`{}` is not a value", line.trim())))
+		})
+		.collect::<Result<Vec<f32>, _>>()?;
+	if golden_values.len() != values.len() {
+		return Err(golden_error(format!(
+			"{} values differ from the {} golden values, set {UPDATE_GOLDENS_VARIABLE} to update them",
+			values.len(),
+			golden_values.len()
+		)));
+	}
+	for (index, (value, golden_value)) in values.iter().zip(golden_values).enumerate() {
+		let is_equal = if value.is_nan() || golden_value.is_nan() {
+			value.is_nan() && golden_value.is_nan()
+		} else {
+			(value - golden_value).abs() <= tolerance
+		};
+		if !is_equal {
+			return Err(golden_error(format!(
+				"value {index} is {value:?} instead of {golden_value:?} (tolerance {tolerance:?}), \
+				set {UPDATE_GOLDENS_VARIABLE} to update the golden values"
+			)));
+		}
+	}
+	Ok(())
+}
+
+/// Polls a future once, returning its output if it is ready, which is the case of device futures on native platforms.
+fn ready<F: future::Future>(future: F) -> Option<F::Output> {
+	match pin::pin!(future).poll(&mut task::Context::from_waker(task::Waker::noop())) {
+		task::Poll::Ready(output) => Some(output),
+		task::Poll::Pending => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs};
+
+	use crate::{
+		testing::{compare_or_update_golden, f32_values, ComputeHarness},
+		ShaderBuilder,
+	};
+
+	#[test]
+	#[ignore = "requires a GPU adapter, run with `cargo test --features testing -- --ignored`"]
+	fn dispatch() {
+		let harness = ComputeHarness::new().expect("no adapter is available");
+		let input: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0]
+			.iter()
+			.flat_map(|value| value.to_ne_bytes())
+			.collect();
+		let buffers = harness
+			.dispatch(
+				ShaderBuilder::new("test_shaders/compute_double.wgsl")
+					.unwrap()
+					.put_constant("FACTOR", 2.0f32),
+				"main",
+				&[&input],
+				(4, 1, 1),
+			)
+			.unwrap();
+		assert_eq!(f32_values(&buffers[0]), [2.0, 4.0, 6.0, 8.0]);
+	}
+
+	#[test]
+	fn golden() {
+		let golden_path = env::temp_dir().join("wgsl_preprocessor_compute_double.golden");
+		fs::copy("test_shaders/compute_double.golden", &golden_path).unwrap();
+		let compare = |values: &[f32]| compare_or_update_golden(&golden_path, values, 0.001, false);
+		compare(&[2.0, 4.0005, 6.0, 8.0]).unwrap();
+		let error = compare(&[2.0, 4.0, 6.5, 8.0]).unwrap_err();
+		assert!(error.to_string().contains("value 2 is 6.5 instead of 6.0"));
+		assert!(compare(&[2.0, 4.0, 6.0]).is_err());
+		assert!(compare(&[2.0, 4.0, f32::NAN, 8.0]).is_err());
+		compare_or_update_golden(&golden_path, &[2.0, 4.0, 6.5, 8.0], 0.001, true).unwrap();
+		compare(&[2.0, 4.0, 6.5, 8.0]).unwrap();
+		fs::remove_file(&golden_path).unwrap();
+	}
+}
@@ -0,0 +1,124 @@
+use crate::WGSLType;
+
+/// Units that a shader expects its constants in, see [`crate::ShaderBuilder::units`].
+/// Constants of unit types like [`Degrees`] are converted to them when they are put.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Units {
+	/// Unit of angles.
+	pub angle: AngleUnit,
+	/// Unit of lengths.
+	pub length: LengthUnit,
+}
+
+/// Unit of angles, see [`Units`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AngleUnit {
+	/// Radians, expected by the trigonometric built-in functions of WGSL.
+	#[default]
+	Radians,
+	/// Degrees.
+	Degrees,
+}
+
+/// Unit of lengths, see [`Units`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LengthUnit {
+	/// Meters.
+	#[default]
+	Meters,
+	/// Millimeters.
+	Millimeters,
+}
+
+/// Angle in degrees, defined as an `f32` in the angle unit of the shader, radians by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Degrees(pub f32);
+
+/// Angle in radians, defined as an `f32` in the angle unit of the shader, radians by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Radians(pub f32);
+
+/// Length in millimeters, defined as an `f32` in the length unit of the shader, meters by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Millimeters(pub f32);
+
+impl WGSLType for Degrees {
+	fn type_name() -> String {
+		f32::type_name()
+	}
+
+	fn string_definition(&self) -> String {
+		self.string_definition_in(Units::default())
+	}
+
+	fn string_definition_in(&self, units: Units) -> String {
+		match units.angle {
+			AngleUnit::Radians => self.0.to_radians(),
+			AngleUnit::Degrees => self.0,
+		}
+		.string_definition()
+	}
+}
+
+impl WGSLType for Radians {
+	fn type_name() -> String {
+		f32::type_name()
+	}
+
+	fn string_definition(&self) -> String {
+		self.string_definition_in(Units::default())
+	}
+
+	fn string_definition_in(&self, units: Units) -> String {
+		match units.angle {
+			AngleUnit::Radians => self.0,
+			AngleUnit::Degrees => self.0.to_degrees(),
+		}
+		.string_definition()
+	}
+}
+
+impl WGSLType for Millimeters {
+	fn type_name() -> String {
+		f32::type_name()
+	}
+
+	fn string_definition(&self) -> String {
+		self.string_definition_in(Units::default())
+	}
+
+	fn string_definition_in(&self, units: Units) -> String {
+		match units.length {
+			LengthUnit::Meters => self.0 / 1000.0,
+			LengthUnit::Millimeters => self.0,
+		}
+		.string_definition()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{AngleUnit, Degrees, LengthUnit, Millimeters, Radians, ShaderBuilder, Units};
+
+	#[test]
+	fn units() {
+		let mut shader_builder = ShaderBuilder::new("test_shaders/included.wgsl").unwrap();
+		shader_builder
+			.put_constant("FOV", Degrees(180.0))
+			.put_constant("NEAR", Millimeters(100.0))
+			.units(Units {
+				angle: AngleUnit::Degrees,
+				length: LengthUnit::Millimeters,
+			})
+			.put_constant("HALF_TURN", Radians(std::f32::consts::PI))
+			.put_constant("FAR", Millimeters(5000.0));
+		let definitions = &shader_builder.config().definitions;
+		assert_eq!(definitions["FOV"], "3.1415927");
+		assert_eq!(definitions["NEAR"], "0.1");
+		assert_eq!(definitions["HALF_TURN"], "180.0");
+		assert_eq!(definitions["FAR"], "5000.0");
+	}
+}
@@ -0,0 +1,86 @@
+use std::{fmt, str};
+
+use crate::SourceLocation;
+
+/// Code of a [`Warning`], used to allow or deny it with [`crate::ShaderBuilder::allow`],
+/// [`crate::ShaderBuilder::deny`], or with an `//!allow(W001, W002)` statement in the module that causes it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WarningCode {
+	/// `W001` - A macro defined in a module is never used.
+	UnusedDefine,
+	/// `W002` - A macro is defined again, either in a module or after being put from Rust code.
+	Redefinition,
+	/// `W003` - A symbol tested by a conditional statement is never defined,
+	/// reported if enabled with [`crate::ShaderBuilder::undefined_symbol_lint`].
+	UndefinedSymbol,
+	/// `W004` - A symbol marked by a `deprecated` statement is used.
+	Deprecated,
+	/// `W005` - A `read_write` storage buffer declared by [`crate::ShaderBuilder::put_bindings`] is never written.
+	UnwrittenStorage,
+	/// `W006` - A module is not valid UTF-8 and was decoded lossily,
+	/// see [`crate::ShaderBuilder::invalid_utf8`].
+	InvalidUtf8,
+	/// `W007` - A module declares a color space with a `color_space` statement that differs from
+	/// the one of the colors put with [`crate::ShaderBuilder::put_color`].
+	ColorSpaceMismatch,
+}
+
+impl WarningCode {
+	/// Returns the code as written in `//!allow` statements, for example `W001`.
+	pub fn code(&self) -> &'static str {
+		match self {
+			Self::UnusedDefine => "W001",
+			Self::Redefinition => "W002",
+			Self::UndefinedSymbol => "W003",
+			Self::Deprecated => "W004",
+			Self::UnwrittenStorage => "W005",
+			Self::InvalidUtf8 => "W006",
+			Self::ColorSpaceMismatch => "W007",
+		}
+	}
+}
+
+impl fmt::Display for WarningCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.code())
+	}
+}
+
+impl str::FromStr for WarningCode {
+	type Err = String;
+
+	fn from_str(code: &str) -> Result<Self, Self::Err> {
+		match code {
+			"W001" => Ok(Self::UnusedDefine),
+			"W002" => Ok(Self::Redefinition),
+			"W003" => Ok(Self::UndefinedSymbol),
+			"W004" => Ok(Self::Deprecated),
+			"W005" => Ok(Self::UnwrittenStorage),
+			"W006" => Ok(Self::InvalidUtf8),
+			"W007" => Ok(Self::ColorSpaceMismatch),
+			_ => Err(format!("unknown warning code `{code}`")),
+		}
+	}
+}
+
+/// Warning of a build, see [`crate::ShaderBuilder::warnings`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+	/// Code of the warning.
+	pub code: WarningCode,
+	/// Description of the warning.
+	pub message: String,
+	/// Line that caused the warning.
+	pub location: SourceLocation,
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"warning[{}]: {}: {}",
+			self.code, self.location, self.message
+		)
+	}
+}
@@ -0,0 +1,5 @@
+//!allow(W002)
+//!define UNUSED 1u
+//!define COUNT 2u
+//!define COUNT 3u
+const count: u32 = COUNT;
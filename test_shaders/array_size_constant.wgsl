@@ -0,0 +1 @@
+var<private> samples: array<vec4<f32>, NUM_SAMPLES>;
\ No newline at end of file
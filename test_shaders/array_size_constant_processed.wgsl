@@ -0,0 +1 @@
+var<private> samples: array<vec4<f32>, 4u>;
\ No newline at end of file
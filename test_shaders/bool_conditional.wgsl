@@ -0,0 +1,6 @@
+//!ifdef SHADOWS
+const shadows: bool = SHADOWS;
+//!endif
+//!ifndef FOG
+const fog: bool = FOG;
+//!endif
@@ -0,0 +1,2 @@
+const shadows: bool = true;
+const fog: bool = false;
@@ -0,0 +1,2 @@
+//!color_space srgb
+const tint: vec4<f32> = TINT;
@@ -0,0 +1,8 @@
+const unrelated: f32 = 0.0;
+//!section palettes
+//!define WARM vec3<f32>(1.0, 0.5, 0.0)
+const warm: vec3<f32> = WARM;
+//!endsection
+//!section tones
+const tone: f32 = 0.5;
+//!endsection
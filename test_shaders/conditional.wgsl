@@ -0,0 +1,14 @@
+//!define FOG
+//!ifdef FOG
+const fog: bool = true;
+//!ifndef QUALITY
+const quality: u32 = 0u;
+//!else
+const quality: u32 = QUALITY;
+//!endif
+//!else
+const fog: bool = false;
+//!ifdef QUALITY
+const ignored: u32 = QUALITY;
+//!endif
+//!endif
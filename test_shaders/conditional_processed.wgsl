@@ -0,0 +1,2 @@
+const fog: bool = true;
+const quality: u32 = 2u;
@@ -0,0 +1,2 @@
+const tile_count: u32 = TILE_COUNT;
+const aspect: f32 = ASPECT;
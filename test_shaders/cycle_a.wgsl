@@ -0,0 +1,2 @@
+//!include cycle_b.wgsl
+const a: f32 = 1.0;
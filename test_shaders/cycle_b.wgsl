@@ -0,0 +1,2 @@
+//!include cycle_a.wgsl
+const b: f32 = 2.0;
@@ -0,0 +1,4 @@
+//!declare struct Light;
+fn intensity(light: Light) -> f32 {
+	return light.intensity;
+}
@@ -0,0 +1,4 @@
+//!include test_shaders/declare_lighting.wgsl
+struct Light {
+	intensity: f32,
+}
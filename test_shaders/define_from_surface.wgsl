@@ -0,0 +1,9 @@
+const resolution: vec2<f32> = vec2<f32>(f32(SURFACE_WIDTH), f32(SURFACE_HEIGHT));
+
+fn output_color(linear: vec3<f32>) -> vec3<f32> {
+//!ifdef SURFACE_SRGB
+	return linear;
+//!else
+	return pow(linear, vec3<f32>(1.0 / 2.2));
+//!endif
+}
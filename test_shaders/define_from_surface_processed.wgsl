@@ -0,0 +1,5 @@
+const resolution: vec2<f32> = vec2<f32>(f32(1920u), f32(1080u));
+
+fn output_color(linear: vec3<f32>) -> vec3<f32> {
+	return linear;
+}
@@ -0,0 +1,5 @@
+//!define VIEW_COUNT 4u
+//!define SAMPLES 4u
+const view_count: u32 = VIEW_COUNT;
+const samples: u32 = SAMPLES;
+const quality: u32 = QUALITY;
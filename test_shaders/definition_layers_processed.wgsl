@@ -0,0 +1,3 @@
+const view_count: u32 = 4u;
+const samples: u32 = 8u;
+const quality: u32 = 3u;
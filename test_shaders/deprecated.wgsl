@@ -0,0 +1,10 @@
+//!define OLD_SCALE 2.0
+//!deprecated OLD_SCALE "use SCALE instead"
+//!deprecated legacy_blend
+fn legacy_blend(a: f32) -> f32 {
+	return a * OLD_SCALE;
+}
+
+fn blend(a: f32) -> f32 {
+	return legacy_blend(a);
+}
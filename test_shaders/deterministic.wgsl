@@ -0,0 +1,4 @@
+﻿//!define FOG_DENSITY 0.5
+//!define FOG 1
+const fog: f32 = FOG * FOG_DENSITY;
+const lights: u32 = LIGHT + LIGHT_COUNT + LIGHT_COUNT_MAX;
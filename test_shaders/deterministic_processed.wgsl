@@ -0,0 +1,2 @@
+const fog: f32 = 1 * 0.5;
+const lights: u32 = 1u + 4u + 8u;
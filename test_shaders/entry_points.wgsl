@@ -0,0 +1,6 @@
+//!ifdef MASKED
+const masked: bool = true;
+//!else
+const masked: bool = false;
+//!endif
+const alpha_cutoff: f32 = ${ALPHA_CUTOFF:0.0};
@@ -0,0 +1,6 @@
+//!include test_shaders/nonexistent.wgsl
+//!ifdef
+const A: u32 = 1u;
+//!endif
+//!endif
+const B: u32 = ${UNDEFINED};
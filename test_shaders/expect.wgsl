@@ -0,0 +1,4 @@
+//!expect MAX_LIGHTS: u32
+//!expect FOG_COLOR: vec4<f32>
+const max_lights: u32 = MAX_LIGHTS;
+const fog_color: vec4<f32> = FOG_COLOR;
@@ -0,0 +1 @@
+//!include test_shaders/legacy_blur.frag
@@ -0,0 +1,7 @@
+@group(0) @binding(0)
+var<storage, read_write> values: array<f32>;
+
+[numthreads(64, 1, 1)]
+fn main(id: vec3<u32> : SV_DispatchThreadID, index: u32 : SV_GroupIndex) {
+	values[id.x] = f32(index);
+}
@@ -0,0 +1 @@
+//!include_first test_shaders/vendor_missing.wgsl test_shaders/included.wgsl test_shaders/included2.wgsl
@@ -0,0 +1 @@
+//!include_first test_shaders/vendor_missing.wgsl test_shaders/generic_missing.wgsl
@@ -0,0 +1,2 @@
+const scale: f32 = 2.0;
+//!include test_shaders/colors.wgsl section=shadows
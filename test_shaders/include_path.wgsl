@@ -0,0 +1 @@
+//!include library_lighting.wgsl
@@ -0,0 +1,2 @@
+//!include test_shaders/colors.wgsl section=palettes
+const scale: f32 = 2.0;
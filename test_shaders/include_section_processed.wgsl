@@ -0,0 +1,2 @@
+const warm: vec3<f32> = vec3<f32>(1.0, 0.5, 0.0);
+const scale: f32 = 2.0;
@@ -0,0 +1,5 @@
+//!include_spirv tint.spv as fn tint
+@fragment
+fn main() -> @location(0) vec4<f32> {
+	return tint();
+}
@@ -0,0 +1,2 @@
+const a: u32 = 1u;
+//!include test_shaders/unbalanced_conditional.wgsl
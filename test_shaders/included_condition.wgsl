@@ -0,0 +1,9 @@
+//!include test_shaders/included.wgsl
+//!if included("included.wgsl")
+const has_included: bool = true;
+//!else
+const has_included: bool = false;
+//!endif
+//!if !included("test_shaders/included2.wgsl")
+const has_included2: bool = false;
+//!endif
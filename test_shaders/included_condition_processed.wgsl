@@ -0,0 +1,3 @@
+//!include test_shaders/included.wgsl
+const has_included: bool = true;
+const has_included2: bool = false;
@@ -0,0 +1,4 @@
+@fragment
+fn fs_main(@location(0) color: vec4<f32>, @location(1) uv: vec2<f32>) -> @location(0) vec4<f32> {
+	return color * uv.x;
+}
@@ -0,0 +1,9 @@
+struct FragmentInput {
+	@location(0) color: vec3<f32>,
+	@location(2) normal: vec3<f32>,
+}
+
+@fragment
+fn fs_main(input: FragmentInput) -> @location(0) vec4<f32> {
+	return vec4<f32>(input.color * input.normal, 1.0);
+}
@@ -0,0 +1,9 @@
+override brightness: f32 = 1.0;
+
+@group(0) @binding(1)
+var<uniform> tint: vec4<f32>;
+
+@fragment
+fn fs_main(@location(0) color: vec4<f32>) -> @location(0) vec4<f32> {
+	return color * tint * brightness;
+}
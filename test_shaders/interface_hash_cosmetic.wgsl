@@ -0,0 +1,14 @@
+override brightness: f32 = 1.0;
+
+@group(0) @binding(0)
+var<uniform> tint: vec4<f32>;
+
+fn shade(color: vec4<f32>) -> vec4<f32> {
+	// Tints the color.
+	return color * tint;
+}
+
+@fragment
+fn fs_main(@location(0) color: vec4<f32>) -> @location(0) vec4<f32> {
+	return shade(color) * brightness;
+}
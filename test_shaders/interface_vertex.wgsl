@@ -0,0 +1,14 @@
+struct VertexOutput {
+	@builtin(position) position: vec4<f32>,
+	@location(0) color: vec4<f32>,
+	@location(1) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+	var output: VertexOutput;
+	output.position = vec4<f32>(f32(index), 0.0, 0.0, 1.0);
+	output.color = vec4<f32>(1.0);
+	output.uv = vec2<f32>(0.0);
+	return output;
+}
@@ -0,0 +1,2 @@
+//!define NUM_SAMPLES -1
+var<private> samples: array<f32, NUM_SAMPLES>;
\ No newline at end of file
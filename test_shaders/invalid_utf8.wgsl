@@ -0,0 +1,3 @@
+const a: f32 = 1.0;
+// 
+const b: f32 = 2.0;
@@ -0,0 +1,17 @@
+struct Camera {
+	view_projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+@group(1) @binding(1)
+var albedo_sampler: sampler;
+@group(1) @binding(0)
+var albedo: texture_2d<f32>;
+@group(2) @binding(0)
+var<uniform> unused: Camera;
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	return textureSample(albedo, albedo_sampler, position.xy) * camera.view_projection[0].x;
+}
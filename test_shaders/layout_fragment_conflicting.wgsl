@@ -0,0 +1,7 @@
+@group(0) @binding(0)
+var<storage> colors: array<vec4<f32>>;
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	return colors[u32(position.x)];
+}
@@ -0,0 +1,11 @@
+struct Camera {
+	view_projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+	return camera.view_projection * vec4<f32>(position, 1.0);
+}
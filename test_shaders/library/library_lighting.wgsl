@@ -0,0 +1 @@
+const library_light: f32 = 1.0;
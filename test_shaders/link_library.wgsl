@@ -0,0 +1,4 @@
+struct Light {
+	intensity: f32,
+}
+const LIGHT_INTENSITY: f32 = SCALE;
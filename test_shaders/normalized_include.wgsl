@@ -0,0 +1,4 @@
+//!include test_shaders/included.wgsl
+//!if included("test_shaders/../test_shaders/included.wgsl")
+const normalized: bool = true;
+//!endif
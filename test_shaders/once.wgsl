@@ -0,0 +1,4 @@
+//!include once_common.wgsl
+//!include once_shading.wgsl
+//!include included.wgsl
+//!include included.wgsl
@@ -0,0 +1,4 @@
+//!once
+struct Light {
+	intensity: f32,
+}
@@ -0,0 +1,2 @@
+//!include once_common.wgsl
+fn shade(light: Light) -> f32 { return light.intensity; }
@@ -0,0 +1,2 @@
+//!include test_shaders/order_declarations_lighting.wgsl
+//!include test_shaders/order_declarations_types.wgsl
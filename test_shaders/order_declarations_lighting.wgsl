@@ -0,0 +1,3 @@
+fn intensity(light: Light) -> f32 {
+	return light.intensity * SCALE;
+}
@@ -0,0 +1,9 @@
+// Light source.
+struct Light {
+	intensity: f32,
+}
+
+const SCALE: f32 = 2.0;
+fn intensity(light: Light) -> f32 {
+	return light.intensity * SCALE;
+}
@@ -0,0 +1,6 @@
+// Light source.
+struct Light {
+	intensity: f32,
+}
+
+const SCALE: f32 = 2.0;
@@ -0,0 +1,12 @@
+override brightness: f32 = 1.5;
+@id(7) override sample_count: u32;
+override use_fog: bool = false;
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+	var color = vec4<f32>(brightness, f32(sample_count), 0.0, 1.0);
+	if (use_fog) {
+		color.b = 1.0;
+	}
+	return color;
+}
@@ -0,0 +1 @@
+//!include @library/library_lighting.wgsl
@@ -0,0 +1,7 @@
+//!ifdef FOG
+//!ifdef FOG_HEIGHT
+const fog_height: f32 = 1.0;
+//!endif
+const fog_density: f32 = 0.5;
+//!endif
+const exposure: f32 = 1.0;
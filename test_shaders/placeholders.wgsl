@@ -0,0 +1,3 @@
+//!define SCALE 2.0
+const COUNT_SCALED: u32 = ${COUNT} * __COUNT__;
+const scale: f32 = ${SCALE};
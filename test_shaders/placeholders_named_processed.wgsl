@@ -0,0 +1,2 @@
+const COUNT_SCALED: u32 = 4u * __COUNT__;
+const scale: f32 = 2.0;
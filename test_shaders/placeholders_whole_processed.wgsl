@@ -0,0 +1,2 @@
+const COUNT_SCALED: u32 = ${COUNT} * 8u;
+const scale: f32 = ${SCALE};
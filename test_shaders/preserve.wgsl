@@ -0,0 +1,6 @@
+//!define SCALE 2.0
+//!preserve
+// Licensed under the terms of SCALE License, see ${LICENSE_URL}.
+//!ifdef NOTHING
+//!endpreserve
+const scale: f32 = SCALE;
@@ -0,0 +1,3 @@
+// Licensed under the terms of SCALE License, see ${LICENSE_URL}.
+//!ifdef NOTHING
+const scale: f32 = 2.0;
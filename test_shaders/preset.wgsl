@@ -0,0 +1,9 @@
+//!ifdef MULTIVIEW
+@group(0) @binding(0)
+var<uniform> view_projections: array<mat4x4<f32>, VIEW_COUNT>;
+//!endif
+
+@vertex
+fn main(VIEW_INDEX, @location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+	return view_projections[view_index] * vec4<f32>(position, 1.0);
+}
@@ -0,0 +1,7 @@
+@group(0) @binding(0)
+var<uniform> view_projections: array<mat4x4<f32>, 2u>;
+
+@vertex
+fn main(@builtin(view_index) view_index: i32, @location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+	return view_projections[view_index] * vec4<f32>(position, 1.0);
+}
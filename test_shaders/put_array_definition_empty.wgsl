@@ -0,0 +1,8 @@
+//!define LIGHT_ARRAY
+fn light_count() -> u32 {
+//!ifdef LIGHT_ARRAY_EMPTY
+	return 0u;
+//!else
+	return LIGHT_ARRAY[0];
+//!endif
+}
@@ -0,0 +1,3 @@
+fn light_count() -> u32 {
+	return 0u;
+}
@@ -1 +1 @@
-var<private> SCALAR_ARRAY: array<i32, 2> = array<i32, 2>(1,0,);
\ No newline at end of file
+var<private> SCALAR_ARRAY: array<i32, 2> = array<i32, 2>(1i,0i,);
\ No newline at end of file
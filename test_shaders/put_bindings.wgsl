@@ -0,0 +1,6 @@
+//!define BINDINGS
+
+@fragment
+fn main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+	return textureSample(albedo, samp, uv) * transform.scale;
+}
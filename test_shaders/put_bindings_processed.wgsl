@@ -0,0 +1,12 @@
+struct Transform {
+	translation: vec3<f32>,
+	scale: f32,
+}
+@group(0) @binding(0) var<uniform> transform: Transform;
+@group(1) @binding(0) var albedo: texture_2d<f32>;
+@group(1) @binding(1) var samp: sampler;
+
+@fragment
+fn main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+	return textureSample(albedo, samp, uv) * transform.scale;
+}
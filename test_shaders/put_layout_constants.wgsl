@@ -0,0 +1,7 @@
+//!define TRANSFORM
+@group(0) @binding(0)
+var<storage> transforms: array<u32>;
+
+fn scale(index: u32) -> f32 {
+	return bitcast<f32>(transforms[(index * TRANSFORM_SIZE + TRANSFORM_SCALE_OFFSET) / 4u]);
+}
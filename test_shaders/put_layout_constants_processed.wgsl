@@ -0,0 +1,9 @@
+const TRANSFORM_SIZE: u32 = 16u;
+const TRANSFORM_TRANSLATION_OFFSET: u32 = 0u;
+const TRANSFORM_SCALE_OFFSET: u32 = 12u;
+@group(0) @binding(0)
+var<storage> transforms: array<u32>;
+
+fn scale(index: u32) -> f32 {
+	return bitcast<f32>(transforms[(index * TRANSFORM_SIZE + TRANSFORM_SCALE_OFFSET) / 4u]);
+}
@@ -0,0 +1,5 @@
+//!define transform
+@vertex
+fn main() -> @builtin(position) vec4<f32> {
+	return vec4<f32>(transform.translation * transform.scale, 1.0);
+}
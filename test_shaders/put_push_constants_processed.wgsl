@@ -0,0 +1,10 @@
+struct Transform {
+	_offset: array<u32, 4>,
+	translation: vec3<f32>,
+	scale: f32,
+}
+var<push_constant> transform: Transform;
+@vertex
+fn main() -> @builtin(position) vec4<f32> {
+	return vec4<f32>(transform.translation * transform.scale, 1.0);
+}
@@ -0,0 +1 @@
+//!define PARTICLES
\ No newline at end of file
@@ -0,0 +1,2 @@
+var<private> PARTICLES_POSITION: array<vec2<f32>, 2> = array<vec2<f32>, 2>(vec2<f32>(1.0, 2.0),vec2<f32>(3.5, 4.0),);
+var<private> PARTICLES_MASS: array<f32, 2> = array<f32, 2>(0.5,2.0,);
\ No newline at end of file
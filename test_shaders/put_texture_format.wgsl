@@ -0,0 +1,13 @@
+@group(0) @binding(0)
+var output: texture_storage_2d<OUTPUT_FORMAT_STORAGE, write>;
+//!ifdef SHADOW_FORMAT_DEPTH
+@group(0) @binding(1)
+var shadow_map: texture_depth_2d;
+//!else
+@group(0) @binding(1)
+var shadow_map: texture_2d<f32>;
+//!endif
+//!ifdef OUTPUT_FORMAT_SRGB
+const OUTPUT_SRGB: bool = true;
+//!endif
+const OUTPUT_COMPONENTS: u32 = OUTPUT_FORMAT_COMPONENTS;
@@ -0,0 +1,5 @@
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(1)
+var shadow_map: texture_depth_2d;
+const OUTPUT_COMPONENTS: u32 = 4u;
@@ -0,0 +1,3 @@
+const sun_direction: vec3<f32> = SUN_DIR;
+const tile_size: vec2<u32> = TILE_SIZE;
+const tint: vec4<f32> = TINT;
@@ -0,0 +1,3 @@
+const sun_direction: vec3<f32> = vec3<f32>(0.2, -1.0, 0.3);
+const tile_size: vec2<u32> = vec2<u32>(16u, 8u);
+const tint: vec4<f32> = vec4<f32>(1.0, 0.5, 0.0, 1.0);
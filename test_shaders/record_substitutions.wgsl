@@ -0,0 +1,7 @@
+//!define SCALE 2.0
+//!preserve
+// SCALE is kept
+// in preserved regions.
+//!endpreserve
+//!include record_substitutions_included.wgsl
+const scaled: f32 = SCALE * FACTOR;
@@ -0,0 +1 @@
+const factor: f32 = FACTOR;
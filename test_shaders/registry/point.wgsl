@@ -0,0 +1,3 @@
+fn point_light() -> f32 { return 1.0; }
+//!define_add LIGHT_TYPE_COUNT 1u
+//!define_append LIGHT_TYPES POINT
@@ -0,0 +1,3 @@
+//!include point.wgsl
+//!include spot.wgsl
+const light_types = array<u32, LIGHT_TYPE_COUNT>(LIGHT_TYPES);
@@ -0,0 +1,3 @@
+fn spot_light() -> f32 { return 0.5; }
+//!define_add LIGHT_TYPE_COUNT 1
+//!define_append LIGHT_TYPES SPOT
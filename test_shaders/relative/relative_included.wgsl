@@ -0,0 +1 @@
+const relative: bool = true;
@@ -0,0 +1 @@
+//!include relative_included.wgsl
@@ -0,0 +1,5 @@
+//!define NAME class
+// let this be ignored
+fn main() {
+	let NAME = 1u;
+}
@@ -0,0 +1,8 @@
+//!define USE_FOG
+//!ifdef USE_FOG
+fn USE_FOG() -> bool { return true; }
+//!endif
+//!ifdef DENSITY
+const density_defined: bool = true;
+//!endif
+const density: f32 = DENSITY;
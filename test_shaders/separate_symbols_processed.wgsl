@@ -0,0 +1,2 @@
+fn USE_FOG() -> bool { return true; }
+const density: f32 = 0.5;
@@ -0,0 +1,9 @@
+//!define BINDINGS
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+	if (transforms.scale == 0.0) {
+		return;
+	}
+	output.scale = transforms.scale * unused.scale;
+}
@@ -0,0 +1,9 @@
+var<private> weights: array<f32, COUNT>;
+//!ifdef FOG
+const FOG_DENSITY: f32 = ${FOG_DENSITY};
+//!endif
+
+@compute @workgroup_size(1)
+fn main() {
+	weights[1] = 1.0;
+}
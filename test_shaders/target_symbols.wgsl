@@ -0,0 +1,12 @@
+//!ifdef TARGET_WEB
+const web: bool = true;
+//!endif
+//!ifdef TARGET_NATIVE
+const web: bool = false;
+//!endif
+//!ifdef TARGET_DEBUG
+const debug: bool = true;
+//!endif
+//!ifdef TARGET_RELEASE
+const debug: bool = false;
+//!endif
@@ -0,0 +1,2 @@
+const web: bool = false;
+const debug: bool = true;
@@ -0,0 +1,4 @@
+//!define EXPOSURE 1.5
+const light_count: u32 = ${LIGHT_COUNT:1u};
+const shadow_count: u32 = ${SHADOW_COUNT:0u};
+const exposure: f32 = ${EXPOSURE};
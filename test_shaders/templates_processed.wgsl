@@ -0,0 +1,3 @@
+const light_count: u32 = 4u;
+const shadow_count: u32 = 0u;
+const exposure: f32 = 1.5;
@@ -0,0 +1,2 @@
+//!ifdef FOG
+const fog: bool = true;
@@ -0,0 +1,2 @@
+//!preserve
+const scale: f32 = 2.0;
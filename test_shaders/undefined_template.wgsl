@@ -0,0 +1 @@
+const exposure: f32 = ${EXPOSURE};
@@ -0,0 +1,3 @@
+//!include lighting.wgsl
+//!requires lighting >= 2
+//!requires lighting < 3.0.0
@@ -0,0 +1,4 @@
+//!requires lighting 3
+//!include lighting.wgsl
+//!requires shadows = 1
+//!module lighting 2.2
@@ -0,0 +1,2 @@
+//!module lighting 2.1
+const lighting_version: u32 = 2u;